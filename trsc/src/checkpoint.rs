@@ -0,0 +1,242 @@
+//! Checks the parameter shapes of a model against a checkpoint manifest
+//! before the weights are loaded. The manifest is a JSON object of
+//! `param_name -> shape` such as `{"fc1.weight": [3, 2], "fc1.bias": [3]}`.
+
+use core::Core;
+use errors::Diag;
+use parsing::term::{AliasAssign, TensorTy};
+use span::CSpan;
+use typing::type_env::{Alias, ModName, TypeEnv};
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// shape by `state_dict` name
+pub type Shapes = BTreeMap<String, Vec<i64>>;
+
+/// every parameter of `module` by its `state_dict` name. Layers that are
+/// declared modules themselves are flattened under the name of the layer.
+pub fn parameter_shapes(core: &Core, tenv: &TypeEnv, module: &str) -> Result<Shapes, Diag> {
+    let mut shapes = BTreeMap::new();
+    collect_shapes(core, tenv, module, "", &mut shapes)?;
+    Ok(shapes)
+}
+
+fn collect_shapes(core: &Core, tenv: &TypeEnv, module: &str, prefix: &str, shapes: &mut Shapes) -> Result<(), Diag> {
+    let mod_name = ModName::Named(module.to_owned());
+    for (name, inits) in tenv.module_inits(&mod_name) {
+        let layer = tenv.resolve_type(&mod_name, &Alias::Variable(name.clone()))
+            .and_then(|ty| ty.as_mod_name());
+        let layer = match layer {
+            Some(ModName::Named(layer)) => layer,
+            _ => continue,
+        };
+        let path = format!("{}{}", prefix, name);
+        match core.find_mod(&layer) {
+            Some(op) => {
+                let params = op.parameters(&inits).ok_or_else(|| Diag::InvalidArgument(
+                    format!("cannot tell the parameter shapes of `{}`", path),
+                    inits.first().map_or_else(CSpan::fresh_span, |a| a.span),
+                ))?;
+                for (param, shape) in params {
+                    shapes.insert(format!("{}.{}", path, param), shape);
+                }
+            }
+            None => collect_shapes(core, tenv, &layer, &format!("{}.", path), shapes)?,
+        }
+    }
+    Ok(())
+}
+
+/// a diagnostic for each parameter whose shape differs, is missing from the
+/// checkpoint, or is in the checkpoint but not in the model
+pub fn check_manifest(model: &Shapes, manifest: &Shapes) -> Vec<Diag> {
+    let mut errs = vec![];
+    for (name, shape) in model.iter() {
+        match manifest.get(name) {
+            None => errs.push(Diag::MissingParam(name.clone())),
+            Some(saved) if saved != shape =>
+                errs.push(Diag::ParamShapeMismatch(name.clone(), shape.clone(), saved.clone())),
+            Some(_) => (),
+        }
+    }
+    for name in manifest.keys().filter(|name| !model.contains_key(*name)) {
+        errs.push(Diag::UnexpectedParam(name.clone()));
+    }
+    errs
+}
+
+/// read a manifest, the error says what was expected where
+pub fn parse_manifest(src: &str) -> Result<Shapes, String> {
+    let mut it = src.chars().peekable();
+    let mut shapes = BTreeMap::new();
+    expect(&mut it, '{')?;
+    if !eat(&mut it, '}') {
+        loop {
+            let name = parse_string(&mut it)?;
+            expect(&mut it, ':')?;
+            let shape = parse_shape(&mut it)?;
+            shapes.insert(name, shape);
+            if eat(&mut it, '}') {
+                break;
+            }
+            expect(&mut it, ',')?;
+        }
+    }
+    skip_ws(&mut it);
+    match it.next() {
+        None => Ok(shapes),
+        Some(c) => Err(format!("unexpected `{}` after the manifest", c)),
+    }
+}
+
+/// a tensor alias for each parameter of an exported `state_dict`, so that
+/// an existing PyTorch model can be ported one layer at a time.
+/// `fc1.weight` becomes `tsr fc1_weight = [3, 2];`
+pub fn import_pytorch_shapes(json: &str) -> Result<Vec<AliasAssign>, Diag> {
+    let shapes = parse_manifest(json).map_err(Diag::MalformedShapes)?;
+    Ok(shapes
+        .into_iter()
+        .map(|(name, shape)| {
+            let span = CSpan::fresh_span();
+            AliasAssign::Tensor {
+                ident: name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect(),
+                params: vec![],
+                rhs: TensorTy::Generic(shape.iter().map(|d| d.to_string()).collect(), span),
+                span,
+            }
+        })
+        .collect())
+}
+
+fn skip_ws(it: &mut Peekable<Chars>) {
+    while it.peek().map_or(false, |c| c.is_whitespace()) {
+        it.next();
+    }
+}
+
+/// consume `c` if it is next
+fn eat(it: &mut Peekable<Chars>, c: char) -> bool {
+    skip_ws(it);
+    if it.peek() == Some(&c) {
+        it.next();
+        true
+    } else {
+        false
+    }
+}
+
+fn expect(it: &mut Peekable<Chars>, c: char) -> Result<(), String> {
+    if eat(it, c) {
+        Ok(())
+    } else {
+        Err(format!("expected `{}`", c))
+    }
+}
+
+fn parse_string(it: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(it, '"')?;
+    let mut buf = String::new();
+    loop {
+        match it.next() {
+            Some('"') => return Ok(buf),
+            Some('\\') => match it.next() {
+                Some(c) => buf.push(c),
+                None => break,
+            },
+            Some(c) => buf.push(c),
+            None => break,
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+fn parse_shape(it: &mut Peekable<Chars>) -> Result<Vec<i64>, String> {
+    expect(it, '[')?;
+    let mut shape = vec![];
+    if eat(it, ']') {
+        return Ok(shape);
+    }
+    loop {
+        skip_ws(it);
+        let mut digits = String::new();
+        while it.peek().map_or(false, |c| c.is_digit(10) || *c == '-') {
+            digits.push(it.next().unwrap());
+        }
+        let n = digits.parse().map_err(|_| format!("expected a dimension, got `{}`", digits))?;
+        shape.push(n);
+        if eat(it, ']') {
+            return Ok(shape);
+        }
+        expect(it, ',')?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compile::compile_str;
+
+    #[test]
+    fn should_report_mismatched_manifest() {
+        let model = compile_str(include_str!("../tests/input/xor.trs")).unwrap();
+        let shapes = parameter_shapes(&model.core.borrow(), &model.tenv.borrow(), "Xor").unwrap();
+        assert_eq!(shapes["fc1.weight"], vec![3, 2]);
+        assert_eq!(shapes["fc2.bias"], vec![1]);
+        assert_eq!(shapes.len(), 4);
+
+        let manifest = parse_manifest(r#"{
+            "fc1.weight": [3, 4],
+            "fc1.bias": [3],
+            "fc2.weight": [1, 3],
+            "fc3.weight": []
+        }"#).unwrap();
+        let errs = check_manifest(&shapes, &manifest);
+        assert_eq!(errs.len(), 3);
+        match errs[0] {
+            Diag::ParamShapeMismatch(ref name, ref ours, ref saved) => {
+                assert_eq!(name, "fc1.weight");
+                assert_eq!((ours.as_slice(), saved.as_slice()), (&[3, 2][..], &[3, 4][..]));
+            }
+            ref e => panic!("{:?}", e),
+        }
+        match errs[1] {
+            Diag::MissingParam(ref name) => assert_eq!(name, "fc2.bias"),
+            ref e => panic!("{:?}", e),
+        }
+        match errs[2] {
+            Diag::UnexpectedParam(ref name) => assert_eq!(name, "fc3.weight"),
+            ref e => panic!("{:?}", e),
+        }
+        assert!(check_manifest(&shapes, &shapes).is_empty());
+    }
+
+    #[test]
+    fn should_reject_malformed_manifest() {
+        assert!(parse_manifest(r#"{"fc1.weight": [3, 2}"#).is_err());
+        assert!(parse_manifest(r#"{"fc1.weight": [3, x]}"#).is_err());
+        assert_eq!(parse_manifest(" { } ").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn should_import_state_dict_shapes_as_aliases() {
+        let aliases = import_pytorch_shapes(r#"{
+            "encoder.fc1.weight": [128, 784],
+            "encoder.fc1.bias": [128]
+        }"#).unwrap();
+        let aliases: Vec<_> = aliases.iter().map(|a| match a {
+            AliasAssign::Tensor { ident, rhs: TensorTy::Generic(dims, _), .. } => (ident.as_str(), dims.clone()),
+            a => panic!("{:?}", a),
+        }).collect();
+        assert_eq!(aliases, vec![
+            ("encoder_fc1_bias", vec!["128".to_owned()]),
+            ("encoder_fc1_weight", vec!["128".to_owned(), "784".to_owned()]),
+        ]);
+
+        match import_pytorch_shapes(r#"{"fc1.weight": 3}"#) {
+            Err(Diag::MalformedShapes(_)) => (),
+            r => panic!("{:?}", r),
+        }
+        assert!(import_pytorch_shapes(r#"{"fc1.weight": [3, 2]"#).is_err());
+    }
+}
@@ -25,6 +25,11 @@ enum Item {
     SelfFnApp(Option<VarName>, FnName, Vec<TyFnAppArg>),
     Ident(bool, String),
     ViewFn(Option<VarName>, Type),
+    /// a lambda parameter bound to the piped value
+    Bind(VarName, VarName),
+    /// opens a `with torch.no_grad():` region, closed by the next `Dedent`
+    NoGrad,
+    Dedent,
 }
 
 pub struct Module {
@@ -122,7 +127,25 @@ impl Module {
                 .push_back(Item::Ident(var.is_none(), i.as_str().to_owned())),
             TyInteger(..) => (),
             TyFloat(..) => (),
+            TyBool(..) => (),
+            TyStr(..) => (),
             TyStmt(t, _) => self.collect_term(t, var, true)?,
+            // checked during type reconstruction, nothing to emit
+            TyShapeAssert(..) => (),
+            TyLambda(box lambda) => {
+                self.collect_term(&lambda.body, var, is_stmt)?;
+                let arg_name = lambda.arg.name.clone().unwrap();
+                if lambda.param != arg_name {
+                    self.codegen_stack.push_back(Item::Bind(lambda.param.clone(), arg_name.clone()));
+                }
+                self.collect_term(&lambda.arg.arg, Some(arg_name), is_stmt)?;
+            }
+            // items are emitted last pushed first
+            TyNoGrad(body, _) => {
+                self.codegen_stack.push_back(Item::Dedent);
+                self.collect_term(body, var, is_stmt)?;
+                self.codegen_stack.push_back(Item::NoGrad);
+            }
             TyNone => (),
             _ => panic!("{:#?}", term),
         }
@@ -173,6 +196,9 @@ impl Module {
                     };
 
                     match (is_global, is_stmt) {
+                        (true, false) if op.is_method() => {
+                            writeln!(self.buf, "{}.{}()", out, op.pytorch_name())?
+                        }
                         (true, false) => {
                             write!(self.buf, "{}(",  op.pytorch_name())?;
                             writeln!(self.buf, "{})", out)?
@@ -205,6 +231,16 @@ impl Module {
                         writeln!(self.buf, "return {}", name)?;
                     }
                 }
+                Item::Bind(name, value) => {
+                    self.indent()?;
+                    writeln!(self.buf, "{} = {}", name, value)?;
+                }
+                Item::NoGrad => {
+                    self.indent()?;
+                    writeln!(self.buf, "with torch.no_grad():")?;
+                    self.tab();
+                }
+                Item::Dedent => self.shift_tab(),
                 Item::ViewFn(var_name, ty) => {
                     self.indent()?;
                     match var_name {
@@ -253,7 +289,7 @@ impl Module {
                     fn_app.name.as_str().to_owned(),
                     fn_app.args.clone(),
                     is_stmt,
-                    Some(mod_ty.as_mod_name()),
+                    mod_ty.as_mod_name(),
                 ));
             }
         }
@@ -0,0 +1,688 @@
+//! Library entry point that runs the whole pipeline on a source string
+//! and hands back either the inferred model or the collected diagnostics.
+//! Nothing is printed here, rendering is left to the caller.
+
+use checkpoint::{self, Shapes};
+use core::Core;
+use errors::{Diag, Emitter, Report};
+use lint;
+use parsing::ast_builder::ASTBuilder;
+use parsing::term::{Decl, Term};
+use profile::Profile;
+use span::CSpan;
+use typing::annotate::Annotator;
+use typing::constraint::{Constraints, Equals};
+use typing::dataflow::check_acyclic_dataflow;
+use typing::inferred_ast::{free_vars, subs};
+use typing::type_env::{Alias, ModName, TypeEnv};
+use typing::typed_term::{TyDecl, TyFnDecl, TyTerm};
+use typing::unifier::{Substitution, Unifier};
+use typing::Type;
+
+use codespan::{ByteSpan, CodeMap};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// options that control how strictly a program is checked
+#[derive(Default)]
+pub struct CompileOptions {
+    /// report every unresolved type variable as an error
+    pub strict_shapes: bool,
+    /// report conflicting dims with the shapes of their tensors
+    pub full_shapes: bool,
+    /// directory searched for `use path::..` before the core library
+    pub import_path: Option<PathBuf>,
+}
+
+/// result of a successful compilation
+pub struct CompiledModel {
+    pub core: Rc<RefCell<Core>>,
+    pub tenv: Rc<RefCell<TypeEnv>>,
+    /// composition of the substitutions from every unification pass
+    pub sub: Substitution,
+    /// fully substituted typed ast
+    pub ast: TyTerm,
+    /// resolved type of each declared module
+    pub modules: BTreeMap<String, Type>,
+}
+
+impl CompiledModel {
+    /// swap the body of `module::method` for `body` and infer only that
+    /// method again. The method keeps the interface reconstructed for it,
+    /// a body that does not fit the declared signature is an error and
+    /// leaves the model as it was.
+    pub fn replace_module_body(&mut self, module: &str, method: &str, body: &Term) -> Result<(), Vec<Diag>> {
+        let (params, ret_ty) = match self.method_mut(module, method) {
+            Some(f) => (f.fn_params.clone(), f.ret_ty.clone()),
+            None => return Err(vec![Diag::SymbolNotFound(format!("{}::{}", module, method), CSpan::fresh_span())]),
+        };
+        let params: Vec<_> = params.iter()
+            .map(|p| (p.name.clone(), self.sub.apply_ty(&p.ty)))
+            .collect();
+        let emitter = Rc::new(RefCell::new(Emitter::new(CodeMap::new(), false)));
+        let tenv = Rc::clone(&self.tenv);
+        let mod_name = ModName::Named(module.to_owned());
+        let inferred = TypeEnv::with_isolated_scopes(&tenv, &mod_name, || {
+            tenv.borrow_mut().set_module(mod_name.clone());
+            let ret = infer_body(body, &params, &ret_ty, &mod_name, &emitter, &tenv);
+            tenv.borrow_mut().set_module(ModName::Global);
+            ret
+        });
+        match inferred {
+            Some((typed, sub)) => {
+                self.method_mut(module, method).unwrap().func_block = box typed;
+                self.sub = self.sub.compose(sub);
+                Ok(())
+            }
+            None => Err(emitter.borrow().errs().to_vec()),
+        }
+    }
+
+    /// compare the parameter shapes of the last declared node, taken to be
+    /// the model, against the shapes saved in a checkpoint manifest
+    pub fn check_weights(&self, saved: &Shapes) -> Vec<Diag> {
+        let node = match self.last_node() {
+            Some(node) => node,
+            None => return vec![],
+        };
+        match checkpoint::parameter_shapes(&self.core.borrow(), &self.tenv.borrow(), &node) {
+            Ok(shapes) => checkpoint::check_manifest(&shapes, saved),
+            Err(e) => vec![e],
+        }
+    }
+
+    fn last_node(&self) -> Option<String> {
+        match self.ast {
+            TyTerm::TyProgram(ref decls) => decls.iter().rev()
+                .filter_map(|d| match d {
+                    TyDecl::TyNodeDecl(node) => Some(node.name.clone()),
+                    _ => None,
+                })
+                .next(),
+            _ => None,
+        }
+    }
+
+    fn method_mut(&mut self, module: &str, method: &str) -> Option<&mut TyFnDecl> {
+        let decls = match self.ast {
+            TyTerm::TyProgram(ref mut decls) => decls,
+            _ => return None,
+        };
+        decls.iter_mut()
+            .filter_map(|d| match d {
+                TyDecl::TyGraphDecl(g) => Some(g),
+                _ => None,
+            })
+            .filter(|g| g.name == module)
+            .flat_map(|g| g.fns.iter_mut())
+            .find(|f| f.name.as_str() == method)
+    }
+}
+
+/// annotate a method body in the current module, with its parameters in
+/// scope, and unify it until it stabilizes, like `run` does for the whole program
+fn infer_body(
+    body: &Term,
+    params: &[(String, Type)],
+    ret_ty: &Type,
+    mod_name: &ModName,
+    emitter: &Rc<RefCell<Emitter>>,
+    tenv: &Rc<RefCell<TypeEnv>>,
+) -> Option<(TyTerm, Substitution)> {
+    let annotator = Annotator::new(Rc::clone(emitter), Rc::clone(tenv));
+    let typed = TypeEnv::with_module_scope(tenv, mod_name, || {
+        for (name, ty) in params {
+            tenv.borrow_mut()
+                .add_type(mod_name, &Alias::Variable(name.clone()), ty.clone())
+                .unwrap_or_else(|e| emitter.borrow_mut().add(e));
+        }
+        annotator.annotate(body)
+    }).unwrap_or_else(|e| {
+        emitter.borrow_mut().add(e);
+        TyTerm::TyNone
+    });
+    let ret = unify_until_stable(typed, emitter, |typed| {
+        if emitter.borrow().has_errors() {
+            return None;
+        }
+        let mut cs = Constraints::new(Rc::clone(emitter), Rc::clone(tenv));
+        TypeEnv::with_collection_scope(tenv, mod_name, || cs.collect(typed));
+        cs.set.insert(Equals(typed.ty(), ret_ty.clone()));
+        Some(Unifier::new(Rc::clone(emitter), Rc::clone(tenv)).unify(cs))
+    })?;
+    if emitter.borrow().has_errors() {
+        return None;
+    }
+    Some(ret)
+}
+
+/// substitute the solution of `step` into `typed` until that no longer
+/// changes it. `step` collects and unifies the constraints of one round,
+/// `None` stops early
+fn unify_until_stable<F>(
+    mut typed: TyTerm,
+    emitter: &Rc<RefCell<Emitter>>,
+    mut step: F,
+) -> Option<(TyTerm, Substitution)>
+    where F: FnMut(&TyTerm) -> Option<Substitution>
+{
+    let mut sub = Substitution::empty();
+    let mut i = 0;
+    loop {
+        let mut new_sub = step(&typed)?;
+        let new_typed = subs(&typed, &mut new_sub);
+        sub = sub.compose(new_sub);
+        if new_typed == typed {
+            return Some((typed, sub));
+        }
+        typed = new_typed;
+        i += 1;
+        if i > 1_000_000 {
+            emitter.borrow_mut().add(Diag::UnknownError);
+            return None;
+        }
+    }
+}
+
+pub fn compile_str(source: &str) -> Result<CompiledModel, Vec<Diag>> {
+    compile_with_imports(source, &[])
+}
+
+/// like `compile_str`, `imports` holds the source of each file that a `use`
+/// can refer to, by path name
+pub fn compile_with_imports(source: &str, imports: &[(&str, &str)]) -> Result<CompiledModel, Vec<Diag>> {
+    compile_with_options(source, imports, &CompileOptions::default())
+}
+
+/// like `compile_with_imports`, checking the program as set by `options`
+pub fn compile_with_options(
+    source: &str,
+    imports: &[(&str, &str)],
+    options: &CompileOptions,
+) -> Result<CompiledModel, Vec<Diag>> {
+    let (emitter, model) = compile(source, imports, options, &mut None);
+    let errs = emitter.borrow().errs().to_vec();
+    model.ok_or(errs)
+}
+
+/// like `compile_str`, also timing each phase
+pub fn compile_profiled(source: &str) -> (Result<CompiledModel, Vec<Diag>>, Profile) {
+    let mut profile = Some(Profile::new());
+    let (emitter, model) = compile(source, &[], &CompileOptions::default(), &mut profile);
+    let errs = emitter.borrow().errs().to_vec();
+    (model.ok_or(errs), profile.unwrap())
+}
+
+/// every diagnostic the pipeline produced, errors and warnings alike
+pub fn check_str(source: &str) -> Vec<Report> {
+    compile(source, &[], &CompileOptions::default(), &mut None).0.borrow().reports()
+}
+
+/// parse the file imported as `path_name` and make its `pub` aliases
+/// available to `use path_name::{..}`
+pub fn load_import(
+    path_name: &str,
+    source: &str,
+    cspan: CSpan,
+    emitter: &Rc<RefCell<Emitter>>,
+    tenv: &Rc<RefCell<TypeEnv>>,
+) -> Option<()> {
+    let program = ASTBuilder::new(Rc::clone(emitter), cspan).parse_str(source)?;
+    tenv.borrow_mut().export_file(path_name, &program);
+    Some(())
+}
+
+/// load `<dir>/<path>.trs` for every `use path::..` in `program` where the file exists.
+/// Other paths are left to the core library.
+pub fn load_import_path(
+    dir: &Path,
+    program: &Term,
+    emitter: &Rc<RefCell<Emitter>>,
+    tenv: &Rc<RefCell<TypeEnv>>,
+) -> Option<()> {
+    let decls = match program {
+        Term::Program(decls) => decls,
+        _ => return Some(()),
+    };
+    for decl in decls.iter() {
+        let path_name = match decl {
+            Decl::UseStmt(u) => &u.mod_name,
+            _ => continue,
+        };
+        let fname = dir.join(format!("{}.trs", path_name));
+        let source = match fs::read_to_string(&fname) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let span = emitter.borrow_mut().add_file(&fname.to_string_lossy(), &source);
+        load_import(path_name, &source, CSpan::new(span), emitter, tenv)?;
+    }
+    Some(())
+}
+
+fn compile(
+    source: &str,
+    imports: &[(&str, &str)],
+    options: &CompileOptions,
+    profile: &mut Option<Profile>,
+) -> (Rc<RefCell<Emitter>>, Option<CompiledModel>) {
+    let mut code_map = CodeMap::new();
+    let file_map = code_map.add_filemap("<input>".to_owned().into(), source.to_owned());
+    let emitter = Rc::new(RefCell::new(Emitter::new(code_map, false)));
+    let imports: Vec<_> = imports.iter()
+        .map(|(path_name, src)| {
+            let span = emitter.borrow_mut().add_file(&format!("{}.trs", path_name), src);
+            (*path_name, *src, span)
+        })
+        .collect();
+    let model = run(source, CSpan::new(file_map.span()), &imports, options, &emitter, profile);
+    (emitter, model)
+}
+
+/// run the pipeline on `source`, which spans `cspan` in the code map of
+/// `emitter`. Diagnostics are left in `emitter` for the caller to render.
+pub fn run(
+    source: &str,
+    cspan: CSpan,
+    imports: &[(&str, &str, ByteSpan)],
+    options: &CompileOptions,
+    emitter: &Rc<RefCell<Emitter>>,
+    profile: &mut Option<Profile>,
+) -> Option<CompiledModel> {
+    // parse into untyped ast
+    let builder = ASTBuilder::new(Rc::clone(emitter), cspan);
+    let program = Profile::time(profile, "parse", "decls",
+        || builder.parse_str(source),
+        |p| p.as_ref().map_or(0, decl_count))?;
+
+    // statements that did not parse are skipped, the rest of the program is
+    // still checked so that their errors are reported in the same run
+    let recovered = emitter.borrow().errs().len();
+    let errs = |tenv: &Rc<RefCell<TypeEnv>>| -> Option<()> {
+        // e.g. running out of type ids, raised outside of a `Result`
+        for e in tenv.borrow_mut().take_diags() {
+            emitter.borrow_mut().add(e);
+        }
+        if emitter.borrow().errs()[recovered..].iter().any(|e| !e.is_warning()) { None } else { Some(()) }
+    };
+
+    // annotate ast with type vars
+    let core = Rc::new(RefCell::new(Core::new()));
+    let tenv = Rc::new(RefCell::new(TypeEnv::new(Rc::clone(&core))));
+    for (path_name, src, span) in imports.iter() {
+        load_import(path_name, src, CSpan::new(*span), emitter, &tenv)?;
+    }
+    if let Some(ref dir) = options.import_path {
+        load_import_path(dir, &program, emitter, &tenv)?;
+    }
+    errs(&tenv)?;
+    let annotator = Annotator::new(Rc::clone(emitter), Rc::clone(&tenv));
+    let ast = Profile::time(profile, "annotate", "decls",
+        || annotator.annotate(&program),
+        |_| decl_count(&program));
+    errs(&tenv)?;
+
+    // shapes are only well defined if the data never flows back
+    for e in check_acyclic_dataflow(&ast) {
+        emitter.borrow_mut().add(e);
+    }
+    errs(&tenv)?;
+
+    // unify until the ast stabilizes
+    let (ast, mut sub) = unify_until_stable(ast, emitter, |ast| {
+        let cs = Profile::time(profile, "collect", "constraints",
+            || {
+                let mut cs = Constraints::new(Rc::clone(emitter), Rc::clone(&tenv));
+                cs.collect(ast);
+                cs
+            },
+            |cs| cs.set.len());
+        errs(&tenv)?;
+        let new_sub = Profile::time(profile, "unify", "substitutions",
+            || {
+                let mut unifier = Unifier::new(Rc::clone(emitter), Rc::clone(&tenv));
+                unifier.set_full_shapes(options.full_shapes);
+                unifier.unify(cs)
+            },
+            |s| s.bindings().count());
+        errs(&tenv)?;
+        Some(new_sub)
+    })?;
+
+    // generic module bodies are checked against their instances once
+    // every body is unified
+    for e in tenv.borrow().check_instances(&sub) {
+        emitter.borrow_mut().add(e);
+    }
+    for w in lint::unused_layers(&ast) {
+        emitter.borrow_mut().add(w);
+    }
+    if options.strict_shapes {
+        // batch dimensions are symbolic by design
+        let batch_dims = tenv.borrow().batch_dims();
+        for ty in free_vars(&ast).into_iter().filter(|t| !batch_dims.contains(t)) {
+            let e = tenv.borrow().ambiguous(ty);
+            emitter.borrow_mut().add(e);
+        }
+    }
+    if emitter.borrow().has_errors() {
+        return None;
+    }
+
+    let modules = tenv.borrow()
+        .module_names()
+        .into_iter()
+        .filter_map(|name| {
+            let ty = tenv.borrow().resolve_type(&ModName::Global, &Alias::Variable(name.clone()))?;
+            match ty {
+                Type::Module(_, Some(_), _) => Some((name, sub.apply_ty(&ty))),
+                _ => None,
+            }
+        })
+        .collect();
+
+    Some(CompiledModel {
+        core,
+        tenv,
+        sub,
+        ast,
+        modules,
+    })
+}
+
+/// number of top level declarations
+pub fn decl_count(program: &Term) -> usize {
+    match program {
+        Term::Program(decls) => decls.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan_reporting::Severity;
+    use typing::types::Precision;
+
+    const XOR: &str = include_str!("../tests/input/xor.trs");
+
+    #[test]
+    fn should_compile_valid_source() {
+        let model = compile_str(XOR).unwrap();
+        match model.modules.get("Xor") {
+            Some(&Type::Module(ref name, Some(_), _)) => assert_eq!(name, "Xor"),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_return_diagnostics_for_broken_source() {
+        match compile_str(include_str!("../tests/input/xor_missing_semicolon.trs")) {
+            Err(errs) => assert!(!errs.is_empty()),
+            Ok(_) => panic!("expected diagnostics"),
+        }
+    }
+
+    #[test]
+    fn should_reject_method_call_on_tensor() {
+        match compile_str(include_str!("../tests/input/method_on_tensor.trs")) {
+            Err(errs) => assert!(errs.iter().any(|e| match e {
+                Diag::NotAModule(..) => true,
+                _ => false,
+            }), "{:?}", errs),
+            Ok(_) => panic!("expected a method call on a tensor to fail"),
+        }
+    }
+
+    #[test]
+    fn should_check_the_rest_of_a_block_with_a_broken_statement() {
+        let source = include_str!("../tests/input/broken_statement.trs");
+        let errs = compile_str(source).err().unwrap();
+        assert!(errs.iter().any(|e| match e {
+            Diag::InvalidStatement(..) => true,
+            _ => false,
+        }), "{:?}", errs);
+        let reports = check_str(source);
+        assert!(reports.iter().any(|r| r.message.starts_with("Syntax error")));
+        assert!(reports.iter().any(|r| r.message.starts_with("Type mismatch")));
+    }
+
+    #[test]
+    fn should_reject_unresolved_dims_with_strict_shapes() {
+        let source = include_str!("../tests/input/underconstrained.trs");
+        assert!(compile_with_options(source, &[], &CompileOptions::default()).is_ok());
+        let strict = CompileOptions { strict_shapes: true, ..CompileOptions::default() };
+        match compile_with_options(source, &[], &strict) {
+            Err(errs) => assert!(errs.iter().any(|e| match e {
+                Diag::AmbiguousType(..) => true,
+                _ => false,
+            }), "{:?}", errs),
+            Ok(_) => panic!("expected unresolved dims to be rejected"),
+        }
+    }
+
+    #[test]
+    fn should_instantiate_parameterized_alias_in_signature() {
+        let model = compile_str(include_str!("../tests/input/tsr_template.trs")).unwrap();
+        assert!(model.modules.contains_key("Xor"));
+    }
+
+    #[test]
+    fn should_instantiate_generic_block_at_two_widths() {
+        let mut model = compile_str(include_str!("../tests/input/generic_block.trs")).unwrap();
+        let net = ModName::Named("Net".to_owned());
+        let mut width = |layer: &str| {
+            let ty = model.tenv.borrow().resolve_type(&net, &Alias::Variable(layer.to_owned()));
+            match ty {
+                Some(Type::Module(_, Some(box f), _)) =>
+                    model.sub.apply_ty(&f.first_arg_ty().unwrap()).as_vec().unwrap()[1].as_num(),
+                ty => panic!("{:?}", ty),
+            }
+        };
+        assert_eq!(width("narrow"), Some(64));
+        assert_eq!(width("wide"), Some(128));
+    }
+
+    #[test]
+    fn should_reject_generic_block_body_contradicting_instance() {
+        // the body fixes `hidden` to 32, both instances supply another width
+        let errs = compile_str(include_str!("../tests/input/generic_block_fixed.trs")).err().unwrap();
+        let mismatches: Vec<_> = errs.iter()
+            .filter_map(|e| match e {
+                Diag::DimensionMismatch(supplied, fixed) => Some((supplied.as_num(), fixed.as_num())),
+                _ => None,
+            })
+            .collect();
+        assert!(mismatches.contains(&(Some(64), Some(32))), "{:?}", errs);
+        assert!(mismatches.contains(&(Some(128), Some(32))), "{:?}", errs);
+    }
+
+    #[test]
+    fn should_accept_trailing_commas() {
+        assert!(compile_str(include_str!("../tests/input/trailing_commas.trs")).is_ok());
+    }
+
+    #[test]
+    fn should_check_asserted_dtype() {
+        assert!(compile_str(include_str!("../tests/input/dtype_scalar.trs")).is_ok());
+        let errs = compile_str(include_str!("../tests/input/dtype_scalar_mismatch.trs")).err().unwrap();
+        assert!(errs.iter().any(|e| match e {
+            Diag::IncompatibleTypes(Type::FLOAT(_), Type::INT(_))
+            | Diag::IncompatibleTypes(Type::INT(_), Type::FLOAT(_)) => true,
+            _ => false,
+        }), "{:?}", errs);
+    }
+
+    #[test]
+    fn should_reject_float_assertion_on_int_tensor() {
+        let source = include_str!("../tests/input/dtype_int_tensor.trs");
+        let errs = compile_str(source).err().unwrap();
+        let sp = errs.iter()
+            .filter_map(|e| match e {
+                Diag::DtypeMismatch(Precision::F32, Precision::I64, sp) => Some(*sp),
+                _ => None,
+            })
+            .next()
+            .expect(&format!("{:?}", errs));
+        // the file starts at byte index 1 in the code map
+        let (start, end) = (sp.start().to_usize() - 1, sp.end().to_usize() - 1);
+        assert!(source[start..end].contains("@dtype x == float"), "{}", &source[start..end]);
+    }
+
+    #[test]
+    fn should_resolve_undeclared_dtype_of_tensor() {
+        assert!(compile_str(include_str!("../tests/input/dtype_f32_tensor.trs")).is_ok());
+        assert!(compile_str(include_str!("../tests/input/dtype_tensor.trs")).is_ok());
+        // `x` holds floats after the first assertion
+        let errs = compile_str(include_str!("../tests/input/dtype_tensor_mismatch.trs")).err().unwrap();
+        assert!(errs.iter().any(|e| match e {
+            Diag::DtypeMismatch(Precision::I64, Precision::F32, _) => true,
+            _ => false,
+        }), "{:?}", errs);
+    }
+
+    #[test]
+    fn should_infer_linear_input_from_flattened_conv() {
+        use typing::typed_term::TyDecl;
+        let model = compile_str(include_str!("../tests/input/inferred_linear_input.trs")).unwrap();
+        let inits = match model.ast {
+            TyTerm::TyProgram(ref decls) => decls.iter()
+                .filter_map(|d| match d {
+                    TyDecl::TyWeightsDecl(w) => Some(w.inits.clone()),
+                    _ => None,
+                })
+                .next()
+                .unwrap(),
+            ref ast => panic!("{:?}", ast),
+        };
+        let fc1 = inits.iter().find(|w_a| w_a.name == "fc1").unwrap();
+        let in_dim = fc1.fn_args.iter().find(|a| a.name == Some("in".to_owned())).unwrap();
+        assert_eq!(in_dim.arg.as_num(), Some(320));
+    }
+
+    const COMMON: &str = include_str!("../tests/input/lib/common.trs");
+
+    #[test]
+    fn should_resolve_alias_exported_by_another_file() {
+        let source = include_str!("../tests/input/use_common.trs");
+        let model = compile_with_imports(source, &[("common", COMMON)]).unwrap();
+        assert!(model.modules.contains_key("Xor"));
+    }
+
+    #[test]
+    fn should_hide_private_aliases() {
+        match compile_with_imports(include_str!("../tests/input/use_private.trs"), &[("common", COMMON)]) {
+            Err(errs) => assert!(errs.iter().any(|e| match e {
+                Diag::ImportError(name, _) => name == "HIDDEN",
+                _ => false,
+            })),
+            Ok(_) => panic!("expected `HIDDEN` to be private"),
+        }
+    }
+
+    /// the untyped body of `forward` in `source`
+    fn forward_body(source: &str) -> Term {
+        let mut code_map = CodeMap::new();
+        let file_map = code_map.add_filemap("<body>".to_owned().into(), source.to_owned());
+        let emitter = Rc::new(RefCell::new(Emitter::new(code_map, false)));
+        let program = ASTBuilder::new(emitter, CSpan::new(file_map.span())).parse_str(source).unwrap();
+        match program {
+            Term::Program(decls) => decls.into_iter()
+                .filter_map(|d| match d {
+                    Decl::GraphDecl(g) => Some(g),
+                    _ => None,
+                })
+                .flat_map(|g| g.fns)
+                .find(|f| f.name == "forward")
+                .map(|f| *f.func_block)
+                .unwrap(),
+            t => panic!("{:?}", t),
+        }
+    }
+
+    #[test]
+    fn should_type_no_grad_block_like_its_contents() {
+        let wrapped = include_str!("../tests/input/no_grad.trs");
+        let shape = |source: &str| {
+            let mut model = compile_str(source).unwrap();
+            let ty = model.method_mut("Xor", "forward").unwrap().func_block.ty();
+            ty.as_vec().unwrap().iter().map(|d| d.as_num()).collect::<Vec<_>>()
+        };
+        assert_eq!(shape(wrapped), shape(XOR));
+        assert_eq!(shape(wrapped), vec![None, Some(1)]);
+    }
+
+    #[test]
+    fn should_reinfer_replaced_method_body() {
+        use typing::inferred_ast::summary;
+
+        let mut model = compile_str(XOR).unwrap();
+        let relu = forward_body(include_str!("../tests/input/xor_relu.trs"));
+        model.replace_module_body("Xor", "forward", &relu).unwrap();
+        let table = summary(&model.ast, &model.tenv.borrow(), &model.sub);
+        assert!(table.lines().any(|l| l.starts_with("relu ") && l.contains("[?, 3]")), "{}", table);
+        assert!(!table.contains("sigmoid"), "{}", table);
+        let out = model.method_mut("Xor", "forward").unwrap().func_block.ty();
+        assert_eq!(out.as_vec().and_then(|dims| dims[1].as_num()), Some(1));
+
+        // stops at the hidden layer, which does not fit `-> [?,1]`
+        let truncated = forward_body(include_str!("../tests/input/xor_hidden_output.trs"));
+        let errs = model.replace_module_body("Xor", "forward", &truncated).err().unwrap();
+        assert!(errs.iter().any(|e| match e {
+            Diag::DimensionMismatch(..) => true,
+            _ => false,
+        }), "{:?}", errs);
+        assert!(summary(&model.ast, &model.tenv.borrow(), &model.sub).contains("relu"));
+
+        assert!(model.replace_module_body("Xor", "backward", &relu).is_err());
+
+        // names other than the parameters are not in scope
+        let unbound = forward_body(include_str!("../tests/input/xor_unbound_input.trs"));
+        let errs = model.replace_module_body("Xor", "forward", &unbound).err().unwrap();
+        assert!(errs.iter().any(|e| match e {
+            Diag::SymbolNotFound(ref name, _) => name == "y",
+            _ => false,
+        }), "{:?}", errs);
+    }
+
+    #[test]
+    fn should_profile_every_phase() {
+        let (model, profile) = compile_profiled(include_str!("../tests/input/mnist.trs"));
+        assert!(model.is_ok());
+        let names: Vec<_> = profile.phases().iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["parse", "annotate", "collect", "unify"]);
+        assert!(profile.phases().iter().all(|p| p.count > 0));
+        assert_eq!(profile.summary().lines().count(), 4);
+    }
+
+    #[test]
+    fn should_report_errors_and_warnings_with_severities() {
+        let reports = check_str(include_str!("../tests/input/ambiguous_import.trs"));
+        let mismatch = reports.iter().find(|r| r.message.starts_with("Type mismatch")).unwrap();
+        assert_eq!(mismatch.severity, Severity::Error);
+        assert_eq!(mismatch.lsp_severity(), 1);
+        let ambiguous = reports.iter().find(|r| r.message.contains("is ambiguous")).unwrap();
+        assert_eq!(ambiguous.severity, Severity::Warning);
+        assert_eq!(ambiguous.lsp_severity(), 2);
+        assert!(ambiguous.span.is_some());
+    }
+
+    #[test]
+    fn should_report_body_output_at_the_signature() {
+        let source = include_str!("../tests/input/identity_body.trs");
+        let errs = compile_str(source).err().unwrap();
+        assert!(errs.iter().any(|e| match e {
+            Diag::SignatureMismatch(..) => true,
+            _ => false,
+        }), "{:?}", errs);
+        let reports = check_str(source);
+        let mismatch = reports.iter().find(|r| r.message.contains("declared signature")).unwrap();
+        let sp = mismatch.span.unwrap();
+        // the file starts at byte index 1 in the code map
+        let (start, end) = (sp.start().to_usize() - 1, sp.end().to_usize() - 1);
+        assert_eq!(&source[start..end], "<[?,2] -> [?,1]>");
+    }
+}
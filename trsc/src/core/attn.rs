@@ -0,0 +1,153 @@
+use core::{init_num, MethodName, Op, PyTorch, Resolve};
+use errors::Diag;
+use span::CSpan;
+use typing::typed_term::{ArgsVecInto, TyFnAppArg, TyTerm};
+use typing::{Type, TypeEnv};
+use std::fmt::Write;
+
+#[derive(Debug, Op)]
+#[path = "attn"]
+#[new = "(embed_dim: int, num_heads: int) -> self"]
+#[forward = "?(query: tsr0, key: tsr0, value: tsr0) -> tsr0"]
+#[stateful]
+pub struct MultiheadAttention;
+
+impl Resolve for MultiheadAttention {
+    /// output has the same shape as the query
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let inits = inits?;
+                let hm = inits.to_btreemap()?;
+                let embed_dim = hm.get("embed_dim").and_then(|t| t.as_num())?;
+                let num_heads = hm.get("num_heads").and_then(|t| t.as_num())?;
+                if num_heads <= 0 || embed_dim % num_heads != 0 {
+                    let span = inits.iter()
+                        .find(|a| a.name == Some("num_heads".to_owned()))
+                        .unwrap()
+                        .span;
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("embed_dim={} is not divisible by num_heads={}", embed_dim, num_heads),
+                        span,
+                    )));
+                }
+
+                let args_ty_map = arg_ty.as_args_map()?;
+                let query_ty = args_ty_map.get("query")?;
+                for name in &["query", "key", "value"] {
+                    let ty = args_ty_map.get(*name)?;
+                    let last_dim = match ty.as_vec() {
+                        Some(dims) => dims.last().cloned()?,
+                        None => return None,
+                    };
+                    if let Some(n) = last_dim.as_num() {
+                        if n != embed_dim {
+                            return Some(Err(Diag::DimensionMismatch(
+                                last_dim.with_span(&ty.span()),
+                                Type::ResolvedDim(embed_dim, ty.span()),
+                            )));
+                        }
+                    }
+                }
+
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty.clone(), query_ty.clone())))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for MultiheadAttention {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.MultiheadAttention"
+    }
+
+    /// query, key and value projections are packed into one matrix
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let e = init_num(inits, "embed_dim")?;
+        Some(vec![
+            ("in_proj_weight".to_owned(), vec![3 * e, e]),
+            ("in_proj_bias".to_owned(), vec![3 * e]),
+            ("out_proj.weight".to_owned(), vec![e, e]),
+            ("out_proj.bias".to_owned(), vec![e]),
+        ])
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "embed_dim={:?}, ", map["embed_dim"].as_num().unwrap()).unwrap();
+                write!(buf, "num_heads={:?})", map["num_heads"].as_num().unwrap()).unwrap();
+                Ok(buf)
+            }
+            "forward" => {
+                let operands: Vec<_> = ["query", "key", "value"]
+                    .iter()
+                    .map(|param| {
+                        let a = args.iter().find(|a| a.name == Some(param.to_string())).unwrap();
+                        match *a.arg {
+                            TyTerm::TyExpr(box TyTerm::TyIdent(_, ref alias, _), ..) |
+                            TyTerm::TyIdent(_, ref alias, _) => alias.as_str().to_owned(),
+                            ref t => t.as_str().unwrap(),
+                        }
+                    })
+                    .collect();
+                write!(buf, "{}", operands.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use span::CSpan;
+    use codespan::{ByteIndex, Span};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use core::test_args::int_arg;
+
+    fn resolve(inits: Vec<TyFnAppArg>) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(16), dim(64)]);
+        let arg_ty = args!(arg!("query", x.clone()), arg!("key", x.clone()), arg!("value", x));
+        MultiheadAttention.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![], Some(inits))
+    }
+
+    #[test]
+    fn should_return_query_shape() {
+        match resolve(vec![int_arg("embed_dim", 64), int_arg("num_heads", 8)]) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims.len(), 3);
+                assert_eq!(dims[1].as_num(), Some(16));
+                assert_eq!(dims[2].as_num(), Some(64));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reject_indivisible_head_count() {
+        let span = Span::new(ByteIndex(10), ByteIndex(22));
+        let num_heads = TyFnAppArg { span, ..int_arg("num_heads", 7) };
+        match resolve(vec![int_arg("embed_dim", 64), num_heads]) {
+            Some(Err(Diag::InvalidArgument(_, sp))) => assert_eq!(sp, span),
+            ty => panic!("{:?}", ty),
+        }
+    }
+}
@@ -1,8 +1,10 @@
-use core::{MethodName, Op, PyTorch, Resolve};
+use core::{expect_positive_float, get_int, get_tuple2, init_num, int_default, read_ints, MethodName, Op, PyTorch, Resolve};
 use errors::Diag;
 use span::CSpan;
 use typing::typed_term::{ArgsVecInto, TyFnAppArg, TyTerm};
 use typing::{Type, TypeEnv};
+use typing::types::Layout;
+use codespan::ByteSpan;
 
 use std::fmt::Write;
 
@@ -20,6 +22,17 @@ macro_rules! read_2_tuple {
 }
 
 macro_rules! read_from_init {
+    ($var:expr) => {
+        {
+            let t = $var?;
+            if let Type::Tuple(..) = t.ty() {
+                read_2_tuple!(t)
+            } else {
+                let p0 = t.as_num()?;
+                (p0, p0)
+            }
+        }
+    };
     ($var:expr, $default:expr) => {
         $var
             .map(|t| (t, t.ty()) )
@@ -34,6 +47,24 @@ macro_rules! read_from_init {
     };
 }
 
+/// output size along one spatial axis,
+/// `(size + 2 * padding - dilation * (kernel - 1) - 1) / stride + 1`.
+/// A symbolic size gives a derived dim that resolves along with it.
+fn out_size(size: &Type, padding: i64, dilation: i64, kernel: i64, stride: i64, span: ByteSpan) -> Type {
+    let offset = 2 * padding - dilation * (kernel - 1) - 1;
+    let shifted = Type::derive('+', size.clone(), Type::ResolvedDim(offset, span), span);
+    let strided = Type::derive('/', shifted, Type::ResolvedDim(stride, span), span);
+    Type::derive('+', strided, Type::ResolvedDim(1, span), span)
+}
+
+/// output size of a transposed conv along one spatial axis,
+/// `(size - 1) * stride - 2 * padding + kernel + output_padding`
+fn transposed_out_size(size: &Type, padding: i64, kernel: i64, stride: i64, output_padding: i64, span: ByteSpan) -> Type {
+    let shrunk = Type::derive('-', size.clone(), Type::ResolvedDim(1, span), span);
+    let strided = Type::derive('*', shrunk, Type::ResolvedDim(stride, span), span);
+    Type::derive('+', strided, Type::ResolvedDim(kernel - 2 * padding + output_padding, span), span)
+}
+
 #[derive(Debug, Op)]
 #[path = "conv"]
 #[forward = "?() -> unit"]
@@ -58,29 +89,37 @@ impl Resolve for Conv2d {
                     None
                 } else {
                     let init_map = inits?.to_btreemap()?;
-                    let (k0, k1) = read_from_init!(init_map.get("kernel_size"), (0, 0));
-                    let (p0, p1) = read_from_init!(init_map.get("padding"), (0, 0));
-                    let (d0, d1) = read_from_init!(init_map.get("dilation"), (1, 1));
-                    let (s0, s1) = read_from_init!(init_map.get("stride"), (1, 1));
-
+                    let (k0, k1) = read_from_init!(init_map.get("kernel_size"));
+                    let (p0, p1) = read_from_init!(init_map.get("padding"));
+                    let (d0, d1) = read_from_init!(init_map.get("dilation"));
+                    let (s0, s1) = read_from_init!(init_map.get("stride"));
 
-                    let in_ch = init_map.get("in_ch").map(|t|t.as_num().unwrap()).expect("does not have in_ch");
-                    let out_ch = init_map.get("out_ch").map(|t|t.as_num().unwrap()).expect("does not have in_ch");
+                    let in_ch = init_map.get("in_ch")?.as_num()?;
+                    let out_ch = init_map.get("out_ch")?.as_num()?;
 
                     let dims = x_ty.as_vec()?;
-                    let (n, c_in, h_in, w_in) = (
-                        dims[0].to_owned(),
-                        dims[1].to_owned().as_num().unwrap(),
-                        dims[2].to_owned().as_num().unwrap(),
-                        dims[3].to_owned().as_num().unwrap()
-                    );
-
-                    assert_eq!(c_in, in_ch);
-                    // println!("BLAH: {:?}", x_ty);
-                    let h_out = (h_in + 2 * p0 - d0 * (k0 -1) - 1) / s0 + 1;
-                    let w_out = (w_in + 2 * p1 - d1 * (k1 -1) - 1) / s1 + 1;
-
+                    if dims.len() != 4 {
+                        return Some(Err(
+                            Diag::InvalidArgument("Conv2d expects a tensor of rank 4".to_owned(), x_ty.span())
+                        ));
+                    }
+                    // a symbolic channel count is left to the unifier
+                    match dims[1].as_num() {
+                        Some(c_in) if c_in != in_ch => return Some(Err(Diag::InvalidArgument(
+                            format!("Conv2d expects {} input channels, got {}", in_ch, c_in),
+                            dims[1].span(),
+                        ))),
+                        _ => (),
+                    }
+                    let n = dims[0].to_owned();
                     let span = x_ty.span();
+                    let h_out = out_size(&dims[2], p0, d0, k0, s0, span);
+                    let w_out = out_size(&dims[3], p1, d1, k1, s1, span);
+
+                    // binds a symbolic channel count to `in_ch`
+                    let mut in_dims = dims.clone();
+                    in_dims[1] = Type::ResolvedDim(in_ch, dims[1].span());
+                    let arg_ty = args!(arg!("x", Type::TSR(in_dims, x_ty.tags(), span)); arg_ty.span());
 
                     Some(Ok( // returns a function
                         fun!(
@@ -90,26 +129,53 @@ impl Resolve for Conv2d {
                             Type::TSR(vec![
                                 n,
                                 Type::ResolvedDim(out_ch, span),
-                                Type::ResolvedDim(h_out, span),
-                                Type::ResolvedDim(w_out, span),
-                            ], span)
+                                h_out,
+                                w_out,
+                            ], x_ty.tags(), span)
                         )
                     ))
                 }
             },
             "new" => {
+                // optional args are typed as supplied
+                let optional = arg_ty.as_args_map()?
+                    .into_iter()
+                    .filter(|(name, _)| self.defaults("new").iter().any(|d| d.name.as_ref() == Some(name)))
+                    .map(|(name, ty)| Type::FnArg(Some(name), box ty, CSpan::fresh_span()));
+                let required = vec![
+                    arg!("in_ch", int!()),
+                    arg!("out_ch", int!()),
+                    arg!("kernel_size", tenv.fresh_var(CSpan::fresh_span(), Some("Conv2d.kernel_size"))),
+                ];
                 Some(Ok(fun!(
                     "Conv2d",
                     "new",
-                    args!(
-                        arg!("in_ch", int!()),
-                        arg!("out_ch", int!()),
-                        arg!("kernel_size", tenv.fresh_var(CSpan::fresh_span()))
-                    ),
+                    Type::FnArgs(required.into_iter().chain(optional).collect(), CSpan::fresh_span()),
                     module!("Conv2d")
                 )))
             }
-            _ => unimplemented!(),
+            _ => None,
+        }
+    }
+
+    fn defaults(&self, fn_name: &str) -> Vec<TyFnAppArg> {
+        match fn_name {
+            "new" => vec![int_default("stride", 1), int_default("padding", 0), int_default("dilation", 1)],
+            _ => vec![],
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "new" => &["in_ch", "out_ch", "kernel_size"],
+            _ => &[],
+        }
+    }
+
+    fn layout(&self, fn_name: &str) -> Option<Layout> {
+        match fn_name {
+            "forward" => Some(Layout::Nchw),
+            _ => None,
         }
     }
 
@@ -121,6 +187,19 @@ impl PyTorch for Conv2d {
         "nn.Conv2d"
     }
 
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let (in_ch, out_ch) = (init_num(inits, "in_ch")?, init_num(inits, "out_ch")?);
+        let kernel = read_ints(&inits.iter().find(|a| a.name == Some("kernel_size".to_owned()))?.arg)?;
+        let (k0, k1) = match kernel.len() {
+            1 => (kernel[0], kernel[0]),
+            _ => (kernel[0], kernel[1]),
+        };
+        Some(vec![
+            ("weight".to_owned(), vec![out_ch, in_ch, k0, k1]),
+            ("bias".to_owned(), vec![out_ch]),
+        ])
+    }
+
     fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
         let mut buf = String::new();
         match name {
@@ -143,6 +222,155 @@ impl PyTorch for Conv2d {
 
 }
 
+#[derive(Debug, Op)]
+#[path = "conv"]
+#[forward = "?() -> unit"]
+#[new = "?() -> unit"]
+#[stateful]
+pub struct ConvTranspose2d;
+
+impl ConvTranspose2d {
+    fn forward(&self, arg_ty: Type, x_ty: &Type, dims: Vec<Type>, inits: &[TyFnAppArg]) -> Result<Type, Diag> {
+        let (k0, k1) = get_tuple2(inits, "kernel_size")?;
+        let (p0, p1) = get_tuple2(inits, "padding")?;
+        let (s0, s1) = get_tuple2(inits, "stride")?;
+        let (o0, o1) = get_tuple2(inits, "output_padding")?;
+        let in_ch = get_int(inits, "in_ch")?;
+        let out_ch = get_int(inits, "out_ch")?;
+
+        if dims.len() != 4 {
+            return Err(Diag::InvalidArgument("ConvTranspose2d expects a tensor of rank 4".to_owned(), x_ty.span()));
+        }
+        match dims[1].as_num() {
+            Some(c_in) if c_in != in_ch => return Err(Diag::InvalidArgument(
+                format!("ConvTranspose2d expects {} input channels, got {}", in_ch, c_in),
+                dims[1].span(),
+            )),
+            _ => (),
+        }
+        let span = x_ty.span();
+        let h_out = transposed_out_size(&dims[2], p0, k0, s0, o0, span);
+        let w_out = transposed_out_size(&dims[3], p1, k1, s1, o1, span);
+
+        Ok(fun!(
+            "ConvTranspose2d",
+            "forward",
+            arg_ty,
+            Type::TSR(vec![
+                dims[0].to_owned(),
+                Type::ResolvedDim(out_ch, span),
+                h_out,
+                w_out,
+            ], x_ty.tags(), span)
+        ))
+    }
+}
+
+impl Resolve for ConvTranspose2d {
+    fn resolve( &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                // spatial dims may stay symbolic, the rank may not
+                let dims = x_ty.as_vec()?;
+                Some(self.forward(arg_ty, &x_ty, dims, &inits?))
+            },
+            "new" => {
+                let optional = arg_ty.as_args_map()?
+                    .into_iter()
+                    .filter(|(name, _)| self.defaults("new").iter().any(|d| d.name.as_ref() == Some(name)))
+                    .map(|(name, ty)| Type::FnArg(Some(name), box ty, CSpan::fresh_span()));
+                let required = vec![
+                    arg!("in_ch", int!()),
+                    arg!("out_ch", int!()),
+                    arg!("kernel_size", tenv.fresh_var(CSpan::fresh_span(), Some("ConvTranspose2d.kernel_size"))),
+                ];
+                Some(Ok(fun!(
+                    "ConvTranspose2d",
+                    "new",
+                    Type::FnArgs(required.into_iter().chain(optional).collect(), CSpan::fresh_span()),
+                    module!("ConvTranspose2d")
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    fn defaults(&self, fn_name: &str) -> Vec<TyFnAppArg> {
+        match fn_name {
+            "new" => vec![int_default("stride", 1), int_default("padding", 0), int_default("output_padding", 0)],
+            _ => vec![],
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "new" => &["in_ch", "out_ch", "kernel_size"],
+            _ => &[],
+        }
+    }
+
+    fn layout(&self, fn_name: &str) -> Option<Layout> {
+        match fn_name {
+            "forward" => Some(Layout::Nchw),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for ConvTranspose2d {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.ConvTranspose2d"
+    }
+
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let (in_ch, out_ch) = (init_num(inits, "in_ch")?, init_num(inits, "out_ch")?);
+        let kernel = read_ints(&inits.iter().find(|a| a.name == Some("kernel_size".to_owned()))?.arg)?;
+        let (k0, k1) = match kernel.len() {
+            1 => (kernel[0], kernel[0]),
+            _ => (kernel[0], kernel[1]),
+        };
+        // the weight is stored the other way round from Conv2d
+        Some(vec![
+            ("weight".to_owned(), vec![in_ch, out_ch, k0, k1]),
+            ("bias".to_owned(), vec![out_ch]),
+        ])
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "in_channels={}, ", map["in_ch"].as_str().unwrap()).unwrap();
+                write!(buf, "out_channels={}, ", map["out_ch"].as_str().unwrap()).unwrap();
+                write!(buf, "kernel_size={}", map["kernel_size"].as_str().unwrap()).unwrap();
+                for opt in &["stride", "padding", "output_padding"] {
+                    if let Some(v) = map.get(*opt).and_then(|v| v.as_str()) {
+                        write!(buf, ", {}={}", opt, v).unwrap();
+                    }
+                }
+                write!(buf, ")").unwrap();
+                Ok(buf)
+            }
+            "forward" => {
+                let args: Vec<_> = args.iter().map(|i| i.name.clone().unwrap()).collect();
+                write!(buf, "{}", args.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Op)]
 #[path = "conv"]
@@ -169,23 +397,24 @@ impl Resolve for maxpool2d {
                 if !x_ty.is_resolved() {
                     None
                 } else {
-                    let (k0, k1) = read_from_init!(args_map.get("kernel_size"), (0, 0));
-                    let (p0, p1) = read_from_init!(args_map.get("padding"), (0, 0));
-                    let (d0, d1) = read_from_init!(args_map.get("dilation"), (1, 1));
+                    let (k0, k1) = read_from_init!(args_map.get("kernel_size"));
+                    let (p0, p1) = read_from_init!(args_map.get("padding"));
+                    let (d0, d1) = read_from_init!(args_map.get("dilation"));
                     let (s0, s1) = read_from_init!(args_map.get("stride"), (k0, k1));
 
                     let dims = x_ty.as_vec()?;
-                    let (n, c_in, h_in, w_in) = (
+                    if dims.len() != 4 {
+                        return Some(Err(
+                            Diag::InvalidArgument("maxpool2d expects a tensor of rank 4".to_owned(), x_ty.span())
+                        ));
+                    }
+                    let (n, c_in) = (
                         dims[0].to_owned(),
                         dims[1].to_owned(),
-                        dims[2].to_owned().as_num().unwrap(),
-                        dims[3].to_owned().as_num().unwrap()
                     );
-                    // println!("BLAH: {:?}", x_ty);
-                    let h_out = (h_in + 2 * p0 - d0 * (k0 -1) - 1) / s0 + 1;
-                    let w_out = (w_in + 2 * p1 - d1 * (k1 -1) - 1) / s1 + 1;
-
                     let span = x_ty.span();
+                    let h_out = out_size(&dims[2], p0, d0, k0, s0, span);
+                    let w_out = out_size(&dims[3], p1, d1, k1, s1, span);
 
                     Some(Ok( // returns a function
                         fun!(
@@ -195,9 +424,9 @@ impl Resolve for maxpool2d {
                             Type::TSR(vec![
                                 n,
                                 c_in.clone(),
-                                Type::ResolvedDim(h_out, span),
-                                Type::ResolvedDim(w_out, span),
-                            ], span)
+                                h_out,
+                                w_out,
+                            ], x_ty.tags(), span)
                         )
                     ))
                 }
@@ -206,6 +435,27 @@ impl Resolve for maxpool2d {
         }
     }
 
+    /// stride defaults to the kernel size, which is filled in by `resolve`
+    fn defaults(&self, fn_name: &str) -> Vec<TyFnAppArg> {
+        match fn_name {
+            "forward" => vec![int_default("padding", 0), int_default("dilation", 1)],
+            _ => vec![],
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["kernel_size"],
+            _ => &[],
+        }
+    }
+
+    fn layout(&self, fn_name: &str) -> Option<Layout> {
+        match fn_name {
+            "forward" => Some(Layout::Nchw),
+            _ => None,
+        }
+    }
 }
 
 impl PyTorch for maxpool2d {
@@ -236,4 +486,401 @@ impl PyTorch for maxpool2d {
             _ => panic!("{} is not implemented", name),
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Op)]
+#[path = "conv"]
+#[new = "(upscale_factor: int) -> self"]
+#[forward = "?(x: tsr0) -> tsr0"]
+pub struct PixelShuffle;
+
+impl Resolve for PixelShuffle {
+    /// `[.., C*r*r, H, W]` to `[.., C, H*r, W*r]`
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let inits = inits?;
+                let r = init_num(&inits, "upscale_factor")?;
+                let x_ty = arg_ty.first_arg_ty()?;
+                let span = x_ty.span();
+                if r <= 0 {
+                    let sp = inits.iter()
+                        .find(|a| a.name == Some("upscale_factor".to_owned()))
+                        .map_or(span, |a| a.span);
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("upscale_factor must be positive, got {}", r),
+                        sp,
+                    )));
+                }
+                let mut dims = x_ty.as_vec()?;
+                if dims.len() < 3 {
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("PixelShuffle expects a tensor of rank 3 or more, got {:?}", x_ty),
+                        span,
+                    )));
+                }
+                let c = dims.len() - 3;
+                if let Some(channels) = dims[c].as_num() {
+                    if channels % (r * r) != 0 {
+                        return Some(Err(Diag::InvalidArgument(
+                            format!("{} channels cannot be shuffled by upscale_factor={}, it is not divisible by {}",
+                                channels, r, r * r),
+                            dims[c].span(),
+                        )));
+                    }
+                }
+                dims[c] = Type::derive('/', dims[c].clone(), Type::ResolvedDim(r * r, span), span);
+                for d in dims[c + 1..].iter_mut() {
+                    *d = Type::derive('*', d.clone(), Type::ResolvedDim(r, span), span);
+                }
+                let tags = x_ty.tags();
+                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", x_ty)), Type::TSR(dims, tags, span))))
+            }
+            _ => None,
+        }
+    }
+
+    fn layout(&self, fn_name: &str) -> Option<Layout> {
+        match fn_name {
+            "forward" => Some(Layout::Nchw),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for PixelShuffle {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.PixelShuffle"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "upscale_factor={:?})", map["upscale_factor"].as_num().unwrap()).unwrap();
+                Ok(buf)
+            }
+            "forward" => {
+                let args: Vec<_> = args.iter().map(|i| i.name.clone().unwrap()).collect();
+                write!(buf, "{}", args.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[derive(Debug, Op)]
+#[path = "conv"]
+#[new = "(in_ch: int, embed_dim: int, patch_size: int) -> self"]
+#[forward = "?(x: tsr0) -> tsr0"]
+#[stateful]
+pub struct PatchEmbed;
+
+impl PatchEmbed {
+    /// `[N, C, H, W]` to `[N, E, H/p, W/p]`, cut into non-overlapping
+    /// patches, so `H` and `W` must be multiples of the patch size
+    fn forward(&self, tenv: &mut TypeEnv, x_ty: Type, dims: Vec<Type>, inits: &[TyFnAppArg]) -> Result<Type, Diag> {
+        let in_ch = get_int(inits, "in_ch")?;
+        let embed_dim = get_int(inits, "embed_dim")?;
+        let p = get_int(inits, "patch_size")?;
+        expect_positive_float(inits, "patch_size")?;
+        let span = x_ty.span();
+        if dims.len() != 4 {
+            return Err(Diag::InvalidArgument("PatchEmbed expects a tensor of rank 4".to_owned(), span));
+        }
+        match dims[1].as_num() {
+            Some(c_in) if c_in != in_ch => return Err(Diag::InvalidArgument(
+                format!("PatchEmbed expects {} input channels, got {}", in_ch, c_in),
+                dims[1].span(),
+            )),
+            _ => (),
+        }
+        tenv.require_divisible(&dims[2], p, dims[2].span());
+        tenv.require_divisible(&dims[3], p, dims[3].span());
+        let out = vec![
+            dims[0].clone(),
+            Type::ResolvedDim(embed_dim, span),
+            Type::derive('/', dims[2].clone(), Type::ResolvedDim(p, span), span),
+            Type::derive('/', dims[3].clone(), Type::ResolvedDim(p, span), span),
+        ];
+        Ok(fun!(self.get_name(), "forward", args!(arg!("x", x_ty.clone())), Type::TSR(out, x_ty.tags(), span)))
+    }
+}
+
+impl Resolve for PatchEmbed {
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.first_arg_ty()?;
+                let dims = x_ty.as_vec()?;
+                Some(self.forward(tenv, x_ty, dims, &inits?))
+            }
+            _ => None,
+        }
+    }
+
+    fn layout(&self, fn_name: &str) -> Option<Layout> {
+        match fn_name {
+            "forward" => Some(Layout::Nchw),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for PatchEmbed {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.Conv2d"
+    }
+
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let (in_ch, e, p) = (init_num(inits, "in_ch")?, init_num(inits, "embed_dim")?, init_num(inits, "patch_size")?);
+        Some(vec![
+            ("weight".to_owned(), vec![e, in_ch, p, p]),
+            ("bias".to_owned(), vec![e]),
+        ])
+    }
+
+    /// a conv whose stride is its kernel size
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                let p = map["patch_size"].as_num().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "in_channels={:?}, ", map["in_ch"].as_num().unwrap()).unwrap();
+                write!(buf, "out_channels={:?}, ", map["embed_dim"].as_num().unwrap()).unwrap();
+                write!(buf, "kernel_size={:?}, stride={:?})", p, p).unwrap();
+                Ok(buf)
+            }
+            "forward" => {
+                let args: Vec<_> = args.iter().map(|i| i.name.clone().unwrap()).collect();
+                write!(buf, "{}", args.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use codespan::{ByteIndex, Span};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use typing::constraint::Equals;
+
+    fn conv2d(tenv: &mut TypeEnv, fn_name: &'static str, arg_ty: Type, inits: Vec<TyFnAppArg>) -> Result<Option<(Type, bool)>, Diag> {
+        let ty = Type::UnresolvedModuleFun("conv", "Conv2d", fn_name, CSpan::fresh_span());
+        match fn_name {
+            "new" => tenv.resolve_unresolved(&ty, fn_name, arg_ty, unit!(), inits, None),
+            _ => tenv.resolve_unresolved(&ty, fn_name, arg_ty, unit!(), vec![], Some(inits)),
+        }
+    }
+
+    fn out_dims(ty: Result<Option<(Type, bool)>, Diag>) -> Vec<Option<i64>> {
+        match ty {
+            Ok(Some((Type::FUN(_, _, _, box Type::TSR(dims, _, _), _), _))) => dims.iter().map(|d| d.as_num()).collect(),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_fill_omitted_args_with_defaults() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(3), dim(8), dim(8)]);
+        let inits = vec![int_default("in_ch", 3), int_default("out_ch", 16), int_default("kernel_size", 3)];
+
+        // stride 1, no padding
+        let ty = conv2d(&mut tenv, "forward", args!(arg!("x", x.clone())), inits.clone());
+        assert_eq!(out_dims(ty), vec![None, Some(16), Some(6), Some(6)]);
+
+        let mut strided = inits.clone();
+        strided.push(int_default("stride", 2));
+        let ty = conv2d(&mut tenv, "forward", args!(arg!("x", x)), strided);
+        assert_eq!(out_dims(ty), vec![None, Some(16), Some(3), Some(3)]);
+
+        let arg_ty = args!(arg!("out_ch", int!()), arg!("kernel_size", int!()));
+        match conv2d(&mut tenv, "new", arg_ty, inits[1..].to_vec()) {
+            Err(Diag::InvalidArgument(msg, _)) => assert!(msg.contains("in_ch"), "{}", msg),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_check_input_channels_once_known() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = CSpan::fresh_span();
+        let dim = |i| Type::ResolvedDim(i, sp);
+        let inits = vec![int_default("in_ch", 3), int_default("out_ch", 16), int_default("kernel_size", 3)];
+        let x = tsr!(vec![tenv.fresh_dim(sp, None), dim(4), dim(8), dim(8)]);
+        match conv2d(&mut tenv, "forward", args!(arg!("x", x)), inits.clone()) {
+            Err(Diag::InvalidArgument(msg, _)) => assert!(msg.contains("3 input channels, got 4"), "{}", msg),
+            ty => panic!("{:?}", ty),
+        }
+
+        let c = tenv.fresh_dim(sp, None);
+        let x = tsr!(vec![tenv.fresh_dim(sp, None), c, dim(8), dim(8)]);
+        let ty = conv2d(&mut tenv, "forward", args!(arg!("x", x)), inits);
+        // the symbolic channel count is bound to `in_ch`
+        match ty {
+            Ok(Some((Type::FUN(_, _, box ref arg_ty, _, _), _))) => {
+                let x = &arg_ty.as_args_map().unwrap()["x"];
+                assert_eq!(x.as_vec().unwrap()[1].as_num(), Some(3));
+            }
+            ref ty => panic!("{:?}", ty),
+        }
+        assert_eq!(out_dims(ty), vec![None, Some(16), Some(6), Some(6)]);
+    }
+
+    #[test]
+    fn should_upsample_with_transposed_conv() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = CSpan::fresh_span();
+        let dim = |i| Type::ResolvedDim(i, sp);
+        let n = tenv.fresh_dim(sp, None);
+        let inits = vec![
+            int_default("in_ch", 16), int_default("out_ch", 8), int_default("kernel_size", 4),
+            int_default("stride", 2), int_default("padding", 1), int_default("output_padding", 0),
+        ];
+        let resolve = |tenv: &mut TypeEnv, x: Type, inits: Vec<TyFnAppArg>|
+            ConvTranspose2d.resolve(tenv, "forward", args!(arg!("x", x)), unit!(), vec![], Some(inits));
+
+        // (8 - 1) * 2 - 2 + 4 = 16
+        let x = tsr!(vec![n.clone(), dim(16), dim(8), dim(8)]);
+        match resolve(&mut tenv, x, inits.clone()) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) =>
+                assert_eq!(dims, vec![n.clone(), dim(8), dim(16), dim(16)]),
+            ty => panic!("{:?}", ty),
+        }
+
+        // the height stays symbolic until it is known
+        let h = tenv.fresh_dim(sp, None);
+        let x = tsr!(vec![n.clone(), dim(16), h.clone(), dim(8)]);
+        let h_out = match resolve(&mut tenv, x, inits.clone()) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims[3], dim(16));
+                dims[2].clone()
+            }
+            ty => panic!("{:?}", ty),
+        };
+        assert_eq!(h_out.as_num(), None);
+        let id = match h { Type::DIM(id, _) => id, _ => unreachable!() };
+        assert_eq!(h_out.substitute(id, &dim(5)).as_num(), Some(10));
+
+        let channels = Span::new(ByteIndex(4), ByteIndex(6));
+        let x = tsr!(vec![n, Type::ResolvedDim(3, channels), dim(8), dim(8)]);
+        match resolve(&mut tenv, x, inits) {
+            Some(Err(Diag::InvalidArgument(msg, span))) => {
+                assert!(msg.contains("16 input channels"), "{}", msg);
+                assert_eq!(span, channels);
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_shuffle_channels_into_space() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = CSpan::fresh_span();
+        let n = tenv.fresh_dim(sp, None);
+        let dim = |i| Type::ResolvedDim(i, sp);
+        let x = tsr!(vec![n.clone(), dim(12), dim(8), dim(8)]);
+        let ty = PixelShuffle.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], Some(vec![int_default("upscale_factor", 2)]));
+        match ty {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) =>
+                assert_eq!(dims, vec![n.clone(), dim(3), dim(16), dim(16)]),
+            ty => panic!("{:?}", ty),
+        }
+
+        let channels = Span::new(ByteIndex(4), ByteIndex(6));
+        let x = tsr!(vec![n, Type::ResolvedDim(10, channels), dim(8), dim(8)]);
+        match PixelShuffle.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], Some(vec![int_default("upscale_factor", 2)])) {
+            Some(Err(Diag::InvalidArgument(msg, span))) => {
+                assert!(msg.contains("divisible by 4"), "{}", msg);
+                assert_eq!(span, channels);
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_require_input_divisible_by_patch_size() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = CSpan::fresh_span();
+        let n = tenv.fresh_dim(sp, None);
+        let h = tenv.fresh_dim(sp, None);
+        let dim = |i| Type::ResolvedDim(i, sp);
+        let inits = vec![int_default("in_ch", 3), int_default("embed_dim", 64), int_default("patch_size", 16)];
+        let x = tsr!(vec![n.clone(), dim(3), h.clone(), dim(224)]);
+        match PatchEmbed.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], Some(inits)) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims[..2].to_vec(), vec![n, dim(64)]);
+                assert_eq!(dims[3], dim(14));
+                assert_eq!(dims[2].as_num(), None);
+            }
+            ty => panic!("{:?}", ty),
+        }
+        // checked by the unifier, the height once it is known
+        let required = tenv.take_required();
+        assert_eq!(required, vec![
+            Equals::divisible_by(h, 16, sp),
+            Equals::divisible_by(dim(224), 16, sp),
+        ]);
+        assert!(tenv.take_required().is_empty());
+    }
+
+    #[test]
+    fn should_reject_channel_last_input_until_permuted() {
+        use core::prelude::permute;
+        use typing::type_env::ModName;
+
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = Span::new(ByteIndex(2), ByteIndex(9));
+        let sig: Vec<_> = ["?", "8", "8", "3", "@nhwc"].iter().map(|t| t.to_string()).collect();
+        let nhwc = tenv.create_tensor(&ModName::Global, &sig, &sp);
+        assert_eq!(nhwc.layout(), Some(Layout::Nhwc));
+        assert_eq!(nhwc.as_rank(), 4);
+        let inits = vec![int_default("in_ch", 3), int_default("out_ch", 16), int_default("kernel_size", 3)];
+        match conv2d(&mut tenv, "forward", args!(arg!("x", nhwc.clone())), inits.clone()) {
+            Err(Diag::LayoutMismatch(Layout::Nchw, Layout::Nhwc, span)) => assert_eq!(span, sp),
+            ty => panic!("{:?}", ty),
+        }
+
+        let order = [0, 3, 1, 2].iter().map(|i| TyInteger(int!(), *i, sp)).collect();
+        let dims = TyFnAppArg {
+            name: Some("dims".to_owned()),
+            arg: box TyTuple(Type::Tuple(vec![int!(); 4], sp), order, sp),
+            span: sp,
+        };
+        let nchw = match permute.resolve(&mut tenv, "forward", args!(arg!("x", nhwc)), unit!(), vec![dims], None) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => ret,
+            ty => panic!("{:?}", ty),
+        };
+        assert_eq!(nchw.layout(), Some(Layout::Nchw));
+        let ty = conv2d(&mut tenv, "forward", args!(arg!("x", nchw)), inits);
+        assert_eq!(out_dims(ty), vec![None, Some(16), Some(6), Some(6)]);
+    }
+}
@@ -0,0 +1,269 @@
+use core::{MethodName, Op, PyTorch, Resolve};
+use errors::Diag;
+use span::CSpan;
+use typing::typed_term::{TyFnAppArg, TyTerm};
+use typing::{Type, TypeEnv};
+use typing::types::{Precision, Tags};
+use codespan::ByteSpan;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// subscripts of each operand and of the output, e.g. `bij,bjk->bik`.
+/// Without `->` the output is every letter used once, in alphabetical order.
+pub fn parse_equation(eq: &str) -> Option<(Vec<Vec<char>>, Vec<char>)> {
+    let eq: String = eq.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut sides = eq.split("->");
+    let lhs = sides.next()?;
+    let inputs: Vec<Vec<char>> = lhs.split(',').map(|s| s.chars().collect()).collect();
+    if inputs.iter().flat_map(|s| s.iter()).any(|c| !c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let output = match sides.next() {
+        Some(rhs) => rhs.chars().collect(),
+        None => {
+            let mut counts = BTreeMap::new();
+            for c in inputs.iter().flat_map(|s| s.iter()) {
+                *counts.entry(*c).or_insert(0) += 1;
+            }
+            counts.into_iter().filter(|(_, n)| *n == 1).map(|(c, _)| c).collect()
+        }
+    };
+    if sides.next().is_some() {
+        return None;
+    }
+    Some((inputs, output))
+}
+
+/// bind each letter to a dim of its operand. Repeated letters must agree
+/// wherever both sides are resolved, a resolved size wins over a symbolic one.
+/// Returns the operands with each dim replaced by the dim its letter is bound
+/// to, so that unifying them ties the same letter across operands, and the
+/// dims of the output.
+pub fn einsum_shape(
+    inputs: &[Vec<char>],
+    output: &[char],
+    operands: &[Type],
+    span: ByteSpan,
+) -> Result<(Vec<Type>, Vec<Type>), Diag> {
+    if inputs.len() != operands.len() {
+        return Err(Diag::InvalidArgument(
+            format!("equation has {} operands, got {}", inputs.len(), operands.len()),
+            span,
+        ));
+    }
+    let mut letters: BTreeMap<char, Type> = BTreeMap::new();
+    for (subscripts, ty) in inputs.iter().zip(operands) {
+        let dims = ty.as_vec().unwrap();
+        if dims.len() != subscripts.len() {
+            return Err(Diag::InvalidArgument(
+                format!("subscripts `{}` do not match tensor of rank {}",
+                    subscripts.iter().collect::<String>(), dims.len()),
+                span,
+            ));
+        }
+        for (c, dim) in subscripts.iter().zip(dims) {
+            let bound = letters.get(c).cloned();
+            match bound {
+                None => { letters.insert(*c, dim); }
+                Some(prev) => match (prev.as_num(), dim.as_num()) {
+                    (Some(n1), Some(n2)) if n1 != n2 =>
+                        return Err(Diag::DimensionMismatch(prev.with_span(&span), dim.with_span(&span))),
+                    (None, Some(_)) => { letters.insert(*c, dim); }
+                    _ => (),
+                },
+            }
+        }
+    }
+    let tied = inputs.iter().zip(operands)
+        .map(|(subscripts, ty)| ty.map_dims(|i, dim| letters[&subscripts[i]].with_span(&dim.span())).unwrap())
+        .collect();
+    let dims = output.iter()
+        .map(|c| letters.get(c).cloned().ok_or_else(|| Diag::InvalidArgument(
+            format!("output subscript `{}` does not appear in the inputs", c),
+            span,
+        )))
+        .collect::<Result<_, _>>()?;
+    Ok((tied, dims))
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct einsum;
+
+impl Resolve for einsum {
+    /// operands are every argument but `equation`, in the order they are given
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let eq_arg = args.iter().find(|a| a.name == Some("equation".to_owned()))?;
+                let eq = eq_arg.arg.as_text()?;
+                let names = args.iter()
+                    .filter(|a| a.name != Some("equation".to_owned()))
+                    .map(|a| a.name.clone())
+                    .collect::<Option<Vec<_>>>()?;
+                let operands = names.iter()
+                    .map(|name| args_ty_map.get(name).cloned())
+                    .collect::<Option<Vec<_>>>()?;
+                if operands.iter().any(|ty| ty.as_vec().is_none()) {
+                    return None;
+                }
+                let (inputs, output) = match parse_equation(&eq) {
+                    Some(parsed) => parsed,
+                    None => return Some(Err(Diag::InvalidArgument(
+                        format!("cannot parse einsum equation {:?}", eq),
+                        eq_arg.span,
+                    ))),
+                };
+                let (tied, dims) = match einsum_shape(&inputs, &output, &operands, eq_arg.span) {
+                    Ok(shape) => shape,
+                    Err(e) => return Some(Err(e)),
+                };
+                let tags = Tags {
+                    layout: None,
+                    precision: operands.iter().fold(None, |p, ty| Precision::promote(p, ty.precision())),
+                };
+                let tied: BTreeMap<_, _> = names.into_iter().zip(tied).collect();
+                let arg_ty = match arg_ty {
+                    Type::FnArgs(tys, sp) => Type::FnArgs(tys.into_iter()
+                        .map(|ty| match ty {
+                            Type::FnArg(Some(ref name), _, s) if tied.contains_key(name) =>
+                                Type::FnArg(Some(name.clone()), box tied[name].clone(), s),
+                            ty => ty,
+                        })
+                        .collect(), sp),
+                    ty => ty,
+                };
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, Type::TSR(dims, tags, eq_arg.span))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for einsum {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.einsum"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let eq = args.iter().find(|a| a.name == Some("equation".to_owned())).unwrap();
+                write!(buf, "{}", eq.arg.as_str().unwrap()).unwrap();
+                for a in args.iter().filter(|a| a.name != Some("equation".to_owned())) {
+                    let operand = match *a.arg {
+                        _ if a.name == Some("x".to_owned()) => "x".to_owned(),
+                        TyTerm::TyExpr(box TyTerm::TyIdent(_, ref alias, _), ..) |
+                        TyTerm::TyIdent(_, ref alias, _) => alias.as_str().to_owned(),
+                        ref t => t.as_str().unwrap(),
+                    };
+                    write!(buf, ", {}", operand).unwrap();
+                }
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use core::test_args::tsr_arg;
+
+    fn resolve(eq: &str, a: Vec<i64>, b: Vec<i64>) -> Option<Result<Type, Diag>> {
+        let dims = |v: Vec<i64>| tsr!(v.into_iter().map(|i| Type::ResolvedDim(i, CSpan::fresh_span())).collect());
+        resolve_tsrs(eq, dims(a), dims(b))
+    }
+
+    fn resolve_tsrs(eq: &str, x: Type, y: Type) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let equation = TyFnAppArg {
+            name: Some("equation".to_owned()),
+            arg: box TyTerm::TyStr(Type::STR(CSpan::fresh_span()), eq.to_owned(), CSpan::fresh_span()),
+            span: CSpan::fresh_span(),
+        };
+        let args = vec![equation, tsr_arg("x", &x), tsr_arg("y", &y)];
+        let arg_ty = args!(arg!("equation", Type::STR(CSpan::fresh_span())), arg!("x", x), arg!("y", y));
+        einsum.resolve(&mut tenv, "forward", arg_ty, unit!(), args, None)
+    }
+
+    #[test]
+    fn should_infer_batched_matmul() {
+        match resolve("bij,bjk->bik", vec![8, 2, 3], vec![8, 3, 5]) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                let dims: Vec<_> = dims.iter().map(|d| d.as_num()).collect();
+                assert_eq!(dims, vec![Some(8), Some(2), Some(5)]);
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_tie_letter_across_operands() {
+        let sp = CSpan::fresh_span();
+        let (j1, j2) = (Type::DIM(1, sp), Type::DIM(2, sp));
+        let x = tsr!(vec![Type::ResolvedDim(2, sp), j1.clone()]);
+        let operands = |ty: Option<Result<Type, Diag>>| match ty {
+            Some(Ok(Type::FUN(_, _, box args, _, _))) => {
+                let map = args.as_args_map().unwrap();
+                (map["x"].as_vec().unwrap(), map["y"].as_vec().unwrap())
+            }
+            ty => panic!("{:?}", ty),
+        };
+        // both sides symbolic, `y` refers to the dim of `x`
+        let (xs, ys) = operands(resolve_tsrs("ij,jk->ik", x.clone(), tsr!(vec![j2, Type::ResolvedDim(5, sp)])));
+        assert_eq!(xs[1], j1);
+        assert_eq!(ys[0], j1);
+        // a resolved size binds the symbolic dim of the other operand
+        let (xs, ys) = operands(resolve_tsrs("ij,jk->ik", x, tsr!(vec![Type::ResolvedDim(3, sp), Type::ResolvedDim(5, sp)])));
+        assert_eq!(xs[1].as_num(), Some(3));
+        assert_eq!(ys[0].as_num(), Some(3));
+    }
+
+    #[test]
+    fn should_promote_precision_of_operands() {
+        let sp = CSpan::fresh_span();
+        let x = tsr!(vec![Type::ResolvedDim(2, sp), Type::ResolvedDim(3, sp)]);
+        let y = tsr!(vec![Type::ResolvedDim(3, sp), Type::ResolvedDim(5, sp)]);
+        let precision = |x: &Type, y: &Type| match resolve_tsrs("ij,jk->ik", x.clone(), y.clone()) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => ret.precision(),
+            ty => panic!("{:?}", ty),
+        };
+        let half = |ty: &Type| ty.with_precision(Some(Precision::F16));
+        assert_eq!(precision(&half(&x), &half(&y)), Some(Precision::F16));
+        assert_eq!(precision(&half(&x), &y.with_precision(Some(Precision::BF16))), Some(Precision::F32));
+        assert_eq!(precision(&x, &y), None);
+    }
+
+    #[test]
+    fn should_reject_conflicting_letter() {
+        match resolve("bij,bjk->bik", vec![8, 2, 3], vec![8, 4, 5]) {
+            Some(Err(Diag::DimensionMismatch(..))) => (),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reject_unbound_output_letter() {
+        match resolve("bij,bjk->bil", vec![8, 2, 3], vec![8, 3, 5]) {
+            Some(Err(Diag::InvalidArgument(..))) => (),
+            ty => panic!("{:?}", ty),
+        }
+    }
+}
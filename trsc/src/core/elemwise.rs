@@ -0,0 +1,345 @@
+//! Elementwise binary ops. They are prelude functions called by keyword,
+//! `add(x=a, y=b)`; the grammar has no infix `+` or `*` on tensors.
+
+use core::{MethodName, Op, PyTorch, Resolve};
+use errors::Diag;
+use span::CSpan;
+use typing::typed_term::{TyFnAppArg, TyTerm};
+use typing::{Type, TypeEnv};
+use typing::types::{Precision, Tags};
+use codespan::ByteSpan;
+use std::fmt::Write;
+
+/// numpy style broadcasting of two tensor shapes, dims are aligned from the right.
+/// A symbolic dim is not known to be 1, so it must equal the other side; that
+/// is left to the unifier as a constraint required through `tenv`.
+pub fn broadcast(tenv: &mut TypeEnv, a: &[Type], b: &[Type], span: ByteSpan) -> Result<Vec<Type>, Diag> {
+    let rank = a.len().max(b.len());
+    let mut ret = Vec::with_capacity(rank);
+    for i in 0..rank {
+        let d1 = if i < a.len() { Some(&a[a.len() - 1 - i]) } else { None };
+        let d2 = if i < b.len() { Some(&b[b.len() - 1 - i]) } else { None };
+        let dim = match (d1, d2) {
+            (Some(d), None) | (None, Some(d)) => d.clone(),
+            (Some(d1), Some(d2)) => match (d1.as_num(), d2.as_num()) {
+                (Some(1), _) => d2.clone(),
+                (_, Some(1)) => d1.clone(),
+                (Some(n1), Some(n2)) if n1 != n2 =>
+                    return Err(Diag::DimensionMismatch(d1.with_span(&span), d2.with_span(&span))),
+                (Some(_), Some(_)) => d1.clone(),
+                (None, Some(_)) => {
+                    tenv.require(d1, d2);
+                    d2.clone()
+                }
+                _ => {
+                    if d1 != d2 {
+                        tenv.require(d1, d2);
+                    }
+                    d1.clone()
+                }
+            },
+            (None, None) => unreachable!(),
+        };
+        ret.push(dim);
+    }
+    ret.reverse();
+    Ok(ret)
+}
+
+/// output type of an elementwise binary op on `x` and `y`
+fn resolve_binary(tenv: &mut TypeEnv, name: &str, arg_ty: Type, args: &[TyFnAppArg]) -> Option<Result<Type, Diag>> {
+    use self::Type::*;
+    let args_ty_map = arg_ty.as_args_map()?;
+    let x_ty = args_ty_map.get("x")?;
+    let y_ty = args_ty_map.get("y")?;
+    if !x_ty.is_resolved() || !y_ty.is_resolved() {
+        return None;
+    }
+    let span = arg_ty.span();
+    let ret_ty = match (x_ty, y_ty) {
+        (TSR(ref d1, _, _), TSR(ref d2, _, _)) => match broadcast(tenv, d1, d2, span) {
+            Ok(dims) => TSR(dims, Tags {
+                layout: None,
+                precision: Precision::promote(x_ty.precision(), y_ty.precision()),
+            }, span),
+            // adding two tensors of the same rank joins the branches of a residual block
+            Err(_) if name == "add" && d1.len() == d2.len() => {
+                let arg_span = |n: &str| args.iter()
+                    .find(|a| a.name == Some(n.to_owned()))
+                    .map_or(span, |a| a.span);
+                return Some(Err(Diag::ResidualMismatch(
+                    x_ty.clone(), arg_span("x"),
+                    y_ty.clone(), arg_span("y"),
+                )));
+            }
+            Err(e) => return Some(Err(e)),
+        },
+        (TSR(..), INT(_)) | (TSR(..), FLOAT(_)) | (TSR(..), BOOL(_)) => x_ty.clone(),
+        (INT(_), TSR(..)) | (FLOAT(_), TSR(..)) | (BOOL(_), TSR(..)) => y_ty.clone(),
+        _ => match promote(name, x_ty, y_ty, span) {
+            Ok(ty) => ty,
+            Err(e) => return Some(Err(e)),
+        },
+    };
+    Some(Ok(fun!(name, "forward", arg_ty, ret_ty)))
+}
+
+/// result of a binary op on two scalars following PyTorch type promotion:
+/// float wins over int, bool takes the other type and true division is
+/// always float. Tensors only promote their precision, see `Precision::promote`.
+pub fn promote(name: &str, x_ty: &Type, y_ty: &Type, span: ByteSpan) -> Result<Type, Diag> {
+    use self::Type::*;
+    let scalar = |ty: &Type| match ty {
+        INT(_) | FLOAT(_) | BOOL(_) => true,
+        _ => false,
+    };
+    match (x_ty, y_ty) {
+        _ if !scalar(x_ty) || !scalar(y_ty) => Err(Diag::TypeError(x_ty.clone(), y_ty.clone())),
+        // PyTorch refuses `-` on two bools
+        (BOOL(_), BOOL(_)) if name == "sub" => Err(Diag::TypeError(x_ty.clone(), y_ty.clone())),
+        _ if name == "div" => Ok(FLOAT(span)),
+        (BOOL(_), BOOL(_)) => Ok(BOOL(span)),
+        (FLOAT(_), _) | (_, FLOAT(_)) => Ok(FLOAT(span)),
+        _ => Ok(INT(span)),
+    }
+}
+
+fn gen_binary(name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+    let mut buf = String::new();
+    match name {
+        "forward" => {
+            let y = args.iter().find(|a| a.name == Some("y".to_owned())).unwrap();
+            let operand = match *y.arg {
+                TyTerm::TyExpr(box TyTerm::TyIdent(_, ref alias, _), ..) |
+                TyTerm::TyIdent(_, ref alias, _) => alias.as_str().to_owned(),
+                ref t => t.as_str().unwrap(),
+            };
+            write!(buf, "x, {}", operand).unwrap();
+            Ok(buf)
+        }
+        _ => panic!("{} is not implemented", name),
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct add;
+
+impl Resolve for add {
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_binary(tenv, self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for add {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.add"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_binary(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct sub;
+
+impl Resolve for sub {
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_binary(tenv, self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for sub {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.sub"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_binary(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct mul;
+
+impl Resolve for mul {
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_binary(tenv, self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for mul {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.mul"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_binary(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct div;
+
+impl Resolve for div {
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_binary(tenv, self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for div {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.div"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_binary(name, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use codespan::{ByteIndex, Span};
+    use typing::constraint::Equals;
+
+    fn dims(ds: &[i64]) -> Type {
+        tsr!(ds.iter().map(|d| Type::ResolvedDim(*d, CSpan::fresh_span())).collect())
+    }
+
+    fn ret_ty(op: &Op, x: Type, y: Type) -> Type {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let arg_ty = args!(arg!("x", x), arg!("y", y));
+        match op.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![], None) {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => r,
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_promote_precision_of_operands() {
+        let tagged = |p| dims(&[4, 10]).with_precision(p);
+        let precision = |x, y| ret_ty(&mul, tagged(x), tagged(y)).precision();
+        assert_eq!(precision(Some(Precision::F16), Some(Precision::F16)), Some(Precision::F16));
+        assert_eq!(precision(Some(Precision::F16), Some(Precision::F32)), Some(Precision::F32));
+        assert_eq!(precision(Some(Precision::F16), Some(Precision::BF16)), Some(Precision::F32));
+        assert_eq!(precision(None, Some(Precision::BF16)), Some(Precision::BF16));
+    }
+
+    #[test]
+    fn should_add_same_shape() {
+        assert_eq!(ret_ty(&add, dims(&[4, 10]), dims(&[4, 10])), dims(&[4, 10]));
+    }
+
+    #[test]
+    fn should_broadcast_add() {
+        assert_eq!(ret_ty(&add, dims(&[4, 1, 10]), dims(&[3, 1])), dims(&[4, 3, 10]));
+    }
+
+    #[test]
+    fn should_require_symbolic_dims_to_match() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = CSpan::fresh_span();
+        let n = Type::DIM(1, sp);
+        let dim = |d| Type::ResolvedDim(d, sp);
+        let ret = broadcast(&mut tenv, &[n.clone(), dim(10)], &[dim(4), dim(10)], sp).unwrap();
+        assert_eq!(ret, vec![dim(4), dim(10)]);
+        assert_eq!(tenv.take_required(), vec![Equals(n.clone(), dim(4))]);
+        // a dim of 1 broadcasts without constraining the other side
+        broadcast(&mut tenv, &[n.clone(), dim(10)], &[dim(1), dim(10)], sp).unwrap();
+        broadcast(&mut tenv, &[n.clone()], &[n], sp).unwrap();
+        assert!(tenv.take_required().is_empty());
+    }
+
+    #[test]
+    fn should_mul_tensor_by_scalar() {
+        assert_eq!(ret_ty(&mul, dims(&[4, 10]), float!()), dims(&[4, 10]));
+    }
+
+    #[test]
+    fn should_promote_int_true_division_to_float() {
+        assert_eq!(ret_ty(&div, int!(), int!()), float!());
+        assert_eq!(ret_ty(&mul, int!(), Type::BOOL(CSpan::fresh_span())), int!());
+        assert_eq!(ret_ty(&add, int!(), float!()), float!());
+    }
+
+    #[test]
+    fn should_reject_bool_subtraction() {
+        let bool_ty = || Type::BOOL(CSpan::fresh_span());
+        match promote("sub", &bool_ty(), &bool_ty(), CSpan::fresh_span()) {
+            Err(Diag::TypeError(..)) => (),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_label_residual_branches() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        // the inner conv of the block went from 8 to 16 channels
+        let arg_ty = args!(arg!("x", dims(&[4, 16, 8, 8])), arg!("y", dims(&[4, 8, 8, 8])));
+        let sp = |i| Span::new(ByteIndex(i), ByteIndex(i + 4));
+        let arg = |name: &str, i| TyFnAppArg { name: Some(name.to_owned()), arg: box TyTerm::TyNone, span: sp(i) };
+        match add.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![arg("x", 1), arg("y", 10)], None) {
+            Some(Err(Diag::ResidualMismatch(_, s1, _, s2))) => {
+                assert_eq!(s1, sp(1));
+                assert_eq!(s2, sp(10));
+            }
+            e => panic!("{:?}", e),
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use core::{MethodName, Op, PyTorch, Resolve};
+use core::{get_float, init_num, MethodName, Op, PyTorch, Resolve};
 use errors::Diag;
 use span::CSpan;
 use typing::typed_term::{ArgsVecInto, TyFnAppArg, TyTerm};
 use typing::{Type, TypeEnv};
+use typing::types::Tags;
 use std::fmt::Write;
 
 #[derive(Debug, Op)]
@@ -34,10 +35,16 @@ impl Resolve for Linear {
                         panic!("Initatialize Linear with parameter out=");
                     }
 
-                    let in_dim = hm.get("in").and_then(|t| unwrap_dim(t))?;
+                    let in_dim = hm.get("in").and_then(|t| init_dim(t))?;
                     let out_dim = hm.get("out").and_then(|t| unwrap_dim(t))?;
 
                     let span = arg_ty.span();
+                    let is_scalar = |ty: Option<Vec<Type>>| ty.map_or(false, |v| v.is_empty());
+                    if is_scalar(arg_ty.first_arg_ty()?.as_vec()) || is_scalar(ret_ty.as_vec()) {
+                        return Some(Err(
+                            Diag::InvalidArgument("Linear expects a tensor of rank 1 or more".to_owned(), span)
+                        ));
+                    }
 
                     let (a, b) = match (arg_ty.first_arg_ty()?.as_vec(), ret_ty.as_vec()) {
                         (None, None) => return None,
@@ -48,7 +55,7 @@ impl Resolve for Linear {
                             {
                                 let mut last_arg_dim = a.last_mut().unwrap();
                                 let mut last_ret_dim = b.last_mut().unwrap();
-                                *last_arg_dim = Type::ResolvedDim(in_dim, CSpan::fresh_span());
+                                *last_arg_dim = in_dim.clone();
                                 *last_ret_dim = Type::ResolvedDim(out_dim, CSpan::fresh_span());
                             };
 
@@ -63,7 +70,7 @@ impl Resolve for Linear {
                             {
                                 let mut last_arg_dim = a.last_mut().unwrap();
                                 let mut last_ret_dim = b.last_mut().unwrap();
-                                *last_arg_dim = Type::ResolvedDim(in_dim, CSpan::fresh_span());
+                                *last_arg_dim = in_dim.clone();
                                 *last_ret_dim = Type::ResolvedDim(out_dim, CSpan::fresh_span());
                             };
 
@@ -74,14 +81,14 @@ impl Resolve for Linear {
                     Some(Ok(fun!(
                         self.get_name(),
                         "forward",
-                        args!(arg!("x",Type::TSR(a, span))),
-                        Type::TSR(b, span)
+                        args!(arg!("x",Type::TSR(a, Tags::default(), span))),
+                        Type::TSR(b, Tags::default(), span)
                     )))
                 } else {
                     None
                 }
             }
-            _ => unimplemented!(),
+            _ => None,
         }
     }
 
@@ -92,13 +99,25 @@ impl PyTorch for Linear {
         "nn.Linear"
     }
 
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let (in_dim, out_dim) = (init_num(inits, "in")?, init_num(inits, "out")?);
+        Some(vec![
+            ("weight".to_owned(), vec![out_dim, in_dim]),
+            ("bias".to_owned(), vec![out_dim]),
+        ])
+    }
+
     fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
         let mut buf = String::new();
         match name {
             "new" => {
                 let map = args.to_btreemap().unwrap();
                 write!(buf, "{}(", self.pytorch_name()).unwrap();
-                write!(buf, "in_features={:?}, ", map["in"].as_num().unwrap()).unwrap();
+                let in_dim = map["in"].as_num().ok_or_else(|| Diag::InvalidArgument(
+                    "`in` of Linear could not be inferred".to_owned(),
+                    map["in"].span(),
+                ))?;
+                write!(buf, "in_features={:?}, ", in_dim).unwrap();
                 write!(buf, "out_features={:?})", map["out"].as_num().unwrap()).unwrap();
                 Ok(buf)
             }
@@ -108,9 +127,8 @@ impl PyTorch for Linear {
                 Ok(buf)
             }
             "init_normal" => {
-                let map = args.to_btreemap().unwrap();
                 write!(buf, "nn.init.normal_(").unwrap();
-                write!(buf, "std={}", map["std"].as_float().unwrap()).unwrap();
+                write!(buf, "std={}", get_float(args, "std")?).unwrap();
                 write!(buf, ")").unwrap();
                 Ok(buf)
             }
@@ -119,6 +137,145 @@ impl PyTorch for Linear {
     }
 }
 
+#[derive(Debug, Op)]
+#[path = "lin"]
+#[new = "(in1: int, in2: int, out: int) -> self"]
+#[forward = "?(x: tsr0, y: tsr0) -> tsr0"]
+#[stateful]
+pub struct Bilinear;
+
+impl Resolve for Bilinear {
+    /// `x` ends in `in1` and `y` in `in2`, the leading dims are shared
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let inits = inits?;
+                let (in1, in2, out) = (init_num(&inits, "in1")?, init_num(&inits, "in2")?, init_num(&inits, "out")?);
+                let args_ty_map = arg_ty.as_args_map()?;
+                let span = arg_ty.span();
+                let arg_span = |name: &str| args.iter()
+                    .find(|a| a.name == Some(name.to_owned()))
+                    .map_or(span, |a| a.span);
+
+                let mut inputs = vec![];
+                for &(name, features) in &[("x", in1), ("y", in2)] {
+                    let mut dims = args_ty_map.get(name)?.as_vec()?;
+                    let last = match dims.pop() {
+                        Some(last) => last,
+                        None => return Some(Err(Diag::InvalidArgument(
+                            format!("Bilinear expects `{}` to be a tensor of rank 1 or more", name),
+                            arg_span(name),
+                        ))),
+                    };
+                    match last.as_num() {
+                        Some(n) if n != features => return Some(Err(Diag::DimensionMismatch(
+                            last.with_span(&arg_span(name)),
+                            Type::ResolvedDim(features, arg_span(name)),
+                        ))),
+                        _ => (),
+                    }
+                    inputs.push(dims);
+                }
+
+                let (x_lead, y_lead) = (&inputs[0], &inputs[1]);
+                if x_lead.len() != y_lead.len() {
+                    return Some(Err(Diag::RankMismatch(
+                        args_ty_map["x"].with_span(&arg_span("x")),
+                        args_ty_map["y"].with_span(&arg_span("y")),
+                    )));
+                }
+                let mut lead = vec![];
+                for (a, b) in x_lead.iter().zip(y_lead) {
+                    let dim = match (a.as_num(), b.as_num()) {
+                        (Some(n1), Some(n2)) if n1 != n2 => return Some(Err(Diag::DimensionMismatch(
+                            a.with_span(&arg_span("x")),
+                            b.with_span(&arg_span("y")),
+                        ))),
+                        (None, Some(_)) => b.clone(),
+                        _ => a.clone(),
+                    };
+                    lead.push(dim);
+                }
+
+                let with_last = |n: i64| {
+                    let mut dims = lead.clone();
+                    dims.push(Type::ResolvedDim(n, span));
+                    Type::TSR(dims, Tags::default(), span)
+                };
+                Some(Ok(fun!(
+                    self.get_name(),
+                    "forward",
+                    args!(arg!("x", with_last(in1)), arg!("y", with_last(in2))),
+                    with_last(out)
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for Bilinear {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.Bilinear"
+    }
+
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let (in1, in2, out) = (init_num(inits, "in1")?, init_num(inits, "in2")?, init_num(inits, "out")?);
+        Some(vec![
+            ("weight".to_owned(), vec![out, in1, in2]),
+            ("bias".to_owned(), vec![out]),
+        ])
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "in1_features={:?}, ", map["in1"].as_num().unwrap()).unwrap();
+                write!(buf, "in2_features={:?}, ", map["in2"].as_num().unwrap()).unwrap();
+                write!(buf, "out_features={:?})", map["out"].as_num().unwrap()).unwrap();
+                Ok(buf)
+            }
+            "forward" => {
+                let operands: Vec<_> = ["x", "y"]
+                    .iter()
+                    .map(|param| {
+                        let a = args.iter().find(|a| a.name == Some(param.to_string())).unwrap();
+                        match *a.arg {
+                            TyTerm::TyExpr(box TyTerm::TyIdent(_, ref alias, _), ..) |
+                            TyTerm::TyIdent(_, ref alias, _) => alias.as_str().to_owned(),
+                            // a piped input is held in `x`
+                            _ => param.to_string(),
+                        }
+                    })
+                    .collect();
+                write!(buf, "{}", operands.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+/// like `unwrap_dim`, but a dim variable from `in=_` is kept to be inferred
+fn init_dim(in_dim: &TyTerm) -> Option<Type> {
+    match in_dim.ty() {
+        Type::INT(_) => in_dim.as_num().map(|n| Type::ResolvedDim(n, CSpan::fresh_span())),
+        ty @ Type::ResolvedDim(..) | ty @ Type::DIM(..) => Some(ty),
+        _ => panic!("{:?} is not a numeric value!", in_dim),
+    }
+}
+
 fn unwrap_dim(in_dim: &TyTerm) -> Option<i64> {
     match in_dim.ty() {
         Type::INT(_) => in_dim.as_num(),
@@ -126,3 +283,47 @@ fn unwrap_dim(in_dim: &TyTerm) -> Option<i64> {
         _ => panic!("{:?} is not a numeric value!", in_dim),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use codespan::{ByteIndex, Span};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use core::test_args::{int_arg, tsr_arg};
+
+    fn bilinear(x: Type, y: Type, args: Vec<TyFnAppArg>) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let inits = vec![int_arg("in1", 5), int_arg("in2", 7), int_arg("out", 3)];
+        Bilinear.resolve(&mut tenv, "forward", args!(arg!("x", x), arg!("y", y)), unit!(), args, Some(inits))
+    }
+
+    #[test]
+    fn should_combine_two_inputs() {
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let n = Type::DIM(1, CSpan::fresh_span());
+        match bilinear(tsr!(vec![n.clone(), dim(5)]), tsr!(vec![n.clone(), dim(7)]), vec![]) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims, vec![n, dim(3)]);
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_point_at_input_with_wrong_features() {
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let n = Type::DIM(1, CSpan::fresh_span());
+        let span = Span::new(ByteIndex(10), ByteIndex(12));
+        let (x, y) = (tsr!(vec![n.clone(), dim(5)]), tsr!(vec![n, dim(5)]));
+        let args = vec![tsr_arg("x", &x), TyFnAppArg { span, ..tsr_arg("y", &y) }];
+        match bilinear(x, y, args) {
+            Some(Err(Diag::DimensionMismatch(found, expected))) => {
+                assert_eq!((found.as_num(), expected.as_num()), (Some(5), Some(7)));
+                assert_eq!(found.span(), span);
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use core::{MethodName, Op, PyTorch, Resolve};
+use core::elemwise::broadcast;
+use errors::Diag;
+use span::CSpan;
+use typing::typed_term::{TyFnAppArg, TyTerm};
+use typing::{Type, TypeEnv};
+use typing::types::{Precision, Tags};
+use std::fmt::Write;
+
+/// a mask is a bool or a tensor tagged `@bool`
+fn check_mask(mask: &Type) -> Result<(), Diag> {
+    match mask {
+        Type::BOOL(_) => Ok(()),
+        Type::TSR(..) => match mask.precision() {
+            Some(Precision::Bool) => Ok(()),
+            Some(p) => Err(Diag::InvalidArgument(
+                format!("mask must be a tensor of bool, found a tensor of {}", p), mask.span())),
+            None => Err(Diag::InvalidArgument(
+                "mask must be a tensor of bool, tag it `@bool` or cast it with `bool()`".to_owned(), mask.span())),
+        },
+        _ => Err(Diag::TypeError(mask.clone(), Type::BOOL(mask.span()))),
+    }
+}
+
+fn operand(args: &[TyFnAppArg], name: &str) -> String {
+    let a = args.iter().find(|a| a.name == Some(name.to_owned())).unwrap();
+    match *a.arg {
+        TyTerm::TyExpr(box TyTerm::TyIdent(_, ref alias, _), ..) |
+        TyTerm::TyIdent(_, ref alias, _) => alias.as_str().to_owned(),
+        ref t => t.as_str().unwrap(),
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct masked_fill;
+
+impl Resolve for masked_fill {
+    /// fills `x` with `value` where `mask` is set, `mask` has the shape of `x`
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?;
+                let mask_ty = args_ty_map.get("mask")?;
+                let value_ty = args_ty_map.get("value")?;
+                if !x_ty.is_resolved() || !mask_ty.is_resolved() || !value_ty.is_resolved() {
+                    return None;
+                }
+                if let Err(e) = check_mask(mask_ty) {
+                    return Some(Err(e));
+                }
+                if let (Type::TSR(ref d1, _, _), Type::TSR(ref d2, _, _)) = (x_ty, mask_ty) {
+                    if d1.len() != d2.len() {
+                        return Some(Err(Diag::RankMismatch(x_ty.clone(), mask_ty.clone())));
+                    }
+                    for (a, b) in d1.iter().zip(d2) {
+                        if let (Some(n1), Some(n2)) = (a.as_num(), b.as_num()) {
+                            if n1 != n2 {
+                                return Some(Err(Diag::DimensionMismatch(a.clone(), b.clone())));
+                            }
+                        }
+                    }
+                }
+                match value_ty {
+                    Type::INT(_) | Type::FLOAT(_) | Type::BOOL(_) => (),
+                    _ => return Some(Err(Diag::TypeError(value_ty.clone(), float!()))),
+                }
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty.clone(), x_ty.clone())))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for masked_fill {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.Tensor.masked_fill"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                write!(buf, "x, {}, {}", operand(args, "mask"), operand(args, "value")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+// `where` is reserved in Rust, the op is `where_` in source as well
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct where_;
+
+impl Resolve for where_ {
+    /// picks `x` where `cond` is set and `y` elsewhere, all three broadcast together
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let cond_ty = args_ty_map.get("cond")?;
+                let x_ty = args_ty_map.get("x")?;
+                let y_ty = args_ty_map.get("y")?;
+                if !cond_ty.is_resolved() || !x_ty.is_resolved() || !y_ty.is_resolved() {
+                    return None;
+                }
+                if let Err(e) = check_mask(cond_ty) {
+                    return Some(Err(e));
+                }
+                let span = arg_ty.span();
+                let mut dims = vec![];
+                for ty in &[cond_ty, x_ty, y_ty] {
+                    match ty {
+                        Type::TSR(ref d, _, _) => match broadcast(tenv, &dims, d, span) {
+                            Ok(d) => dims = d,
+                            Err(e) => return Some(Err(e)),
+                        },
+                        Type::BOOL(_) | Type::INT(_) | Type::FLOAT(_) => (),
+                        _ => return Some(Err(Diag::TypeError((*ty).clone(), x_ty.clone()))),
+                    }
+                }
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty.clone(), Type::TSR(dims, Tags::default(), span))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for where_ {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.where"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                write!(buf, "{}, x, {}", operand(args, "cond"), operand(args, "y")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    fn fill(mask: Type) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![dim(4), dim(8)]);
+        let arg_ty = args!(arg!("x", x), arg!("mask", mask), arg!("value", float!()));
+        masked_fill.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![], None)
+    }
+
+    #[test]
+    fn should_fill_with_mask_of_same_shape() {
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        match fill(tsr!(vec![dim(4), dim(8)]).with_precision(Some(Precision::Bool))) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims[0].as_num(), Some(4));
+                assert_eq!(dims[1].as_num(), Some(8));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reject_float_tensor_mask() {
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        match fill(tsr!(vec![dim(4), dim(8)]).with_precision(Some(Precision::F32))) {
+            Some(Err(Diag::InvalidArgument(ref msg, _))) => assert!(msg.contains("float32"), "{}", msg),
+            ty => panic!("{:?}", ty),
+        }
+    }
+}
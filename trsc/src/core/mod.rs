@@ -1,6 +1,9 @@
-use typing::typed_term::TyFnAppArg;
+use typing::typed_term::{TyFnAppArg, TyTerm};
 use errors::Diag;
 use typing::{Type, TypeEnv};
+use typing::types::{Layout, Precision};
+use codespan::ByteSpan;
+use span::CSpan;
 use std::collections::HashMap;
 use std::fmt::Debug;
 
@@ -9,6 +12,12 @@ mod conv;
 mod lin;
 mod reg;
 mod nonlin;
+mod elemwise;
+mod attn;
+mod mask;
+mod einsum;
+#[cfg(test)]
+mod test_args;
 
 pub trait Op: PyTorch + Resolve {
     fn get_name(&self) -> &'static str;
@@ -16,6 +25,113 @@ pub trait Op: PyTorch + Resolve {
     fn ty_sigs(&self, tenv: &mut TypeEnv) -> Vec<(MethodName, Type)>;
 
     fn is_stateful(&self) -> bool;
+
+    /// trailing `*param` of the signature of `fn_name`, if it has one
+    fn variadic(&self, _fn_name: &str) -> Option<Variadic> {
+        None
+    }
+}
+
+/// a signature ending in `*name` such as `?(dim: int, *tensors: tsr0)`.
+/// Every call argument that is not one of the named params before it is
+/// collected, in call order, into a tuple argument `name` for `resolve`.
+/// At least one is required, each of the element type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variadic {
+    pub name: &'static str,
+    pub fixed: &'static [&'static str],
+    /// keyword of the element type, e.g. `tsr0`
+    pub elem: &'static str,
+}
+
+impl Variadic {
+    /// pack the variadic arguments of a call into one tuple argument.
+    /// Also returns the argument names in call order for `unpack`.
+    pub fn pack(
+        &self,
+        arg_ty: Type,
+        args: Vec<TyFnAppArg>,
+        span: ByteSpan,
+    ) -> Result<(Type, Vec<TyFnAppArg>, Vec<String>), Diag> {
+        let is_fixed = |name: &Option<String>| name.as_ref().map_or(false, |n| self.fixed.contains(&n.as_str()));
+        let (tys, sp) = match arg_ty {
+            Type::FnArgs(tys, sp) => (tys, sp),
+            ty => return Ok((ty, args, vec![])),
+        };
+        let names = tys.iter()
+            .filter_map(|ty| match ty {
+                Type::FnArg(Some(name), ..) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let (mut fixed_tys, rest_tys): (Vec<_>, Vec<_>) = tys.into_iter().partition(|ty| match ty {
+            Type::FnArg(name, ..) => is_fixed(name),
+            _ => true,
+        });
+        let (mut fixed_args, rest_args): (Vec<_>, Vec<_>) = args.into_iter().partition(|a| is_fixed(&a.name));
+        if rest_args.is_empty() {
+            return Err(Diag::InvalidArgument(
+                format!("expected at least one argument for `*{}`", self.name),
+                span,
+            ));
+        }
+        let elems: Vec<_> = rest_tys.into_iter()
+            .filter_map(|ty| match ty {
+                Type::FnArg(_, box ty, _) => Some(ty),
+                _ => None,
+            })
+            .collect();
+        if let Some(ty) = elems.iter().find(|ty| !self.is_elem(ty)) {
+            return Err(Diag::InvalidArgument(
+                format!("`*{}` takes arguments of type {}, got {:?}", self.name, self.elem, ty),
+                ty.span(),
+            ));
+        }
+        let tuple_ty = Type::Tuple(elems, sp);
+        let terms = rest_args.into_iter().map(|a| *a.arg).collect();
+        fixed_tys.push(Type::FnArg(Some(self.name.to_owned()), box tuple_ty.clone(), sp));
+        fixed_args.push(TyFnAppArg {
+            name: Some(self.name.to_owned()),
+            arg: box TyTerm::TyTuple(tuple_ty, terms, sp),
+            span: sp,
+        });
+        Ok((Type::FnArgs(fixed_tys, sp), fixed_args, names))
+    }
+
+    /// `ty` is of the element type, or not inferred yet
+    fn is_elem(&self, ty: &Type) -> bool {
+        match (self.elem, ty) {
+            (_, Type::VAR(..)) => true,
+            ("tsr0", Type::TSR(..)) | ("int", Type::INT(_)) | ("float", Type::FLOAT(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// spread the tuple argument of a resolved function type back over the
+    /// original arguments so each one is unified with its element type
+    pub fn unpack(&self, ty: Type, names: &[String]) -> Type {
+        let (m, n, tys, ret, sp, args_sp) = match ty {
+            Type::FUN(m, n, box Type::FnArgs(tys, args_sp), ret, sp) => (m, n, tys, ret, sp, args_sp),
+            ty => return ty,
+        };
+        let mut fixed = HashMap::new();
+        let mut elems = vec![].into_iter();
+        for ty in tys {
+            match ty {
+                Type::FnArg(Some(ref name), box Type::Tuple(ref vs, _), _) if name == self.name =>
+                    elems = vs.clone().into_iter(),
+                Type::FnArg(Some(name), ty, s) => { fixed.insert(name, (ty, s)); }
+                _ => (),
+            }
+        }
+        let tys = names.iter()
+            .filter_map(|name| match fixed.remove(name) {
+                Some((ty, s)) => Some(Type::FnArg(Some(name.clone()), ty, s)),
+                None => elems.next().map(|ty| Type::FnArg(Some(name.clone()), box ty, args_sp)),
+            })
+            .collect();
+        Type::FUN(m, n, box Type::FnArgs(tys, args_sp), ret, sp)
+    }
 }
 
 pub trait Resolve {
@@ -30,6 +146,148 @@ pub trait Resolve {
     ) -> Option<Result<Type, Diag>> {
         panic!("{} is not yet implemented", fn_name);
     }
+
+    /// args of `fn_name` that may be omitted, with the value they take
+    fn defaults(&self, _fn_name: &str) -> Vec<TyFnAppArg> {
+        vec![]
+    }
+
+    /// args of `fn_name` that must be supplied
+    fn required(&self, _fn_name: &str) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// layout `fn_name` expects its `x` in, checked before `resolve`
+    fn layout(&self, _fn_name: &str) -> Option<Layout> {
+        None
+    }
+
+    /// precision `fn_name` expects its `x` in, a mismatch only warns
+    fn precision(&self, _fn_name: &str) -> Option<Precision> {
+        None
+    }
+
+    /// reject supplied args outside of their domain, checked before `resolve`
+    fn validate(&self, _fn_name: &str, _args: &[TyFnAppArg]) -> Result<(), Diag> {
+        Ok(())
+    }
+}
+
+/// an int arg for `Resolve::defaults`
+pub fn int_default(name: &str, value: i64) -> TyFnAppArg {
+    TyFnAppArg {
+        name: Some(name.to_owned()),
+        arg: box TyTerm::TyInteger(int!(), value, CSpan::fresh_span()),
+        span: CSpan::fresh_span(),
+    }
+}
+
+/// a float arg for `Resolve::defaults`
+pub fn float_default(name: &str, value: f64) -> TyFnAppArg {
+    TyFnAppArg {
+        name: Some(name.to_owned()),
+        arg: box TyTerm::TyFloat(float!(), value, CSpan::fresh_span()),
+        span: CSpan::fresh_span(),
+    }
+}
+
+/// value of the numeric literal arg `name` and where it was supplied
+fn float_arg(args: &[TyFnAppArg], name: &str) -> Option<(f64, ByteSpan)> {
+    let arg = args.iter().find(|a| a.name.as_ref().map(|n| n.as_str()) == Some(name))?;
+    let value = arg.arg.as_float().or_else(|| arg.arg.as_num().map(|n| n as f64))?;
+    Some((value, arg.span))
+}
+
+/// the arg `name`, if supplied as a literal, must be greater than 0
+pub fn expect_positive_float(args: &[TyFnAppArg], name: &str) -> Result<(), Diag> {
+    match float_arg(args, name) {
+        Some((v, sp)) if !(v > 0.) => Err(Diag::OutOfRange(name.to_owned(), v, "positive".to_owned(), sp)),
+        _ => Ok(()),
+    }
+}
+
+/// the arg `name`, if supplied as a literal, must be in `[lo, hi)`
+pub fn expect_range(args: &[TyFnAppArg], name: &str, lo: f64, hi: f64) -> Result<(), Diag> {
+    match float_arg(args, name) {
+        Some((v, sp)) if !(lo <= v && v < hi) =>
+            Err(Diag::OutOfRange(name.to_owned(), v, format!("in [{}, {})", lo, hi), sp)),
+        _ => Ok(()),
+    }
+}
+
+/// the arg `name`, if supplied as a literal, must be a probability in `[0, 1]`
+pub fn expect_probability(args: &[TyFnAppArg], name: &str) -> Result<(), Diag> {
+    match float_arg(args, name) {
+        Some((v, sp)) if !(0. <= v && v <= 1.) =>
+            Err(Diag::OutOfRange(name.to_owned(), v, "in [0, 1]".to_owned(), sp)),
+        _ => Ok(()),
+    }
+}
+
+/// the args `lo` and `hi`, if both supplied as literals, must not be inverted
+pub fn expect_ordered(args: &[TyFnAppArg], lo: &str, hi: &str) -> Result<(), Diag> {
+    match (float_arg(args, lo), float_arg(args, hi)) {
+        (Some((a, _)), Some((b, sp))) if a > b => Err(Diag::InvalidArgument(
+            format!("`{}` must not exceed `{}`, got {} > {}", lo, hi, a, b),
+            sp,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// the arg `name`, if supplied, must be a number
+pub fn expect_float(args: &[TyFnAppArg], name: &str) -> Result<(), Diag> {
+    match args.iter().find(|a| a.name.as_ref().map(|n| n.as_str()) == Some(name)) {
+        Some(a) => match a.arg.ty() {
+            Type::FLOAT(_) | Type::INT(_) | Type::VAR(..) => Ok(()),
+            ty => Err(Diag::InvalidArgument(
+                format!("`{}` must be a float, got {:?}", name, ty),
+                a.span,
+            )),
+        },
+        None => Ok(()),
+    }
+}
+
+/// `args` followed by each default that was not supplied
+pub fn with_defaults(mut args: Vec<TyFnAppArg>, defaults: Vec<TyFnAppArg>) -> Vec<TyFnAppArg> {
+    for d in defaults {
+        if !args.iter().any(|a| a.name == d.name) {
+            args.push(d);
+        }
+    }
+    args
+}
+
+/// `x` of `arg_ty` must be in the `expected` layout if its own is declared
+pub fn check_layout(arg_ty: &Type, expected: Layout) -> Result<(), Diag> {
+    match arg_ty.as_args_map().and_then(|m| m.get("x").cloned()) {
+        Some(x_ty) => match x_ty.layout() {
+            Some(found) if found != expected => Err(Diag::LayoutMismatch(expected, found, x_ty.span())),
+            _ => Ok(()),
+        },
+        None => Ok(()),
+    }
+}
+
+/// a warning if `x` of `arg_ty` is known to hold floats of another precision than `expected`
+pub fn check_precision(arg_ty: &Type, expected: Precision) -> Option<Diag> {
+    let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+    match x_ty.precision() {
+        Some(found) if found != expected => Some(Diag::PrecisionMismatch(expected, found, x_ty.span())),
+        _ => None,
+    }
+}
+
+/// the first of `required` that is missing from `args` is an error at `span`
+pub fn check_required(args: &[TyFnAppArg], required: &[&str], fn_name: &str, span: ByteSpan) -> Result<(), Diag> {
+    match required.iter().find(|r| !args.iter().any(|a| a.name.as_ref().map(|n| n.as_str()) == Some(**r))) {
+        Some(r) => Err(Diag::InvalidArgument(
+            format!("missing required argument `{}` of `{}`", r, fn_name),
+            span,
+        )),
+        None => Ok(()),
+    }
 }
 
 pub trait PyTorch: Debug {
@@ -38,6 +296,72 @@ pub trait PyTorch: Debug {
         panic!("{:?}::{} function call is not yet implemented", self, name);
         // unimplemented!()
     }
+
+    /// shape of each entry of the `state_dict` of the layer created with `inits`,
+    /// `None` while a shape is not known
+    fn parameters(&self, _inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        Some(vec![])
+    }
+
+    /// called as a method of its input, `x.detach()`, rather than `f(x)`
+    fn is_method(&self) -> bool {
+        false
+    }
+}
+
+/// value of the int arg `name`
+pub fn init_num(inits: &[TyFnAppArg], name: &str) -> Option<i64> {
+    inits.iter().find(|a| a.name == Some(name.to_owned()))?.arg.as_num()
+}
+
+/// read a single int or a tuple of ints such as `(2, 3)`
+pub fn read_ints(t: &TyTerm) -> Option<Vec<i64>> {
+    match *t {
+        TyTerm::TyExpr(box TyTerm::TyTuple(_, ref vs, _), ..) |
+        TyTerm::TyTuple(_, ref vs, _) => vs.iter().map(|v| v.as_num()).collect(),
+        _ => Some(vec![t.as_num()?]),
+    }
+}
+
+/// the arg `name`, missing if it was not supplied
+fn get_arg<'a>(args: &'a [TyFnAppArg], name: &str) -> Result<&'a TyFnAppArg, Diag> {
+    args.iter()
+        .find(|a| a.name.as_ref().map(|n| n.as_str()) == Some(name))
+        .ok_or_else(|| {
+            let span = match (args.first(), args.last()) {
+                (Some(first), Some(last)) => ByteSpan::new(first.span.start(), last.span.end()),
+                _ => CSpan::fresh_span(),
+            };
+            Diag::InvalidArgument(format!("missing argument `{}`", name), span)
+        })
+}
+
+fn wrong_type(arg: &TyFnAppArg, name: &str, expected: &str) -> Diag {
+    Diag::InvalidArgument(format!("`{}` must be {}, got {:?}", name, expected, arg.arg.ty()), arg.span)
+}
+
+/// value of the int arg `name`
+pub fn get_int(args: &[TyFnAppArg], name: &str) -> Result<i64, Diag> {
+    let arg = get_arg(args, name)?;
+    arg.arg.as_num().ok_or_else(|| wrong_type(arg, name, "an int"))
+}
+
+/// value of the float arg `name`, an int is taken as a float
+pub fn get_float(args: &[TyFnAppArg], name: &str) -> Result<f64, Diag> {
+    let arg = get_arg(args, name)?;
+    arg.arg.as_float()
+        .or_else(|| arg.arg.as_num().map(|n| n as f64))
+        .ok_or_else(|| wrong_type(arg, name, "a float"))
+}
+
+/// the pair of ints `name`, such as a kernel size, a single int is used for both
+pub fn get_tuple2(args: &[TyFnAppArg], name: &str) -> Result<(i64, i64), Diag> {
+    let arg = get_arg(args, name)?;
+    match read_ints(&arg.arg) {
+        Some(ref v) if v.len() == 1 => Ok((v[0], v[0])),
+        Some(ref v) if v.len() == 2 => Ok((v[0], v[1])),
+        _ => Err(wrong_type(arg, name, "an int or a pair of ints")),
+    }
 }
 
 #[derive(Debug)]
@@ -52,30 +376,101 @@ impl Core {
         let maps = hashmap! {
             "conv" => hashmap! {
                 "Conv2d" => box self::conv::Conv2d as Box<Op>,
+                "ConvTranspose2d" => box self::conv::ConvTranspose2d as Box<Op>,
                 "maxpool2d" => box self::conv::maxpool2d as Box<Op>,
+                "PixelShuffle" => box self::conv::PixelShuffle as Box<Op>,
+                "PatchEmbed" => box self::conv::PatchEmbed as Box<Op>,
             },
             "nonlin" => hashmap! {
-                "relu" => box self::nonlin::relu as Box<Op>,
-                "tanh" => box self::nonlin::tanh as Box<Op>,
-                "leaky_relu" => box self::nonlin::leaky_relu as Box<Op>,
+                "relu" => box Core::shape_preserving_unary("relu", "F.relu") as Box<Op>,
+                "tanh" => box Core::shape_preserving_unary("tanh", "F.tanh") as Box<Op>,
+                "sigmoid" => box Core::shape_preserving_unary("sigmoid", "F.sigmoid") as Box<Op>,
+                "gelu" => box Core::shape_preserving_unary("gelu", "F.gelu") as Box<Op>,
+                "softplus" => box Core::shape_preserving_unary("softplus", "F.softplus") as Box<Op>,
+                "elu" => box Core::shape_preserving_unary("elu", "F.elu") as Box<Op>,
+                "leaky_relu" => box Core::shape_preserving_unary("leaky_relu", "F.leaky_relu")
+                    .with_float_param("p") as Box<Op>,
                 "log_softmax" => box self::nonlin::log_softmax as Box<Op>,
-                "sigmoid" => box self::nonlin::sigmoid as Box<Op>,
+                "PReLU" => box self::nonlin::PReLU as Box<Op>,
             },
             "lin" => hashmap! {
                 "Linear" => box self::lin::Linear as Box<Op>,
+                "Bilinear" => box self::lin::Bilinear as Box<Op>,
+            },
+            "attn" => hashmap! {
+                "MultiheadAttention" => box self::attn::MultiheadAttention as Box<Op>,
             },
             "prelude" => hashmap! {
                 "view" => box self::prelude::view as Box<Op>,
+                "pad" => box self::prelude::pad as Box<Op>,
+                "slice" => box self::prelude::slice as Box<Op>,
+                "repeat" => box self::prelude::repeat as Box<Op>,
+                "sum" => box self::prelude::sum as Box<Op>,
+                "mean" => box self::prelude::mean as Box<Op>,
+                "max" => box self::prelude::max as Box<Op>,
+                "argmax" => box self::prelude::argmax as Box<Op>,
+                "argmin" => box self::prelude::argmin as Box<Op>,
+                "add" => box self::elemwise::add as Box<Op>,
+                "sub" => box self::elemwise::sub as Box<Op>,
+                "mul" => box self::elemwise::mul as Box<Op>,
+                "div" => box self::elemwise::div as Box<Op>,
+                "masked_fill" => box self::mask::masked_fill as Box<Op>,
+                "where_" => box self::mask::where_ as Box<Op>,
+                "einsum" => box self::einsum::einsum as Box<Op>,
+                "cat" => box self::prelude::cat as Box<Op>,
+                "stack" => box self::prelude::stack as Box<Op>,
+                "transpose" => box self::prelude::transpose as Box<Op>,
+                "permute" => box self::prelude::permute as Box<Op>,
+                "detach" => box self::prelude::detach as Box<Op>,
+                "size" => box self::prelude::Size as Box<Op>,
+                "clamp" => box self::prelude::clamp as Box<Op>,
+                "clip" => box self::prelude::clamp as Box<Op>,
+                "normalize" => box self::prelude::normalize as Box<Op>,
+                "l2norm" => box self::prelude::normalize as Box<Op>,
+                "chunk" => box self::prelude::chunk as Box<Op>,
+                "split" => box self::prelude::chunk as Box<Op>,
+                "half" => box self::prelude::Cast::new("half", Precision::F16) as Box<Op>,
+                "bfloat16" => box self::prelude::Cast::new("bfloat16", Precision::BF16) as Box<Op>,
+                "float" => box self::prelude::Cast::new("float", Precision::F32) as Box<Op>,
+                "long" => box self::prelude::Cast::new("long", Precision::I64) as Box<Op>,
+                "bool" => box self::prelude::Cast::new("bool", Precision::Bool) as Box<Op>,
+                "one_hot" => box self::prelude::one_hot as Box<Op>,
+                "repeat_interleave" => box self::prelude::repeat_interleave as Box<Op>,
+                "stop_gradient" => box self::prelude::detach as Box<Op>,
             },
             "reg" => hashmap! {
                 "Dropout2d" => box self::reg::Dropout2d as Box<Op>,
                 "BatchNorm1d" => box self::reg::BatchNorm1d as Box<Op>,
+                "LayerNorm" => box self::reg::LayerNorm as Box<Op>,
+                "GroupNorm" => box self::reg::GroupNorm as Box<Op>,
+                "InstanceNorm2d" => box self::reg::InstanceNorm2d as Box<Op>,
             }
         };
         Self {
             maps,
         }
     }
+
+    /// an activation whose `forward` maps `x` to a tensor of the same shape
+    pub fn shape_preserving_unary(name: &'static str, pytorch_name: &'static str) -> self::nonlin::ShapePreservingUnary {
+        self::nonlin::ShapePreservingUnary::new(name, pytorch_name)
+    }
+
+    /// register `op` as `path_name::mod_name`, replacing any previous op
+    pub fn register(&mut self, path_name: &'static str, mod_name: &'static str, op: Box<Op>) {
+        self.maps.entry(path_name).or_insert_with(HashMap::new).insert(mod_name, op);
+    }
+
+    /// names registered under `path_name`, sorted so that imports are
+    /// deterministic
+    pub fn names(&self, path_name: &str) -> Vec<MethodName> {
+        let mut ret: Vec<_> = self.maps.get(path_name)
+            .map(|m| m.keys().cloned().collect())
+            .unwrap_or_default();
+        ret.sort();
+        ret
+    }
+
     pub fn import(&self, path_name: &str, mod_name: &str, tenv: &mut TypeEnv) -> Option<Vec<(MethodName, Type)>> {
         let op = self.find(path_name, mod_name)?;
         Some(op.ty_sigs(tenv))
@@ -95,3 +490,50 @@ impl Core {
             .to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan::ByteIndex;
+
+    fn arg(name: &str, arg: TyTerm, span: ByteSpan) -> TyFnAppArg {
+        TyFnAppArg { name: Some(name.to_owned()), arg: box arg, span }
+    }
+
+    fn span(start: u32, end: u32) -> ByteSpan {
+        ByteSpan::new(ByteIndex(start), ByteIndex(end))
+    }
+
+    #[test]
+    fn should_read_named_args() {
+        let sp = CSpan::fresh_span();
+        let args = vec![
+            arg("stride", TyTerm::TyInteger(int!(), 2, sp), span(4, 12)),
+            arg("p", TyTerm::TyFloat(float!(), 0.5, sp), span(14, 19)),
+            arg("kernel_size", TyTerm::TyTuple(tuple!(int 2), vec![
+                TyTerm::TyInteger(int!(), 3, sp),
+                TyTerm::TyInteger(int!(), 5, sp),
+            ], sp), span(21, 40)),
+        ];
+        assert_eq!(get_int(&args, "stride").ok(), Some(2));
+        assert_eq!(get_float(&args, "p").ok(), Some(0.5));
+        assert_eq!(get_float(&args, "stride").ok(), Some(2.));
+        assert_eq!(get_tuple2(&args, "kernel_size").ok(), Some((3, 5)));
+        assert_eq!(get_tuple2(&args, "stride").ok(), Some((2, 2)));
+
+        match get_int(&args, "padding") {
+            Err(Diag::InvalidArgument(msg, sp)) => {
+                assert_eq!(msg, "missing argument `padding`");
+                assert_eq!(sp, span(4, 40));
+            }
+            r => panic!("{:?}", r),
+        }
+        match get_int(&args, "p") {
+            Err(Diag::InvalidArgument(msg, sp)) => {
+                assert!(msg.starts_with("`p` must be an int"), "{}", msg);
+                assert_eq!(sp, span(14, 19));
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+}
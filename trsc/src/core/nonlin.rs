@@ -1,88 +1,100 @@
 use self::Type::*;
-use core::{MethodName, Op, PyTorch, Resolve};
+use core::{expect_float, MethodName, Op, PyTorch, Resolve};
 use std::fmt::Write;
 use span::CSpan;
 use typing::typed_term::TyFnAppArg;
+use typing::typed_term::ArgsVecInto;
 use typing::{Type, TypeEnv};
 use errors::Diag;
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Op)]
-#[path = "nonlin"]
-#[forward = "?()"]
-pub struct sigmoid;
+/// an elementwise activation such as `relu`, `forward` returns a tensor
+/// of the same shape as its `x`
+#[derive(Debug)]
+pub struct ShapePreservingUnary {
+    name: &'static str,
+    pytorch_name: &'static str,
+    /// float arg the activation may take, such as the slope of `leaky_relu`
+    float_param: Option<&'static str>,
+}
 
-impl Resolve for sigmoid {
-    fn resolve(
-        &self,
-        tenv: &mut TypeEnv,
-        fn_name: &str,
-        _arg_ty: Type,
-        _ret_ty: Type,
-        _args: Vec<TyFnAppArg>,
-        _inits: Option<Vec<TyFnAppArg>>,
-    ) -> Option<Result<Type, Diag>> {
-        match fn_name {
-            "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
-                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone())), ty)))
-            }
-            _ => unimplemented!(),
+impl ShapePreservingUnary {
+    pub fn new(name: &'static str, pytorch_name: &'static str) -> Self {
+        Self {
+            name,
+            pytorch_name,
+            float_param: None,
         }
     }
+
+    pub fn with_float_param(mut self, param: &'static str) -> Self {
+        self.float_param = Some(param);
+        self
+    }
 }
 
-impl PyTorch for sigmoid {
-    fn pytorch_name(&self) -> &'static str {
-        "F.sigmoid"
+impl Op for ShapePreservingUnary {
+    fn get_name(&self) -> &'static str {
+        self.name
     }
-    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
-        let mut buf = String::new();
-        match name {
-            "forward" => {
-                write!(buf, "x").unwrap();
-                Ok(buf)
-            }
-            _ => panic!("{} is not implemented", name),
-        }
+    fn ty_sigs(&self, _tenv: &mut TypeEnv) -> Vec<(MethodName, Type)> {
+        vec![("forward", UnresolvedModuleFun("nonlin", self.name, "forward", CSpan::fresh_span()))]
+    }
+    fn is_stateful(&self) -> bool {
+        false
     }
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Op)]
-#[path = "nonlin"]
-#[forward = "?()"]
-pub struct tanh;
-
-impl Resolve for tanh {
+impl Resolve for ShapePreservingUnary {
     fn resolve(
         &self,
         tenv: &mut TypeEnv,
         fn_name: &str,
         _arg_ty: Type,
         _ret_ty: Type,
-        _args: Vec<TyFnAppArg>,
+        args: Vec<TyFnAppArg>,
         _inits: Option<Vec<TyFnAppArg>>,
     ) -> Option<Result<Type, Diag>> {
         match fn_name {
             "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
-                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone())), ty)))
+                let ty = tenv.fresh_var(CSpan::fresh_span(), Some(&format!("{}.x", self.get_name())));
+                let mut params = vec![arg!("x", ty.clone())];
+                // the float param is optional, it is typed only if supplied
+                if let Some(param) = self.float_param {
+                    if args.iter().any(|a| a.name.as_ref().map(|n| n.as_str()) == Some(param)) {
+                        params.push(arg!(param, float!()));
+                    }
+                }
+                Some(Ok(fun!(self.get_name(), "forward", FnArgs(params, CSpan::fresh_span()), ty)))
             }
-            _ => unimplemented!(),
+            _ => None,
+        }
+    }
+
+    fn validate(&self, _fn_name: &str, args: &[TyFnAppArg]) -> Result<(), Diag> {
+        match self.float_param {
+            Some(param) => expect_float(args, param),
+            None => Ok(()),
         }
     }
 }
 
-impl PyTorch for tanh {
+impl PyTorch for ShapePreservingUnary {
     fn pytorch_name(&self) -> &'static str {
-        "F.tanh"
+        self.pytorch_name
     }
-    fn gen_fn_app(&self, name: &str, _args: &[TyFnAppArg]) -> Result<String, Diag> {
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
         let mut buf = String::new();
         match name {
             "forward" => {
                 write!(buf, "x").unwrap();
+                // passed positionally, PyTorch names it after the activation,
+                // e.g. `negative_slope` of `leaky_relu`
+                let value = self.float_param
+                    .and_then(|param| args.iter().find(|a| a.name.as_ref().map(|n| n.as_str()) == Some(param)))
+                    .and_then(|a| a.arg.as_str());
+                if let Some(value) = value {
+                    write!(buf, ", {}", value).unwrap();
+                }
                 Ok(buf)
             }
             _ => panic!("{} is not implemented", name),
@@ -94,9 +106,9 @@ impl PyTorch for tanh {
 #[derive(Debug, Op)]
 #[path = "nonlin"]
 #[forward = "?()"]
-pub struct relu;
+pub struct log_softmax;
 
-impl Resolve for relu {
+impl Resolve for log_softmax {
     fn resolve(
         &self,
         tenv: &mut TypeEnv,
@@ -108,23 +120,24 @@ impl Resolve for relu {
     ) -> Option<Result<Type, Diag>> {
         match fn_name {
             "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
-                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone())), ty)))
+                let ty = tenv.fresh_var(CSpan::fresh_span(), Some(&format!("{}.x", self.get_name())));
+                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone()), arg!("dim", int!())), ty)))
             }
-            _ => unimplemented!(),
+            _ => None,
         }
     }
 }
 
-impl PyTorch for relu {
+impl PyTorch for log_softmax {
     fn pytorch_name(&self) -> &'static str {
-        "F.relu"
+        "F.log_softmax"
     }
-    fn gen_fn_app(&self, name: &str, _args: &[TyFnAppArg]) -> Result<String, Diag> {
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
         let mut buf = String::new();
         match name {
             "forward" => {
-                write!(buf, "x").unwrap();
+                let args: Vec<_> = args.iter().map(|i| i.name.clone().unwrap()).collect();
+                write!(buf, "{}", args.join(", ")).unwrap();
                 Ok(buf)
             }
             _ => panic!("{} is not implemented", name),
@@ -132,87 +145,161 @@ impl PyTorch for relu {
     }
 }
 
-#[allow(non_camel_case_types)]
+// a learned slope shared by all channels, or one per channel of
+// `x: [N, C, ...]` when `num_parameters` is not 1
 #[derive(Debug, Op)]
 #[path = "nonlin"]
-#[forward = "?()"]
-pub struct leaky_relu;
+#[new = "(num_parameters: int) -> self"]
+#[forward = "?(x: tsr0) -> tsr0"]
+#[stateful]
+pub struct PReLU;
 
-impl Resolve for leaky_relu {
+impl Resolve for PReLU {
     fn resolve(
         &self,
-        tenv: &mut TypeEnv,
+        _tenv: &mut TypeEnv,
         fn_name: &str,
-        _arg_ty: Type,
+        arg_ty: Type,
         _ret_ty: Type,
         _args: Vec<TyFnAppArg>,
-        _inits: Option<Vec<TyFnAppArg>>,
+        inits: Option<Vec<TyFnAppArg>>,
     ) -> Option<Result<Type, Diag>> {
         match fn_name {
             "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
-                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone())), ty)))
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                let dims = x_ty.as_vec()?;
+                let init = inits?
+                    .into_iter()
+                    .find(|a| a.name == Some("num_parameters".to_owned()))?;
+                let n = init.arg.as_num()?;
+                if n != 1 {
+                    if dims.len() < 2 {
+                        return Some(Err(Diag::InvalidArgument(
+                            format!("{} with {} parameters expects input of shape [N, C, ...], got {:?}", self.get_name(), n, x_ty),
+                            x_ty.span(),
+                        )));
+                    }
+                    match dims[1].as_num() {
+                        Some(c) if c != n => return Some(Err(Diag::DimensionMismatch(
+                            dims[1].with_span(&x_ty.span()),
+                            ResolvedDim(n, init.span),
+                        ))),
+                        _ => (),
+                    }
+                }
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, x_ty)))
             }
-            _ => unimplemented!(),
+            _ => None,
         }
     }
 }
 
-impl PyTorch for leaky_relu {
+impl PyTorch for PReLU {
     fn pytorch_name(&self) -> &'static str {
-        "F.leaky_relu"
+        "nn.PReLU"
+    }
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let n = inits.iter().find(|a| a.name == Some("num_parameters".to_owned()))?.arg.as_num()?;
+        Some(vec![("weight".to_owned(), vec![n])])
     }
-    fn gen_fn_app(&self, name: &str, _args: &[TyFnAppArg]) -> Result<String, Diag> {
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
         let mut buf = String::new();
         match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(num_parameters={})", self.pytorch_name(), map["num_parameters"].as_num().unwrap()).unwrap();
+            }
             "forward" => {
                 write!(buf, "x").unwrap();
-                Ok(buf)
             }
-            _ => panic!("{} is not implemented", name),
+            _ => unimplemented!(),
         }
+
+        Ok(buf)
     }
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Op)]
-#[path = "nonlin"]
-#[forward = "?()"]
-pub struct log_softmax;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use typing::typed_term::TyTerm;
+    use codespan::{ByteIndex, ByteSpan};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use core::test_args::float_arg;
 
-impl Resolve for log_softmax {
-    fn resolve(
-        &self,
-        tenv: &mut TypeEnv,
-        fn_name: &str,
-        _arg_ty: Type,
-        _ret_ty: Type,
-        _args: Vec<TyFnAppArg>,
-        _inits: Option<Vec<TyFnAppArg>>,
-    ) -> Option<Result<Type, Diag>> {
-        match fn_name {
-            "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
-                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone()), arg!("dim", int!())), ty)))
+    #[test]
+    fn should_preserve_shape_for_each_activation() {
+        let core = Core::new();
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        for name in &["relu", "tanh", "sigmoid", "gelu", "softplus", "elu", "leaky_relu"] {
+            let op = core.find("nonlin", name).unwrap();
+            let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), Type::ResolvedDim(8, CSpan::fresh_span())]);
+            match op.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], None) {
+                Some(Ok(Type::FUN(_, _, box p, box r, _))) => assert_eq!(p.first_arg_ty(), Some(r)),
+                ty => panic!("{}: {:?}", name, ty),
             }
-            _ => unimplemented!(),
         }
     }
-}
 
-impl PyTorch for log_softmax {
-    fn pytorch_name(&self) -> &'static str {
-        "F.log_softmax"
+    #[test]
+    fn should_check_float_param_of_leaky_relu() {
+        let core = Core::new();
+        let op = core.find("nonlin", "leaky_relu").unwrap();
+        let slope = |arg| vec![TyFnAppArg { name: Some("p".to_owned()), arg: box arg, span: CSpan::fresh_span() }];
+        assert!(op.validate("forward", &slope(TyTerm::TyFloat(float!(), 0.2, CSpan::fresh_span()))).is_ok());
+        match op.validate("forward", &slope(TyTerm::TyBool(Type::BOOL(CSpan::fresh_span()), true, CSpan::fresh_span()))) {
+            Err(Diag::InvalidArgument(..)) => (),
+            r => panic!("{:?}", r),
+        }
     }
-    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
-        let mut buf = String::new();
-        match name {
-            "forward" => {
-                let args: Vec<_> = args.iter().map(|i| i.name.clone().unwrap()).collect();
-                write!(buf, "{}", args.join(", ")).unwrap();
-                Ok(buf)
+
+    fn prelu(num_parameters: i64, channels: i64) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(channels), dim(8), dim(8)]);
+        let sp = ByteSpan::new(ByteIndex(10), ByteIndex(12));
+        let init = TyFnAppArg {
+            name: Some("num_parameters".to_owned()),
+            arg: box TyTerm::TyInteger(int!(), num_parameters, sp),
+            span: sp,
+        };
+        PReLU.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], Some(vec![init]))
+    }
+
+    #[test]
+    fn should_check_prelu_parameter_count() {
+        for &(n, c) in &[(1, 32), (32, 32)] {
+            match prelu(n, c) {
+                Some(Ok(Type::FUN(_, _, box p, box r, _))) => assert_eq!(p.first_arg_ty(), Some(r)),
+                ty => panic!("{:?}", ty),
             }
-            _ => panic!("{} is not implemented", name),
         }
+        match prelu(16, 32) {
+            Some(Err(Diag::DimensionMismatch(a, b))) => {
+                assert_eq!(a.as_num(), Some(32));
+                assert_eq!(b.as_num(), Some(16));
+                assert_eq!(b.span(), ByteSpan::new(ByteIndex(10), ByteIndex(12)));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_pass_slope_of_leaky_relu() {
+        let core = Core::new();
+        let op = core.find("nonlin", "leaky_relu").unwrap();
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let slope = float_arg("p", 0.2);
+        match op.resolve(&mut tenv, "forward", unit!(), unit!(), vec![slope.clone()], None) {
+            Some(Ok(Type::FUN(_, _, box Type::FnArgs(params, _), _, _))) => {
+                assert_eq!(params.len(), 2);
+                assert_eq!(params[1], arg!("p", float!()));
+            }
+            ty => panic!("{:?}", ty),
+        }
+        assert_eq!(op.gen_fn_app("forward", &[slope]).unwrap(), "x, 0.2");
+        assert_eq!(op.gen_fn_app("forward", &[]).unwrap(), "x");
     }
 }
@@ -1,8 +1,11 @@
-use core::{MethodName, Op, PyTorch, Resolve};
+use core::{expect_ordered, expect_positive_float, float_default, int_default, read_ints, MethodName, Op, PyTorch, Resolve, Variadic};
 use errors::Diag;
 use span::CSpan;
-use typing::typed_term::TyFnAppArg;
+use typing::typed_term::{ArgsVecInto, TyFnAppArg, TyTerm};
 use typing::{Type, TypeEnv};
+use typing::types::{Precision, Tags};
+use std::fmt::Write;
+use codespan::ByteSpan;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Op)]
@@ -65,7 +68,7 @@ impl Resolve for view {
                     panic!("{} {}", ret_prod, arg_prod);// ...
                 }
             }
-            _ => unimplemented!(),
+            _ => None,
         }
     }
 }
@@ -74,4 +77,1965 @@ impl PyTorch for view {
     fn pytorch_name(&self) -> &'static str {
         unimplemented!();
     }
-}
\ No newline at end of file
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct pad;
+
+impl Resolve for pad {
+    /// pads the spatial dims of a [N, C, H, W] tensor
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?;
+                if !x_ty.is_resolved() { return None; }
+                let args_map = args.to_btreemap()?;
+                let amount = |name: &str| args_map.get(name).and_then(|t| t.as_num()).unwrap_or(0);
+                let (left, right, top, bottom) = (amount("left"), amount("right"), amount("top"), amount("bottom"));
+                if left < 0 || right < 0 || top < 0 || bottom < 0 {
+                    return Some(Err(
+                        Diag::InvalidArgument("Cannot pad by a negative amount".to_owned(), arg_ty.span())
+                    ));
+                }
+
+                let dims = x_ty.as_vec()?;
+                if dims.len() != 4 {
+                    return Some(Err(
+                        Diag::InvalidArgument("pad expects a tensor of rank 4".to_owned(), x_ty.span())
+                    ));
+                }
+                let span = x_ty.span();
+                let mut padded = |dim: &Type, amount: i64, label: &str| match dim.as_num() {
+                    Some(_) if amount == 0 => dim.clone(),
+                    Some(d) => Type::ResolvedDim(d + amount, span),
+                    None if amount == 0 => dim.clone(),
+                    None => tenv.fresh_dim(span, Some(label)),
+                };
+                let h_out = padded(&dims[2], top + bottom, "pad.output.H");
+                let w_out = padded(&dims[3], left + right, "pad.output.W");
+
+                Some(Ok(
+                    fun!(
+                        "pad",
+                        "forward",
+                        arg_ty,
+                        Type::TSR(vec![
+                            dims[0].clone(),
+                            dims[1].clone(),
+                            h_out,
+                            w_out,
+                        ], Tags::default(), span)
+                    )
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for pad {
+    fn pytorch_name(&self) -> &'static str {
+        "F.pad"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                let amount = |name: &str| map.get(name).and_then(|t| t.as_num()).unwrap_or(0);
+                write!(buf, "x, ({}, {}, {}, {})",
+                    amount("left"), amount("right"), amount("top"), amount("bottom")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+/// how a single axis is indexed
+#[derive(Debug, Clone, PartialEq)]
+pub enum AxisSpec {
+    /// `:`, the axis is kept as is
+    Full,
+    /// a single index, the axis is dropped
+    Index(i64),
+    /// `start:end`, either end may be left open
+    Range(Option<i64>, Option<i64>),
+}
+
+/// shape of `dims` indexed by `specs`, axes without a spec are kept.
+/// Negative indices count from the end and are checked against resolved sizes.
+/// A range of a symbolic dim has a known size only if both bounds count from
+/// the end, otherwise the range may be clipped and the size is left to a fresh dim.
+pub fn slice_shape(
+    tenv: &mut TypeEnv,
+    dims: &[Type],
+    specs: &[AxisSpec],
+    span: ByteSpan,
+) -> Result<Vec<Type>, Diag> {
+    if specs.len() > dims.len() {
+        return Err(Diag::InvalidArgument(
+            format!("too many indices for tensor of rank {}", dims.len()),
+            span,
+        ));
+    }
+    let out_of_range = |i: i64, n: i64| Diag::InvalidArgument(
+        format!("index {} is out of range for dimension of size {}", i, n),
+        span,
+    );
+    let mut ret = vec![];
+    for (i, dim) in dims.iter().enumerate() {
+        match (specs.get(i).unwrap_or(&AxisSpec::Full), dim.as_num()) {
+            (AxisSpec::Full, _) | (AxisSpec::Range(None, None), _) => ret.push(dim.clone()),
+            (AxisSpec::Index(idx), Some(n)) => if *idx < -n || *idx >= n {
+                return Err(out_of_range(*idx, n));
+            },
+            (AxisSpec::Index(_), None) => (),
+            (AxisSpec::Range(start, end), Some(n)) => {
+                let bound = |b: Option<i64>, default: i64| match b {
+                    None => Ok(default),
+                    Some(b) if b < -n || b > n => Err(out_of_range(b, n)),
+                    Some(b) if b < 0 => Ok(b + n),
+                    Some(b) => Ok(b),
+                };
+                let (start, end) = (bound(*start, 0)?, bound(*end, n)?);
+                ret.push(Type::ResolvedDim((end - start).max(0), span));
+            }
+            (AxisSpec::Range(Some(start), Some(end)), None) if *start < 0 && *end < 0 => {
+                ret.push(Type::ResolvedDim((end - start).max(0), span));
+            }
+            (AxisSpec::Range(..), None) => ret.push(tenv.fresh_dim(span, Some(&format!("slice.output[{}]", i)))),
+        }
+    }
+    Ok(ret)
+}
+
+/// read the axis and its spec from `dim=` and `index=` or `start=`/`end=`
+fn read_axis_spec(args: &[TyFnAppArg]) -> Option<(i64, AxisSpec, ByteSpan)> {
+    let map = args.to_btreemap()?;
+    let num = |name: &str| map.get(name).and_then(|t| t.as_num());
+    let span = |name: &str| args.iter()
+        .find(|a| a.name == Some(name.to_owned()))
+        .map(|a| a.span);
+    let dim = num("dim")?;
+    if let Some(idx) = num("index") {
+        Some((dim, AxisSpec::Index(idx), span("index")?))
+    } else {
+        let sp = span("start").or_else(|| span("end")).or_else(|| span("dim"))?;
+        Some((dim, AxisSpec::Range(num("start"), num("end")), sp))
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct slice;
+
+impl Resolve for slice {
+    /// selects a single index or a range along the one axis `dim`. There is
+    /// no `x[:, 0]` syntax, indexing several axes takes a `slice` per axis.
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?;
+                let dims = x_ty.as_vec()?;
+                let (dim, spec, span) = read_axis_spec(&args)?;
+                let rank = dims.len() as i64;
+                if dim < -rank || dim >= rank {
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("dim {} is out of range for tensor of rank {}", dim, rank),
+                        span,
+                    )));
+                }
+                let axis = (if dim < 0 { dim + rank } else { dim }) as usize;
+                let mut specs = vec![AxisSpec::Full; axis];
+                specs.push(spec);
+                match slice_shape(tenv, &dims, &specs, span) {
+                    Ok(dims) => Some(Ok(fun!("slice", "forward", arg_ty, Type::TSR(dims, x_ty.tags(), x_ty.span())))),
+                    Err(e) => Some(Err(e)),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for slice {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.Tensor.__getitem__"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let (dim, spec, _) = read_axis_spec(args).unwrap();
+                let bound = |b: Option<i64>| b.map(|b| b.to_string()).unwrap_or_else(|| "None".to_owned());
+                let spec = match spec {
+                    AxisSpec::Index(i) => i.to_string(),
+                    AxisSpec::Range(start, end) => format!("slice({}, {})", bound(start), bound(end)),
+                    AxisSpec::Full => "slice(None)".to_owned(),
+                };
+                let mut items = vec![];
+                if dim < 0 {
+                    items.push("Ellipsis".to_owned());
+                    items.push(spec);
+                    items.extend((0..(-dim - 1)).map(|_| "slice(None)".to_owned()));
+                } else {
+                    items.extend((0..dim).map(|_| "slice(None)".to_owned()));
+                    items.push(spec);
+                }
+                write!(buf, "x, ({},)", items.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct repeat;
+
+impl Resolve for repeat {
+    /// tiles each axis by its count in `counts`
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?;
+                let dims = x_ty.as_vec()?;
+                let counts_arg = args.iter().find(|a| a.name == Some("counts".to_owned()))?;
+                let counts = read_ints(&counts_arg.arg)?;
+                if counts.len() != dims.len() {
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("expected {} repeat counts for tensor of rank {}, got {}",
+                            dims.len(), dims.len(), counts.len()),
+                        counts_arg.span,
+                    )));
+                }
+                if counts.iter().any(|c| *c <= 0) {
+                    return Some(Err(Diag::InvalidArgument(
+                        "repeat counts must be positive".to_owned(),
+                        counts_arg.span,
+                    )));
+                }
+                let span = x_ty.span();
+                let tiled = dims.iter()
+                    .zip(counts.iter())
+                    .enumerate()
+                    .map(|(i, (dim, count))| match dim.as_num() {
+                        _ if *count == 1 => dim.clone(),
+                        Some(d) => Type::ResolvedDim(d * count, span),
+                        None => tenv.fresh_dim(span, Some(&format!("repeat.output[{}]", i))),
+                    })
+                    .collect();
+                Some(Ok(fun!("repeat", "forward", arg_ty, Type::TSR(tiled, x_ty.tags(), span))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for repeat {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.Tensor.repeat"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let counts_arg = args.iter().find(|a| a.name == Some("counts".to_owned())).unwrap();
+                let counts: Vec<_> = read_ints(&counts_arg.arg).unwrap()
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect();
+                write!(buf, "x, {}", counts.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+/// output type of a reduction of `x` along `dim`, the axis is dropped
+/// or kept with size 1 if `keepdim` is set
+fn resolve_reduce(name: &str, arg_ty: Type, args: &[TyFnAppArg]) -> Option<Result<Type, Diag>> {
+    let args_ty_map = arg_ty.as_args_map()?;
+    let x_ty = args_ty_map.get("x")?;
+    let mut dims = x_ty.as_vec()?;
+    let dim_arg = args.iter().find(|a| a.name == Some("dim".to_owned()))?;
+    let dim = dim_arg.arg.as_num()?;
+    let keepdim = args.iter()
+        .find(|a| a.name == Some("keepdim".to_owned()))
+        .and_then(|a| a.arg.as_bool())
+        .unwrap_or(false);
+    let rank = dims.len() as i64;
+    // like torch, a rank-0 tensor reduces over dim 0 or -1 to itself
+    if rank == 0 && (dim == 0 || dim == -1) {
+        return Some(Ok(fun!(name, "forward", arg_ty, x_ty.clone())));
+    }
+    if dim < -rank || dim >= rank {
+        return Some(Err(Diag::InvalidArgument(
+            format!("dim {} is out of range for tensor of rank {}", dim, rank),
+            dim_arg.span,
+        )));
+    }
+    let axis = (if dim < 0 { dim + rank } else { dim }) as usize;
+    let span = x_ty.span();
+    // the dtype is kept, the layout only if no axis is dropped
+    let tags = if keepdim {
+        dims[axis] = Type::ResolvedDim(1, span);
+        x_ty.tags()
+    } else {
+        dims.remove(axis);
+        Tags { layout: None, ..x_ty.tags() }
+    };
+    Some(Ok(fun!(name, "forward", arg_ty, Type::TSR(dims, tags, span))))
+}
+
+/// like `resolve_reduce`, the result holds indices along `dim`
+fn resolve_arg_reduce(name: &str, arg_ty: Type, args: &[TyFnAppArg]) -> Option<Result<Type, Diag>> {
+    resolve_reduce(name, arg_ty, args).map(|ty| ty.map(|ty| match ty {
+        Type::FUN(m, n, arg_ty, box ret, sp) => Type::FUN(m, n, arg_ty, box ret.with_precision(Some(Precision::I64)), sp),
+        ty => ty,
+    }))
+}
+
+fn gen_reduce(name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+    let mut buf = String::new();
+    match name {
+        "forward" => {
+            let map = args.to_btreemap().unwrap();
+            write!(buf, "x, dim={}", map["dim"].as_num().unwrap()).unwrap();
+            if let Some(keepdim) = map.get("keepdim") {
+                write!(buf, ", keepdim={}", keepdim.as_str().unwrap()).unwrap();
+            }
+            Ok(buf)
+        }
+        _ => panic!("{} is not implemented", name),
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct sum;
+
+impl Resolve for sum {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_reduce(self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for sum {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.sum"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_reduce(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct mean;
+
+impl Resolve for mean {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_reduce(self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for mean {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.mean"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_reduce(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct max;
+
+impl Resolve for max {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_reduce(self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for max {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.amax"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_reduce(name, args)
+    }
+}
+
+// index of the largest value along `dim`, a tensor of int64
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct argmax;
+
+impl Resolve for argmax {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_arg_reduce(self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for argmax {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.argmax"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_reduce(name, args)
+    }
+}
+
+// index of the smallest value along `dim`, see `argmax`
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct argmin;
+
+impl Resolve for argmin {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_arg_reduce(self.get_name(), arg_ty, &args),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for argmin {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.argmin"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_reduce(name, args)
+    }
+}
+
+/// each tensor packed into the variadic `tensors` arg, with the span of its argument
+fn variadic_tsrs(arg_ty: &Type, args: &[TyFnAppArg]) -> Option<Vec<(Vec<Type>, ByteSpan)>> {
+    let tys = match arg_ty.as_args_map()?.remove("tensors")? {
+        Type::Tuple(tys, _) => tys,
+        _ => return None,
+    };
+    let spans: Vec<ByteSpan> = match *args.iter().find(|a| a.name == Some("tensors".to_owned()))?.arg {
+        TyTerm::TyTuple(_, ref terms, _) => terms.iter().map(|t| t.span()).collect(),
+        _ => return None,
+    };
+    tys.iter()
+        .zip(spans)
+        .map(|(ty, sp)| Some((ty.as_vec()?, sp)))
+        .collect()
+}
+
+/// replace the element types of the variadic `tensors` arg
+fn with_tsrs(arg_ty: Type, elems: Vec<Type>) -> Type {
+    match arg_ty {
+        Type::FnArgs(vs, sp) => Type::FnArgs(
+            vs.into_iter()
+                .map(|ty| match ty {
+                    Type::FnArg(Some(ref name), box Type::Tuple(_, tsp), s) if name == "tensors" =>
+                        Type::FnArg(Some(name.clone()), box Type::Tuple(elems.clone(), tsp), s),
+                    ty => ty,
+                })
+                .collect(),
+            sp,
+        ),
+        ty => ty,
+    }
+}
+
+/// dims shared by every tensor, a resolved size wins over a symbolic one.
+/// Sizes along `except` may differ. Errors point at the first divergent tensor.
+fn common_dims(tsrs: &[(Vec<Type>, ByteSpan)], except: Option<usize>) -> Result<Vec<Type>, Diag> {
+    let mut common = tsrs[0].0.clone();
+    for (t, sp) in tsrs.iter().skip(1) {
+        if t.len() != common.len() {
+            return Err(Diag::InvalidArgument(
+                format!("expected tensor of rank {}, got rank {}", common.len(), t.len()),
+                *sp,
+            ));
+        }
+        for (i, d) in t.iter().enumerate() {
+            if Some(i) == except {
+                continue;
+            }
+            match (common[i].as_num(), d.as_num()) {
+                (Some(n1), Some(n2)) if n1 != n2 =>
+                    return Err(Diag::DimensionMismatch(common[i].with_span(&tsrs[0].1), d.with_span(sp))),
+                (None, Some(_)) => common[i] = d.clone(),
+                _ => (),
+            }
+        }
+    }
+    Ok(common)
+}
+
+/// position of `dim` among `len` axes, negative dims count from the end
+fn to_axis(dim: i64, len: i64, span: ByteSpan) -> Result<usize, Diag> {
+    if dim < -len || dim >= len {
+        return Err(Diag::InvalidArgument(
+            format!("dim {} is out of range for {} axes", dim, len),
+            span,
+        ));
+    }
+    Ok((if dim < 0 { dim + len } else { dim }) as usize)
+}
+
+/// every tensor agrees on all dims but `dim`, where the sizes add up
+fn cat_shape(tsrs: &[(Vec<Type>, ByteSpan)], dim: i64, span: ByteSpan) -> Result<(Vec<Type>, Type), Diag> {
+    let axis = to_axis(dim, tsrs[0].0.len() as i64, span)?;
+    let common = common_dims(tsrs, Some(axis))?;
+    let elems = tsrs.iter()
+        .map(|(t, _)| {
+            let mut dims = common.clone();
+            dims[axis] = t[axis].clone();
+            Type::TSR(dims, Tags::default(), span)
+        })
+        .collect();
+    let mut dims = common;
+    dims[axis] = tsrs.iter()
+        .skip(1)
+        .fold(tsrs[0].0[axis].clone(), |acc, (t, _)| Type::derive('+', acc, t[axis].clone(), span));
+    Ok((elems, Type::TSR(dims, Tags::default(), span)))
+}
+
+/// every tensor has the same shape, a new axis of their count is inserted at `dim`
+fn stack_shape(tsrs: &[(Vec<Type>, ByteSpan)], dim: i64, span: ByteSpan) -> Result<(Vec<Type>, Type), Diag> {
+    let axis = to_axis(dim, tsrs[0].0.len() as i64 + 1, span)?;
+    let common = common_dims(tsrs, None)?;
+    let elems = tsrs.iter().map(|_| Type::TSR(common.clone(), Tags::default(), span)).collect();
+    let mut dims = common;
+    dims.insert(axis, Type::ResolvedDim(tsrs.len() as i64, span));
+    Ok((elems, Type::TSR(dims, Tags::default(), span)))
+}
+
+/// shared by ops taking a `dim` and variadic `tensors`
+fn resolve_variadic<F>(name: &str, arg_ty: Type, args: &[TyFnAppArg], shape: F) -> Option<Result<Type, Diag>>
+where
+    F: Fn(&[(Vec<Type>, ByteSpan)], i64, ByteSpan) -> Result<(Vec<Type>, Type), Diag>,
+{
+    let tsrs = variadic_tsrs(&arg_ty, args)?;
+    let dim_arg = args.iter().find(|a| a.name == Some("dim".to_owned()))?;
+    let dim = dim_arg.arg.as_num()?;
+    match shape(&tsrs, dim, dim_arg.span) {
+        Ok((elems, ret)) => Some(Ok(fun!(name, "forward", with_tsrs(arg_ty, elems), ret))),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+/// operands of a variadic call in order, then the `dim` arg
+fn gen_variadic(name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+    let mut buf = String::new();
+    match name {
+        "forward" => {
+            let mut dim = None;
+            let mut operands = vec![];
+            for a in args.iter() {
+                match *a.arg {
+                    _ if a.name == Some("dim".to_owned()) => dim = a.arg.as_num(),
+                    _ if a.name == Some("x".to_owned()) => operands.push("x".to_owned()),
+                    TyTerm::TyExpr(box TyTerm::TyIdent(_, ref alias, _), ..) |
+                    TyTerm::TyIdent(_, ref alias, _) => operands.push(alias.as_str().to_owned()),
+                    ref t => operands.push(t.as_str().unwrap()),
+                }
+            }
+            write!(buf, "({},), dim={}", operands.join(", "), dim.unwrap()).unwrap();
+            Ok(buf)
+        }
+        _ => panic!("{} is not implemented", name),
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?(dim: int, *tensors: tsr0) -> tsr0"]
+pub struct cat;
+
+impl Resolve for cat {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_variadic(self.get_name(), arg_ty, &args, cat_shape),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for cat {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.cat"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_variadic(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?(dim: int, *tensors: tsr0) -> tsr0"]
+pub struct stack;
+
+impl Resolve for stack {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => resolve_variadic(self.get_name(), arg_ty, &args, stack_shape),
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for stack {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.stack"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        gen_variadic(name, args)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct transpose;
+
+impl Resolve for transpose {
+    /// swap axes `dim0` and `dim1`, the others stay in place
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?;
+                let mut dims = x_ty.as_vec()?;
+                let rank = dims.len() as i64;
+                let mut axes = vec![];
+                for name in &["dim0", "dim1"] {
+                    let arg = args.iter().find(|a| a.name == Some((*name).to_owned()))?;
+                    match to_axis(arg.arg.as_num()?, rank, arg.span) {
+                        Ok(axis) => axes.push(axis),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                dims.swap(axes[0], axes[1]);
+                let tags = Tags { layout: None, ..x_ty.tags() };
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, Type::TSR(dims, tags, x_ty.span()))))
+            }
+            _ => None,
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["dim0", "dim1"],
+            _ => &[],
+        }
+    }
+}
+
+impl PyTorch for transpose {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.transpose"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "x, {}, {}", map["dim0"].as_num().unwrap(), map["dim1"].as_num().unwrap()).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct permute;
+
+impl Resolve for permute {
+    /// axis `i` of the output is axis `dims[i]` of `x`, a declared layout
+    /// follows along when the order turns it into the other one
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?;
+                let dims = x_ty.as_vec()?;
+                let order_arg = args.iter().find(|a| a.name == Some("dims".to_owned()))?;
+                let order = read_ints(&order_arg.arg)?;
+                let mut sorted = order.clone();
+                sorted.sort();
+                if sorted != (0..dims.len() as i64).collect::<Vec<_>>() {
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("{:?} does not order each of the {} axes once", order, dims.len()),
+                        order_arg.span,
+                    )));
+                }
+                let permuted = order.iter().map(|i| dims[*i as usize].clone()).collect();
+                let tags = Tags { layout: x_ty.layout().and_then(|l| l.permuted(&order)), ..x_ty.tags() };
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, Type::TSR(permuted, tags, x_ty.span()))))
+            }
+            _ => None,
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["dims"],
+            _ => &[],
+        }
+    }
+}
+
+impl PyTorch for permute {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.Tensor.permute"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                let order: Vec<_> = read_ints(&map["dims"]).unwrap()
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect();
+                write!(buf, "x, {}", order.join(", ")).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+// identity on the value, cuts the gradient flowing back through it
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct detach;
+
+impl Resolve for detach {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, x_ty)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for detach {
+    fn pytorch_name(&self) -> &'static str {
+        "detach"
+    }
+
+    fn gen_fn_app(&self, name: &str, _args: &[TyFnAppArg]) -> Result<String, Diag> {
+        match name {
+            "forward" => Ok("x".to_owned()),
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+
+    fn is_method(&self) -> bool {
+        true
+    }
+}
+
+/// `x.half()` and the like, the same tensor holding another element type
+#[derive(Debug)]
+pub struct Cast {
+    name: &'static str,
+    precision: Precision,
+}
+
+impl Cast {
+    pub fn new(name: &'static str, precision: Precision) -> Self {
+        Self { name, precision }
+    }
+}
+
+impl Op for Cast {
+    fn get_name(&self) -> &'static str {
+        self.name
+    }
+    fn ty_sigs(&self, _tenv: &mut TypeEnv) -> Vec<(MethodName, Type)> {
+        vec![("forward", Type::UnresolvedModuleFun("prelude", self.name, "forward", CSpan::fresh_span()))]
+    }
+    fn is_stateful(&self) -> bool {
+        false
+    }
+}
+
+impl Resolve for Cast {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                x_ty.as_vec()?;
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, x_ty.with_precision(Some(self.precision)))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for Cast {
+    fn pytorch_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn gen_fn_app(&self, name: &str, _args: &[TyFnAppArg]) -> Result<String, Diag> {
+        match name {
+            "forward" => Ok("x".to_owned()),
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+
+    fn is_method(&self) -> bool {
+        true
+    }
+}
+
+/// size of axis `dim` of `x` as an int, its dim variable while it is symbolic.
+/// `Op` is not derived, a unit struct named `size` would shadow every
+/// `size` binding in this module
+#[derive(Debug)]
+pub struct Size;
+
+impl Op for Size {
+    fn get_name(&self) -> &'static str {
+        "size"
+    }
+    fn ty_sigs(&self, _tenv: &mut TypeEnv) -> Vec<(MethodName, Type)> {
+        vec![("forward", Type::UnresolvedModuleFun("prelude", "size", "forward", CSpan::fresh_span()))]
+    }
+    fn is_stateful(&self) -> bool {
+        false
+    }
+}
+
+impl Resolve for Size {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let dims = arg_ty.as_args_map()?.get("x")?.as_vec()?;
+                let dim_arg = args.iter().find(|a| a.name == Some("dim".to_owned()))?;
+                match to_axis(dim_arg.arg.as_num()?, dims.len() as i64, dim_arg.span) {
+                    Ok(axis) => Some(Ok(fun!(self.get_name(), "forward", arg_ty, dims[axis].clone()))),
+                    Err(e) => Some(Err(e)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["dim"],
+            _ => &[],
+        }
+    }
+}
+
+impl PyTorch for Size {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.Tensor.size"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "x, {}", map["dim"].as_num().unwrap()).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+// elementwise `min(max(x, min), max)`. The bounds are ordered, and ints
+// for a tensor of int64
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct clamp;
+
+impl Resolve for clamp {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let args_ty_map = arg_ty.as_args_map()?;
+                let x_ty = args_ty_map.get("x")?.clone();
+                x_ty.as_vec()?;
+                if x_ty.precision() == Some(Precision::I64) {
+                    for bound in &["min", "max"] {
+                        if let Some(&Type::FLOAT(sp)) = args_ty_map.get(*bound) {
+                            let span = args.iter()
+                                .find(|a| a.name.as_ref().map(|n| n.as_str()) == Some(*bound))
+                                .map_or(sp, |a| a.span);
+                            return Some(Err(Diag::InvalidArgument(
+                                format!("`{}` must be an int to clamp a tensor of int64, got a float", bound),
+                                span,
+                            )));
+                        }
+                    }
+                }
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, x_ty)))
+            }
+            _ => None,
+        }
+    }
+
+    fn validate(&self, fn_name: &str, args: &[TyFnAppArg]) -> Result<(), Diag> {
+        match fn_name {
+            "forward" => expect_ordered(args, "min", "max"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PyTorch for clamp {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.clamp"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "x").unwrap();
+                for bound in &["min", "max"] {
+                    if let Some(v) = map.get(*bound) {
+                        write!(buf, ", {}={}", bound, v.as_str().unwrap()).unwrap();
+                    }
+                }
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+// divides `x` by its `p`-norm along axis `dim`, the shape is unchanged
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct normalize;
+
+impl Resolve for normalize {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                let rank = x_ty.as_vec()?.len() as i64;
+                let dim_arg = args.iter().find(|a| a.name == Some("dim".to_owned()))?;
+                match to_axis(dim_arg.arg.as_num()?, rank, dim_arg.span) {
+                    Ok(_) => Some(Ok(fun!(self.get_name(), "forward", arg_ty, x_ty))),
+                    Err(e) => Some(Err(e)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn defaults(&self, fn_name: &str) -> Vec<TyFnAppArg> {
+        match fn_name {
+            "forward" => vec![int_default("dim", 1), float_default("p", 2.)],
+            _ => vec![],
+        }
+    }
+
+    fn validate(&self, fn_name: &str, args: &[TyFnAppArg]) -> Result<(), Diag> {
+        match fn_name {
+            "forward" => expect_positive_float(args, "p"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PyTorch for normalize {
+    fn pytorch_name(&self) -> &'static str {
+        "F.normalize"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "x").unwrap();
+                for arg in &["p", "dim"] {
+                    if let Some(v) = map.get(*arg) {
+                        write!(buf, ", {}={}", arg, v.as_str().unwrap()).unwrap();
+                    }
+                }
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+// splits `x` into `chunks` tensors of equal size along axis `dim`
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct chunk;
+
+impl Resolve for chunk {
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                let mut dims = x_ty.as_vec()?;
+                let n_arg = args.iter().find(|a| a.name == Some("chunks".to_owned()))?;
+                let n = n_arg.arg.as_num()?;
+                if n <= 0 {
+                    return Some(Err(Diag::OutOfRange("chunks".to_owned(), n as f64, "positive".to_owned(), n_arg.span)));
+                }
+                let dim_arg = args.iter().find(|a| a.name == Some("dim".to_owned()))?;
+                let axis = match to_axis(dim_arg.arg.as_num()?, dims.len() as i64, dim_arg.span) {
+                    Ok(axis) => axis,
+                    Err(e) => return Some(Err(e)),
+                };
+                // a symbolic size is checked by the unifier once resolved
+                let len = dims[axis].clone();
+                match len.as_num() {
+                    Some(s) if s % n != 0 => return Some(Err(Diag::NotDivisible(len, n, n_arg.span))),
+                    Some(_) => (),
+                    None => tenv.require_divisible(&len, n, n_arg.span),
+                }
+                dims[axis] = Type::derive('/', len, Type::ResolvedDim(n, n_arg.span), n_arg.span);
+                let part = Type::TSR(dims, x_ty.tags(), x_ty.span());
+                let parts = Type::Tuple(vec![part; n as usize], n_arg.span);
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, parts)))
+            }
+            _ => None,
+        }
+    }
+
+    fn defaults(&self, fn_name: &str) -> Vec<TyFnAppArg> {
+        match fn_name {
+            "forward" => vec![int_default("dim", 0)],
+            _ => vec![],
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["chunks"],
+            _ => &[],
+        }
+    }
+}
+
+impl PyTorch for chunk {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.chunk"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "x, {}", map["chunks"].as_num().unwrap()).unwrap();
+                if let Some(dim) = map.get("dim") {
+                    write!(buf, ", dim={}", dim.as_num().unwrap()).unwrap();
+                }
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+// `x` of int64 class indices to a float tensor with a trailing axis of
+// `num_classes`. An `x` of undeclared precision is taken to hold ints
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct one_hot;
+
+impl Resolve for one_hot {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                let mut dims = x_ty.as_vec()?;
+                match x_ty.precision() {
+                    Some(Precision::I64) | None => (),
+                    Some(found) => return Some(Err(Diag::InvalidArgument(
+                        format!("one_hot expects a tensor of int64, found {}", found),
+                        x_ty.span(),
+                    ))),
+                }
+                let n_arg = args.iter().find(|a| a.name == Some("num_classes".to_owned()))?;
+                let n = n_arg.arg.as_num()?;
+                if n <= 0 {
+                    return Some(Err(Diag::OutOfRange("num_classes".to_owned(), n as f64, "positive".to_owned(), n_arg.span)));
+                }
+                dims.push(Type::ResolvedDim(n, n_arg.span));
+                let tags = Tags { layout: None, precision: Some(Precision::F32) };
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, Type::TSR(dims, tags, x_ty.span()))))
+            }
+            _ => None,
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["num_classes"],
+            _ => &[],
+        }
+    }
+}
+
+impl PyTorch for one_hot {
+    /// `F.one_hot` returns int64, the call is cast with the method `float`
+    fn pytorch_name(&self) -> &'static str {
+        "float"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                Ok(format!("F.one_hot(x, num_classes={})", map["num_classes"].as_num().unwrap()))
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+
+    fn is_method(&self) -> bool {
+        true
+    }
+}
+
+// repeats each element of `x` `repeats` times along axis `dim`, unlike
+// `repeat` which tiles the whole tensor
+#[allow(non_camel_case_types)]
+#[derive(Debug, Op)]
+#[path = "prelude"]
+#[forward = "?() -> unit"]
+pub struct repeat_interleave;
+
+impl Resolve for repeat_interleave {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        args: Vec<TyFnAppArg>,
+        _inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                let mut dims = x_ty.as_vec()?;
+                let r_arg = args.iter().find(|a| a.name == Some("repeats".to_owned()))?;
+                let repeats = r_arg.arg.as_num()?;
+                if repeats <= 0 {
+                    return Some(Err(Diag::OutOfRange("repeats".to_owned(), repeats as f64, "positive".to_owned(), r_arg.span)));
+                }
+                let dim_arg = args.iter().find(|a| a.name == Some("dim".to_owned()))?;
+                let axis = match to_axis(dim_arg.arg.as_num()?, dims.len() as i64, dim_arg.span) {
+                    Ok(axis) => axis,
+                    Err(e) => return Some(Err(e)),
+                };
+                let len = dims[axis].clone();
+                dims[axis] = Type::derive('*', len, Type::ResolvedDim(repeats, r_arg.span), r_arg.span);
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, Type::TSR(dims, x_ty.tags(), x_ty.span()))))
+            }
+            _ => None,
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "forward" => &["repeats", "dim"],
+            _ => &[],
+        }
+    }
+}
+
+impl PyTorch for repeat_interleave {
+    fn pytorch_name(&self) -> &'static str {
+        "torch.repeat_interleave"
+    }
+
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "forward" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "x, {}, dim={}", map["repeats"].as_num().unwrap(), map["dim"].as_num().unwrap()).unwrap();
+                Ok(buf)
+            }
+            _ => panic!("{} is not implemented", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use codespan::{ByteIndex, Span};
+    use core::test_args::{float_arg, int_arg, tsr_arg};
+
+    #[test]
+    fn should_clamp_to_ordered_bounds() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(10)]);
+        let arg_ty = args!(arg!("x", x.clone()));
+        match clamp.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![], None) {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => assert_eq!(r, x),
+            ty => panic!("{:?}", ty),
+        }
+
+        let bound = |name: &str, v: f64, sp| TyFnAppArg {
+            name: Some(name.to_owned()),
+            arg: box TyTerm::TyFloat(float!(), v, sp),
+            span: sp,
+        };
+        let sp = Span::new(ByteIndex(5), ByteIndex(12));
+        assert!(clamp.validate("forward", &[bound("min", 0., sp), bound("max", 6., sp)]).is_ok());
+        match clamp.validate("forward", &[bound("min", 1., sp), bound("max", -1., sp)]) {
+            Err(Diag::InvalidArgument(msg, span)) => {
+                assert!(msg.contains("1 > -1"), "{}", msg);
+                assert_eq!(span, sp);
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn should_clamp_int_tensor_to_int_bounds() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None)]).with_precision(Some(Precision::I64));
+        let sp = Span::new(ByteIndex(5), ByteIndex(12));
+        let min = TyFnAppArg { span: sp, ..float_arg("min", 0.5) };
+        let arg_ty = args!(arg!("x", x.clone()), arg!("min", float!()));
+        match clamp.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![min], None) {
+            Some(Err(Diag::InvalidArgument(msg, span))) => {
+                assert!(msg.contains("`min` must be an int"), "{}", msg);
+                assert_eq!(span, sp);
+            }
+            ty => panic!("{:?}", ty),
+        }
+
+        let arg_ty = args!(arg!("x", x.clone()), arg!("min", int!()));
+        match clamp.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![int_arg("min", 0)], None) {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => assert_eq!(r.precision(), Some(Precision::I64)),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_normalize_along_axis_in_range() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(128)]);
+        let arg_ty = args!(arg!("x", x.clone()));
+        let ty = normalize.resolve(&mut tenv, "forward", arg_ty.clone(), unit!(), vec![int_arg("dim", -1)], None);
+        match ty {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => assert_eq!(r, x),
+            ty => panic!("{:?}", ty),
+        }
+
+        let sp = Span::new(ByteIndex(3), ByteIndex(8));
+        let axis = TyFnAppArg {
+            name: Some("dim".to_owned()),
+            arg: box TyTerm::TyInteger(int!(), 2, sp),
+            span: sp,
+        };
+        match normalize.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![axis], None) {
+            Some(Err(Diag::InvalidArgument(msg, span))) => {
+                assert_eq!(msg, "dim 2 is out of range for 2 axes");
+                assert_eq!(span, sp);
+            }
+            ty => panic!("{:?}", ty),
+        }
+        let p = |v| TyFnAppArg {
+            name: Some("p".to_owned()),
+            arg: box TyTerm::TyFloat(float!(), v, sp),
+            span: sp,
+        };
+        assert!(normalize.validate("forward", &[p(2.)]).is_ok());
+        assert!(normalize.validate("forward", &[p(0.)]).is_err());
+    }
+
+    #[test]
+    fn should_chunk_into_equal_parts() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let n = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let x = tsr!(vec![n.clone(), dim(8)]);
+        let arg_ty = args!(arg!("x", x));
+        let args = vec![int_arg("chunks", 2), int_arg("dim", 1)];
+        match chunk.resolve(&mut tenv, "forward", arg_ty.clone(), unit!(), args, None) {
+            Some(Ok(Type::FUN(_, _, _, box Type::Tuple(parts, _), _))) => {
+                assert_eq!(parts, vec![tsr!(vec![n.clone(), dim(4)]), tsr!(vec![n.clone(), dim(4)])]);
+            }
+            ty => panic!("{:?}", ty),
+        }
+
+        let args = vec![int_arg("chunks", 3), int_arg("dim", 1)];
+        match chunk.resolve(&mut tenv, "forward", arg_ty, unit!(), args, None) {
+            Some(Err(Diag::NotDivisible(len, 3, _))) => assert_eq!(len.as_num(), Some(8)),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_pad_resolved_spatial_dims() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(3), dim(8), dim(8)]);
+        let args = vec![int_arg("left", 1), int_arg("right", 1), int_arg("top", 1), int_arg("bottom", 1)];
+        let arg_ty = args!(arg!("x", x.clone()));
+        let ty = pad.resolve(&mut tenv, "forward", arg_ty, unit!(), args, None).unwrap().unwrap();
+        match ty {
+            Type::FUN(_, _, _, box Type::TSR(dims, _, _), _) => {
+                assert_eq!(dims[1].as_num(), Some(3));
+                assert_eq!(dims[2].as_num(), Some(10));
+                assert_eq!(dims[3].as_num(), Some(10));
+            }
+            _ => panic!("{:?}", ty),
+        }
+    }
+
+    fn last_timestep(index: i64) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(5), dim(8)]);
+        let args = vec![int_arg("dim", 1), int_arg("index", index)];
+        slice.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), args, None)
+    }
+
+    #[test]
+    fn should_drop_indexed_axis() {
+        match last_timestep(-1) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims.len(), 2);
+                assert_eq!(dims[0].as_num(), None);
+                assert_eq!(dims[1].as_num(), Some(8));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_size_range_of_symbolic_dim_only_from_the_end() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = CSpan::fresh_span();
+        let t = tenv.fresh_dim(sp, None);
+        let range = |start, end| vec![AxisSpec::Range(Some(start), Some(end))];
+        let last_two = slice_shape(&mut tenv, &[t.clone()], &range(-3, -1), sp).unwrap();
+        assert_eq!(last_two[0].as_num(), Some(2));
+        // `t` may be shorter than 4, the range is clipped then
+        let first_four = slice_shape(&mut tenv, &[t.clone()], &range(0, 4), sp).unwrap();
+        assert_eq!(first_four[0].as_num(), None);
+        assert!(first_four[0] != t);
+    }
+
+    #[test]
+    fn should_reject_out_of_range_index() {
+        match last_timestep(5) {
+            Some(Err(Diag::InvalidArgument(..))) => (),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    fn counts_arg(counts: &[i64]) -> TyFnAppArg {
+        let sp = CSpan::fresh_span();
+        let items = counts.iter().map(|c| TyTerm::TyInteger(int!(), *c, sp)).collect();
+        TyFnAppArg {
+            name: Some("counts".to_owned()),
+            arg: box TyTerm::TyTuple(Type::Tuple(vec![int!(); counts.len()], sp), items, sp),
+            span: sp,
+        }
+    }
+
+    #[test]
+    fn should_repeat_each_axis() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![dim(1), dim(3)]);
+        let ty = repeat.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![counts_arg(&[4, 1])], None);
+        match ty {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims[0].as_num(), Some(4));
+                assert_eq!(dims[1].as_num(), Some(3));
+            }
+            _ => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reject_counts_of_wrong_rank() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let x = tsr!(vec![Type::ResolvedDim(1, CSpan::fresh_span()), Type::ResolvedDim(3, CSpan::fresh_span())]);
+        let ty = repeat.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![counts_arg(&[4, 1, 1])], None);
+        match ty {
+            Some(Err(Diag::InvalidArgument(..))) => (),
+            _ => panic!("{:?}", ty),
+        }
+    }
+
+    fn mean_of(keepdim: Option<bool>) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(4), dim(16)]);
+        let mut args = vec![int_arg("dim", 1)];
+        if let Some(b) = keepdim {
+            args.push(TyFnAppArg {
+                name: Some("keepdim".to_owned()),
+                arg: box TyTerm::TyBool(Type::BOOL(CSpan::fresh_span()), b, CSpan::fresh_span()),
+                span: CSpan::fresh_span(),
+            });
+        }
+        mean.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), args, None)
+    }
+
+    #[test]
+    fn should_drop_reduced_axis() {
+        match mean_of(None) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims.len(), 2);
+                assert_eq!(dims[0].as_num(), None);
+                assert_eq!(dims[1].as_num(), Some(16));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_keep_reduced_axis_as_one() {
+        match mean_of(Some(true)) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims.len(), 3);
+                assert_eq!(dims[1].as_num(), Some(1));
+                assert_eq!(dims[2].as_num(), Some(16));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_keep_precision_of_reduced_tensor() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let x = tsr!(vec![Type::ResolvedDim(2, CSpan::fresh_span()), Type::ResolvedDim(8, CSpan::fresh_span())])
+            .with_precision(Some(Precision::F16));
+        match sum.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![int_arg("dim", 1)], None) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => assert_eq!(ret.precision(), Some(Precision::F16)),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reject_out_of_range_reduce_axis() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let x = tsr!(vec![Type::ResolvedDim(2, CSpan::fresh_span())]);
+        let span = Span::new(ByteIndex(3), ByteIndex(8));
+        let dim = TyFnAppArg { span, ..int_arg("dim", 1) };
+        match sum.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![dim], None) {
+            Some(Err(Diag::InvalidArgument(_, sp))) => assert_eq!(sp, span),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_sum_vector_to_scalar() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let x = tsr!(vec![Type::ResolvedDim(8, CSpan::fresh_span())]);
+        let scalar = match sum.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![int_arg("dim", 0)], None) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => ret,
+            ty => panic!("{:?}", ty),
+        };
+        assert_eq!(scalar.as_rank(), 0);
+        assert!(scalar.is_resolved());
+        assert_eq!(format!("{:?}", scalar), "[]");
+        assert_eq!(scalar.as_string(), "");
+
+        let arg_ty = args!(arg!("x", scalar.clone()));
+        match sum.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![int_arg("dim", -1)], None) {
+            Some(Ok(Type::FUN(_, _, box p, box r, _))) => {
+                assert_eq!(p.first_arg_ty(), Some(scalar));
+                assert_eq!(r.as_rank(), 0);
+            }
+            ty => panic!("{:?}", ty),
+        }
+        assert_eq!(args!().first_arg_ty(), None);
+    }
+
+    fn call_variadic(tenv: &mut TypeEnv, op: &'static str, dim: i64, tsrs: &[(&str, Type)]) -> Result<Option<(Type, bool)>, Diag> {
+        let mut args = vec![int_arg("dim", dim)];
+        let mut arg_tys = vec![arg!("dim", int!())];
+        for (name, ty) in tsrs.iter() {
+            args.push(tsr_arg(name, ty));
+            arg_tys.push(arg!(*name, ty.clone()));
+        }
+        let ty = Type::UnresolvedModuleFun("prelude", op, "forward", CSpan::fresh_span());
+        tenv.resolve_unresolved(&ty, "forward", Type::FnArgs(arg_tys, CSpan::fresh_span()), unit!(), args, None)
+    }
+
+    #[test]
+    fn should_type_check_every_concatenated_tensor() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let batch = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let hidden = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let tsrs = vec![
+            ("x", tsr!(vec![batch, dim(3)])),
+            ("y", tsr!(vec![dim(4), dim(5)])),
+            ("z", tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), hidden])),
+        ];
+        let ty = call_variadic(&mut tenv, "cat", 1, &tsrs).unwrap().unwrap().0;
+        match ty {
+            Type::FUN(_, _, box Type::FnArgs(vs, _), box Type::TSR(dims, _, _), _) => {
+                assert_eq!(dims[0].as_num(), Some(4));
+                assert_eq!(dims[1].as_num(), None);
+                let names: Vec<_> = vs.iter().filter_map(|v| match v {
+                    Type::FnArg(Some(name), ..) => Some(name.as_str()),
+                    _ => None,
+                }).collect();
+                assert_eq!(names, vec!["dim", "x", "y", "z"]);
+                let operands: Vec<_> = vs.iter().filter_map(|v| match v {
+                    Type::FnArg(_, box Type::TSR(dims, _, _), _) => Some(dims.clone()),
+                    _ => None,
+                }).collect();
+                assert_eq!(operands.len(), 3);
+                assert!(operands.iter().all(|dims| dims[0].as_num() == Some(4)));
+                assert_eq!(operands[0][1].as_num(), Some(3));
+                assert_eq!(operands[1][1].as_num(), Some(5));
+            }
+            ty => panic!("{:?}", ty),
+        }
+
+        let mismatched = vec![
+            ("x", tsr!(vec![dim(2), dim(3)])),
+            ("y", tsr!(vec![dim(4), dim(5)])),
+        ];
+        match call_variadic(&mut tenv, "cat", 1, &mismatched) {
+            Err(Diag::DimensionMismatch(..)) => (),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_require_a_variadic_argument() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        match call_variadic(&mut tenv, "cat", 1, &[]) {
+            Err(Diag::InvalidArgument(..)) => (),
+            ty => panic!("{:?}", ty),
+        }
+        // every packed argument is a tensor
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        match call_variadic(&mut tenv, "cat", 1, &[("x", tsr!(vec![dim(4), dim(3)])), ("y", float!())]) {
+            Err(Diag::InvalidArgument(msg, _)) => assert!(msg.contains("`*tensors`"), "{}", msg),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_stack_on_a_new_axis() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let c = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let tsrs = vec![
+            ("x", tsr!(vec![c.clone(), dim(5)])),
+            ("y", tsr!(vec![c.clone(), dim(5)])),
+            ("z", tsr!(vec![c.clone(), tenv.fresh_dim(CSpan::fresh_span(), None)])),
+        ];
+        match call_variadic(&mut tenv, "stack", 0, &tsrs).unwrap().unwrap().0 {
+            Type::FUN(_, _, box Type::FnArgs(vs, _), box Type::TSR(dims, _, _), _) => {
+                assert_eq!(dims, vec![dim(3), c.clone(), dim(5)]);
+                assert!(vs.iter().all(|v| match v {
+                    Type::FnArg(_, box Type::TSR(dims, _, _), _) => dims[1].as_num() == Some(5),
+                    _ => true,
+                }));
+            }
+            ty => panic!("{:?}", ty),
+        }
+
+        let span = Span::new(ByteIndex(10), ByteIndex(12));
+        let divergent = vec![
+            ("x", tsr!(vec![c.clone(), dim(5)])),
+            ("y", tsr!(vec![c.clone(), dim(5)])),
+            ("z", Type::TSR(vec![c.clone(), dim(6)], Tags::default(), span)),
+        ];
+        match call_variadic(&mut tenv, "stack", 0, &divergent) {
+            Err(Diag::DimensionMismatch(_, d)) => assert_eq!(d.span(), span),
+            ty => panic!("{:?}", ty),
+        }
+
+        let single = vec![("x", tsr!(vec![c, dim(5)]))];
+        match call_variadic(&mut tenv, "stack", 0, &single) {
+            Ok(Some((Type::FUN(_, _, _, box Type::TSR(dims, _, _), _), _))) => assert_eq!(dims[0].as_num(), Some(1)),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    fn transpose_ncl(dim0: i64, dim1: i64) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(4), dim(16)]);
+        let mut dim1 = int_arg("dim1", dim1);
+        dim1.span = Span::new(ByteIndex(3), ByteIndex(8));
+        let args = vec![int_arg("dim0", dim0), dim1];
+        transpose.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), args, None)
+    }
+
+    #[test]
+    fn should_swap_two_axes() {
+        match transpose_ncl(1, 2) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                let dims: Vec<_> = dims.iter().map(|d| d.as_num()).collect();
+                assert_eq!(dims, vec![None, Some(16), Some(4)]);
+            }
+            ty => panic!("{:?}", ty),
+        }
+        match transpose_ncl(1, 3) {
+            Some(Err(Diag::InvalidArgument(_, sp))) => assert_eq!(sp, Span::new(ByteIndex(3), ByteIndex(8))),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reduce_class_axis_of_argmax() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let n = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let x = tsr!(vec![n.clone(), Type::ResolvedDim(10, CSpan::fresh_span())]);
+        match argmax.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![int_arg("dim", 1)], None) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, tags, _), _))) => {
+                assert_eq!(dims, vec![n]);
+                assert_eq!(tags.precision, Some(Precision::I64));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_keep_shape_of_detached_tensor() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let n = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let x = tsr!(vec![n, Type::ResolvedDim(10, CSpan::fresh_span())]);
+        match detach.resolve(&mut tenv, "forward", args!(arg!("x", x.clone())), unit!(), vec![], None) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => assert_eq!(ret, x),
+            ty => panic!("{:?}", ty),
+        }
+        assert!(detach.is_method());
+    }
+
+    #[test]
+    fn should_tag_precision_of_cast() {
+        use typing::type_env::ModName;
+
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = Span::new(ByteIndex(1), ByteIndex(4));
+        let sig: Vec<_> = ["?", "10", "@f32"].iter().map(|t| t.to_string()).collect();
+        let x = tenv.create_tensor(&ModName::Global, &sig, &sp);
+        assert_eq!((x.as_rank(), x.precision()), (2, Some(Precision::F32)));
+
+        let half = Cast::new("half", Precision::F16);
+        let h = match half.resolve(&mut tenv, "forward", args!(arg!("x", x.clone())), unit!(), vec![], None) {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => r,
+            ty => panic!("{:?}", ty),
+        };
+        assert_eq!(h, x);
+        assert_eq!(h.precision(), Some(Precision::F16));
+        assert_eq!(format!("{:?}", h), format!("{:?} @f16", tsr!(x.as_vec().unwrap())));
+        assert!(half.is_method());
+    }
+
+    #[test]
+    fn should_read_size_of_axis_into_later_op() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let n = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let c = Type::ResolvedDim(16, CSpan::fresh_span());
+        let x = tsr!(vec![n.clone(), c.clone()]);
+        let batch = match Size.resolve(&mut tenv, "forward", args!(arg!("x", x.clone())), unit!(), vec![int_arg("dim", 0)], None) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => ret,
+            ty => panic!("{:?}", ty),
+        };
+        assert_eq!(batch, n);
+        match Size.resolve(&mut tenv, "forward", args!(arg!("x", x.clone())), unit!(), vec![int_arg("dim", -1)], None) {
+            Some(Ok(Type::FUN(_, _, _, box ret, _))) => assert_eq!(ret.as_num(), Some(16)),
+            ty => panic!("{:?}", ty),
+        }
+        match Size.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![int_arg("dim", 2)], None) {
+            Some(Err(Diag::InvalidArgument(..))) => (),
+            ty => panic!("{:?}", ty),
+        }
+        // a `[C, N]` tensor built from the size, transposed back to `[N, C]`
+        let y = tsr!(vec![c, batch]);
+        let args = vec![int_arg("dim0", 0), int_arg("dim1", 1)];
+        match transpose.resolve(&mut tenv, "forward", args!(arg!("x", y)), unit!(), args, None) {
+            Some(Ok(Type::FUN(_, _, _, box Type::TSR(dims, _, _), _))) => {
+                assert_eq!(dims[0], n);
+                assert_eq!(dims[1].as_num(), Some(16));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_one_hot_encode_int_labels() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let n = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let labels = tsr!(vec![n.clone()]).with_precision(Some(Precision::I64));
+        let ty = one_hot.resolve(&mut tenv, "forward", args!(arg!("x", labels)), unit!(), vec![int_arg("num_classes", 10)], None);
+        match ty {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => {
+                assert_eq!(r, tsr!(vec![n.clone(), Type::ResolvedDim(10, CSpan::fresh_span())]));
+                assert_eq!(r.precision(), Some(Precision::F32));
+            }
+            ty => panic!("{:?}", ty),
+        }
+
+        let sp = Span::new(ByteIndex(3), ByteIndex(8));
+        let x = Type::TSR(vec![n], Tags { layout: None, precision: Some(Precision::F32) }, sp);
+        match one_hot.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![int_arg("num_classes", 10)], None) {
+            Some(Err(Diag::InvalidArgument(msg, span))) => {
+                assert_eq!(msg, "one_hot expects a tensor of int64, found float32");
+                assert_eq!(span, sp);
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_interleave_a_single_axis() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let n = tenv.fresh_dim(CSpan::fresh_span(), None);
+        let arg_ty = args!(arg!("x", tsr!(vec![n.clone(), dim(3)])));
+        let args = vec![int_arg("repeats", 2), int_arg("dim", 1)];
+        match repeat_interleave.resolve(&mut tenv, "forward", arg_ty.clone(), unit!(), args, None) {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => assert_eq!(r, tsr!(vec![n.clone(), dim(6)])),
+            ty => panic!("{:?}", ty),
+        }
+
+        for &repeats in &[0, -2] {
+            let args = vec![int_arg("repeats", repeats), int_arg("dim", 1)];
+            match repeat_interleave.resolve(&mut tenv, "forward", arg_ty.clone(), unit!(), args, None) {
+                Some(Err(Diag::OutOfRange(ref name, v, _, _))) => assert_eq!((name.as_str(), v), ("repeats", repeats as f64)),
+                ty => panic!("{:?}", ty),
+            }
+        }
+    }
+}
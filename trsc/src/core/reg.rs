@@ -1,8 +1,9 @@
-use core::{MethodName, Op, PyTorch, Resolve};
+use core::{expect_positive_float, expect_probability, expect_range, float_default, read_ints, MethodName, Op, PyTorch, Resolve};
 use errors::Diag;
 use span::CSpan;
 use typing::typed_term::TyFnAppArg;
 use typing::{Type, TypeEnv};
+use typing::types::{Precision, Tags};
 use typing::typed_term::ArgsVecInto;
 use std::fmt::Write;
 
@@ -13,21 +14,36 @@ use std::fmt::Write;
 pub struct Dropout2d;
 
 impl Resolve for Dropout2d {
+    /// zeroes whole channels, so the input must be an image batch
     fn resolve(
         &self,
-        tenv: &mut TypeEnv,
+        _tenv: &mut TypeEnv,
         fn_name: &str,
-        _arg_ty: Type,
+        arg_ty: Type,
         _ret_ty: Type,
         _args: Vec<TyFnAppArg>,
         _inits: Option<Vec<TyFnAppArg>>,
     ) -> Option<Result<Type, Diag>> {
         match fn_name {
             "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
-                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone())), ty)))
+                let x_ty = arg_ty.first_arg_ty()?;
+                let rank = x_ty.as_vec()?.len();
+                if rank != 4 {
+                    return Some(Err(Diag::InvalidArgument(
+                        format!("Dropout2d expects a tensor of rank 4, got rank {}", rank),
+                        x_ty.span(),
+                    )));
+                }
+                Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", x_ty.clone())), x_ty)))
             }
-            _ => unimplemented!(),
+            _ => None,
+        }
+    }
+
+    fn validate(&self, fn_name: &str, args: &[TyFnAppArg]) -> Result<(), Diag> {
+        match fn_name {
+            "new" => expect_probability(args, "p"),
+            _ => Ok(()),
         }
     }
 }
@@ -42,7 +58,7 @@ impl PyTorch for Dropout2d {
         let mut buf = String::new();
         match name {
             "new" => {
-                write!(buf, "{}(", self.get_name()).unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
                 let map = args.to_btreemap().unwrap();
                 write!(buf, "p={})", map["p"].as_str().unwrap()).unwrap();
                 Ok(buf)
@@ -59,7 +75,7 @@ impl PyTorch for Dropout2d {
 
 #[derive(Debug, Op)]
 #[path = "reg"]
-#[new = "(num_features: int) -> self"]
+#[new = "?() -> unit"]
 #[forward = "?(x: tsr0) -> tsr0"]
 #[stateful]
 pub struct BatchNorm1d;
@@ -69,17 +85,63 @@ impl Resolve for BatchNorm1d {
         &self,
         tenv: &mut TypeEnv,
         fn_name: &str,
-        _arg_ty: Type,
+        arg_ty: Type,
         _ret_ty: Type,
         _args: Vec<TyFnAppArg>,
         _inits: Option<Vec<TyFnAppArg>>,
     ) -> Option<Result<Type, Diag>> {
         match fn_name {
             "forward" => {
-                let ty = tenv.fresh_var(CSpan::fresh_span());
+                let ty = tenv.fresh_var(CSpan::fresh_span(), Some(&format!("{}.x", self.get_name())));
                 Some(Ok(fun!(self.get_name(), "forward", args!(arg!("x", ty.clone())), ty)))
             }
-            _ => unimplemented!(),
+            "new" => {
+                // optional args are typed as supplied
+                let optional = arg_ty.as_args_map()?
+                    .into_iter()
+                    .filter(|(name, _)| self.defaults("new").iter().any(|d| d.name.as_ref() == Some(name)))
+                    .map(|(name, ty)| Type::FnArg(Some(name), box ty, CSpan::fresh_span()));
+                let required = vec![arg!("num_features", int!())];
+                Some(Ok(fun!(
+                    self.get_name(),
+                    "new",
+                    Type::FnArgs(required.into_iter().chain(optional).collect(), CSpan::fresh_span()),
+                    module!(self.get_name())
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    fn defaults(&self, fn_name: &str) -> Vec<TyFnAppArg> {
+        match fn_name {
+            "new" => vec![float_default("eps", 1e-5), float_default("momentum", 0.1)],
+            _ => vec![],
+        }
+    }
+
+    fn required(&self, fn_name: &str) -> &'static [&'static str] {
+        match fn_name {
+            "new" => &["num_features"],
+            _ => &[],
+        }
+    }
+
+    /// the batch statistics lose too much in half precision
+    fn precision(&self, fn_name: &str) -> Option<Precision> {
+        match fn_name {
+            "forward" => Some(Precision::F32),
+            _ => None,
+        }
+    }
+
+    fn validate(&self, fn_name: &str, args: &[TyFnAppArg]) -> Result<(), Diag> {
+        match fn_name {
+            "new" => {
+                expect_positive_float(args, "eps")?;
+                expect_range(args, "momentum", 0., 1.)
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -88,14 +150,30 @@ impl PyTorch for BatchNorm1d {
     fn pytorch_name(&self) -> &'static str {
         "nn.BatchNorm1d"
     }
+    /// the running statistics are buffers but are saved all the same
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let n = find_init(inits, "num_features")?.arg.as_num()?;
+        Some(vec![
+            ("weight".to_owned(), vec![n]),
+            ("bias".to_owned(), vec![n]),
+            ("running_mean".to_owned(), vec![n]),
+            ("running_var".to_owned(), vec![n]),
+            ("num_batches_tracked".to_owned(), vec![]),
+        ])
+    }
     fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
         let mut buf = String::new();
         match name {
             "new" => {
                 let map = args.to_btreemap().unwrap();
                 write!(buf, "{}(", self.pytorch_name()).unwrap();
-                write!(buf, "num_features={})",
-                    map["num_features"].as_num().unwrap()).unwrap();
+                write!(buf, "num_features={}", map["num_features"].as_num().unwrap()).unwrap();
+                for opt in &["eps", "momentum"] {
+                    if let Some(v) = map.get(*opt).and_then(|v| v.as_str()) {
+                        write!(buf, ", {}={}", opt, v).unwrap();
+                    }
+                }
+                write!(buf, ")").unwrap();
             }
             "forward" => {
                 // let map = args.to_btreemap().unwrap();
@@ -107,3 +185,395 @@ impl PyTorch for BatchNorm1d {
         Ok(buf)
     }
 }
+
+#[derive(Debug, Op)]
+#[path = "reg"]
+#[new = "?() -> unit"]
+#[forward = "?(x: tsr0) -> tsr0"]
+#[stateful]
+pub struct LayerNorm;
+
+impl Resolve for LayerNorm {
+    /// shape preserving, trailing dims must equal `normalized_shape`
+    fn resolve(
+        &self,
+        tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+                let dims = x_ty.as_vec()?;
+                let init = inits?
+                    .into_iter()
+                    .find(|a| a.name == Some("normalized_shape".to_owned()))?;
+                let shape = read_ints(&init.arg)?;
+                if shape.len() > dims.len() {
+                    let shape_ty = Type::TSR(
+                        shape.iter().map(|n| Type::ResolvedDim(*n, init.span)).collect(),
+                        Tags::default(),
+                        init.span,
+                    );
+                    return Some(Err(Diag::RankMismatch(x_ty, shape_ty)));
+                }
+                let trailing = &dims[dims.len() - shape.len()..];
+                for (dim, n) in trailing.iter().zip(shape.iter()) {
+                    match dim.as_num() {
+                        Some(m) if m != *n => return Some(Err(Diag::DimensionMismatch(
+                            dim.with_span(&x_ty.span()),
+                            Type::ResolvedDim(*n, init.span),
+                        ))),
+                        _ => (),
+                    }
+                }
+                Some(Ok(fun!(self.get_name(), "forward", arg_ty, x_ty)))
+            }
+            "new" => {
+                Some(Ok(fun!(
+                    self.get_name(),
+                    "new",
+                    args!(arg!("normalized_shape", tenv.fresh_var(CSpan::fresh_span(), Some(&format!("{}.normalized_shape", self.get_name()))))),
+                    module!(self.get_name())
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for LayerNorm {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.LayerNorm"
+    }
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let shape = read_ints(&find_init(inits, "normalized_shape")?.arg)?;
+        Some(vec![
+            ("weight".to_owned(), shape.clone()),
+            ("bias".to_owned(), shape),
+        ])
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "normalized_shape={})",
+                    map["normalized_shape"].as_str().unwrap()).unwrap();
+            }
+            "forward" => {
+                write!(buf, "x").unwrap();
+            }
+            _ => unimplemented!(),
+        }
+
+        Ok(buf)
+    }
+}
+
+/// `num_channels` must split evenly into `num_groups` and equal the
+/// channel dim of `x`, which is `[N, C, ...]`. The shape is unchanged.
+fn resolve_group_norm(
+    name: &str,
+    arg_ty: Type,
+    groups: &TyFnAppArg,
+    channels: &TyFnAppArg,
+) -> Option<Result<Type, Diag>> {
+    let x_ty = arg_ty.as_args_map()?.get("x")?.clone();
+    let dims = x_ty.as_vec()?;
+    let num_groups = groups.arg.as_num()?;
+    let num_channels = channels.arg.as_num()?;
+    if num_groups <= 0 || num_channels % num_groups != 0 {
+        return Some(Err(Diag::InvalidArgument(
+            format!("{} channels cannot be split into {} groups", num_channels, num_groups),
+            groups.span,
+        )));
+    }
+    if dims.len() < 2 {
+        return Some(Err(Diag::InvalidArgument(
+            format!("{} expects input of shape [N, C, ...], got {:?}", name, x_ty),
+            x_ty.span(),
+        )));
+    }
+    match dims[1].as_num() {
+        Some(c) if c != num_channels => Some(Err(Diag::DimensionMismatch(
+            dims[1].with_span(&x_ty.span()),
+            Type::ResolvedDim(num_channels, channels.span),
+        ))),
+        _ => Some(Ok(fun!(name, "forward", arg_ty, x_ty))),
+    }
+}
+
+fn find_init<'a>(inits: &'a [TyFnAppArg], name: &str) -> Option<&'a TyFnAppArg> {
+    inits.iter().find(|a| a.name == Some(name.to_owned()))
+}
+
+#[derive(Debug, Op)]
+#[path = "reg"]
+#[new = "(num_groups: int, num_channels: int) -> self"]
+#[forward = "?(x: tsr0) -> tsr0"]
+#[stateful]
+pub struct GroupNorm;
+
+impl Resolve for GroupNorm {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let inits = inits?;
+                let groups = find_init(&inits, "num_groups")?;
+                let channels = find_init(&inits, "num_channels")?;
+                resolve_group_norm(self.get_name(), arg_ty, groups, channels)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for GroupNorm {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.GroupNorm"
+    }
+    fn parameters(&self, inits: &[TyFnAppArg]) -> Option<Vec<(String, Vec<i64>)>> {
+        let c = find_init(inits, "num_channels")?.arg.as_num()?;
+        Some(vec![
+            ("weight".to_owned(), vec![c]),
+            ("bias".to_owned(), vec![c]),
+        ])
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "num_groups={}, num_channels={})",
+                    map["num_groups"].as_num().unwrap(),
+                    map["num_channels"].as_num().unwrap()).unwrap();
+            }
+            "forward" => {
+                write!(buf, "x").unwrap();
+            }
+            _ => unimplemented!(),
+        }
+
+        Ok(buf)
+    }
+}
+
+// GroupNorm with one group per channel
+#[derive(Debug, Op)]
+#[path = "reg"]
+#[new = "(num_features: int) -> self"]
+#[forward = "?(x: tsr0) -> tsr0"]
+#[stateful]
+pub struct InstanceNorm2d;
+
+impl Resolve for InstanceNorm2d {
+    fn resolve(
+        &self,
+        _tenv: &mut TypeEnv,
+        fn_name: &str,
+        arg_ty: Type,
+        _ret_ty: Type,
+        _args: Vec<TyFnAppArg>,
+        inits: Option<Vec<TyFnAppArg>>,
+    ) -> Option<Result<Type, Diag>> {
+        match fn_name {
+            "forward" => {
+                let inits = inits?;
+                let features = find_init(&inits, "num_features")?;
+                resolve_group_norm(self.get_name(), arg_ty, features, features)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PyTorch for InstanceNorm2d {
+    fn pytorch_name(&self) -> &'static str {
+        "nn.InstanceNorm2d"
+    }
+    fn gen_fn_app(&self, name: &str, args: &[TyFnAppArg]) -> Result<String, Diag> {
+        let mut buf = String::new();
+        match name {
+            "new" => {
+                let map = args.to_btreemap().unwrap();
+                write!(buf, "{}(", self.pytorch_name()).unwrap();
+                write!(buf, "num_features={})", map["num_features"].as_num().unwrap()).unwrap();
+            }
+            "forward" => {
+                write!(buf, "x").unwrap();
+            }
+            _ => unimplemented!(),
+        }
+
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use typing::typed_term::TyTerm;
+    use codespan::{ByteIndex, ByteSpan};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use core::test_args::{float_arg, int_arg};
+
+    fn shape_arg(dims: &[i64]) -> TyFnAppArg {
+        let sp = CSpan::fresh_span();
+        let items = dims.iter().map(|d| TyTerm::TyInteger(int!(), *d, sp)).collect();
+        TyFnAppArg {
+            name: Some("normalized_shape".to_owned()),
+            arg: box TyTerm::TyTuple(Type::Tuple(vec![int!(); dims.len()], sp), items, sp),
+            span: sp,
+        }
+    }
+
+    fn resolve(x: &[i64], shape: &[i64]) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let mut dims = vec![tenv.fresh_dim(CSpan::fresh_span(), None)];
+        dims.extend(x.iter().map(|d| Type::ResolvedDim(*d, CSpan::fresh_span())));
+        let arg_ty = args!(arg!("x", tsr!(dims)));
+        LayerNorm.resolve(&mut tenv, "forward", arg_ty, unit!(), vec![], Some(vec![shape_arg(shape)]))
+    }
+
+    #[test]
+    fn should_preserve_shape_when_trailing_dims_match() {
+        match resolve(&[16, 64], &[16, 64]) {
+            Some(Ok(Type::FUN(_, _, box p, box r, _))) => assert_eq!(p.first_arg_ty(), Some(r)),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_require_image_batch_for_spatial_dropout() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = ByteSpan::new(ByteIndex(3), ByteIndex(8));
+        let dim = |i| Type::ResolvedDim(i, sp);
+        let x = Type::TSR(vec![dim(2), dim(3), dim(8), dim(8)], Tags::default(), sp);
+        match Dropout2d.resolve(&mut tenv, "forward", args!(arg!("x", x.clone())), unit!(), vec![], None) {
+            Some(Ok(Type::FUN(_, _, _, box r, _))) => assert_eq!(r, x),
+            ty => panic!("{:?}", ty),
+        }
+
+        let x = Type::TSR(vec![dim(2), dim(3)], Tags::default(), sp);
+        match Dropout2d.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], None) {
+            Some(Err(Diag::InvalidArgument(msg, span))) => {
+                assert!(msg.contains("rank 4"), "{}", msg);
+                assert_eq!(span, sp);
+            }
+            ty => panic!("{:?}", ty),
+        }
+
+        assert!(Dropout2d.validate("new", &[float_default("p", 0.5)]).is_ok());
+        // drops every channel
+        assert!(Dropout2d.validate("new", &[float_default("p", 1.)]).is_ok());
+        match Dropout2d.validate("new", &[float_default("p", 1.5)]) {
+            Err(Diag::OutOfRange(ref name, v, _, _)) => assert_eq!((name.as_str(), v), ("p", 1.5)),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    fn group_norm(groups: i64, channels: i64) -> Option<Result<Type, Diag>> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let dim = |i| Type::ResolvedDim(i, CSpan::fresh_span());
+        let x = tsr!(vec![tenv.fresh_dim(CSpan::fresh_span(), None), dim(32), dim(8), dim(8)]);
+        let inits = vec![
+            TyFnAppArg { span: ByteSpan::new(ByteIndex(10), ByteIndex(12)), ..int_arg("num_groups", groups) },
+            TyFnAppArg { span: ByteSpan::new(ByteIndex(14), ByteIndex(16)), ..int_arg("num_channels", channels) },
+        ];
+        GroupNorm.resolve(&mut tenv, "forward", args!(arg!("x", x)), unit!(), vec![], Some(inits))
+    }
+
+    #[test]
+    fn should_preserve_shape_for_valid_grouping() {
+        match group_norm(8, 32) {
+            Some(Ok(Type::FUN(_, _, box p, box r, _))) => assert_eq!(p.first_arg_ty(), Some(r)),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_point_at_non_divisible_groups() {
+        match group_norm(5, 32) {
+            Some(Err(Diag::InvalidArgument(_, sp))) => {
+                assert_eq!(sp, ByteSpan::new(ByteIndex(10), ByteIndex(12)));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_warn_of_half_precision_batch_norm() {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let sp = ByteSpan::new(ByteIndex(6), ByteIndex(9));
+        let ty = Type::UnresolvedModuleFun("reg", "BatchNorm1d", "forward", CSpan::fresh_span());
+        let x = Type::TSR(vec![Type::ResolvedDim(8, sp), Type::ResolvedDim(64, sp)], Tags::default(), sp);
+
+        let resolved = tenv.resolve_unresolved(&ty, "forward", args!(arg!("x", x.clone())), unit!(), vec![], None);
+        assert!(resolved.unwrap().is_some());
+        assert!(tenv.take_diags().is_empty());
+
+        // still resolved, the mismatch is only a warning
+        let half = x.with_precision(Some(Precision::F16));
+        let resolved = tenv.resolve_unresolved(&ty, "forward", args!(arg!("x", half)), unit!(), vec![], None);
+        assert!(resolved.unwrap().is_some());
+        let warnings = tenv.take_diags();
+        assert_eq!(warnings.len(), 1);
+        match warnings[0] {
+            Diag::PrecisionMismatch(Precision::F32, Precision::F16, span) => assert_eq!(span, sp),
+            ref w => panic!("{:?}", w),
+        }
+    }
+
+    fn batch_norm_new(arg: TyFnAppArg) -> Result<Option<(Type, bool)>, Diag> {
+        let mut tenv = TypeEnv::new(Rc::new(RefCell::new(Core::new())));
+        let ty = Type::UnresolvedModuleFun("reg", "BatchNorm1d", "new", CSpan::fresh_span());
+        let arg_ty = args!(arg!("num_features", int!()), arg!(arg.name.clone().unwrap().as_str(), float!()));
+        tenv.resolve_unresolved(&ty, "new", arg_ty, unit!(), vec![int_arg("num_features", 64), arg], None)
+    }
+
+    #[test]
+    fn should_check_float_hyperparameter_domain() {
+        let eps = ByteSpan::new(ByteIndex(20), ByteIndex(25));
+        match batch_norm_new(TyFnAppArg { span: eps, ..float_arg("eps", -1e-5) }) {
+            Err(Diag::OutOfRange(ref name, v, ref expected, sp)) => {
+                assert_eq!((name.as_str(), v, expected.as_str(), sp), ("eps", -1e-5, "positive", eps));
+            }
+            ty => panic!("{:?}", ty),
+        }
+        match batch_norm_new(float_arg("momentum", 0.9)) {
+            Ok(Some((Type::FUN(_, _, box Type::FnArgs(args, _), _, _), true))) => assert_eq!(args.len(), 2),
+            ty => panic!("{:?}", ty),
+        }
+        match batch_norm_new(float_arg("momentum", 1.0)) {
+            Err(Diag::OutOfRange(_, _, ref expected, _)) => assert_eq!(expected, "in [0, 1)"),
+            ty => panic!("{:?}", ty),
+        }
+    }
+
+    #[test]
+    fn should_reject_mismatched_last_dim() {
+        match resolve(&[16, 64], &[16, 32]) {
+            Some(Err(Diag::DimensionMismatch(a, b))) => {
+                assert_eq!(a.as_num(), Some(64));
+                assert_eq!(b.as_num(), Some(32));
+            }
+            ty => panic!("{:?}", ty),
+        }
+    }
+}
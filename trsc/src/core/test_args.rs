@@ -0,0 +1,32 @@
+use span::CSpan;
+use typing::Type;
+use typing::type_env::Alias;
+use typing::typed_term::{TyFnAppArg, TyTerm};
+
+/// `name=i`, literals are located by the tests that need it with
+/// `TyFnAppArg { span, ..int_arg(name, i) }`
+pub fn int_arg(name: &str, i: i64) -> TyFnAppArg {
+    TyFnAppArg {
+        name: Some(name.to_owned()),
+        arg: box TyTerm::TyInteger(int!(), i, CSpan::fresh_span()),
+        span: CSpan::fresh_span(),
+    }
+}
+
+/// `name=f`
+pub fn float_arg(name: &str, f: f64) -> TyFnAppArg {
+    TyFnAppArg {
+        name: Some(name.to_owned()),
+        arg: box TyTerm::TyFloat(float!(), f, CSpan::fresh_span()),
+        span: CSpan::fresh_span(),
+    }
+}
+
+/// a tensor of type `ty` passed by the name of its parameter
+pub fn tsr_arg(name: &str, ty: &Type) -> TyFnAppArg {
+    TyFnAppArg {
+        name: Some(name.to_owned()),
+        arg: box TyTerm::TyIdent(ty.clone(), Alias::Variable(name.to_owned()), ty.span()),
+        span: CSpan::fresh_span(),
+    }
+}
@@ -1,5 +1,6 @@
 use codespan_reporting::{Diagnostic, Label, Severity};
 use typing::Type;
+use typing::types::{Layout, Precision};
 use codespan::CodeMap;
 use codespan::{ByteSpan, LineIndex};
 
@@ -13,7 +14,41 @@ pub enum Diag {
     ImportError(String, ByteSpan),
     DuplicateVarInScope(String, Type, Type),
     TypeError(Type, Type),
+    IncompatibleTypes(Type, Type),
     EllisionError(String, ByteSpan),
+    InvalidDimParam(String, Type),
+    InvalidArgument(String, ByteSpan),
+    AmbiguousType(Type, Option<String>),
+    DuplicateModule(String),
+    FloatDimension(String, ByteSpan),
+    ConflictingTensorShape(String, Type, Type),
+    ConflictingDimensions(Type, ByteSpan, Type, ByteSpan),
+    AmbiguousImport(String, Vec<String>, ByteSpan),
+    InvalidDimExpr(String, String, ByteSpan),
+    ResidualMismatch(Type, ByteSpan, Type, ByteSpan),
+    NotAModule(Type, ByteSpan),
+    ParamShapeMismatch(String, Vec<i64>, Vec<i64>),
+    MissingParam(String),
+    UnexpectedParam(String),
+    UnusedLayer(String, ByteSpan),
+    InvalidStatement(ByteSpan),
+    CircularDim(Type, Type),
+    ScopeTooDeep(String, usize),
+    LayoutMismatch(Layout, Layout, ByteSpan),
+    MalformedShapes(String),
+    OutOfRange(String, f64, String, ByteSpan),
+    InvalidLiteral(String, ByteSpan),
+    CyclicDataflow(Vec<String>, ByteSpan),
+    NotDivisible(Type, i64, ByteSpan),
+    PrecisionMismatch(Precision, Precision, ByteSpan),
+    /// expected and found element type of two tensors that must be equal
+    DtypeMismatch(Precision, Precision, ByteSpan),
+    /// declared output of a module, the type its body produces
+    SignatureMismatch(Type, Type),
+    /// two tensors and the axis at which their dims differ
+    ShapeMismatch(Type, Type, usize),
+    /// kind of id, every fresh id of it has been handed out
+    OutOfTypeIds(String),
 }
 
 impl Diag {
@@ -29,7 +64,7 @@ impl Diag {
                 .with_label(Label::new_primary(*s2))
             }
 
-            RankMismatch(Type::TSR(dims1, s1), Type::TSR(dims2, s2)) => {
+            RankMismatch(Type::TSR(dims1, _, s1), Type::TSR(dims2, _, s2)) => {
                 Diagnostic::new(
                     Severity::Error,
                     format!("Tensor rank mismatch: rank({:?}) != rank({:?})", dims1, dims2),
@@ -88,6 +123,15 @@ impl Diag {
                 .with_label(Label::new_primary(ty2.span()))
             }
 
+            IncompatibleTypes(ty1, ty2) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Incompatible types: {:?} cannot be unified with {:?}", ty1, ty2),
+                )
+                .with_label(Label::new_primary(ty1.span()))
+                .with_label(Label::new_primary(ty2.span()))
+            }
+
             EllisionError(msg, span) => {
                 Diagnostic::new(
                     Severity::Error,
@@ -96,8 +140,276 @@ impl Diag {
                 .with_label(Label::new_primary(*span))
             }
 
+            InvalidDimParam(name, ty) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Module parameter `{}` must be a free dimension, got {:?}", name, ty),
+                )
+                .with_label(Label::new_primary(ty.span()))
+            }
+
+            InvalidArgument(msg, span) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Invalid argument: {}", msg),
+                )
+                .with_label(Label::new_primary(*span))
+            }
+
+            AmbiguousType(ty, label) => {
+                let what = match label {
+                    Some(label) => format!("{:?} ({})", ty, label),
+                    None => format!("{:?}", ty),
+                };
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Cannot infer type: {} is not resolved", what),
+                )
+                .with_label(Label::new_primary(ty.span()))
+            }
+
+            OutOfTypeIds(what) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Ran out of {} ids", what),
+                )
+            }
+
+            DuplicateModule(name) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Module `{}` is already defined", name),
+                )
+            }
+
+            FloatDimension(name, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("`{}` is a float and cannot be used as a tensor dimension", name),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            ConflictingTensorShape(name, ty1, ty2) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Conflicting shapes for tensor `{}`: {:?} vs {:?}", name, ty1, ty2),
+                )
+                .with_label(Label::new_primary(ty1.span()))
+                .with_label(Label::new_primary(ty2.span()))
+            }
+
+            ConflictingDimensions(ty1, o1, ty2, o2) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Dimension mismatch: {:?} != {:?}", ty1, ty2),
+                )
+                .with_label(Label::new_primary(ty1.span()))
+                .with_label(Label::new_primary(ty2.span()))
+                .with_label(Label::new_secondary(*o1).with_message(format!("{:?} is from here", ty1)))
+                .with_label(Label::new_secondary(*o2).with_message(format!("{:?} is from here", ty2)))
+            }
+
+            AmbiguousImport(name, paths, sp) => {
+                let candidates: Vec<_> = paths.iter().map(|p| format!("{}::{}", p, name)).collect();
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!("`{}` is ambiguous, candidates are {}; using {}",
+                        name, candidates.join(", "), candidates[0]),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            InvalidDimExpr(expr, msg, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Invalid dimension expression `{}`: {}", expr, msg),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            ResidualMismatch(ty1, s1, ty2, s2) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    "Residual branch shape differs from input".to_owned(),
+                )
+                .with_label(Label::new_primary(*s1).with_message(format!("branch output is {:?}", ty1)))
+                .with_label(Label::new_primary(*s2).with_message(format!("input is {:?}", ty2)))
+            }
+
+            NotAModule(ty, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Cannot call a method on {:?}, it is not a module", ty),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            ParamShapeMismatch(name, shape, saved) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Parameter `{}` has shape {:?} but the checkpoint has {:?}", name, shape, saved),
+                )
+            }
+
+            MissingParam(name) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Parameter `{}` is missing from the checkpoint", name),
+                )
+            }
+
+            UnexpectedParam(name) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Checkpoint has `{}` which is not a parameter of the model", name),
+                )
+            }
+
+            InvalidStatement(sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    "Syntax error, the statement is skipped".to_owned(),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            UnusedLayer(name, sp) => {
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!("Layer `{}` is declared but its output is never used", name),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            CircularDim(dim, derived) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Dimension {:?} is derived from itself: {:?}", dim, derived),
+                )
+                .with_label(Label::new_primary(derived.span()))
+            }
+
+            ScopeTooDeep(module, depth) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Too many nested scopes in {}, the limit is {}", module, depth),
+                )
+            }
+
+            LayoutMismatch(expected, found, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Layout mismatch: expected a {} tensor, found {}", expected, found),
+                )
+                .with_label(Label::new_primary(*sp).with_message("permute it to the expected layout"))
+            }
+
+            DtypeMismatch(expected, found, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Dtype mismatch: expected a tensor of {}, found a tensor of {}", expected, found),
+                )
+                .with_label(Label::new_primary(*sp).with_message(format!("cast it with `{}()`", expected.cast_fn())))
+            }
+
+            MalformedShapes(msg) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Invalid shape manifest: {}", msg),
+                )
+            }
+
+            OutOfRange(name, value, expected, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("`{}` must be {}, got {}", name, expected, value),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            InvalidLiteral(lit, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Invalid literal: {} is not a finite number", lit),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            CyclicDataflow(nodes, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Cyclic dataflow: {}", nodes.join(" -> ")),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            NotDivisible(dim, factor, sp) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Dimension {:?} is not divisible by {}", dim, factor),
+                )
+                .with_label(Label::new_primary(*sp))
+            }
+
+            PrecisionMismatch(expected, found, sp) => {
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!("Precision mismatch: expected {}, found {}", expected, found),
+                )
+                .with_label(Label::new_primary(*sp).with_message(format!("cast it with `{}()`", expected.cast_fn())))
+            }
+
+            SignatureMismatch(declared, found) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Type does not match the declared signature: expected {:?}, found {:?}", declared, found),
+                )
+                .with_label(Label::new_primary(declared.span()).with_message("declared here"))
+                .with_label(Label::new_secondary(found.span()))
+            }
+
+            ShapeMismatch(ts1, ts2, axis) => {
+                Diagnostic::new(
+                    Severity::Error,
+                    format!("Shape mismatch: expected {}, found {} (differ at axis {})", shape(ts1), shape(ts2), axis),
+                )
+                .with_label(Label::new_primary(ts1.span()))
+                .with_label(Label::new_primary(ts2.span()))
+            }
+
             _ => unimplemented!(),
         }
     }
 
+    /// context that is not tied to a span, shown after the message
+    pub fn notes(&self) -> Vec<String> {
+        match self {
+            Diag::AmbiguousImport(..) => vec!["import it by its full path to use another candidate".to_owned()],
+            Diag::UnusedLayer(..) => vec!["remove the declaration or use the layer in `forward`".to_owned()],
+            _ => vec![],
+        }
+    }
+
+    /// diagnostics that do not stop compilation
+    pub fn is_warning(&self) -> bool {
+        match self {
+            Diag::AmbiguousImport(..) | Diag::UnusedLayer(..) | Diag::PrecisionMismatch(..) => true,
+            _ => false,
+        }
+    }
+
+}
+
+/// `[32, 64, 7, 7]`, a dim that is not resolved is shown as its type
+fn shape(ty: &Type) -> String {
+    match ty {
+        Type::TSR(dims, _, _) => {
+            let dims: Vec<_> = dims.iter()
+                .map(|d| d.as_num().map_or_else(|| format!("{:?}", d), |n| n.to_string()))
+                .collect();
+            format!("[{}]", dims.join(", "))
+        }
+        _ => format!("{:?}", ty),
+    }
 }
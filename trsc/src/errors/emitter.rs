@@ -1,15 +1,19 @@
 use std::str::FromStr;
-use codespan::CodeMap;
+use codespan::{ByteSpan, CodeMap};
 use codespan_reporting::termcolor::StandardStream;
-use codespan_reporting::{emit, ColorArg, Diagnostic, Severity };
+use codespan_reporting::{emit, ColorArg, Diagnostic, LabelStyle, Severity };
 use super::diagnostic::Diag;
+use super::report::{LspDiagnostic, Report};
 use std::process::exit;
+use std::mem::discriminant;
 
 #[derive(Debug, Clone)]
 pub struct Emitter {
     errs: Vec<Diag>,
     code_map: CodeMap,
     print_ast: bool,
+    /// diagnostics shown before the rest are summed up in a note
+    max_errors: Option<usize>,
 }
 
 impl Emitter {
@@ -18,24 +22,213 @@ impl Emitter {
             errs: vec![],
             code_map,
             print_ast,
+            max_errors: None,
         }
     }
 
+    pub fn set_max_errors(&mut self, max_errors: Option<usize>) {
+        self.max_errors = max_errors;
+    }
+
+    /// register another source file so that its spans can be rendered
+    pub fn add_file(&mut self, name: &str, source: &str) -> ByteSpan {
+        self.code_map.add_filemap(name.to_owned().into(), source.to_owned()).span()
+    }
+
     pub fn add(&mut self, e: Diag) {
         self.errs.push(e);
     }
 
-    pub fn print_errs(&self) {
-        let mut diagnostics: Vec<Diagnostic> = self.errs
+    pub fn errs(&self) -> &[Diag] {
+        &self.errs
+    }
+
+    /// whether any diagnostic so far is more than a warning
+    pub fn has_errors(&self) -> bool {
+        self.errs.iter().any(|e| !e.is_warning())
+    }
+
+    pub fn reports(&self) -> Vec<Report> {
+        self.diagnostics()
+            .into_iter()
+            .map(|(d, notes)| Report::from_diagnostic(d, notes))
+            .collect()
+    }
+
+    /// the reports as LSP diagnostics, with ranges in the files they point into
+    pub fn diagnostics_as_lsp(&self) -> Vec<LspDiagnostic> {
+        self.reports()
             .iter()
-            .map(|e|e.as_diagnostic(&self.code_map))
-            .collect();
+            .map(|r| r.to_lsp(&self.code_map))
+            .collect()
+    }
+
+    /// rendered diagnostics with repeats dropped, in source order. Errors are
+    /// the same if they are of the same kind with the same message at the same spans.
+    /// Each comes with the notes of its `Diag`
+    fn diagnostics(&self) -> Vec<(Diagnostic, Vec<String>)> {
+        let mut seen = vec![];
+        let mut diagnostics = vec![];
+        for e in self.errs.iter() {
+            let d = e.as_diagnostic(&self.code_map);
+            let labels: Vec<_> = d.labels.iter().map(|l| (l.span, l.message.clone())).collect();
+            let key = (discriminant(e), d.message.clone(), labels);
+            if !seen.contains(&key) {
+                seen.push(key);
+                diagnostics.push((d, e.notes()));
+            }
+        }
+        // by primary label, those without one last, then errors before warnings
+        diagnostics.sort_by_key(|(d, _)| {
+            let start = d.labels.iter()
+                .find(|l| l.style == LabelStyle::Primary)
+                .map(|l| l.span.start());
+            (start.is_none(), start, severity_rank(d.severity), d.message.clone())
+        });
+        match self.max_errors {
+            Some(max) if diagnostics.len() > max => {
+                let hidden = diagnostics.len() - max;
+                diagnostics.truncate(max);
+                diagnostics.push((Diagnostic::new(Severity::Note, format!("… and {} more", hidden)), vec![]));
+            }
+            _ => (),
+        }
+        diagnostics
+    }
+
+    pub fn print_errs(&self) {
+        let diagnostics: Vec<_> = self.reports().iter().map(|r| r.to_diagnostic()).collect();
         let writer = StandardStream::stderr(ColorArg::from_str("auto").unwrap().into());
-        let mut is_err = false;
-        while let Some(diagnostic) = &diagnostics.pop() { // consumes so it only prints once
-            if diagnostic.severity == Severity::Error { is_err = true }
+        for diagnostic in diagnostics.iter() {
             emit(&mut writer.lock(), &self.code_map, &diagnostic).unwrap();
         }
-        if is_err && !self.print_ast { exit(-1) }
+        if self.should_exit() { exit(-1) }
+    }
+
+    /// whether `print_errs` exits, also when the errors are past `max_errors`
+    fn should_exit(&self) -> bool {
+        self.has_errors() && !self.print_ast
     }
-}
\ No newline at end of file
+}
+
+fn severity_rank(severity: Severity) -> u8 {
+    match severity {
+        Severity::Bug => 0,
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Note => 3,
+        Severity::Help => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use typing::Type;
+    use codespan::{ByteIndex, Span};
+    use span::CSpan;
+
+    #[test]
+    fn should_drop_repeated_errors() {
+        let mut code_map = CodeMap::new();
+        code_map.add_filemap("<input>".to_owned().into(), "dim a = 1;\ndim b = 2;".to_owned());
+        let mut emitter = Emitter::new(code_map, false);
+        let dim = |n, i| Type::ResolvedDim(n, Span::new(ByteIndex(i), ByteIndex(i + 1)));
+        emitter.add(Diag::DimensionMismatch(dim(1, 9), dim(2, 20)));
+        emitter.add(Diag::DimensionMismatch(dim(1, 9), dim(2, 20)));
+        // same message but at other spans
+        emitter.add(Diag::DimensionMismatch(dim(1, 2), dim(2, 13)));
+        assert_eq!(emitter.errs().len(), 3);
+        assert_eq!(emitter.reports().len(), 2);
+    }
+
+    #[test]
+    fn should_emit_in_source_order() {
+        let source = "dim a = 1;\ndim b = 2;\ndim c = 3;\ndim d = 4;\ndim e = 5;";
+        let mut code_map = CodeMap::new();
+        code_map.add_filemap("<input>".to_owned().into(), source.to_owned());
+        let mut emitter = Emitter::new(code_map, false);
+        // byte offsets start at 1
+        let line = |n: u32| {
+            let i = 1 + 11 * (n - 1);
+            Span::new(ByteIndex(i), ByteIndex(i + 3))
+        };
+        emitter.add(Diag::SymbolNotFound("e".to_owned(), line(5)));
+        emitter.add(Diag::SymbolNotFound("b".to_owned(), line(2)));
+        let spans: Vec<_> = emitter.reports().iter().map(|r| r.span).collect();
+        assert_eq!(spans, vec![Some(line(2)), Some(line(5))]);
+    }
+
+    #[test]
+    fn should_cap_diagnostics_at_max_errors() {
+        let source = "dim a = 1;\n".repeat(12);
+        let mut code_map = CodeMap::new();
+        code_map.add_filemap("<input>".to_owned().into(), source);
+        let mut emitter = Emitter::new(code_map, false);
+        emitter.set_max_errors(Some(5));
+        for n in 0..12 {
+            let i = 1 + 11 * n;
+            emitter.add(Diag::SymbolNotFound(format!("v{}", n), Span::new(ByteIndex(i), ByteIndex(i + 3))));
+        }
+        let reports = emitter.reports();
+        assert_eq!(reports.len(), 6);
+        assert!(reports[..5].iter().all(|r| r.severity == Severity::Error));
+        assert_eq!(reports[0].span, Some(Span::new(ByteIndex(1), ByteIndex(4))));
+        assert_eq!(reports[5].severity, Severity::Note);
+        assert_eq!(reports[5].message, "… and 7 more");
+        // the cap only limits what is shown
+        assert_eq!(emitter.errs().len(), 12);
+    }
+
+    #[test]
+    fn should_exit_on_errors_past_max_errors() {
+        let mut code_map = CodeMap::new();
+        code_map.add_filemap("<input>".to_owned().into(), "dim a = 1;\ndim b = 2;".to_owned());
+        let mut emitter = Emitter::new(code_map, false);
+        emitter.set_max_errors(Some(1));
+        emitter.add(Diag::UnusedLayer("a".to_owned(), Span::new(ByteIndex(5), ByteIndex(6))));
+        emitter.add(Diag::SymbolNotFound("b".to_owned(), Span::new(ByteIndex(16), ByteIndex(17))));
+        // only the warning is shown, the error is summed up in the note
+        let reports = emitter.reports();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.severity != Severity::Error));
+        assert!(emitter.should_exit());
+    }
+
+    #[test]
+    fn should_carry_notes_of_each_diag() {
+        let mut code_map = CodeMap::new();
+        code_map.add_filemap("<input>".to_owned().into(), "dim a = 1;".to_owned());
+        let mut emitter = Emitter::new(code_map, false);
+        emitter.add(Diag::UnusedLayer("fc1".to_owned(), Span::new(ByteIndex(1), ByteIndex(4))));
+        emitter.add(Diag::SymbolNotFound("b".to_owned(), Span::new(ByteIndex(5), ByteIndex(6))));
+        let reports = emitter.reports();
+        assert_eq!(reports[0].notes, Diag::UnusedLayer("fc1".to_owned(), CSpan::fresh_span()).notes());
+        assert!(!reports[0].notes.is_empty());
+        assert!(reports[1].notes.is_empty());
+        assert!(reports[0].to_diagnostic().message.ends_with(&reports[0].notes[0]));
+    }
+
+    #[test]
+    fn should_convert_to_lsp_diagnostics() {
+        let source = "dim a = 1;\ndim b = 2;\ntsr x = [a, b];";
+        let mut code_map = CodeMap::new();
+        code_map.add_filemap("<input>".to_owned().into(), source.to_owned());
+        let mut emitter = Emitter::new(code_map, false);
+        // byte offsets start at 1
+        let span = |i, len| Span::new(ByteIndex(i), ByteIndex(i + len));
+        emitter.add(Diag::ConflictingDimensions(
+            Type::ResolvedDim(1, span(32, 1)),
+            span(9, 1),
+            Type::ResolvedDim(2, span(35, 1)),
+            span(20, 1),
+        ));
+        let lsp = emitter.diagnostics_as_lsp();
+        assert_eq!(lsp.len(), 1);
+        assert_eq!(lsp[0].severity, 1);
+        assert_eq!(lsp[0].range, ((2, 9), (2, 10)));
+        let related: Vec<_> = lsp[0].related.iter().map(|r| r.range).collect();
+        assert_eq!(related, vec![((0, 8), (0, 9)), ((1, 8), (1, 9))]);
+        assert!(lsp[0].related[0].message.ends_with("is from here"));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod diagnostic;
 pub mod emitter;
+pub mod report;
 
 pub use self::emitter::Emitter;
-pub use self::diagnostic::Diag;
\ No newline at end of file
+pub use self::diagnostic::Diag;
+pub use self::report::Report;
\ No newline at end of file
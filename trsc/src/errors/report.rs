@@ -0,0 +1,110 @@
+use codespan::{ByteSpan, CodeMap};
+use codespan_reporting::{Diagnostic, Label, LabelStyle, Severity};
+
+/// zero-based (line, column) of a start and an end
+pub type Range = ((usize, usize), (usize, usize));
+
+/// A rendered diagnostic as a plain value, for tooling such as editors
+/// that want severity, location and message without going through the terminal.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub severity: Severity,
+    /// span of the first primary label
+    pub span: Option<ByteSpan>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    /// extra context that is not tied to a span
+    pub notes: Vec<String>,
+}
+
+impl Report {
+    /// `d` rendered from a `Diag`, with the `notes` of that `Diag`
+    pub fn from_diagnostic(d: Diagnostic, notes: Vec<String>) -> Self {
+        let span = d.labels.iter()
+            .find(|l| l.style == LabelStyle::Primary)
+            .map(|l| l.span);
+        Self {
+            severity: d.severity,
+            span,
+            message: d.message,
+            labels: d.labels,
+            notes,
+        }
+    }
+
+    /// back into a codespan diagnostic for the CLI
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let message = if self.notes.is_empty() {
+            self.message.clone()
+        } else {
+            format!("{}\n{}", self.message, self.notes.join("\n"))
+        };
+        self.labels.iter().cloned().fold(
+            Diagnostic::new(self.severity, message),
+            |d, l| d.with_label(l),
+        )
+    }
+
+    /// zero-based (line, column) of the start and end of the primary span, as in LSP ranges
+    pub fn range(&self, code_map: &CodeMap) -> Option<Range> {
+        span_range(code_map, self.span?)
+    }
+
+    /// the shape of an LSP `Diagnostic`, a report without a location is
+    /// put at the start of the file
+    pub fn to_lsp(&self, code_map: &CodeMap) -> LspDiagnostic {
+        let related = self.labels.iter()
+            .filter(|l| l.style == LabelStyle::Secondary)
+            .filter_map(|l| Some(LspRelated {
+                range: span_range(code_map, l.span)?,
+                message: l.message.clone().unwrap_or_default(),
+            }))
+            .collect();
+        let message = if self.notes.is_empty() {
+            self.message.clone()
+        } else {
+            format!("{}\n{}", self.message, self.notes.join("\n"))
+        };
+        LspDiagnostic {
+            range: self.range(code_map).unwrap_or(((0, 0), (0, 0))),
+            severity: self.lsp_severity(),
+            message,
+            related,
+        }
+    }
+
+    /// LSP `DiagnosticSeverity`: 1 error, 2 warning, 3 information, 4 hint
+    pub fn lsp_severity(&self) -> u8 {
+        match self.severity {
+            Severity::Bug | Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Note => 3,
+            Severity::Help => 4,
+        }
+    }
+}
+
+/// An LSP `Diagnostic` as a plain value, for the editor side to map onto
+/// whichever protocol types it uses
+#[derive(Debug, Clone, PartialEq)]
+pub struct LspDiagnostic {
+    pub range: Range,
+    /// see `Report::lsp_severity`
+    pub severity: u8,
+    pub message: String,
+    /// `relatedInformation`, one for each secondary label
+    pub related: Vec<LspRelated>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LspRelated {
+    pub range: Range,
+    pub message: String,
+}
+
+fn span_range(code_map: &CodeMap, span: ByteSpan) -> Option<Range> {
+    let file = code_map.find_file(span.start())?;
+    let (l1, c1) = file.location(span.start()).ok()?;
+    let (l2, c2) = file.location(span.end()).ok()?;
+    Some(((l1.to_usize(), c1.to_usize()), (l2.to_usize(), c2.to_usize())))
+}
@@ -0,0 +1,72 @@
+//! How it works:
+//! 1. PEG parser parses into token tree. The downside of PEG parser is that
+//! it is mostly magic, which means either it works or not, very difficult
+//! to debug or rigorously test other than trial and error. The Pest crate handles
+//! lexing and parsing in this compiler.
+//!
+//! 2. Parses token tree into untyped AST. This constructs a simple traversable tree
+//! structure for quality of life. The typing step might as well be merged to this part.
+//!
+//! 3. Annotate untyped AST into typed AST for type inference and reconstruction. The
+//! idea is to annotate each tree node with a dummy type variable.
+//!
+//! 4. Hindley-Milner type inference for type reconstruction. This is consisted
+//! of a few substeps.
+//!
+//!   a. Collect constraints. (handled in constraint.rs)
+//!       In this step, traverse typed ast and collect types of adjacent nodes that should
+//!       be equivalent. This generates a Constraint struct which is just a thin wrapper
+//!       around a btreeset of (Type, Type) tuple.
+//!
+//!   b. Unify constraints by generating substitutions.
+//!       This is a variant to Algorithm W in H-M type inference. Bascially, unify_one
+//!       function tries to replace 1 type var with a concrete type. The parent function, unify,
+//!       then uses that substitution on the rest of the constraints, thus eliminating the type
+//!       variable from the constraint set. The process is iterated until one of these conditions are met:
+//!       a) all type variable are exhausted. b) equivalence that can never happen. c) circular
+//!       type dependence (handled by occurs check).
+//!
+//!   c. Generate Substitutions
+//!       Now after the unification is complete, the function returns a list of substitutions that
+//!       should remove all type variables from the typed AST.
+//!
+//! 5. code gen // ... todo
+//!
+//!
+//! A note about `Span`s: Span contains the location in the source code for
+//! error reporting. Think of it as a lightweight tag that can be associated with
+//! data structures such as AST nodes, types, etc...
+
+#![feature(iterator_flatten)]
+#![feature(transpose_result)]
+#![feature(box_syntax)]
+#![feature(box_patterns)]
+#![feature(custom_attribute)]
+#![feature(attr_literals)]
+#![cfg_attr(test, feature(test))]
+#![cfg_attr(test, feature(set_stdio))]
+
+#[macro_use]
+extern crate trsc_core_derive;
+extern crate pest;
+#[macro_use]
+pub mod typing;
+#[macro_use]
+extern crate pest_derive;
+#[macro_use]
+extern crate maplit;
+
+extern crate codespan;
+extern crate codespan_reporting;
+#[cfg(test)]
+extern crate test;
+
+pub mod core;
+pub mod parsing;
+pub mod span;
+pub mod errors;
+pub mod codegen;
+pub mod compile;
+pub mod profile;
+pub mod checkpoint;
+mod lint;
@@ -0,0 +1,113 @@
+//! Warnings over the resolved typed AST, these never stop compilation.
+
+use errors::Diag;
+use typing::typed_term::{TyDecl, TyFnDecl, TyTerm};
+use std::collections::BTreeSet;
+
+/// a warning for each declared layer whose output never reaches the value
+/// returned by `forward`, directly or through the `self` methods it calls
+pub fn unused_layers(ast: &TyTerm) -> Vec<Diag> {
+    let decls = match ast {
+        TyTerm::TyProgram(decls) => decls,
+        _ => return vec![],
+    };
+    let mut warnings = vec![];
+    for decl in decls.iter() {
+        let weights = match decl {
+            TyDecl::TyWeightsDecl(weights) => weights,
+            _ => continue,
+        };
+        let fns = decls.iter()
+            .filter_map(|d| match d {
+                TyDecl::TyGraphDecl(graph) if graph.name == weights.name => Some(&graph.fns),
+                _ => None,
+            })
+            .next();
+        let used = match fns {
+            Some(fns) if fns.iter().any(|f| f.name.as_str() == "forward") => consumed_layers(fns),
+            _ => continue,
+        };
+        for w_a in weights.inits.iter().filter(|w_a| !used.contains(&w_a.name)) {
+            warnings.push(Diag::UnusedLayer(w_a.name.clone(), w_a.span));
+        }
+    }
+    warnings
+}
+
+/// names of everything called on the way to the output of `forward`
+fn consumed_layers(fns: &[TyFnDecl]) -> BTreeSet<String> {
+    let mut used = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let mut pending = vec!["forward".to_owned()];
+    while let Some(name) = pending.pop() {
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+        if let Some(f) = fns.iter().find(|f| f.name.as_str() == name) {
+            reach(&f.func_block, &mut used, &mut pending);
+        }
+    }
+    used
+}
+
+/// walk back from the value of `term`, statements are discarded so only
+/// the returned expression of a block is followed
+fn reach(term: &TyTerm, used: &mut BTreeSet<String>, pending: &mut Vec<String>) {
+    use self::TyTerm::*;
+    match term {
+        TyFnApp(ref fn_app) => {
+            match fn_app.orig_name {
+                Some(ref name) if name == "self" => pending.push(fn_app.name.as_str().to_owned()),
+                Some(ref name) => {
+                    used.insert(name.clone());
+                }
+                None => (),
+            }
+            for a in fn_app.args.iter() {
+                reach(&a.arg, used, pending);
+            }
+        }
+        TyLambda(ref lambda) => {
+            reach(&lambda.arg.arg, used, pending);
+            reach(&lambda.body, used, pending);
+        }
+        TyBlock { ref ret, .. } => reach(ret, used, pending),
+        TyExpr(ref items, ..) | TyNoGrad(ref items, _) => reach(items, used, pending),
+        TyList(ref terms) | TyTuple(_, ref terms, _) => for t in terms.iter() {
+            reach(t, used, pending);
+        },
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan_reporting::Severity;
+    use compile::{check_str, compile_str};
+
+    #[test]
+    fn should_warn_about_unused_layer() {
+        let source = include_str!("../tests/input/unused_layer.trs");
+        let model = compile_str(source).unwrap();
+        let warnings = unused_layers(&model.ast);
+        assert_eq!(warnings.len(), 1);
+        match warnings[0] {
+            Diag::UnusedLayer(ref name, _) => assert_eq!(name, "fc3"),
+            ref e => panic!("{:?}", e),
+        }
+        let report = check_str(source).into_iter()
+            .find(|r| r.message.contains("`fc3`"))
+            .unwrap();
+        assert_eq!(report.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn should_not_warn_when_every_layer_is_used() {
+        let model = compile_str(include_str!("../tests/input/xor.trs")).unwrap();
+        assert!(unused_layers(&model.ast).is_empty());
+        // `fc2` is only used through `self.example()`
+        let model = compile_str(include_str!("../tests/input/mnist.trs")).unwrap();
+        assert!(unused_layers(&model.ast).is_empty());
+    }
+}
@@ -1,86 +1,21 @@
-#![feature(iterator_flatten)]
-#![feature(transpose_result)]
-#![feature(box_syntax)]
-#![feature(box_patterns)]
-#![feature(custom_attribute)]
-#![feature(attr_literals)]
-
-/// How it works:
-/// 1. PEG parser parses into token tree. The downside of PEG parser is that
-/// it is mostly magic, which means either it works or not, very difficult
-/// to debug or rigorously test other than trial and error. The Pest crate handles
-/// lexing and parsing in this compiler.
-///
-/// 2. Parses token tree into untyped AST. This constructs a simple traversable tree
-/// structure for quality of life. The typing step might as well be merged to this part.
-///
-/// 3. Annotate untyped AST into typed AST for type inference and reconstruction. The
-/// idea is to annotate each tree node with a dummy type variable.
-///
-/// 4. Hindley-Milner type inference for type reconstruction. This is consisted
-/// of a few substeps.
-///
-///   a. Collect constraints. (handled in constraint.rs)
-///       In this step, traverse typed ast and collect types of adjacent nodes that should
-///       be equivalent. This generates a Constraint struct which is just a thin wrapper
-///       around a btreeset of (Type, Type) tuple.
-///
-///   b. Unify constraints by generating substitutions.
-///       This is a variant to Algorithm W in H-M type inference. Bascially, unify_one
-///       function tries to replace 1 type var with a concrete type. The parent function, unify,
-///       then uses that substitution on the rest of the constraints, thus eliminating the type
-///       variable from the constraint set. The process is iterated until one of these conditions are met:
-///       a) all type variable are exhausted. b) equivalence that can never happen. c) circular
-///       type dependence (handled by occurs check).
-///
-///   c. Generate Substitutions
-///       Now after the unification is complete, the function returns a list of substitutions that
-///       should remove all type variables from the typed AST.
-///
-/// 5. code gen // ... todo
-///
-///
-/// A note about `Span`s: Span contains the location in the source code for
-/// error reporting. Think of it as a lightweight tag that can be associated with
-/// data structures such as AST nodes, types, etc...
-///
-
-#[macro_use]
-extern crate trsc_core_derive;
-extern crate pest;
-#[macro_use]
-mod typing;
-#[macro_use]
-extern crate pest_derive;
-#[macro_use]
-extern crate maplit;
-
+extern crate trsc;
 extern crate codespan;
 extern crate clap;
-extern crate codespan_reporting;
 
-mod core;
-mod parsing;
-mod span;
-mod errors;
-mod codegen;
-
-
-use typing::constraint::Constraints;
-use typing::unifier::Unifier;
-use typing::annotate::Annotator;
-use codegen::pytorch::Generator;
-use typing::type_env::TypeEnv;
-use typing::Type;
-use typing::inferred_ast::subs;
-use errors::{Emitter};
-use parsing::ast_builder::ASTBuilder;
-use span::CSpan;
+use trsc::checkpoint;
+use trsc::compile::{self, CompileOptions};
+use trsc::codegen::pytorch::Generator;
+use trsc::typing::unifier::Substitution;
+use trsc::typing::inferred_ast::{dump, summary, to_dot};
+use trsc::errors::Emitter;
+use trsc::profile::Profile;
+use trsc::span::CSpan;
 
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::fs::File;
 use std::io::Read;
+use std::path::PathBuf;
 use std::process::exit;
 
 use codespan::CodeMap;
@@ -98,9 +33,46 @@ fn get_matches<'a>() -> ArgMatches<'a> {
             .help("Sets a custom input file")
             .takes_value(true)
             .required(true))
+        .arg(Arg::with_name("import_path")
+            .short("I")
+            .long("import-path")
+            .value_name("DIR")
+            .help("Looks up `use path::..` in DIR/path.trs before the core library")
+            .takes_value(true))
         .arg(Arg::with_name("print_ast")
             .long("print-ast")
             .help("Prints AST"))
+        .arg(Arg::with_name("dump_typed_ast")
+            .long("dump-typed-ast")
+            .help("Prints the typed AST with resolved types"))
+        .arg(Arg::with_name("emit_dot")
+            .long("emit-dot")
+            .help("Prints the dataflow of each graph as Graphviz DOT"))
+        .arg(Arg::with_name("summary")
+            .long("summary")
+            .help("Prints the input and output shapes of each layer"))
+        .arg(Arg::with_name("profile")
+            .long("profile")
+            .help("Prints the time spent in each phase to stderr"))
+        .arg(Arg::with_name("strict_shapes")
+            .long("strict-shapes")
+            .help("Fails if any type or dimension is left unresolved"))
+        .arg(Arg::with_name("full_shapes")
+            .long("full-shapes")
+            .help("Reports a dimension mismatch with the full shapes of both tensors"))
+        .arg(Arg::with_name("check_weights")
+            .long("check-weights")
+            .value_name("MANIFEST")
+            .help("Checks the parameter shapes of the last node against a JSON manifest of name -> shape")
+            .takes_value(true))
+        .arg(Arg::with_name("max_errors")
+            .long("max-errors")
+            .value_name("N")
+            .help("Prints at most N diagnostics")
+            .takes_value(true)
+            .validator(|n| n.parse::<usize>()
+                .map(|_| ())
+                .map_err(|_| format!("--max-errors takes a number, got `{}`", n))))
         .get_matches()
 }
 
@@ -108,6 +80,17 @@ fn main() {
     // --------------- get command line options -----------------
     let matches = get_matches();
     let print_ast = matches.is_present("print_ast");
+    let dump_typed_ast = matches.is_present("dump_typed_ast");
+    let emit_dot = matches.is_present("emit_dot");
+    let print_summary = matches.is_present("summary");
+    let options = CompileOptions {
+        strict_shapes: matches.is_present("strict_shapes"),
+        full_shapes: matches.is_present("full_shapes"),
+        import_path: matches.value_of("import_path").map(PathBuf::from),
+    };
+    // checked by the validator of `--max-errors`
+    let max_errors = matches.value_of("max_errors").map(|n| n.parse().unwrap());
+    let mut profile = if matches.is_present("profile") { Some(Profile::new()) } else { None };
     let fname = matches.value_of("input").unwrap();
     let mut file = File::open(fname).expect("Unable to open the file");
     let mut src = String::new();
@@ -116,64 +99,49 @@ fn main() {
     let mut code_map = CodeMap::new();
     let file_map = code_map.add_filemap(fname.to_owned().into(), src.clone());
     let emitter = Rc::new(RefCell::new(Emitter::new(code_map, print_ast)));
-    // --------------- parse into untyped ast   -----------------
+    emitter.borrow_mut().set_max_errors(max_errors);
+    // ------------------ parse, infer and check ------------------
     let cspan = CSpan::new(file_map.span());
-    let builder = ASTBuilder::new(Rc::clone(&emitter), cspan);
-    let parsed_terms = builder.parse_str(&src);
-    let program = parsed_terms
+    let model = compile::run(&src, cspan, &[], &options, &emitter, &mut profile)
         .unwrap_or_else(||{ emitter.borrow().print_errs(); exit(-1); });
-    // ------------- annotate ast with type vars --------------
-    let core = Rc::new(RefCell::new(core::Core::new()));
-    let tenv = Rc::new(RefCell::new(TypeEnv::new(core.clone())));
-    let annotator = Annotator::new(Rc::clone(&emitter), Rc::clone(&tenv));
-    let ast = annotator.annotate(&program);
-    emitter.borrow().print_errs();
-    // println!("{:#?}", ast);
-    // println!("initial tenv: {:#?}", tenv);
-    // ------------ first unitfication pass ---------------
-    let mut cs = Constraints::new(Rc::clone(&emitter), Rc::clone(&tenv));
-    cs.collect(&ast);
-    let mut unifier = Unifier::new(Rc::clone(&emitter), Rc::clone(&tenv));
-    let mut last_sub = unifier.unify(cs.clone());
-    emitter.borrow().print_errs();
-    // println!("{:#?}", last_sub);
-
-    // ------------ resolve module constraints until it stabilizes ----------
-    let mut last_ast = subs(&ast, &mut last_sub);;
-    let em_clone = emitter.clone();
-    let tenv_clone = tenv.clone();
-    let resolve_ast = move || {
-        let mut i = 0;
-        loop {
-            // collect constraints
-            let mut new_cs = Constraints::new(Rc::clone(&em_clone), Rc::clone(&tenv_clone));
-            new_cs.collect(&last_ast);
-            em_clone.borrow().print_errs();
-            // unify constraints
-            let mut new_unifier = Unifier::new(Rc::clone(&em_clone), Rc::clone(&tenv_clone));
-            let mut new_sub = new_unifier.unify(new_cs.clone());
-            em_clone.borrow().print_errs();
-            let temp_ast = subs(&last_ast, &mut new_sub);
-            if temp_ast != last_ast {
-                last_ast = temp_ast;
-                i += 1;
-                if i > 1_000_000 {
-                    println!("Error: does not halt");
-                    exit(1);
-                }
-
-                continue;
-            }
-            return last_ast;
+    if let Some(manifest) = matches.value_of("check_weights") {
+        let mut src = String::new();
+        File::open(manifest)
+            .and_then(|mut f| f.read_to_string(&mut src))
+            .expect("Unable to read the checkpoint manifest");
+        let saved = checkpoint::parse_manifest(&src)
+            .unwrap_or_else(|e| { eprintln!("Invalid checkpoint manifest: {}", e); exit(-1); });
+        for e in model.check_weights(&saved) {
+            emitter.borrow_mut().add(e);
         }
-    };
-    let final_ast = resolve_ast();
+    }
+    emitter.borrow().print_errs();
     if print_ast {
-        println!("{:#?}", final_ast);
+        println!("{:#?}", model.ast);
+        exit(0);
+    }
+    if dump_typed_ast {
+        print!("{}", dump(&model.ast, &model.tenv.borrow(), &Substitution::empty()));
+        exit(0);
+    }
+    if emit_dot {
+        print!("{}", to_dot(&model.ast, &Substitution::empty()));
+        exit(0);
+    }
+    if print_summary {
+        print!("{}", summary(&model.ast, &model.tenv.borrow(), &Substitution::empty()));
         exit(0);
     }
     // ---------------------------- code gen -----------------------------------
-    let mut generator = Generator::new(emitter.clone(), tenv.clone(), core.clone());
-    generator.generate(&final_ast).unwrap();
+    let mut generator = Generator::new(emitter.clone(), model.tenv.clone(), model.core.clone());
+    Profile::time(&mut profile, "codegen", "lines",
+        || {
+            generator.generate(&model.ast).unwrap();
+            generator.buf.lines().count()
+        },
+        |lines| *lines);
     println!("{}", generator.buf);
+    if let Some(profile) = profile {
+        eprint!("{}", profile.summary());
+    }
 }
@@ -61,6 +61,7 @@ impl ASTBuilder {
                         node_decl => self.build_node_decl(pair),
                         dim_assign => self.build_dim_tsr_assign(pair).map(Decl::AliasAssign),
                         tsr_assign => self.build_dim_tsr_assign(pair).map(Decl::AliasAssign),
+                        export => self.build_export(pair),
                         _ => panic!("Only node, graph, weights, use supported at top level. Got: {}", pair.as_str()),
                     }
                 })
@@ -83,6 +84,8 @@ impl ASTBuilder {
             // node_decl_body => build_node_decl_body(pair),
             int_lit => self.build_int_lit(pair),
             float_lit => self.build_float_lit(pair),
+            bool_lit => self.build_bool_lit(pair),
+            str_lit => self.build_str_lit(pair),
             graph_decl_body => self.build_graph_decl_body(pair),
 
             fn_decls => self.build_fn_decls(pair),
@@ -91,6 +94,11 @@ impl ASTBuilder {
             stmt => self.build_stmt(pair),
             expr => self.build_expr(pair),
             tuple => self.build_tuple(pair),
+            lambda => self.build_lambda(pair),
+            no_grad => self.build_no_grad(pair),
+            bad_stmt => self.build_bad_stmt(pair),
+            shape_assert => self.build_shape_assert(pair),
+            dtype_assert => self.build_dtype_assert(pair),
             block => self.build_block(pair),
             pipes => self.build_pipes(pair),
             semicolon => Ok(Term::None),
@@ -108,6 +116,46 @@ impl ASTBuilder {
         Ok(Term::Tuple(res, sp))
     }
 
+    fn build_lambda(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let sp = self.cspan.convert_span(&pair.clone().into_span());
+        let mut tokens = pair.into_inner();
+        let param = eat!(tokens, ident, "Failed to parse lambda parameter", sp)?;
+        let body = eat!(tokens, expr, "Failed to parse lambda body", sp)?;
+        Ok(Term::Lambda(param.as_str().to_owned(), box self.build_expr(body)?, sp))
+    }
+
+    fn build_no_grad(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let sp = self.cspan.convert_span(&pair.clone().into_span());
+        let mut tokens = pair.into_inner();
+        let body = eat!(tokens, block, "Failed to parse no_grad block", sp)?;
+        Ok(Term::NoGrad(box self.build_block(body)?, sp))
+    }
+
+    /// report the statement and leave an error node in its place
+    fn build_bad_stmt(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let sp = self.cspan.convert_span(&pair.into_span());
+        self.emitter.borrow_mut().add(Diag::InvalidStatement(sp));
+        Ok(Term::Error(sp))
+    }
+
+    fn build_shape_assert(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let sp = self.cspan.convert_span(&pair.clone().into_span());
+        let mut tokens = pair.into_inner();
+        let name = eat!(tokens, ident, "Failed to parse asserted variable", sp)?;
+        let _ = eat!(tokens, op_eq, "Expected `==` in shape assertion", sp)?;
+        let dims = eat!(tokens, ty_ident_list, "Failed to parse asserted shape", sp)?;
+        Ok(Term::ShapeAssert(name.as_str().to_owned(), to_idents!(dims), sp))
+    }
+
+    fn build_dtype_assert(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let sp = self.cspan.convert_span(&pair.clone().into_span());
+        let mut tokens = pair.into_inner();
+        let name = eat!(tokens, ident, "Failed to parse asserted variable", sp)?;
+        let _ = eat!(tokens, op_eq, "Expected `==` in dtype assertion", sp)?;
+        let dtype = eat!(tokens, dtype_name, "Expected one of `int`, `float` or `bool`", sp)?;
+        Ok(Term::DtypeAssert(name.as_str().to_owned(), dtype.as_str().to_owned(), sp))
+    }
+
     fn build_block(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
         let sp = self.cspan.convert_span(&pair.clone().into_span());
         let mut tokens = pair.into_inner();
@@ -198,6 +246,8 @@ impl ASTBuilder {
                     let temp = temp?.into_inner().next().unwrap();
                     if temp.as_rule() == ident {
                         TensorTy::Tensor(temp.as_str().to_owned(), sp)
+                    } else if temp.as_rule() == tensor_app_ty {
+                        build_tensor_app_ty(temp, sp)
                     } else {
                         TensorTy::Generic(to_idents!(temp), sp)
                     }
@@ -371,6 +421,8 @@ impl ASTBuilder {
             Term::Ident(curr.as_str().to_owned(), span)
         } else if curr.as_rule() == view_fn {
             Term::ViewFn(self.build_view_fn(curr).unwrap())
+        } else if curr.as_rule() == lambda {
+            self.build_lambda(curr).unwrap()
         } else {
             panic!("{:?}", curr.as_rule());
         }
@@ -468,7 +520,14 @@ impl ASTBuilder {
         let mut tokens = pair.into_inner();
         let mut head = eat!(tokens, node_decl_head, "Parsing `node_head` error", sp)?.into_inner();
         let node_name = eat!(head, cap_ident, "Does not have a node name", sp)?.as_str();
-        let ty_decl = eat!(head, fn_ty_sig, "Failed to parse `fn_ty_sig`", sp)?;
+        let mut ty_decl = eat!(head, "Failed to parse `fn_ty_sig`", sp)?;
+        let params = if ty_decl.as_rule() == node_dim_params {
+            let params = to_idents!(ty_decl.into_inner().next().unwrap());
+            ty_decl = eat!(head, fn_ty_sig, "Failed to parse `fn_ty_sig`", sp)?;
+            params
+        } else {
+            vec![]
+        };
         let node_body = eat!(tokens, node_decl_body, "Failed to parse `node_decl_body`", sp)?;
 
         let ty_signature = self.build_fn_ty_sig(ty_decl)?;
@@ -485,6 +544,7 @@ impl ASTBuilder {
 
         Ok(Decl::NodeDecl(NodeDecl {
             name: node_name.to_owned(),
+            dim_params: params,
             ty_sig: ty_signature,
             defs: macros,
             span: sp,
@@ -506,7 +566,17 @@ impl ASTBuilder {
         let sp = self.cspan.convert_span(&pair.clone().into_span());
         let mut tokens = pair.into_inner();
         let identifier = eat!(tokens, ident, "Failed to parse `ident`", sp)?;
-        let _assign = eat!(tokens, op_assign, "Cannot parse `=`", sp)?;
+        let mut assign = eat!(tokens, "Cannot parse `=`", sp)?;
+        let params = if assign.as_rule() == tsr_params {
+            let params = to_idents!(assign.into_inner().next().unwrap());
+            assign = eat!(tokens, "Cannot parse `=`", sp)?;
+            params
+        } else {
+            vec![]
+        };
+        if assign.as_rule() != op_assign {
+            return Err(err!("Cannot parse `=`", sp));
+        }
 
         let identifier = identifier.as_str().to_owned();
 
@@ -523,6 +593,7 @@ impl ASTBuilder {
             let ty = to_idents!(ty);
             Ok(AliasAssign::Tensor {
                 ident: id,
+                params,
                 rhs: TensorTy::Generic(ty, sp),
                 span: sp,
             })
@@ -537,12 +608,33 @@ impl ASTBuilder {
         }
     }
 
+    fn build_export(&self, pair: Pair<Rule>) -> Result<Decl, Diag> {
+        let sp = self.cspan.convert_span(&pair.clone().into_span());
+        let mut tokens = pair.into_inner();
+        let _pub_lit = eat!(tokens, pub_lit, "Parsing `pub` error", sp)?;
+        let assign = eat!(tokens, "no exported alias", sp)?;
+        self.build_dim_tsr_assign(assign).map(Decl::Export)
+    }
+
     fn build_float_lit(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
         let ret = pair.as_str().parse().unwrap();
         let span = self.cspan.convert_span(&pair.into_span());
         Ok(Term::Float(ret, span))
     }
 
+    fn build_bool_lit(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let ret = pair.as_str() == "true";
+        let span = self.cspan.convert_span(&pair.into_span());
+        Ok(Term::Bool(ret, span))
+    }
+
+    fn build_str_lit(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
+        let quoted = pair.as_str();
+        let ret = quoted[1..quoted.len() - 1].to_owned();
+        let span = self.cspan.convert_span(&pair.into_span());
+        Ok(Term::Str(ret, span))
+    }
+
     fn build_int_lit(&self, pair: Pair<Rule>) -> Result<Term, Diag> {
         let ret = pair.as_str().parse().unwrap();
         let span = self.cspan.convert_span(&pair.into_span());
@@ -558,6 +650,7 @@ impl ASTBuilder {
         let handle = |tok: Pair<Rule>| match tok.as_rule() {
             ty_ident_list => handle_tensor_ty(tok),
             ident => handle_alias(tok),
+            tensor_app_ty => build_tensor_app_ty(tok, sp),
             _ => unimplemented!(),
         };
 
@@ -603,3 +696,10 @@ fn unexpected_token(pair: &Pair<Rule>) -> ! {
     let message = format!("Unexpected token: {:#}", pair);
     panic!(message);
 }
+
+/// `Batch[32]`, the name of the alias followed by its arguments
+fn build_tensor_app_ty(pair: Pair<Rule>, sp: ByteSpan) -> TensorTy {
+    let mut tokens = pair.into_inner();
+    let name = tokens.next().unwrap().as_str().to_owned();
+    TensorTy::Applied(name, tokens.map(|t| t.as_str().to_owned()).collect(), sp)
+}
@@ -13,6 +13,8 @@ pub enum Term {
     Program(Vec<Decl>),
     Integer(i64, ByteSpan),
     Float(f64, ByteSpan),
+    Bool(bool, ByteSpan),
+    Str(String, ByteSpan),
     List(Vec<Term>),
     Ident(String, ByteSpan),
     ViewFn(ViewFn),
@@ -27,6 +29,16 @@ pub enum Term {
     Stmt(Box<Term>, ByteSpan),
     Pipes(Vec<Term>),
     Tuple(Vec<Term>, ByteSpan),
+    /// `@shape x == [N, 256];`
+    ShapeAssert(String, Vec<String>, ByteSpan),
+    /// `@dtype lr == float;`, a tensor asserts the dtype of its elements
+    DtypeAssert(String, String, ByteSpan),
+    /// `(def t => body)`, the parameter is bound to the piped input
+    Lambda(String, Box<Term>, ByteSpan),
+    /// `@no_grad { .. }`, typed like its block and run without gradients
+    NoGrad(Box<Term>, ByteSpan),
+    /// a statement that failed to parse, already reported
+    Error(ByteSpan),
 }
 
 // impl Term {
@@ -69,6 +81,8 @@ pub enum Decl {
     GraphDecl(GraphDecl),
     UseStmt(UseStmt),
     AliasAssign(AliasAssign),
+    /// `pub` alias that other files can bring into scope with `use`
+    Export(AliasAssign),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -81,6 +95,8 @@ pub struct UseStmt {
 #[derive(Debug, PartialEq, Clone)]
 pub struct NodeDecl {
     pub name: String,
+    /// dimension parameters of a generic node, `node Block(hidden)<...>`
+    pub dim_params: Vec<String>,
     pub ty_sig: FnTySig,
     pub defs: Vec<AliasAssign>,
     pub span: ByteSpan,
@@ -165,15 +181,27 @@ pub enum AliasAssign {
     },
     Tensor {
         ident: String,
+        /// dims supplied where the alias is used, e.g. `n` in `tsr Batch[n] = ..`
+        params: Vec<String>,
         rhs: TensorTy,
         span: ByteSpan,
     },
 }
 
+impl AliasAssign {
+    pub fn ident(&self) -> &str {
+        match self {
+            AliasAssign::Dimension { ident, .. } | AliasAssign::Tensor { ident, .. } => ident,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TensorTy {
     Tensor(String, ByteSpan),
     Generic(Vec<String>, ByteSpan),
+    /// a parameterized alias and its arguments, e.g. `Batch[32]`
+    Applied(String, Vec<String>, ByteSpan),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -0,0 +1,66 @@
+//! Wall time and output size of each phase of the pipeline, for `--profile`.
+
+use std::time::{Duration, Instant};
+use std::fmt::Write;
+
+#[derive(Debug)]
+pub struct Phase {
+    pub name: &'static str,
+    /// what `count` measures, e.g. constraints
+    pub unit: &'static str,
+    pub elapsed: Duration,
+    /// size of the phase output, summed over repeated passes
+    pub count: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    phases: Vec<Phase>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile { phases: vec![] }
+    }
+
+    /// run `f` as part of phase `name`, `count` measures what it produced.
+    /// Without a profile this is just `f()` so nothing is timed or counted.
+    pub fn time<T, F, C>(profile: &mut Option<Profile>, name: &'static str, unit: &'static str, f: F, count: C) -> T
+    where
+        F: FnOnce() -> T,
+        C: FnOnce(&T) -> usize,
+    {
+        let profile = match profile {
+            Some(profile) => profile,
+            None => return f(),
+        };
+        let start = Instant::now();
+        let ret = f();
+        let elapsed = start.elapsed();
+        profile.add(name, unit, elapsed, count(&ret));
+        ret
+    }
+
+    fn add(&mut self, name: &'static str, unit: &'static str, elapsed: Duration, count: usize) {
+        if let Some(phase) = self.phases.iter_mut().find(|p| p.name == name) {
+            phase.elapsed += elapsed;
+            phase.count += count;
+            return;
+        }
+        self.phases.push(Phase { name, unit, elapsed, count });
+    }
+
+    /// phases in the order they first ran
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    pub fn summary(&self) -> String {
+        let mut buf = String::new();
+        for p in self.phases.iter() {
+            let ms = p.elapsed.as_secs() as f64 * 1e3 + f64::from(p.elapsed.subsec_nanos()) / 1e6;
+            writeln!(buf, "{:<10} {:>10.3} ms {:>8} {}", p.name, ms, p.count, p.unit).unwrap();
+        }
+        buf
+    }
+}
@@ -19,6 +19,11 @@ impl CSpan {
         Span::new(ByteIndex(0), ByteIndex(0))
     }
 
+    /// whether `sp` is a placeholder from `fresh_span` rather than a location
+    pub fn is_fresh(sp: &ByteSpan) -> bool {
+        *sp == Self::fresh_span()
+    }
+
     pub fn convert_span(&self, sp: &PestSpan) -> ByteSpan {
         // Span::new(ByteIndex(sp.start() as u32 + 1), ByteIndex(sp.end() as u32 + 1))
         self.sp.subspan(ByteOffset(sp.start() as i64), ByteOffset(sp.end() as i64))
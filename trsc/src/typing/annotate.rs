@@ -5,9 +5,10 @@ use span::CSpan;
 use typing::type_env::{Alias, ModName, TypeEnv};
 use typing::typed_term::ArgsVecInto;
 use typing::typed_term::{TyDecl, TyFieldAccess, TyFnApp, TyFnAppArg, TyFnDecl, TyFnDeclParam,
-                            TyGraphDecl, TyNodeDecl, TyTerm, TyUseStmt, TyWeightsAssign,
+                            TyGraphDecl, TyLambda, TyNodeDecl, TyTerm, TyUseStmt, TyWeightsAssign,
                             TyWeightsDecl, TyAliasAssign};
 use typing::Type;
+use typing::types::Precision;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::process::exit;
@@ -37,16 +38,14 @@ impl Annotator {
                     .resolve_type(&module, &Alias::Variable(id.clone()))
                     .or_else(|| self.tenv.borrow()
                         .resolve_type(&ModName::Global, &Alias::Variable(id.clone()))
-                    )
-                    .unwrap_or_else(|| {
-                        let mut em = self.emitter.borrow_mut();
-                        em.add(Diag::SymbolNotFound(id.to_string(), *span));
-                        em.print_errs();
-                        exit(-1);
-                    })
-                    .with_span(&span);
-                let alias = Alias::Variable(id.to_owned());
-                TyTerm::TyIdent(ty, alias, *span)
+                    );
+                match ty {
+                    Some(ty) => TyTerm::TyIdent(ty.with_span(&span), Alias::Variable(id.to_owned()), *span),
+                    None => {
+                        self.emitter.borrow_mut().add(Diag::SymbolNotFound(id.to_string(), *span));
+                        TyTerm::TyNone
+                    }
+                }
             }
 
             Program(ref decls) => TyProgram({
@@ -73,20 +72,23 @@ impl Annotator {
 
             Integer(i, s) => TyInteger(Type::INT(*s), *i, *s),
             Float(i, s) => TyFloat(Type::FLOAT(*s), *i, *s),
+            Bool(b, s) => TyBool(Type::BOOL(*s), *b, *s),
+            Str(v, s) => TyStr(Type::STR(*s), v.clone(), *s),
             Block {
                 ref stmts,
                 ref ret,
                 ref span,
             } => {
                 let module = self.tenv.borrow().module();
-                self.tenv.borrow_mut().push_scope(&module);
-                let ret = TyBlock {
+                TypeEnv::with_module_scope(&self.tenv, &module, || TyBlock {
                     stmts: Box::new(self.annotate(&stmts)),
                     ret: Box::new(self.annotate(&ret)),
                     span: *span,
-                };
-                self.tenv.borrow_mut().pop_scope(&module);
-                ret
+                })
+                .unwrap_or_else(|e| {
+                    self.emitter.borrow_mut().add(e);
+                    TyNone
+                })
             }
             List(ref stmts) => TyList(stmts.iter().map(|s| self.annotate(&s)).collect()),
             Stmt(ref items, ref span) => TyStmt(
@@ -97,6 +99,46 @@ impl Annotator {
             None => TyNone,
             Pipes(ref pipes) => self.annotate_pipes(pipes),
             Tuple(ref terms, ref s) => self.annotate_tuples(terms, s),
+            ShapeAssert(ref id, ref dims, ref span) => {
+                let var = self.annotate(&Ident(id.clone(), *span));
+                let asserted = self.tenv.borrow_mut().create_tensor(&module, dims, span);
+                TyShapeAssert(box var, asserted, *span)
+            }
+            DtypeAssert(ref id, ref dtype, ref span) => {
+                let var = self.annotate(&Ident(id.clone(), *span));
+                let asserted = match var.ty() {
+                    // a tensor asserts its element tag, the declared float width is kept
+                    ref tsr @ Type::TSR(..) => {
+                        let precision = match (dtype.as_str(), tsr.precision()) {
+                            ("int", _) => Precision::I64,
+                            ("float", Some(p)) if p.is_float() => p,
+                            ("float", _) => Precision::F32,
+                            ("bool", _) => Precision::Bool,
+                            _ => unreachable!(),
+                        };
+                        // an undeclared dtype is the asserted one from here on
+                        let alias = Alias::Variable(id.clone());
+                        let declared = self.tenv.borrow().resolve_type(&module, &alias);
+                        match declared {
+                            Some(ref ty) if ty.precision().is_none() => unsafe {
+                                self.tenv.borrow_mut().replace_type(&module, &alias, ty.with_precision(Some(precision)));
+                            },
+                            _ => (),
+                        }
+                        tsr.with_precision(Some(precision)).with_span(span)
+                    }
+                    _ => match dtype.as_str() {
+                        "int" => Type::INT(*span),
+                        "float" => Type::FLOAT(*span),
+                        "bool" => Type::BOOL(*span),
+                        _ => unreachable!(),
+                    },
+                };
+                TyShapeAssert(box var, asserted, *span)
+            }
+            NoGrad(ref body, ref span) => TyNoGrad(box self.annotate(&body), *span),
+            // already reported while parsing
+            Error(_) => TyNone,
             _ => unimplemented!(),
         }
     }
@@ -128,8 +170,8 @@ impl Annotator {
             let t = match t {
                 // this may be `fc1`
                 Term::Ident(ref id, ref span) => {
-                    let arg_ty = self.tenv.borrow_mut().fresh_var(*span);
-                    let ret_ty = self.tenv.borrow_mut().fresh_var(*span);
+                    let arg_ty = self.tenv.borrow_mut().fresh_var(*span, None);
+                    let ret_ty = self.tenv.borrow_mut().fresh_var(*span, None);
                     TyTerm::TyFnApp(box TyFnApp {
                         mod_name: Some(
                             self.tenv.borrow().resolve_type(&module, &Alias::Variable(id.clone()))
@@ -148,17 +190,7 @@ impl Annotator {
                     })
                 }
                 Term::FnApp(ref fn_app) => {
-                    let mut typed_fn_app = self.annotate_fn_app(&fn_app);
-                    if typed_fn_app.mod_name.is_none() {
-                        // log_softmax(dim=1)
-                        typed_fn_app.mod_name = Some(
-                            self.tenv.borrow().resolve_type(&module, &typed_fn_app.name)
-                                .or_else(||self.tenv.borrow().resolve_type(&ModName::Global, &typed_fn_app.name))
-                                .unwrap()
-                                .as_string()
-                        );
-                        typed_fn_app.name = Alias::Function("forward".to_owned());
-                    }
+                    let mut typed_fn_app = self.annotate_call(&fn_app);
                     typed_fn_app.extend_arg(&prev_arg);
                     TyTerm::TyFnApp(box typed_fn_app)
                 }
@@ -174,6 +206,8 @@ impl Annotator {
                     }
                 }
                 Term::ViewFn(ref v_f) => TyTerm::TyFnApp(box self.annotate_view_fn(&v_f, &prev_arg)),
+                Term::Lambda(ref param, ref body, ref span) =>
+                    TyTerm::TyLambda(box self.annotate_lambda(param, body, span, &prev_arg)),
                 _ => unimplemented!(),
             };
             term0 = t.clone();
@@ -182,6 +216,52 @@ impl Annotator {
         term0
     }
 
+    /// a call of a module by name, such as `log_softmax(dim=1)`, is its `forward`
+    fn annotate_call(&self, fn_app: &FnApp) -> TyFnApp {
+        let module = self.tenv.borrow().module();
+        let mut typed_fn_app = self.annotate_fn_app(&fn_app);
+        if typed_fn_app.mod_name.is_none() {
+            typed_fn_app.mod_name = Some(
+                self.tenv.borrow().resolve_type(&module, &typed_fn_app.name)
+                    .or_else(||self.tenv.borrow().resolve_type(&ModName::Global, &typed_fn_app.name))
+                    .unwrap()
+                    .as_string()
+            );
+            typed_fn_app.name = Alias::Function("forward".to_owned());
+        }
+        typed_fn_app
+    }
+
+    /// `(def t => body)` applied to `arg`. The parameter shadows the
+    /// enclosing scope, other names in the body resolve through it.
+    fn annotate_lambda(&self, param: &str, body: &Term, span: &ByteSpan, arg: &TyFnAppArg) -> TyLambda {
+        let module = self.tenv.borrow().module();
+        let arg_ty = self.tenv.borrow_mut().fresh_var(*span, Some(param));
+        let ret_ty = self.tenv.borrow_mut().fresh_var(*span, None);
+        let body = TypeEnv::with_module_scope(&self.tenv, &module, || {
+            self.tenv.borrow_mut()
+                .add_type(&module, &Alias::Variable(param.to_owned()), arg_ty.clone())
+                .unwrap_or_else(|e| self.emitter.borrow_mut().add(e));
+            match *body {
+                Term::Expr(box Term::FnApp(ref fn_app), _) | Term::FnApp(ref fn_app) =>
+                    TyTerm::TyFnApp(box self.annotate_call(fn_app)),
+                _ => self.annotate(body),
+            }
+        })
+        .unwrap_or_else(|e| {
+            self.emitter.borrow_mut().add(e);
+            TyTerm::TyNone
+        });
+        TyLambda {
+            param: param.to_owned(),
+            arg_ty,
+            ret_ty,
+            arg: arg.clone(),
+            body: box body,
+            span: *span,
+        }
+    }
+
     fn annotate_view_fn(&self, v_fn: &ViewFn, arg: &TyFnAppArg) -> TyFnApp {
         let module = self.tenv.borrow().module();
         let tsr = self.tenv.borrow_mut().create_tensor(&module, &v_fn.dims, &v_fn.span);
@@ -229,6 +309,13 @@ impl Annotator {
                     decl.span,
                 );
 
+                // dimension parameters are instantiated per use site
+                if !decl.dim_params.is_empty() {
+                    self.tenv.borrow_mut()
+                        .generalize_module(&module, &decl.dim_params, &decl.span)
+                        .unwrap_or_else(|e| self.emitter.borrow_mut().add(e));
+                }
+
                 // add current name into global scope
                 self.tenv.borrow_mut().add_type(
                     &ModName::Global,
@@ -280,20 +367,9 @@ impl Annotator {
                 // import names into scope
                 // also import module and its associated functions
                 for name in &decl.imported_names {
-                    let ty = Type::Module(name.to_string(), None, decl.span);
                     self.tenv.borrow_mut()
-                        .add_type(
-                            &ModName::Global,
-                            &Alias::Variable(name.to_string()),
-                            ty)
-                        .unwrap_or_else(|e|self.emitter.borrow_mut().add(e));
-                    let import_result = self.tenv.borrow_mut().import_module(&decl.mod_name, &name);
-                    match import_result {
-                        Some(Ok(())) => (),
-                        Some(Err(e)) => self.emitter.borrow_mut().add(e),
-                        None => self.emitter.borrow_mut()
-                            .add(Diag::ImportError(name.to_string(), decl.span))
-                    }
+                        .import_name(&decl.mod_name, name, decl.span)
+                        .unwrap_or_else(|e| self.emitter.borrow_mut().add(e));
                 }
 
                 TyDecl::TyUseStmt(TyUseStmt {
@@ -302,7 +378,7 @@ impl Annotator {
                     span: decl.span,
                 })
             }
-            AliasAssign(ref assign) => {
+            AliasAssign(ref assign) | Export(ref assign) => {
                 let module = ModName::Global;
                 self.tenv.borrow_mut().set_module(module.clone());
                 self.tenv.borrow_mut()
@@ -338,26 +414,63 @@ impl Annotator {
                     );
                 match ty {
                     Some(t) => Ok(t.clone().with_span(sp)),
-                    None =>
-                        Err(Diag::SymbolNotFound(als.clone(), *sp)),
+                    // a parameterized alias used without its arguments
+                    None => self.tenv.borrow_mut().instantiate_tsr(&module, als, &[], sp),
                 }
             }
+            Applied(ref als, ref args, ref sp) => self.tenv.borrow_mut().instantiate_tsr(&module, als, args, sp),
         }
     }
 
     fn annotate_weights_assign(&self, w_assign: &WeightsAssign) -> TyWeightsAssign {
         let name = w_assign.name.clone();
-        let fn_ty = w_assign
-            .clone()
-            .mod_sig
-            .map(|sig|
-                box self.annotate_fn_ty_sig(
-                    w_assign.mod_name.to_owned(),
-                    "forward".to_owned(),
-                    &sig,
-                    &w_assign.span
-                ).unwrap()
-            );
+        let fn_args: Vec<TyFnAppArg> = w_assign
+            .fn_args
+            .iter()
+            .map(|a| match *a.arg {
+                // `in=_` is a dim inferred from how the layer is called
+                Term::Expr(box Term::Ident(ref id, ref sp), _) if id == "_" => {
+                    let label = format!("{}.{}", name, a.name);
+                    TyFnAppArg {
+                        name: Some(a.name.clone()),
+                        arg: box TyTerm::TyIdent(
+                            self.tenv.borrow_mut().fresh_dim(*sp, Some(&label)),
+                            Alias::Variable(id.clone()),
+                            *sp,
+                        ),
+                        span: a.span,
+                    }
+                }
+                _ => self.annotate_fn_app_arg(a),
+            })
+            .collect();
+
+        let is_generic = self.tenv.borrow().is_generic_module(&w_assign.mod_name);
+        let fn_ty = if is_generic {
+            // bind the dimension parameters supplied to `new`
+            let dims = fn_args
+                .iter()
+                .filter_map(|a| Some((a.name.clone()?, a.arg.as_num()?)))
+                .collect();
+            let inst = self.tenv.borrow_mut()
+                .instantiate_module(&w_assign.mod_name, &dims, &w_assign.span);
+            match inst {
+                Some(Type::Module(_, fn_ty, _)) => fn_ty,
+                _ => None,
+            }
+        } else {
+            w_assign
+                .clone()
+                .mod_sig
+                .map(|sig|
+                    box self.annotate_fn_ty_sig(
+                        w_assign.mod_name.to_owned(),
+                        "forward".to_owned(),
+                        &sig,
+                        &w_assign.span
+                    ).unwrap()
+                )
+        };
 
         let module = self.tenv.borrow().module();
         self.tenv.borrow_mut().add_type(
@@ -371,12 +484,6 @@ impl Annotator {
         )
         .unwrap_or_else(|e|self.emitter.borrow_mut().add(e));
 
-        let fn_args: Vec<TyFnAppArg> = w_assign
-            .fn_args
-            .iter()
-            .map(|a| self.annotate_fn_app_arg(a))
-            .collect();
-
         self.tenv.borrow_mut().add_init(&module, &name, fn_args.clone());
 
         TyWeightsAssign {
@@ -413,21 +520,44 @@ impl Annotator {
             name: Alias::Variable(name.to_owned()),
             arg_ty,
             args: t_args,
-            ret_ty: self.tenv.borrow_mut().fresh_var(*span),
+            ret_ty: self.tenv.borrow_mut().fresh_var(*span, None),
             span: *span,
         }
     }
 
     fn annotate_fn_decl(&self, f: &FnDecl) -> TyFnDecl {
         let module = self.tenv.borrow().module().clone();
-        { self.tenv.borrow_mut().push_scope(&module); }
+        let decl = TypeEnv::with_module_scope(&self.tenv, &module, || self.annotate_fn_decl_in_scope(f, &module))
+            .unwrap_or_else(|e| {
+                self.emitter.borrow_mut().add(e);
+                self.annotate_fn_decl_in_scope(f, &module)
+            });
+
+        // insert this function into typeenv
+        self.tenv.borrow_mut().add_type(
+            &module,
+            &Alias::Function(f.name.clone()),
+            Type::FUN(
+                module.as_str().to_owned(),
+                f.name.clone(),
+                box decl.arg_ty.clone(),
+                box decl.ret_ty.clone(),
+                decl.span.clone()
+            )
+        )
+        .unwrap_or_else(|e|self.emitter.borrow_mut().add(e));
+
+        decl
+    }
+
+    fn annotate_fn_decl_in_scope(&self, f: &FnDecl, module: &ModName) -> TyFnDecl {
         let mod_ty = { self.tenv.borrow().resolve_type(
             &ModName::Global,
             &Alias::Variable(module.as_str().to_owned()),
         ).unwrap().clone().with_span(&f.span) };
 
-        let arg_ty = self.tenv.borrow_mut().fresh_var(f.span);
-        let ret_ty = self.tenv.borrow_mut().fresh_var(f.span);
+        let arg_ty = self.tenv.borrow_mut().fresh_var(f.span, None);
+        let ret_ty = self.tenv.borrow_mut().fresh_var(f.span, None);
         let mut decl = TyFnDecl {
             name: Alias::Function(f.name.clone()),
             fn_params: {
@@ -473,7 +603,7 @@ impl Annotator {
 
                     // override the old first argument which is []
                     unsafe {
-                        self.tenv.borrow_mut().add_type_allow_replace(&module, &Alias::Variable(name0.clone()), ty_sig);
+                        self.tenv.borrow_mut().add_type_allow_replace(module, &Alias::Variable(name0.clone()), ty_sig);
                     }
 
                     // // type the function return parameter
@@ -488,12 +618,16 @@ impl Annotator {
             }
             _ => {
                 decl.arg_ty = decl.fn_params.to_ty(&f.span);
-                decl.ret_ty = self.tenv
+                let ret_ty = self.tenv
                     .borrow_mut()
                     .resolve_tensor(
-                        &module,
+                        module,
                         &f.return_ty.clone().unwrap(),
                     );
+                decl.ret_ty = ret_ty.unwrap_or_else(|e| {
+                    self.emitter.borrow_mut().add(e);
+                    self.tenv.borrow_mut().fresh_var(f.span, None)
+                });
                 // decl.fn_ty = Type::FUN(box decl.param_ty.clone(), box decl.return_ty.clone());
             }
         };
@@ -506,22 +640,6 @@ impl Annotator {
 
         decl.func_block = Box::new(self.annotate(&f.func_block));
 
-        self.tenv.borrow_mut().pop_scope(&module);
-
-        // insert this function into typeenv
-        self.tenv.borrow_mut().add_type(
-            &module,
-            &Alias::Function(f.name.clone()),
-            Type::FUN(
-                module.as_str().to_owned(),
-                f.name.clone(),
-                box decl.arg_ty.clone(),
-                box decl.ret_ty.clone(),
-                decl.span.clone()
-            )
-        )
-        .unwrap_or_else(|e|self.emitter.borrow_mut().add(e));
-
         decl
     }
 
@@ -529,6 +647,10 @@ impl Annotator {
         let module = self.tenv.borrow().module();
         let name = p.name.clone();
         let ty = self.tenv.borrow_mut().resolve_tensor(&module, &p.ty_sig);
+        let ty = ty.unwrap_or_else(|e| {
+            self.emitter.borrow_mut().add(e);
+            self.tenv.borrow_mut().fresh_var(p.span, None)
+        });
         self.tenv.borrow_mut()
             .add_type(&module, &Alias::Variable(name.clone()), ty.clone())
             .unwrap_or_else(|e| {
@@ -550,7 +672,7 @@ impl Annotator {
                 None => TyTerm::TyFieldAccess(TyFieldAccess {
                     mod_name: f_a.mod_name.clone(),
                     field_name: f_a.field_name.clone(),
-                    ty: self.tenv.borrow_mut().fresh_var(f_a.span),
+                    ty: self.tenv.borrow_mut().fresh_var(f_a.span, None),
                     span: f_a.span,
                 }),
                 Some(ref v) => {
@@ -563,7 +685,7 @@ impl Annotator {
                         name: Alias::Function(f_a.field_name.clone()),
                         arg_ty: args_ty,
                         args,
-                        ret_ty: self.tenv.borrow_mut().fresh_var(f_a.span),
+                        ret_ty: self.tenv.borrow_mut().fresh_var(f_a.span, None),
                         span: f_a.span,
                     })
                 }
@@ -9,10 +9,21 @@ use std::cell::RefCell;
 use errors::{ Emitter, Diag };
 
 use span::CSpan;
+use codespan::ByteSpan;
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone, PartialOrd, Ord)]
 pub struct Equals(pub Type, pub Type);
 
+impl Equals {
+    /// `dim % factor == 0`, deferred by the unifier until `dim` is resolved
+    pub fn divisible_by(dim: Type, factor: i64, span: ByteSpan) -> Equals {
+        Equals(
+            Type::DerivedDim('%', box dim, box Type::ResolvedDim(factor, span), span),
+            Type::ResolvedDim(0, span),
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Constraints {
     pub set: BTreeSet<Equals>,
@@ -30,12 +41,39 @@ impl Constraints {
         }
     }
 
+    /// constraints from equations produced elsewhere, e.g. by another pass
+    pub fn from_equals<I>(emitter: Rc<RefCell<Emitter>>, tenv: Rc<RefCell<TypeEnv>>, eqs: I) -> Self
+    where
+        I: IntoIterator<Item = Equals>,
+    {
+        Constraints {
+            set: eqs.into_iter().collect(),
+            emitter,
+            tenv,
+        }
+    }
+
+    /// every collected equation, in a stable order. Both sides may still
+    /// contain `VAR`s and `DIM`s that a solver is expected to bind.
+    pub fn iter(&self) -> impl Iterator<Item = &Equals> {
+        self.set.iter()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.set.is_empty()
     }
 
     fn add(&mut self, a: Type, b: Type) {
         // println!("{:?} {:?}", a, b);
+        // a synthesized type is located where the type it must equal is
+        let (a, b) = match (CSpan::is_fresh(&a.span()), CSpan::is_fresh(&b.span())) {
+            (true, false) => (a.with_span(&b.span()), b),
+            (false, true) => {
+                let sp = a.span();
+                (a, b.with_span(&sp))
+            }
+            _ => (a, b),
+        };
         self.set.insert(Equals(a, b));
     }
 
@@ -50,30 +88,54 @@ impl Constraints {
                 .collect(),
             TyInteger(_, _, _) => (),
             TyFloat(_, _, _) => (),
+            TyBool(_, _, _) => (),
+            TyStr(_, _, _) => (),
             TyList(ref terms) => terms.iter().map(|t| self.collect(&t)).collect(),
             TyTuple(_, ref terms, _) => terms.iter().map(|t| self.collect(&t)).collect(),
+            // an init arg left to infer, it is not bound in scope
+            TyIdent(_, ref name, _) if name.as_str() == "_" => (),
             TyIdent(ref t, ref name, ref sp) => {
-                let ty =  self.tenv.borrow()
+                let ty = self.tenv.borrow()
                     .resolve_type(&module, &name)
-                    .or_else(|| self.tenv.borrow().resolve_type(&ModName::Global, &name))
-                    .unwrap() // ... todo:
-                    .clone()
-                    .with_span(&sp);
-                self.add(t.clone(), ty);
+                    .or_else(|| self.tenv.borrow().resolve_type(&ModName::Global, &name));
+                match ty {
+                    Some(ty) => self.add(t.clone(), ty.with_span(&sp)),
+                    None => self.emitter.borrow_mut().add(Diag::SymbolNotFound(name.as_str().to_owned(), *sp)),
+                }
             }
             // &TyFieldAccess(TyFieldAccess),
             TyFnApp(ref fn_app) => self.collect_fn_app(&fn_app),
             TyBlock { ref stmts, ref ret, .. } => {
-                self.tenv.borrow_mut().push_scope_collection(&module);
-                self.collect(&stmts);
-                self.collect(&ret);
-                self.tenv.borrow_mut().pop_scope(&module);
+                let tenv = Rc::clone(&self.tenv);
+                TypeEnv::with_collection_scope(&tenv, &module, || {
+                    self.collect(&stmts);
+                    self.collect(&ret);
+                });
             }
             TyExpr(ref items, ref ty, _) => {
                 self.collect(&items);
                 self.add(ty.clone(), items.ty());
             }
-            TyStmt(ref items, _) => self.collect(&items),
+            TyStmt(ref items, _) | TyNoGrad(ref items, _) => self.collect(&items),
+            TyShapeAssert(ref var, ref asserted, ref span) => {
+                self.collect(&var);
+                // `@dtype` on a tensor whose dtype is already known
+                match (asserted.precision(), var.ty().precision()) {
+                    (Some(expected), Some(found)) if expected != found =>
+                        self.emitter.borrow_mut().add(Diag::DtypeMismatch(expected, found, *span)),
+                    _ => (),
+                }
+                self.add(var.ty(), asserted.clone());
+            }
+            TyLambda(ref lambda) => {
+                self.collect(&lambda.arg.arg);
+                self.add(lambda.arg_ty.clone(), lambda.arg.arg.ty());
+                let tenv = Rc::clone(&self.tenv);
+                TypeEnv::with_collection_scope(&tenv, &module, || {
+                    self.collect(&lambda.body);
+                });
+                self.add(lambda.ret_ty.clone(), lambda.body.ty());
+            }
             TyNone => (),
             _ => {
                 panic!("{:#?}", typed_term);
@@ -117,17 +179,18 @@ impl Constraints {
 
     fn collect_fn_decl(&mut self, decl: &TyFnDecl) {
         let module = self.tenv.borrow().module();
-        self.tenv.borrow_mut().push_scope_collection(&module);
-
-        self.collect(&decl.func_block);
-        self.add(decl.func_block.ty(), decl.ret_ty.clone());
-
-
-        // if decl.name == Alias::Function("forward".to_owned()) {
-        //     panic!("{:?}, {:?}", decl.fn_ty, func);
-        // }
-
-        self.tenv.borrow_mut().pop_scope(&module);
+        let tenv = Rc::clone(&self.tenv);
+        let is_forward = decl.name == Alias::Function("forward".to_owned());
+        TypeEnv::with_collection_scope(&tenv, &module, || {
+            self.collect(&decl.func_block);
+            if is_forward {
+                // the body must produce the output in the module signature
+                self.tenv.borrow_mut().declare_output(decl.ret_ty.span());
+                self.add(decl.ret_ty.clone(), decl.func_block.ty());
+            } else {
+                self.add(decl.func_block.ty(), decl.ret_ty.clone());
+            }
+        });
     }
 
     fn collect_node_decl(&mut self, decl: &TyNodeDecl) {
@@ -174,7 +237,7 @@ impl Constraints {
     fn collect_weights_assign(&mut self, w_a: &TyWeightsAssign) {
         let mod_name = &w_a.mod_name;
         // convert into a fn_app and collect on `self.new` method
-        let ret_ty = self.tenv.borrow_mut().fresh_var(w_a.span);
+        let ret_ty = self.tenv.borrow_mut().fresh_var(w_a.span, None);
         self.collect_fn_app(
             &TyFnApp {
                 mod_name: Some(mod_name.to_string()),
@@ -227,7 +290,14 @@ impl Constraints {
             }
         };
 
-        let symbol_modname = ModName::Named(symbol_mod_ty.as_string()); // Linear
+        let symbol_modname = match symbol_mod_ty.as_mod_name() { // Linear
+            Some(mod_name) => mod_name,
+            None => {
+                let e = Diag::NotAModule(symbol_mod_ty, fn_app.span);
+                self.emitter.borrow_mut().add(e);
+                return;
+            }
+        };
         let fn_name = &fn_app.name; // F(forward)
         let resolved_ty = self.tenv.borrow().resolve_type(&symbol_modname, &fn_name) // function / Unresolved
                     .or_else(|| self.tenv.borrow().resolve_type(&ModName::Global, &fn_name));
@@ -267,6 +337,11 @@ impl Constraints {
                     inits
                 )
             };
+            let required = self.tenv.borrow_mut().take_required();
+            self.set.extend(required);
+            for w in self.tenv.borrow_mut().take_diags() {
+                self.emitter.borrow_mut().add(w);
+            }
 
             match resolution {
                 Ok(Some((resolved_fn_ty, is_stateful))) => {
@@ -276,7 +351,8 @@ impl Constraints {
                             symbol_name,
                             fn_app.name.as_str(),
                             fn_app.arg_ty.clone(),
-                            fn_app.ret_ty.clone()
+                            fn_app.ret_ty.clone(),
+                            fn_app.span
                         )
                     );
                     // set alias for symbol if stateful
@@ -310,10 +386,16 @@ impl Constraints {
             }
         }
 
-        self.add(
-            ty.clone(),
-            fun!(symbol_name, fn_app.name.as_str(), fn_app.arg_ty.clone(), fn_app.ret_ty.clone()),
-        );
+        // the forward type of a generic module is instantiated at the
+        // use site, tying it to the declaration would leak its dims. The
+        // body is checked against each instance after unification instead
+        let is_generic = self.tenv.borrow().is_generic_module(&symbol_mod_ty.as_string());
+        if !(is_generic && fn_name.as_str() == "forward") {
+            self.add(
+                ty.clone(),
+                fun!(symbol_name, fn_app.name.as_str(), fn_app.arg_ty.clone(), fn_app.ret_ty.clone(), fn_app.span),
+            );
+        }
 
         self.add(fn_app.arg_ty.clone(), fn_app.args.to_ty(&fn_app.span));
 
@@ -321,7 +403,7 @@ impl Constraints {
             if let Type::Module(_, Some(box supplied_ty), _) = symbol_mod_ty {
                 if let Type::FUN(_,_,box p,box r, _) = supplied_ty {
                     self.add(fn_app.arg_ty.clone().clone(),
-                        args!(arg!("x",p.clone())));
+                        args!(arg!("x", p.clone(), fn_app.span); fn_app.span));
                     self.add(fn_app.ret_ty.clone(), r.clone());
                 }
             }
@@ -0,0 +1,210 @@
+//! Dataflow of the methods of each graph, which must be acyclic for the
+//! shapes flowing through it to be well defined.
+
+use codespan::ByteSpan;
+use errors::Diag;
+use typing::typed_term::{TyDecl, TyGraphDecl, TyTerm};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// a named value: the output of a method or a bound variable
+struct Node {
+    label: String,
+    span: ByteSpan,
+    deps: BTreeSet<String>,
+}
+
+/// a `CyclicDataflow` error for each cycle in the dataflow of a graph. A
+/// layer application is not a node, so a recurrent layer applied to its
+/// own state is not a cycle, but methods calling each other through `self`
+/// are.
+pub fn check_acyclic_dataflow(ast: &TyTerm) -> Vec<Diag> {
+    let decls = match ast {
+        TyTerm::TyProgram(decls) => decls,
+        _ => return vec![],
+    };
+    let mut errs = vec![];
+    for decl in decls.iter() {
+        if let TyDecl::TyGraphDecl(graph) = decl {
+            errs.extend(cycles(&dataflow(graph)));
+        }
+    }
+    errs
+}
+
+/// methods are keyed as `self.f`, the params of a method `f` as `f.x` and
+/// the variables bound in its body as `f.x#n`, each binding its own node
+fn dataflow(graph: &TyGraphDecl) -> BTreeMap<String, Node> {
+    let mut nodes = BTreeMap::new();
+    for f in graph.fns.iter() {
+        let method = f.name.as_str();
+        let mut deps = BTreeSet::new();
+        sources(&f.func_block, method, &BTreeMap::new(), &mut nodes, &mut deps);
+        nodes.insert(format!("self.{}", method), Node {
+            label: format!("self.{}", method),
+            span: f.span,
+            deps,
+        });
+    }
+    nodes
+}
+
+/// collect the named values flowing into `term` in `deps`, registering
+/// the variables it binds along the way. `scope` maps a variable to the
+/// node of its innermost binding
+fn sources(
+    term: &TyTerm,
+    method: &str,
+    scope: &BTreeMap<String, String>,
+    nodes: &mut BTreeMap<String, Node>,
+    deps: &mut BTreeSet<String>,
+) {
+    use self::TyTerm::*;
+    match term {
+        TyIdent(_, ref alias, _) => {
+            let name = alias.as_str();
+            deps.insert(scope.get(name).cloned().unwrap_or_else(|| format!("{}.{}", method, name)));
+        }
+        TyFnApp(ref fn_app) => {
+            if fn_app.orig_name.as_ref().map(|n| n.as_str()) == Some("self") {
+                deps.insert(format!("self.{}", fn_app.name.as_str()));
+            }
+            for a in fn_app.args.iter() {
+                sources(&a.arg, method, scope, nodes, deps);
+            }
+        }
+        TyLambda(ref lambda) => {
+            let mut arg_deps = BTreeSet::new();
+            sources(&lambda.arg.arg, method, scope, nodes, &mut arg_deps);
+            let key = format!("{}.{}#{}", method, lambda.param, nodes.len());
+            nodes.insert(key.clone(), Node {
+                label: lambda.param.clone(),
+                span: lambda.span,
+                deps: arg_deps,
+            });
+            let mut inner = scope.clone();
+            inner.insert(lambda.param.clone(), key);
+            sources(&lambda.body, method, &inner, nodes, deps);
+        }
+        TyBlock { ref stmts, ref ret, .. } => {
+            // statements bind variables but their values are discarded
+            sources(stmts, method, scope, nodes, &mut BTreeSet::new());
+            sources(ret, method, scope, nodes, deps);
+        }
+        TyExpr(ref items, ..) | TyStmt(ref items, _) | TyNoGrad(ref items, _) =>
+            sources(items, method, scope, nodes, deps),
+        TyList(ref terms) | TyTuple(_, ref terms, _) => for t in terms.iter() {
+            sources(t, method, scope, nodes, deps);
+        },
+        _ => (),
+    }
+}
+
+fn cycles(nodes: &BTreeMap<String, Node>) -> Vec<Diag> {
+    let mut errs = vec![];
+    let mut done = BTreeSet::new();
+    for key in nodes.keys() {
+        let mut stack = vec![];
+        visit(key, nodes, &mut stack, &mut done, &mut errs);
+    }
+    errs
+}
+
+/// depth first, a dependency that is still on `stack` closes a cycle
+fn visit<'a>(
+    key: &'a str,
+    nodes: &'a BTreeMap<String, Node>,
+    stack: &mut Vec<&'a str>,
+    done: &mut BTreeSet<&'a str>,
+    errs: &mut Vec<Diag>,
+) {
+    if done.contains(key) {
+        return;
+    }
+    let node = match nodes.get(key) {
+        Some(node) => node,
+        None => return,
+    };
+    if let Some(pos) = stack.iter().position(|k| *k == key) {
+        let mut labels: Vec<_> = stack[pos..].iter().map(|k| nodes[*k].label.clone()).collect();
+        labels.push(node.label.clone());
+        errs.push(Diag::CyclicDataflow(labels, node.span));
+        return;
+    }
+    stack.push(key);
+    for dep in node.deps.iter() {
+        visit(dep, nodes, stack, done, errs);
+    }
+    stack.pop();
+    done.insert(key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compile::compile_str;
+    use span::CSpan;
+    use typing::Type;
+    use typing::type_env::Alias;
+    use typing::typed_term::{TyFnAppArg, TyFnDecl, TyLambda};
+
+    #[test]
+    fn should_accept_feed_forward_model() {
+        let model = compile_str(include_str!("../../tests/input/xor.trs")).unwrap();
+        assert!(check_acyclic_dataflow(&model.ast).is_empty());
+    }
+
+    fn node(label: &str, deps: &[&str]) -> Node {
+        Node {
+            label: label.to_owned(),
+            span: CSpan::fresh_span(),
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn should_detect_feedback_edge() {
+        // `decode` feeds its output back into `encode`
+        let nodes = btreemap!{
+            "self.forward".to_owned() => node("self.forward", &["self.encode", "forward.x"]),
+            "self.encode".to_owned() => node("self.encode", &["encode.x", "self.decode"]),
+            "self.decode".to_owned() => node("self.decode", &["self.encode"]),
+        };
+        let errs = cycles(&nodes);
+        assert_eq!(errs.len(), 1);
+        match errs[0] {
+            Diag::CyclicDataflow(ref labels, _) =>
+                assert_eq!(labels, &vec!["self.decode", "self.encode", "self.decode"]),
+            ref e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_bind_lambda_param_to_its_own_node() {
+        let sp = CSpan::fresh_span();
+        // `x |> (def x => x)`, the input is the method's `x`, not the lambda's
+        let x = || TyTerm::TyIdent(Type::VAR(1, sp), Alias::Variable("x".to_owned()), sp);
+        let lambda = TyTerm::TyLambda(box TyLambda {
+            param: "x".to_owned(),
+            arg_ty: Type::VAR(1, sp),
+            ret_ty: Type::VAR(1, sp),
+            arg: TyFnAppArg { name: None, arg: box x(), span: sp },
+            body: box x(),
+            span: sp,
+        });
+        let forward = TyFnDecl {
+            name: Alias::Function("forward".to_owned()),
+            fn_params: vec![],
+            arg_ty: Type::VAR(2, sp),
+            ret_ty: Type::VAR(1, sp),
+            func_block: box lambda,
+            span: sp,
+        };
+        let ast = TyTerm::TyProgram(vec![TyDecl::TyGraphDecl(TyGraphDecl {
+            name: "Shadow".to_owned(),
+            ty_sig: Type::VAR(3, sp),
+            fns: vec![forward],
+            span: sp,
+        })]);
+        assert!(check_acyclic_dataflow(&ast).is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+/// Arithmetic over dimension aliases and integer literals in a tensor
+/// signature, e.g. `[n, n/2]` or `[n, 2*k]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DimExpr {
+    Num(i64),
+    Alias(String),
+    BinOp(char, Box<DimExpr>, Box<DimExpr>),
+}
+
+impl DimExpr {
+    /// whether a dim token is an expression rather than a single alias or literal
+    pub fn is_expr(token: &str) -> bool {
+        token.contains(|c| "+-*/".contains(c))
+    }
+
+    /// parse with the usual precedence, `*` and `/` bind tighter than `+` and `-`
+    pub fn parse(token: &str) -> Option<DimExpr> {
+        let terms = split_keep(token, "+-");
+        let mut acc = DimExpr::parse_product(terms[0])?;
+        for pair in terms[1..].chunks(2) {
+            let rhs = DimExpr::parse_product(pair[1])?;
+            acc = DimExpr::BinOp(pair[0].chars().next()?, box acc, box rhs);
+        }
+        Some(acc)
+    }
+
+    fn parse_product(token: &str) -> Option<DimExpr> {
+        let factors = split_keep(token, "*/");
+        let mut acc = DimExpr::atom(factors[0])?;
+        for pair in factors[1..].chunks(2) {
+            let rhs = DimExpr::atom(pair[1])?;
+            acc = DimExpr::BinOp(pair[0].chars().next()?, box acc, box rhs);
+        }
+        Some(acc)
+    }
+
+    fn atom(token: &str) -> Option<DimExpr> {
+        let token = token.trim();
+        if token.is_empty() {
+            return None;
+        }
+        match token.parse::<i64>() {
+            Ok(i) => Some(DimExpr::Num(i)),
+            Err(_) => Some(DimExpr::Alias(token.to_owned())),
+        }
+    }
+
+    /// aliases referenced by the expression
+    pub fn aliases(&self) -> Vec<String> {
+        match self {
+            DimExpr::Num(_) => vec![],
+            DimExpr::Alias(a) => vec![a.to_owned()],
+            DimExpr::BinOp(_, l, r) => {
+                let mut v = l.aliases();
+                v.extend(r.aliases());
+                v
+            }
+        }
+    }
+
+    /// fold into a constant. `Ok(None)` if some alias is not resolved yet,
+    /// `Err` with a reason if the arithmetic itself is invalid.
+    pub fn eval<F>(&self, lookup: &F) -> Result<Option<i64>, String>
+    where
+        F: Fn(&str) -> Option<i64>,
+    {
+        match self {
+            DimExpr::Num(i) => Ok(Some(*i)),
+            DimExpr::Alias(a) => Ok(lookup(a)),
+            DimExpr::BinOp(op, l, r) => {
+                let (l, r) = match (l.eval(lookup)?, r.eval(lookup)?) {
+                    (Some(l), Some(r)) => (l, r),
+                    _ => return Ok(None),
+                };
+                match op {
+                    '+' => Ok(Some(l + r)),
+                    '-' => Ok(Some(l - r)),
+                    '*' => Ok(Some(l * r)),
+                    '/' if r == 0 => Err("division by zero".to_owned()),
+                    '/' if l % r != 0 => Err(format!("{} is not divisible by {}", l, r)),
+                    '/' => Ok(Some(l / r)),
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+/// split on any of `seps`, keeping each separator as its own item
+fn split_keep<'a>(s: &'a str, seps: &str) -> Vec<&'a str> {
+    let mut v = vec![];
+    let mut last = 0;
+    for (i, c) in s.char_indices() {
+        if seps.contains(c) {
+            v.push(&s[last..i]);
+            v.push(&s[i..i + 1]);
+            last = i + 1;
+        }
+    }
+    v.push(&s[last..]);
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(token: &str, n: Option<i64>) -> Result<Option<i64>, String> {
+        DimExpr::parse(token).unwrap().eval(&|a: &str| if a == "n" { n } else { None })
+    }
+
+    #[test]
+    fn should_fold_with_precedence() {
+        assert_eq!(eval("n/2", Some(64)), Ok(Some(32)));
+        assert_eq!(eval("2*n + 1", Some(3)), Ok(Some(7)));
+        assert_eq!(eval("n - 4 / 2", Some(10)), Ok(Some(8)));
+    }
+
+    #[test]
+    fn should_defer_unresolved_alias() {
+        assert_eq!(eval("n/2", None), Ok(None));
+        assert_eq!(DimExpr::parse("n*k").unwrap().aliases(), vec!["n", "k"]);
+    }
+
+    #[test]
+    fn should_reject_inexact_division() {
+        assert!(eval("n/2", Some(63)).is_err());
+        assert!(DimExpr::parse("n/").is_none());
+    }
+}
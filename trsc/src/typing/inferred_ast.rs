@@ -3,6 +3,10 @@ use self::TyTerm::*;
 use typing::typed_term;
 use typing::typed_term::*;
 use typing::unifier::Substitution;
+use typing::type_env::ModName;
+use typing::{Type, TypeEnv};
+use std::collections::BTreeMap;
+use std::fmt::Write;
 
 pub fn subs(typed_term: &TyTerm, s: &mut Substitution) -> TyTerm {
     // println!("{}", typed_term);
@@ -10,6 +14,8 @@ pub fn subs(typed_term: &TyTerm, s: &mut Substitution) -> TyTerm {
         TyProgram(ref decls) => TyProgram(decls.iter().map(|decl| subs_decl(&decl, s)).collect()),
         TyInteger(ref ty, ref a, ref sp) => TyInteger(s.apply_ty(&ty), *a, *sp),
         TyFloat(ref ty, ref a, ref sp) => TyFloat(s.apply_ty(&ty), *a, *sp),
+        TyBool(ref ty, ref a, ref sp) => TyBool(s.apply_ty(&ty), *a, *sp),
+        TyStr(ref ty, ref a, ref sp) => TyStr(s.apply_ty(&ty), a.clone(), *sp),
         TyList(ref terms) => TyList(terms.iter().map(|t| subs(&t, s)).collect()),
         TyIdent(ref t, ref name, ref span) => TyIdent(s.apply_ty(t), name.clone(), *span),
         // // &TyFieldAccess(TyFieldAccess),
@@ -32,12 +38,29 @@ pub fn subs(typed_term: &TyTerm, s: &mut Substitution) -> TyTerm {
             box subs(&items, s),
             *span,
         ),
+        TyNoGrad(ref body, ref span) => TyNoGrad(
+            box subs(&body, s),
+            *span,
+        ),
         TyNone => TyNone,
+        TyShapeAssert(ref var, ref asserted, ref span) => TyShapeAssert(
+            box subs(&var, s),
+            s.apply_ty(asserted),
+            *span,
+        ),
         TyTuple(ref ty, ref vs, ref span) => TyTuple(
             s.apply_ty(ty),
             vs.iter().map(|i|subs(i,s)).collect(),
             *span
         ),
+        TyLambda(ref lambda) => TyLambda(box typed_term::TyLambda {
+            param: lambda.param.clone(),
+            arg_ty: s.apply_ty(&lambda.arg_ty),
+            ret_ty: s.apply_ty(&lambda.ret_ty),
+            arg: subs_fn_app_arg(&lambda.arg, s),
+            body: box subs(&lambda.body, s),
+            span: lambda.span,
+        }),
         _ => {
             panic!("{:#?}", typed_term);
         }
@@ -116,3 +139,460 @@ fn subs_fn_app_arg(a: &TyFnAppArg, s: &mut Substitution) -> TyFnAppArg {
         span: a.span,
     }
 }
+
+
+/// collect the type variables left in the typed AST after substitution
+pub fn free_vars(typed_term: &TyTerm) -> Vec<Type> {
+    let mut vars = vec![];
+    free_vars_term(typed_term, &mut vars);
+    vars
+}
+
+fn add_vars(ty: &Type, vars: &mut Vec<Type>) {
+    for v in ty.free_vars() {
+        if !vars.contains(&v) {
+            vars.push(v);
+        }
+    }
+}
+
+fn free_vars_term(typed_term: &TyTerm, vars: &mut Vec<Type>) {
+    match typed_term {
+        TyProgram(ref decls) => for decl in decls.iter() {
+            free_vars_decl(decl, vars);
+        },
+        TyInteger(ref ty, ..) | TyFloat(ref ty, ..) | TyBool(ref ty, ..) | TyStr(ref ty, ..) | TyIdent(ref ty, ..) => add_vars(ty, vars),
+        TyList(ref terms) => for t in terms.iter() {
+            free_vars_term(t, vars);
+        },
+        TyFnApp(ref fn_app) => {
+            add_vars(&fn_app.arg_ty, vars);
+            add_vars(&fn_app.ret_ty, vars);
+            for a in fn_app.args.iter() {
+                free_vars_term(&a.arg, vars);
+            }
+        }
+        TyBlock { ref stmts, ref ret, .. } => {
+            free_vars_term(stmts, vars);
+            free_vars_term(ret, vars);
+        }
+        TyExpr(ref items, ref ty, _) => {
+            free_vars_term(items, vars);
+            add_vars(ty, vars);
+        }
+        TyStmt(ref items, _) | TyNoGrad(ref items, _) => free_vars_term(items, vars),
+        TyShapeAssert(ref var, ref asserted, _) => {
+            free_vars_term(var, vars);
+            add_vars(asserted, vars);
+        }
+        TyTuple(ref ty, ref vs, _) => {
+            add_vars(ty, vars);
+            for v in vs.iter() {
+                free_vars_term(v, vars);
+            }
+        }
+        TyFieldAccess(ref f_a) => add_vars(&f_a.ty, vars),
+        TyLambda(ref lambda) => {
+            add_vars(&lambda.fn_ty(), vars);
+            free_vars_term(&lambda.arg.arg, vars);
+            free_vars_term(&lambda.body, vars);
+        }
+        TyNone => (),
+    }
+}
+
+fn free_vars_decl(decl: &TyDecl, vars: &mut Vec<Type>) {
+    use self::TyDecl::*;
+    match decl {
+        TyGraphDecl(d) => {
+            add_vars(&d.ty_sig, vars);
+            for f in d.fns.iter() {
+                add_vars(&f.arg_ty, vars);
+                add_vars(&f.ret_ty, vars);
+                free_vars_term(&f.func_block, vars);
+            }
+        }
+        TyNodeDecl(d) => add_vars(&d.ty_sig, vars),
+        TyWeightsDecl(d) => {
+            add_vars(&d.ty_sig, vars);
+            for w_a in d.inits.iter() {
+                add_vars(&w_a.arg_ty, vars);
+            }
+        }
+        TyUseStmt(_) | TyAliasAssign(_) => (),
+    }
+}
+
+/// render the typed AST after substitution as an indented tree,
+/// one `name : Type` per line, marking types that are not fully resolved.
+/// Batch dimensions are symbolic by design and are not marked.
+pub fn dump(typed_term: &TyTerm, tenv: &TypeEnv, s: &Substitution) -> String {
+    let mut s = Substitution(s.0.clone());
+    let batch_dims = tenv.batch_dims();
+    let mut buf = String::new();
+    dump_term(&subs(typed_term, &mut s), 0, &batch_dims, &mut buf);
+    buf
+}
+
+fn dump_line(name: &str, ty: &Type, depth: usize, batch_dims: &[Type], buf: &mut String) {
+    let unresolved = match ty {
+        Type::UnresolvedModuleFun(..) => true,
+        _ => ty.free_vars().iter().any(|v| !batch_dims.contains(v)),
+    };
+    write!(buf, "{}{} : {:?}", "  ".repeat(depth), name, ty).unwrap();
+    if unresolved {
+        write!(buf, " (unresolved)").unwrap();
+    }
+    writeln!(buf).unwrap();
+}
+
+fn dump_term(typed_term: &TyTerm, depth: usize, batch_dims: &[Type], buf: &mut String) {
+    match typed_term {
+        TyProgram(ref decls) => for decl in decls.iter() {
+            dump_decl(decl, depth, batch_dims, buf);
+        },
+        TyInteger(ref ty, ref i, _) => dump_line(&i.to_string(), ty, depth, batch_dims, buf),
+        TyFloat(ref ty, ref f, _) => dump_line(&f.to_string(), ty, depth, batch_dims, buf),
+        TyBool(ref ty, ref b, _) => dump_line(&b.to_string(), ty, depth, batch_dims, buf),
+        TyStr(ref ty, ref v, _) => dump_line(&format!("{:?}", v), ty, depth, batch_dims, buf),
+        TyIdent(ref ty, ref alias, _) => dump_line(alias.as_str(), ty, depth, batch_dims, buf),
+        TyList(ref terms) => for t in terms.iter() {
+            dump_term(t, depth, batch_dims, buf);
+        },
+        TyFnApp(ref fn_app) => {
+            let name = match fn_app.mod_name {
+                Some(ref m) => format!("{}::{}", m, fn_app.name.as_str()),
+                None => fn_app.name.as_str().to_owned(),
+            };
+            dump_line(&name, &fn_app.ret_ty, depth, batch_dims, buf);
+            for a in fn_app.args.iter() {
+                dump_term(&a.arg, depth + 1, batch_dims, buf);
+            }
+        }
+        TyBlock { ref stmts, ref ret, .. } => {
+            dump_term(stmts, depth, batch_dims, buf);
+            dump_term(ret, depth, batch_dims, buf);
+        }
+        TyExpr(ref items, ..) => dump_term(items, depth, batch_dims, buf),
+        TyStmt(ref items, _) => dump_term(items, depth, batch_dims, buf),
+        TyNoGrad(ref body, _) => {
+            dump_line("@no_grad", &body.ty(), depth, batch_dims, buf);
+            dump_term(body, depth + 1, batch_dims, buf);
+        }
+        TyShapeAssert(ref var, ref asserted, _) => {
+            let name = match **var {
+                TyIdent(_, ref alias, _) => alias.as_str().to_owned(),
+                _ => String::new(),
+            };
+            let kind = match asserted {
+                Type::TSR(..) => "@shape",
+                _ => "@dtype",
+            };
+            dump_line(&format!("{} {}", kind, name), asserted, depth, batch_dims, buf)
+        }
+        TyTuple(ref ty, ref vs, _) => {
+            dump_line("tuple", ty, depth, batch_dims, buf);
+            for v in vs.iter() {
+                dump_term(v, depth + 1, batch_dims, buf);
+            }
+        }
+        TyFieldAccess(ref f_a) => {
+            dump_line(&format!("{}.{}", f_a.mod_name, f_a.field_name), &f_a.ty, depth, batch_dims, buf)
+        }
+        TyLambda(ref lambda) => {
+            dump_line(&format!("lambda {}", lambda.param), &lambda.fn_ty(), depth, batch_dims, buf);
+            dump_term(&lambda.arg.arg, depth + 1, batch_dims, buf);
+            dump_term(&lambda.body, depth + 1, batch_dims, buf);
+        }
+        TyNone => (),
+    }
+}
+
+fn dump_decl(decl: &TyDecl, depth: usize, batch_dims: &[Type], buf: &mut String) {
+    use self::TyDecl::*;
+    match decl {
+        TyGraphDecl(d) => {
+            dump_line(&format!("graph {}", d.name), &d.ty_sig, depth, batch_dims, buf);
+            for f in d.fns.iter() {
+                let fn_ty = Type::FUN(
+                    d.name.clone(),
+                    f.name.as_str().to_owned(),
+                    box f.arg_ty.clone(),
+                    box f.ret_ty.clone(),
+                    f.span,
+                );
+                dump_line(&format!("def {}", f.name.as_str()), &fn_ty, depth + 1, batch_dims, buf);
+                dump_term(&f.func_block, depth + 2, batch_dims, buf);
+            }
+        }
+        TyNodeDecl(d) => dump_line(&format!("node {}", d.name), &d.ty_sig, depth, batch_dims, buf),
+        TyWeightsDecl(d) => {
+            dump_line(&format!("weights {}", d.name), &d.ty_sig, depth, batch_dims, buf);
+            for w_a in d.inits.iter() {
+                let name = format!("{} = {}::{}", w_a.name, w_a.mod_name, w_a.fn_name);
+                dump_line(&name, &w_a.arg_ty, depth + 1, batch_dims, buf);
+            }
+        }
+        TyUseStmt(_) | TyAliasAssign(_) => (),
+    }
+}
+
+/// export the dataflow of each graph as Graphviz DOT. Every function
+/// application becomes a node labeled with its input and output shapes,
+/// edges follow the order of the pipes. Unresolved dims are shown as `?`.
+pub fn to_dot(typed_term: &TyTerm, s: &Substitution) -> String {
+    let mut s = Substitution(s.0.clone());
+    let mut dot = Dot { buf: String::new(), count: 0 };
+    writeln!(dot.buf, "digraph {{").unwrap();
+    if let TyProgram(ref decls) = subs(typed_term, &mut s) {
+        for d in decls.iter() {
+            if let TyDecl::TyGraphDecl(ref d) = d {
+                for f in d.fns.iter() {
+                    writeln!(dot.buf, "  subgraph \"cluster_{}_{}\" {{", d.name, f.name.as_str()).unwrap();
+                    writeln!(dot.buf, "    label=\"{}::{}\";", d.name, f.name.as_str()).unwrap();
+                    let mut idents = BTreeMap::new();
+                    dot.term(&f.func_block, &mut idents);
+                    writeln!(dot.buf, "  }}").unwrap();
+                }
+            }
+        }
+    }
+    writeln!(dot.buf, "}}").unwrap();
+    dot.buf
+}
+
+/// a `model.summary()` style table for each method of each graph: every
+/// layer or op in the order the data flows through it, with its input and
+/// output shapes and how many parameters it has. Unresolved dims are `?`.
+pub fn summary(typed_term: &TyTerm, tenv: &TypeEnv, s: &Substitution) -> String {
+    let mut s = Substitution(s.0.clone());
+    let mut buf = String::new();
+    if let TyProgram(ref decls) = subs(typed_term, &mut s) {
+        for d in decls.iter() {
+            if let TyDecl::TyGraphDecl(ref d) = d {
+                let module = ModName::Named(d.name.clone());
+                for f in d.fns.iter() {
+                    let mut rows = vec![];
+                    summary_rows(&f.func_block, &module, tenv, &mut rows);
+                    if rows.is_empty() {
+                        continue;
+                    }
+                    writeln!(buf, "{}::{}", d.name, f.name.as_str()).unwrap();
+                    write_table(&["Layer", "Input", "Output", "Param #"], &rows, &mut buf);
+                    writeln!(buf).unwrap();
+                }
+            }
+        }
+    }
+    buf
+}
+
+fn summary_rows(typed_term: &TyTerm, module: &ModName, tenv: &TypeEnv, rows: &mut Vec<Vec<String>>) {
+    match typed_term {
+        TyFnApp(ref fn_app) => {
+            for a in fn_app.args.iter() {
+                summary_rows(&a.arg, module, tenv, rows);
+            }
+            let name = match (&fn_app.orig_name, &fn_app.mod_name) {
+                (Some(n), Some(m)) if n != m => format!("{} ({})", n, m),
+                (Some(n), _) => n.to_owned(),
+                (None, Some(m)) => format!("{}::{}", m, fn_app.name.as_str()),
+                (None, None) => fn_app.name.as_str().to_owned(),
+            };
+            let params: i64 = fn_app.orig_name.as_ref()
+                .and_then(|n| tenv.layer_parameters(module, n))
+                .map_or(0, |ps| ps.iter().map(|(_, shape)| shape.iter().product::<i64>()).sum());
+            rows.push(vec![name, dot_shape(&fn_app.arg_ty), dot_shape(&fn_app.ret_ty), params.to_string()]);
+        }
+        TyList(ref terms) => for t in terms.iter() {
+            summary_rows(t, module, tenv, rows);
+        },
+        TyBlock { ref stmts, ref ret, .. } => {
+            summary_rows(stmts, module, tenv, rows);
+            summary_rows(ret, module, tenv, rows);
+        }
+        TyExpr(ref items, ..) | TyStmt(ref items, _) | TyNoGrad(ref items, _) =>
+            summary_rows(items, module, tenv, rows),
+        TyLambda(ref lambda) => {
+            summary_rows(&lambda.arg.arg, module, tenv, rows);
+            summary_rows(&lambda.body, module, tenv, rows);
+        }
+        _ => (),
+    }
+}
+
+/// left aligned columns separated by two spaces
+fn write_table(header: &[&str], rows: &[Vec<String>], buf: &mut String) {
+    let mut widths: Vec<_> = header.iter().map(|h| h.len()).collect();
+    for row in rows.iter() {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let header: Vec<_> = header.iter().map(|h| h.to_string()).collect();
+    for row in Some(&header).into_iter().chain(rows.iter()) {
+        let line: Vec<_> = row.iter().zip(widths.iter()).map(|(cell, w)| format!("{:<w$}", cell, w = *w)).collect();
+        writeln!(buf, "{}", line.join("  ").trim_right()).unwrap();
+    }
+}
+
+/// render a type as a shape with unresolved dims as `?`
+fn dot_shape(ty: &Type) -> String {
+    match ty {
+        Type::TSR(dims, _, _) => {
+            let dims: Vec<_> = dims.iter().map(dot_shape).collect();
+            format!("[{}]", dims.join(", "))
+        }
+        Type::ResolvedDim(i, _) => i.to_string(),
+        Type::FnArgs(args, _) if args.len() == 1 => dot_shape(&args[0]),
+        Type::FnArgs(args, _) => {
+            let args: Vec<_> = args.iter().map(dot_shape).collect();
+            format!("({})", args.join(", "))
+        }
+        Type::FnArg(_, ty, _) => dot_shape(ty),
+        Type::INT(_) => "int".to_owned(),
+        Type::FLOAT(_) => "float".to_owned(),
+        Type::BOOL(_) => "bool".to_owned(),
+        Type::STR(_) => "str".to_owned(),
+        Type::Unit(_) => "()".to_owned(),
+        _ => "?".to_owned(),
+    }
+}
+
+struct Dot {
+    buf: String,
+    count: usize,
+}
+
+impl Dot {
+    fn node(&mut self, label: &str) -> String {
+        let id = format!("n{}", self.count);
+        self.count += 1;
+        writeln!(self.buf, "    {} [label=\"{}\"];", id, label.replace('"', "\\\"")).unwrap();
+        id
+    }
+
+    /// emits the nodes of a term and returns the node producing its value
+    fn term(&mut self, typed_term: &TyTerm, idents: &mut BTreeMap<String, String>) -> Option<String> {
+        match typed_term {
+            TyIdent(ref ty, ref alias, _) => {
+                let name = alias.as_str().to_owned();
+                if let Some(id) = idents.get(&name) {
+                    return Some(id.clone());
+                }
+                let id = self.node(&format!("{}: {}", name, dot_shape(ty)));
+                idents.insert(name, id.clone());
+                Some(id)
+            }
+            TyFnApp(ref fn_app) => {
+                let srcs: Vec<_> = fn_app.args.iter()
+                    .filter_map(|a| self.term(&a.arg, idents))
+                    .collect();
+                let name = match (&fn_app.orig_name, &fn_app.mod_name) {
+                    (Some(n), _) => n.to_owned(),
+                    (None, Some(m)) => format!("{}::{}", m, fn_app.name.as_str()),
+                    (None, None) => fn_app.name.as_str().to_owned(),
+                };
+                let label = format!("{}: {} -> {}", name, dot_shape(&fn_app.arg_ty), dot_shape(&fn_app.ret_ty));
+                let id = self.node(&label);
+                for src in srcs {
+                    writeln!(self.buf, "    {} -> {};", src, id).unwrap();
+                }
+                Some(id)
+            }
+            TyList(ref terms) => terms.iter().fold(None, |_, t| self.term(t, idents)),
+            TyBlock { ref stmts, ref ret, .. } => {
+                self.term(stmts, idents);
+                self.term(ret, idents)
+            }
+            TyExpr(ref items, ..) | TyStmt(ref items, _) | TyNoGrad(ref items, _) => self.term(items, idents),
+            TyLambda(ref lambda) => {
+                if let Some(src) = self.term(&lambda.arg.arg, idents) {
+                    idents.insert(lambda.param.clone(), src);
+                }
+                self.term(&lambda.body, idents)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compile::compile_str;
+
+    #[test]
+    fn should_dump_resolved_method_signature() {
+        let model = compile_str(include_str!("../../tests/input/xor.trs")).unwrap();
+        let dump = dump(&model.ast, &model.tenv.borrow(), &model.sub);
+        assert!(dump.contains("graph Xor : "));
+        let forward = dump.lines()
+            .find(|l| l.starts_with("  def forward : Xor::forward("))
+            .unwrap();
+        assert!(forward.contains("<2>]") && forward.contains("-> ["), "{}", forward);
+    }
+
+    #[test]
+    fn should_summarize_layers_in_dataflow_order() {
+        let model = compile_str(include_str!("../../tests/input/xor.trs")).unwrap();
+        let summary = summary(&model.ast, &model.tenv.borrow(), &model.sub);
+        let lines: Vec<_> = summary.lines().collect();
+        assert_eq!(lines[0], "Xor::forward");
+        assert!(lines[1].starts_with("Layer") && lines[1].ends_with("Param #"), "{}", lines[1]);
+        let rows: Vec<Vec<_>> = lines[2..5].iter().map(|l| l.split("  ").map(|c| c.trim()).filter(|c| !c.is_empty()).collect()).collect();
+        assert_eq!(rows, vec![
+            vec!["fc1 (Linear)", "[?, 2]", "[?, 3]", "9"],
+            vec!["sigmoid", "[?, 3]", "[?, 3]", "0"],
+            vec!["fc2 (Linear)", "[?, 3]", "[?, 1]", "4"],
+        ]);
+        // columns line up
+        let at = |l: &str| l.find('[').unwrap();
+        assert!(lines[2..5].iter().all(|l| at(l) == at(lines[2])));
+    }
+
+    #[test]
+    fn should_pass_shape_through_inline_lambda() {
+        let model = compile_str(include_str!("../../tests/input/inline_lambda.trs")).unwrap();
+        let dump = dump(&model.ast, &model.tenv.borrow(), &model.sub);
+        let lambda = dump.lines()
+            .map(|l| l.trim())
+            .find(|l| l.starts_with("lambda t : ::lambda("))
+            .unwrap();
+        assert!(!lambda.ends_with("(unresolved)"), "{}", lambda);
+        let sig = &lambda["lambda t : ::lambda(".len()..lambda.len() - 1];
+        let (arg, ret) = sig.split_at(sig.find(" -> ").unwrap());
+        assert!(arg.ends_with("<3>]"), "{}", lambda);
+        assert_eq!(arg, &ret[" -> ".len()..]);
+    }
+
+    #[test]
+    fn should_collect_each_unique_free_var_once() {
+        use codespan::{ByteIndex, Span};
+        use typing::type_env::Alias;
+        use typing::types::Tags;
+        let sp1 = Span::new(ByteIndex(1), ByteIndex(2));
+        let sp2 = Span::new(ByteIndex(5), ByteIndex(9));
+        let x = |sp| Type::TSR(vec![Type::DIM(1, sp), Type::ResolvedDim(8, sp)], Tags::default(), sp);
+        let ident = |ty, sp| TyIdent(ty, Alias::Variable("x".to_owned()), sp);
+        // the same vars found at different places in the source
+        let term = TyList(vec![
+            ident(x(sp1), sp1),
+            ident(x(sp2), sp2),
+            ident(Type::FnArg(Some("x".to_owned()), box x(sp2), sp2), sp2),
+            // a var and a dim with the same id are different variables
+            ident(Type::VAR(1, sp1), sp1),
+            ident(Type::DIM(1, sp2), sp2),
+        ]);
+        assert_eq!(free_vars(&term), vec![Type::DIM(1, sp1), Type::VAR(1, sp1)]);
+    }
+
+    #[test]
+    fn should_export_dot_with_resolved_layer_shapes() {
+        let model = compile_str(include_str!("../../tests/input/xor.trs")).unwrap();
+        let dot = to_dot(&model.ast, &model.sub);
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains("[label=\"fc1: [?, 2] -> [?, 3]\"]"), "{}", dot);
+        assert!(dot.contains(" -> n"), "{}", dot);
+    }
+}
@@ -1,11 +1,13 @@
 #[macro_use]
 pub mod types;
 pub mod annotate;
+pub mod dim_expr;
 pub mod type_env;
 pub mod typed_term;
 
 pub use self::type_env::TypeEnv;
 pub use self::types::Type;
 pub mod constraint;
+pub mod dataflow;
 pub mod inferred_ast;
 pub mod unifier;
@@ -1,5 +1,5 @@
 use codespan::ByteSpan;
-use core::Core;
+use core::{check_layout, check_precision, check_required, with_defaults, Core, Op};
 use span::CSpan;
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -10,11 +10,17 @@ use std::cell::RefCell;
 /// 1. Type Aliasing during the first pass (annotate)
 /// 2. pushing and popping scopes (during `annotate` and `collect`)
 /// 3. module type and method type reconstruction
-use parsing::term::{AliasAssign, TensorTy, Term};
-use std::collections::{BTreeMap, VecDeque};
-use std::fmt::{Debug, Error, Formatter};
+use parsing::term::{AliasAssign, Decl, TensorTy, Term};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::mem;
+use std::fmt::{Debug, Display, Error, Formatter};
+use std::ops::{Deref, DerefMut};
 use typing::typed_term::TyFnAppArg;
+use typing::constraint::Equals;
+use typing::unifier::Substitution;
 use typing::Type;
+use typing::types::{Layout, Precision, Tags};
+use typing::dim_expr::DimExpr;
 use errors::Diag;
 use self::ModName::*;
 
@@ -49,19 +55,29 @@ impl Debug for ModName {
 pub struct Scope {
     /// type information of aliases
     types: BTreeMap<Alias, Type>,
+    /// scopes are queued in the order they were opened, which is the
+    /// order the collection pass revisits them in
+    opened: usize,
 }
 
 impl Scope {
     pub fn new() -> Scope {
         Scope {
             types: BTreeMap::new(),
+            opened: 0,
         }
     }
 }
 
 type ScopeStack = VecDeque<Scope>;
 type ScopeQueue = VecDeque<Scope>;
-type InitMap = BTreeMap<String, Vec<TyFnAppArg>>;
+/// stateful initializations in declaration order
+type InitMap = Vec<(String, Vec<TyFnAppArg>)>;
+/// dimension parameters of a generic module and the dim variables they quantify
+type Scheme = Vec<(String, TypeId)>;
+
+/// default for `TypeEnv::set_max_scope_depth`
+pub const MAX_SCOPE_DEPTH: usize = 256;
 
 #[derive(Debug)]
 pub struct TypeEnv {
@@ -70,6 +86,30 @@ pub struct TypeEnv {
     var_counter: TypeId,
     current_mod: ModName,
     modules: BTreeMap<ModName, (ScopeStack, ScopeQueue, InitMap)>,
+    generics: BTreeMap<String, Scheme>,
+    /// shape a tensor alias was first bound to in each module
+    tsr_shapes: BTreeMap<(ModName, String), Type>,
+    /// params and dims of each parameterized tensor alias, see `add_tsr_template`
+    tsr_templates: BTreeMap<(ModName, String), (Vec<String>, Vec<String>)>,
+    /// paths each imported name was requested from
+    imports: BTreeMap<String, Vec<String>>,
+    /// public aliases of other files, by the path they are imported from
+    exports: BTreeMap<String, Vec<AliasAssign>>,
+    /// what each labeled dim variable stands for, e.g. `Conv2d.output.H`
+    dim_labels: BTreeMap<TypeId, String>,
+    /// what each labeled type variable stands for
+    var_labels: BTreeMap<TypeId, String>,
+    /// scopes a module may nest before `push_scope` refuses
+    max_scope_depth: usize,
+    /// scopes pushed so far, see `Scope::opened`
+    scopes_opened: usize,
+    /// constraints an op required while resolving, see `require`
+    required: Vec<Equals>,
+    /// diagnostics raised outside of a `Result`, such as precision
+    /// warnings while resolving, see `take_diags`
+    diags: Vec<Diag>,
+    /// spans of the outputs declared by module signatures
+    declared_outputs: BTreeSet<ByteSpan>,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
@@ -94,59 +134,289 @@ impl Alias {
             Alias::Variable(s) => s,
         }
     }
+
+    pub fn is_function(&self) -> bool {
+        match self {
+            Alias::Function(_) => true,
+            Alias::Variable(_) => false,
+        }
+    }
+
+    pub fn is_variable(&self) -> bool {
+        !self.is_function()
+    }
+}
+
+/// an op that is in scope in every program without a `use` statement
+pub struct PreludeEntry {
+    pub name: &'static str,
+    pub op: Box<Op>,
 }
 
 impl TypeEnv {
     pub fn new(core: Rc<RefCell<Core>>) -> Self {
+        let mut ret = Self::without_prelude(core);
+
+        // import basic functions such as view
+        ret.import_prelude().unwrap();
+
+        ret
+    }
+
+    /// type environment whose prelude is exactly `entries`, registered on
+    /// its own `core` so that no other environment sees them
+    pub fn with_prelude(mut core: Core, entries: Vec<PreludeEntry>) -> Result<Self, Diag> {
+        let names: Vec<_> = entries.iter().map(|e| e.name).collect();
+        for PreludeEntry { name, op } in entries {
+            core.register("prelude", name, op);
+        }
+        let mut ret = Self::without_prelude(Rc::new(RefCell::new(core)));
+        for name in names {
+            ret.import_prelude_fn(name)?;
+        }
+        Ok(ret)
+    }
+
+    fn without_prelude(core: Rc<RefCell<Core>>) -> Self {
         let mut ret = Self {
             core,
             dim_counter: 0,
             var_counter: 0,
             current_mod: Global,
             modules: BTreeMap::new(),
+            generics: BTreeMap::new(),
+            tsr_shapes: BTreeMap::new(),
+            tsr_templates: BTreeMap::new(),
+            imports: BTreeMap::new(),
+            exports: BTreeMap::new(),
+            dim_labels: BTreeMap::new(),
+            var_labels: BTreeMap::new(),
+            max_scope_depth: MAX_SCOPE_DEPTH,
+            scopes_opened: 0,
+            required: vec![],
+            diags: vec![],
+            declared_outputs: BTreeSet::new(),
         };
+        ret.upsert_module(&Global);
+        ret
+    }
 
-        // import basic functions such as view
-        ret.import_prelude().unwrap();
+    /// `a` and `b` must unify, for types an op cannot compare until the
+    /// unifier has bound them
+    pub fn require(&mut self, a: &Type, b: &Type) {
+        self.required.push(Equals(a.clone(), b.clone()));
+    }
 
-        ret
+    /// `dim` must be a multiple of `factor`. Checked by the unifier once
+    /// `dim` is resolved, as the constraint `dim % factor == 0`
+    pub fn require_divisible(&mut self, dim: &Type, factor: i64, span: ByteSpan) {
+        self.required.push(Equals::divisible_by(dim.clone(), factor, span));
+    }
+
+    /// the constraints required since the last call
+    pub fn take_required(&mut self) -> Vec<Equals> {
+        mem::replace(&mut self.required, vec![])
+    }
+
+    /// a tensor located at `span` is the output declared by a module
+    /// signature, mismatches against it are reported there
+    pub fn declare_output(&mut self, span: ByteSpan) {
+        self.declared_outputs.insert(span);
     }
 
-    /// create new dimension type variable
-    pub fn fresh_dim(&mut self, span: ByteSpan) -> Type {
-        self.dim_counter += 1;
+    pub fn is_declared_output(&self, span: &ByteSpan) -> bool {
+        self.declared_outputs.contains(span)
+    }
+
+    /// the diagnostics raised since the last call
+    pub fn take_diags(&mut self) -> Vec<Diag> {
+        mem::replace(&mut self.diags, vec![])
+    }
+
+    /// create new dimension type variable, `label` describes what it stands for.
+    /// Panics once the ids run out: handing one out twice would silently
+    /// unify two unrelated dims.
+    pub fn fresh_dim(&mut self, span: ByteSpan, label: Option<&str>) -> Type {
+        self.dim_counter = next_id(self.dim_counter, "dimension");
+        if let Some(label) = label {
+            self.dim_labels.insert(self.dim_counter, label.to_owned());
+        }
         Type::DIM(self.dim_counter, span)
     }
 
-    /// create new type variable
-    pub fn fresh_var(&mut self, span: ByteSpan) -> Type {
-        self.var_counter += 1;
+    /// create new type variable, `label` describes what it stands for.
+    /// Runs out of ids like `fresh_dim`.
+    pub fn fresh_var(&mut self, span: ByteSpan, label: Option<&str>) -> Type {
+        self.var_counter = next_id(self.var_counter, "type variable");
+        if let Some(label) = label {
+            self.var_labels.insert(self.var_counter, label.to_owned());
+        }
         Type::VAR(self.var_counter, span)
     }
 
-    /// push scope onto stack during tree traversal
-    pub fn push_scope(&mut self, mod_name: &ModName) {
+    /// label a fresh variable was created with
+    pub fn label(&self, ty: &Type) -> Option<&str> {
+        let label = match ty {
+            Type::DIM(id, _) => self.dim_labels.get(id),
+            Type::VAR(id, _) => self.var_labels.get(id),
+            _ => None,
+        };
+        label.map(|l| l.as_str())
+    }
+
+    /// `ty` for display with dimensions under their declared names,
+    /// e.g. `[batch, channels, 28, 28]`
+    pub fn named<'a>(&'a self, ty: &'a Type) -> NamedType<'a> {
+        NamedType(self, ty)
+    }
+
+    /// `ty` could not be inferred, mentioning what it stands for if known
+    pub fn ambiguous(&self, ty: Type) -> Diag {
+        let label = self.label(&ty).map(|l| l.to_owned());
+        Diag::AmbiguousType(ty, label)
+    }
+
+    /// scope the id counters, e.g. to one compilation in a long lived env.
+    /// Ids handed out inside the scope are reused once it is dropped,
+    /// so types created in it must not be kept past that.
+    pub fn fresh_scope(&mut self) -> CounterScope {
+        CounterScope {
+            dim_counter: self.dim_counter,
+            var_counter: self.var_counter,
+            tenv: self,
+        }
+    }
+
+    /// number of scopes open in `mod_name`, its outermost scope included
+    pub fn current_scope_depth(&self, mod_name: &ModName) -> usize {
+        self.modules.get(mod_name).map_or(0, |stack| stack.0.len())
+    }
+
+    pub fn set_max_scope_depth(&mut self, depth: usize) {
+        self.max_scope_depth = depth;
+    }
+
+    fn check_scope_depth(&self, mod_name: &ModName) -> Result<(), Diag> {
+        if self.current_scope_depth(mod_name) >= self.max_scope_depth {
+            let name = match mod_name {
+                Named(ref n) => n.clone(),
+                Global => "global scope".to_owned(),
+            };
+            Err(Diag::ScopeTooDeep(name, self.max_scope_depth))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// push scope onto stack during tree traversal, nothing is pushed
+    /// once the module is `max_scope_depth` scopes deep
+    pub fn push_scope(&mut self, mod_name: &ModName) -> Result<(), Diag> {
+        self.check_scope_depth(mod_name)?;
+        let opened = self.scopes_opened;
+        self.scopes_opened += 1;
         let stack = self.modules.get_mut(mod_name).unwrap();
-        stack.0.push_back(Scope::new());
+        stack.0.push_back(Scope { opened, ..Scope::new() });
+        Ok(())
     }
 
-    /// during constraint collection, push the popped scopes back
-    pub fn push_scope_collection(&mut self, mod_name: &ModName) {
+    /// during constraint collection, push the popped scopes back.
+    /// Fails at the same depth as `push_scope` did, so no scope is queued.
+    pub fn push_scope_collection(&mut self, mod_name: &ModName) -> Result<(), Diag> {
+        self.check_scope_depth(mod_name)?;
         let stack = self.modules.get_mut(mod_name).unwrap();
         let scp = stack.1.pop_front().unwrap();
         stack.0.push_back(scp);
+        Ok(())
     }
 
-    /// exiting block during tree traversal
+    /// exiting block during tree traversal. A nested scope is closed
+    /// before the one enclosing it, but is queued after it
     pub fn pop_scope(&mut self, mod_name: &ModName) {
         let stack = self.modules.get_mut(mod_name).unwrap();
         let popped = stack.0.pop_back().unwrap();
-        stack.1.push_back(popped);
+        let at = stack.1.iter().position(|s| s.opened > popped.opened).unwrap_or(stack.1.len());
+        stack.1.insert(at, popped);
+    }
+
+    /// run `f` in a new scope of `mod_name` and pop it afterwards, whatever
+    /// `f` returns. The env is not borrowed while `f` runs.
+    /// `f` is not run if the scope is too deep to push.
+    pub fn with_module_scope<F, R>(tenv: &RefCell<TypeEnv>, mod_name: &ModName, f: F) -> Result<R, Diag>
+    where
+        F: FnOnce() -> R,
+    {
+        tenv.borrow_mut().push_scope(mod_name)?;
+        let ret = f();
+        tenv.borrow_mut().pop_scope(mod_name);
+        Ok(ret)
+    }
+
+    /// same as `with_module_scope` for the collection pass, which revisits
+    /// the scopes created while annotating. Where annotating was refused a
+    /// scope, `f` runs in the enclosing one, the error is already reported.
+    pub fn with_collection_scope<F, R>(tenv: &RefCell<TypeEnv>, mod_name: &ModName, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        if tenv.borrow_mut().push_scope_collection(mod_name).is_err() {
+            return f();
+        }
+        let ret = f();
+        tenv.borrow_mut().pop_scope(mod_name);
+        ret
+    }
+
+    /// run `f` with an empty queue of collection scopes for `mod_name`, so
+    /// that a pass over a single method does not revisit the scopes of the
+    /// rest of the module. The queue is put back afterwards.
+    pub fn with_isolated_scopes<F, R>(tenv: &RefCell<TypeEnv>, mod_name: &ModName, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let queue = mem::replace(&mut tenv.borrow_mut().modules.get_mut(mod_name).unwrap().1, VecDeque::new());
+        let ret = f();
+        tenv.borrow_mut().modules.get_mut(mod_name).unwrap().1 = queue;
+        ret
     }
 
+    /// resolve the stateful initialization of an alias,
+    /// same lookup order as types: current module first, then global scope
     pub fn resolve_init(&self, mod_name: &ModName, alias: &str) -> Option<Vec<TyFnAppArg>> {
-        let stack = &self.modules[mod_name];
-        stack.2.get(alias).cloned()
+        let find = |m: &ModName| self.modules.get(m)?.2.iter()
+            .find(|(name, _)| name == alias)
+            .map(|(_, args)| args.clone());
+        find(mod_name).or_else(|| find(&Global))
+    }
+
+    /// parameter shapes of layer `alias` declared in `mod_name`,
+    /// `None` if it is not a layer of a core module
+    pub fn layer_parameters(&self, mod_name: &ModName, alias: &str) -> Option<Vec<(String, Vec<i64>)>> {
+        let inits = self.resolve_init(mod_name, alias)?;
+        let layer = match self.resolve_type(mod_name, &Alias::Variable(alias.to_owned()))?.as_mod_name()? {
+            Named(layer) => layer,
+            _ => return None,
+        };
+        let core = self.core.borrow();
+        let params = core.find_mod(&layer)?.parameters(&inits);
+        params
+    }
+
+    /// all stateful initializations of a module in declaration order
+    pub fn module_inits(&self, mod_name: &ModName) -> Vec<(String, Vec<TyFnAppArg>)> {
+        self.modules.get(mod_name)
+            .map(|stack| stack.2.clone())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// names of all named modules, declared or imported
+    pub fn module_names(&self) -> Vec<String> {
+        self.modules
+            .keys()
+            .filter_map(|m| match m {
+                Named(ref s) => Some(s.clone()),
+                Global => None,
+            })
+            .collect()
     }
 
     /// resolve the type of an identifier
@@ -178,6 +448,69 @@ impl TypeEnv {
             .collect()
     }
 
+    /// every alias visible from `mod_name`, i.e. its scope stack on top of
+    /// `Global` where imports live. Only the innermost of names defined in
+    /// several scopes is kept.
+    pub fn types_in_scope(&self, mod_name: &ModName) -> Vec<(Alias, Type)> {
+        let mut visible = BTreeMap::new();
+        let mut mods = vec![&Global];
+        if *mod_name != Global {
+            mods.push(mod_name);
+        }
+        for m in mods {
+            let stack = match self.modules.get(m) {
+                Some(stack) => &stack.0,
+                None => continue,
+            };
+            for scope in stack.iter() {
+                for (alias, ty) in scope.types.iter() {
+                    visible.insert(alias.clone(), ty.clone());
+                }
+            }
+        }
+        visible.into_iter().collect()
+    }
+
+    /// names of the aliases in the scope stack of `mod_name` for which
+    /// `predicate` holds, the innermost definition of each. Unlike
+    /// `types_in_scope` this leaves out `Global` unless it is `mod_name`
+    pub fn aliases_of_kind(&self, mod_name: &ModName, predicate: fn(&Alias) -> bool) -> Vec<(String, Type)> {
+        let mut found = BTreeMap::new();
+        if let Some(stack) = self.modules.get(mod_name) {
+            for scope in stack.0.iter() {
+                for (alias, ty) in scope.types.iter().filter(|(a, _)| predicate(a)) {
+                    found.insert(alias.as_str().to_owned(), ty.clone());
+                }
+            }
+        }
+        found.into_iter().collect()
+    }
+
+    /// methods such as `forward`, and layers declared in `mod_name`
+    pub fn function_aliases(&self, mod_name: &ModName) -> Vec<(String, Type)> {
+        self.aliases_of_kind(mod_name, Alias::is_function)
+    }
+
+    pub fn variable_aliases(&self, mod_name: &ModName) -> Vec<(String, Type)> {
+        self.aliases_of_kind(mod_name, Alias::is_variable)
+    }
+
+    /// rewrite every type stored in every module with `subst`, so that
+    /// later lookups see the solved types. Applying it again changes nothing
+    pub fn resolve_all(&mut self, subst: &Substitution) {
+        let mut subst = subst.clone();
+        for (stack, queue, _) in self.modules.values_mut() {
+            for scope in stack.iter_mut().chain(queue.iter_mut()) {
+                for ty in scope.types.values_mut() {
+                    *ty = subst.apply_ty(ty);
+                }
+            }
+        }
+        for ty in self.tsr_shapes.values_mut() {
+            *ty = subst.apply_ty(ty);
+        }
+    }
+
     /// if current module does not exist, create and insert it, nop otherwise
     pub fn upsert_module(&mut self, mod_name: &ModName) {
         if !self.modules.contains_key(mod_name) {
@@ -185,7 +518,7 @@ impl TypeEnv {
                 // if the module does not yet exist, add with an empty scope
                 let mut q = VecDeque::new();
                 q.push_back(Scope::new());
-                (q, VecDeque::new(), BTreeMap::new())
+                (q, VecDeque::new(), vec![])
             });
         }
     }
@@ -196,7 +529,7 @@ impl TypeEnv {
             // if the module does not yet exist, add with an empty scope
             let mut q = VecDeque::new();
             q.push_back(Scope::new());
-            (q, VecDeque::new(), BTreeMap::new())
+            (q, VecDeque::new(), vec![])
         });
 
         let top = stack.0.len() - 1;
@@ -223,7 +556,7 @@ impl TypeEnv {
             // if the module does not yet exist, add with an empty scope
             let mut q = VecDeque::new();
             q.push_back(Scope::new());
-            (q, VecDeque::new(), BTreeMap::new())
+            (q, VecDeque::new(), vec![])
         });
 
         let top = stack.0.len() - 1;
@@ -240,7 +573,7 @@ impl TypeEnv {
             // if the module does not yet exist, add with an empty scope
             let mut q = VecDeque::new();
             q.push_back(Scope::new());
-            (q, VecDeque::new(), BTreeMap::new())
+            (q, VecDeque::new(), vec![])
         });
 
         for scope in &mut stack.0 {
@@ -254,15 +587,15 @@ impl TypeEnv {
     pub fn add_init(&mut self, mod_name: &ModName, alias: &str, ty: Vec<TyFnAppArg>) {
         let stack = self.modules.get_mut(&mod_name).unwrap();
 
-        if stack.2.contains_key(alias) {
+        if stack.2.iter().any(|(name, _)| name == alias) {
             panic!("duplicate item");
         }
-        let _ = stack.2.insert(alias.to_owned(), ty);
+        stack.2.push((alias.to_owned(), ty));
     }
 
     /// tie an alias with a type variable dimension
     pub fn add_dim_alias(&mut self, mod_name: &ModName, alias: &Alias, span: ByteSpan) -> Result<(), Diag> {
-        let tyvar = self.fresh_dim(span);
+        let tyvar = self.fresh_dim(span, Some(alias.as_str()));
         self.add_type(mod_name, alias, tyvar)
     }
 
@@ -286,8 +619,9 @@ impl TypeEnv {
         tsr: &[String],
         span: &ByteSpan,
     ) -> Result<(), Diag> {
+        self.check_tensor_dims(mod_name, tsr, span)?;
         // first insert all the dims
-        for t in tsr.iter() {
+        for t in dim_aliases(tsr).iter() {
             let alias = Alias::Variable(t.to_string());
             if !self.exists(mod_name, &alias) {
                 self.add_dim_alias(mod_name, &alias, *span)?;
@@ -296,7 +630,86 @@ impl TypeEnv {
 
         // then insert the tensor itself
         let tsr = self.create_tensor(mod_name, tsr, span);
-        self.add_type(mod_name, alias, tsr)
+        self.add_type(mod_name, alias, tsr.clone())?;
+        self.check_tensor_shape(mod_name, alias, tsr)
+    }
+
+    /// `tsr Batch[n] = [n, 3, 224, 224]`, a tensor alias whose `params` are
+    /// substituted by the dims supplied wherever it is used
+    pub fn add_tsr_template(
+        &mut self,
+        mod_name: &ModName,
+        alias: &Alias,
+        params: &[String],
+        tsr: &[String],
+        span: &ByteSpan,
+    ) -> Result<(), Diag> {
+        let dims: Vec<String> = tsr.iter()
+            .filter(|t| !params.contains(t))
+            .cloned()
+            .collect();
+        self.check_tensor_dims(mod_name, &dims, span)?;
+        for t in dim_aliases(&dims).iter().filter(|t| t.parse::<i64>().is_err()) {
+            let alias = Alias::Variable(t.to_string());
+            if !self.exists(mod_name, &alias) {
+                self.add_dim_alias(mod_name, &alias, *span)?;
+            }
+        }
+        self.tsr_templates.insert(
+            (mod_name.clone(), alias.as_str().to_owned()),
+            (params.to_vec(), tsr.to_vec()),
+        );
+        Ok(())
+    }
+
+    /// the tensor `Batch[args..]` where `Batch` is a parameterized alias
+    pub fn instantiate_tsr(
+        &mut self,
+        mod_name: &ModName,
+        name: &str,
+        args: &[String],
+        span: &ByteSpan,
+    ) -> Result<Type, Diag> {
+        let (params, dims) = self.tsr_templates.get(&(mod_name.clone(), name.to_owned()))
+            .or_else(|| self.tsr_templates.get(&(Global, name.to_owned())))
+            .cloned()
+            .ok_or_else(|| Diag::SymbolNotFound(name.to_owned(), *span))?;
+        if params.len() != args.len() {
+            return Err(Diag::InvalidArgument(
+                format!("wrong number of dims for `{}`: expected {}, got {}", name, params.len(), args.len()),
+                *span,
+            ));
+        }
+        let dims: Vec<String> = dims.iter()
+            .map(|d| match params.iter().position(|p| p == d) {
+                Some(i) => args[i].clone(),
+                None => d.clone(),
+            })
+            .collect();
+        self.check_tensor_dims(mod_name, &dims, span)?;
+        Ok(self.create_tensor(mod_name, &dims, span))
+    }
+
+    /// a tensor alias that is bound again in the same module, or that
+    /// shadows a global one, must agree with the shape it was first bound
+    /// to wherever that shape was resolved. Other modules may reuse the name.
+    fn check_tensor_shape(&mut self, mod_name: &ModName, alias: &Alias, tsr: Type) -> Result<(), Diag> {
+        let key = (mod_name.clone(), alias.as_str().to_owned());
+        let first = self.first_tensor_shape(&key).cloned();
+        match first {
+            Some(ref first) if conflicting_shapes(first, &tsr) =>
+                Err(Diag::ConflictingTensorShape(key.1, first.clone(), tsr)),
+            _ => {
+                self.tsr_shapes.entry(key).or_insert(tsr);
+                Ok(())
+            }
+        }
+    }
+
+    /// the shape the alias `key` was first bound to in its module, or globally
+    fn first_tensor_shape(&self, key: &(ModName, String)) -> Option<&Type> {
+        self.tsr_shapes.get(key)
+            .or_else(|| self.tsr_shapes.get(&(Global, key.1.clone())))
     }
 
     // make a new tensor based on type signature
@@ -306,18 +719,33 @@ impl TypeEnv {
         dims: &[String],
         span: &ByteSpan,
     ) -> Type {
+        let mut tags = Tags::default();
+        let mut dims = dims;
+        // layout and precision tags trail the dims
+        while dims.last().map_or(false, |t| Tags::is_tag(t)) {
+            let (tag, rest) = dims.split_last().unwrap();
+            tags.layout = tags.layout.or(Layout::from_tag(tag));
+            tags.precision = tags.precision.or(Precision::from_tag(tag));
+            dims = rest;
+        }
         // each dimension alias in the tensor type signature must exist
         let dims_ty = dims.iter()
             .map(|t| {
+                if DimExpr::is_expr(t) {
+                    return match self.fold_dim_expr(mod_name, t, span) {
+                        Ok(Some(i)) => vec![Type::ResolvedDim(i, *span)],
+                        _ => vec![self.fresh_dim(*span, Some(t.as_str()))],
+                    };
+                }
                 match t.parse::<i64>() {
                     Ok(i) => vec![Type::ResolvedDim(i, *span)],
                     Err(_e) => {
                         let alias = Alias::Variable(t.to_string());
                         let ty = self.resolve_type(mod_name, &alias)
                             .or_else(|| self.resolve_type(&Global, &alias))
-                            .unwrap_or_else(|| self.fresh_dim(*span))
+                            .unwrap_or_else(|| self.fresh_dim(*span, Some(t.as_str())))
                             .clone();
-                        if let Type::TSR(vs, _) = ty {
+                        if let Type::TSR(vs, _, _) = ty {
                             vs
                         } else {
                             vec![ty]
@@ -328,24 +756,39 @@ impl TypeEnv {
             .flatten()
             .collect();
         // create the tensor type
-        Type::TSR(dims_ty, *span)
+        Type::TSR(dims_ty, tags, *span)
     }
 
     /// generate a tensor from untyped ast tensor signature
-    pub fn resolve_tensor(&mut self, mod_name: &ModName, t: &TensorTy) -> Type {
+    pub fn resolve_tensor(&mut self, mod_name: &ModName, t: &TensorTy) -> Result<Type, Diag> {
         match t {
             TensorTy::Generic(ref dims, ref sp) => {
-                self.create_tensor(mod_name, &dims, sp)
+                Ok(self.create_tensor(mod_name, &dims, sp))
             }
             TensorTy::Tensor(ref alias, ref sp) => {
                 self.resolve_type(mod_name, &Alias::Variable(alias.to_string()))
                     .or_else(|| self.resolve_type(&Global, &Alias::Variable(alias.to_string())))
-                    .unwrap()
-                    .with_span(sp)
+                    .map(|ty| ty.with_span(sp))
+                    .ok_or_else(|| Diag::SymbolNotFound(alias.to_string(), *sp))
+            }
+            TensorTy::Applied(ref alias, ref args, ref sp) => {
+                self.instantiate_tsr(mod_name, alias, args, sp)
             }
         }
     }
 
+    /// dimension variables bound to the batch alias `?`, these are
+    /// expected to stay symbolic
+    pub fn batch_dims(&self) -> Vec<Type> {
+        let batch = Alias::Variable("?".to_owned());
+        self.modules
+            .values()
+            .flat_map(|stack| stack.0.iter().chain(stack.1.iter()))
+            .filter_map(|sc| sc.types.get(&batch))
+            .cloned()
+            .collect()
+    }
+
     /// check if an alias exists
     pub fn exists(&self, mod_name: &ModName, alias: &Alias) -> bool {
         let types = self.get_scoped_types(mod_name, alias);
@@ -357,10 +800,16 @@ impl TypeEnv {
         match a {
             AliasAssign::Tensor {
                 ident: ref id,
+                ref params,
                 rhs: TensorTy::Generic(ref tys, ref sp),
                 ..
             } => {
-                self.add_tsr_alias(mod_name, &Alias::Variable(id.to_string()), tys, sp)
+                let alias = Alias::Variable(id.to_string());
+                if params.is_empty() {
+                    self.add_tsr_alias(mod_name, &alias, tys, sp)
+                } else {
+                    self.add_tsr_template(mod_name, &alias, params, tys, sp)
+                }
             }
             AliasAssign::Dimension {
                 ident: ref id,
@@ -369,21 +818,289 @@ impl TypeEnv {
             } => {
                 self.add_resolved_dim_alias(mod_name, &Alias::Variable(id.to_string()), *num, span)
             }
+            AliasAssign::Dimension {
+                ident: ref id,
+                rhs: Term::Float(num, _),
+                ref span,
+            } => {
+                if !num.is_finite() {
+                    return Err(Diag::InvalidLiteral(num.to_string(), *span));
+                }
+                // a float is a value alias, dimensions must be integral
+                self.add_type(mod_name, &Alias::Variable(id.to_string()), Type::FLOAT(*span))
+            }
             _ => unimplemented!(),
         }
     }
 
+    /// dimensions in a tensor signature must not refer to float aliases
+    fn check_tensor_dims(&self, mod_name: &ModName, dims: &[String], span: &ByteSpan) -> Result<(), Diag> {
+        for t in dim_aliases(dims).iter() {
+            if let Some(Type::FLOAT(_)) = self.resolve_dim_alias(mod_name, t) {
+                return Err(Diag::FloatDimension(t.to_string(), *span));
+            }
+        }
+        for t in dims.iter().filter(|t| DimExpr::is_expr(t)) {
+            self.fold_dim_expr(mod_name, t, span)?;
+        }
+        Ok(())
+    }
+
+    /// look up a dim alias in the module if it exists, then globally
+    fn resolve_dim_alias(&self, mod_name: &ModName, name: &str) -> Option<Type> {
+        let alias = Alias::Variable(name.to_owned());
+        let ty = if self.modules.contains_key(mod_name) {
+            self.resolve_type(mod_name, &alias)
+        } else {
+            None
+        };
+        ty.or_else(|| self.resolve_type(&Global, &alias))
+    }
+
+    /// fold a dim expression such as `n/2`, `None` if an operand is not resolved yet
+    fn fold_dim_expr(&self, mod_name: &ModName, token: &str, span: &ByteSpan) -> Result<Option<i64>, Diag> {
+        let expr = DimExpr::parse(token)
+            .ok_or_else(|| Diag::InvalidDimExpr(token.to_owned(), "cannot parse".to_owned(), *span))?;
+        expr.eval(&|name: &str| self.resolve_dim_alias(mod_name, name).and_then(|ty| ty.as_num()))
+            .map_err(|msg| Diag::InvalidDimExpr(token.to_owned(), msg, *span))
+    }
+
     pub fn import_top_level_ty_sig(&mut self, mod_name: &ModName, ty_sig: &TensorTy) -> Result<(), Diag> {
-        if let TensorTy::Generic(dims, span) = ty_sig {
-            // first insert all the dims
-            for t in dims.iter().filter(|t| t.parse::<i64>().is_err()) {
-                let alias =  Alias::Variable(t.to_string());
-                if !self.exists(mod_name, &alias) {
-                    self.add_dim_alias(mod_name, &alias, *span)?;
+        let (dims, span) = match ty_sig {
+            TensorTy::Generic(dims, span) | TensorTy::Applied(_, dims, span) => (dims, span),
+            TensorTy::Tensor(..) => return Ok(()),
+        };
+        self.check_tensor_dims(mod_name, dims, span)?;
+        // first insert all the dims
+        for t in dim_aliases(dims).iter().filter(|t| t.parse::<i64>().is_err()) {
+            let alias =  Alias::Variable(t.to_string());
+            if !self.exists(mod_name, &alias) {
+                self.add_dim_alias(mod_name, &alias, *span)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// generalize the dimension parameters of a module so that each
+    /// instantiation gets its own copy of the dim variables
+    pub fn generalize_module(&mut self, mod_name: &ModName, params: &[String], span: &ByteSpan) -> Result<(), Diag> {
+        let mut scheme = vec![];
+        for p in params.iter() {
+            match self.resolve_type(mod_name, &Alias::Variable(p.to_owned())) {
+                Some(Type::DIM(id, _)) => scheme.push((p.to_owned(), id)),
+                Some(ty) => return Err(Diag::InvalidDimParam(p.to_owned(), ty)),
+                None => return Err(Diag::SymbolNotFound(p.to_owned(), *span)),
+            }
+        }
+        self.generics.insert(mod_name.as_str().to_owned(), scheme);
+        Ok(())
+    }
+
+    /// check if a module takes dimension parameters
+    pub fn is_generic_module(&self, name: &str) -> bool {
+        self.generics.contains_key(name)
+    }
+
+    /// instantiate the type of a generic module. Supplied parameters are bound
+    /// to resolved dims, the others to fresh dimension variables.
+    pub fn instantiate_module(&mut self, name: &str, dims: &BTreeMap<String, i64>, span: &ByteSpan) -> Option<Type> {
+        let ty = self.resolve_type(&Global, &Alias::Variable(name.to_owned()))?;
+        let scheme = match self.generics.get(name) {
+            Some(scheme) => scheme.clone(),
+            None => return Some(ty),
+        };
+        Some(scheme.iter().fold(ty, |ty, (param, id)| {
+            let replacement = match dims.get(param) {
+                Some(d) => Type::ResolvedDim(*d, *span),
+                None => self.fresh_dim(*span, Some(&format!("{}.{}", name, param))),
+            };
+            ty.replace_dim(*id, &replacement)
+        }))
+    }
+
+    /// instances of generic modules that supply a dimension parameter the
+    /// module body fixes to another value. The body is only checked against
+    /// its declaration, so this runs once `subst` binds the dims of every body.
+    pub fn check_instances(&self, subst: &Substitution) -> Vec<Diag> {
+        let mut subst = subst.clone();
+        let mut errs = vec![];
+        for mod_name in self.modules.keys() {
+            for (name, args) in self.module_inits(mod_name) {
+                let scheme = match self.resolve_type(mod_name, &Alias::Variable(name)) {
+                    Some(Type::Module(ref m, _, _)) => match self.generics.get(m) {
+                        Some(scheme) => scheme,
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                for (param, id) in scheme.iter() {
+                    let supplied = args.iter()
+                        .find(|a| a.name.as_ref() == Some(param))
+                        .and_then(|a| Some(Type::ResolvedDim(a.arg.as_num()?, a.span)));
+                    let supplied = match supplied {
+                        Some(ty) => ty,
+                        None => continue,
+                    };
+                    let fixed = subst.apply_ty(&Type::DIM(*id, supplied.span()));
+                    match fixed.as_num() {
+                        Some(d) if Some(d) != supplied.as_num() =>
+                            errs.push(Diag::DimensionMismatch(supplied, Type::ResolvedDim(d, fixed.span()))),
+                        _ => (),
+                    }
                 }
             }
         }
+        errs
+    }
+
+    /// rename a module and rewrite every stored type that refers to it
+    pub fn rename_module(&mut self, old: &ModName, new: &ModName) -> Result<(), Diag> {
+        let (old_name, new_name) = (old.as_str().to_owned(), new.as_str().to_owned());
+        if self.modules.contains_key(new) {
+            return Err(Diag::DuplicateModule(new_name));
+        }
+        if let Some(stack) = self.modules.remove(old) {
+            self.modules.insert(new.clone(), stack);
+        }
+        if let Some(scheme) = self.generics.remove(&old_name) {
+            self.generics.insert(new_name.clone(), scheme);
+        }
 
+        let old_alias = Alias::Variable(old_name.clone());
+        for stack in self.modules.values_mut() {
+            for scope in stack.0.iter_mut().chain(stack.1.iter_mut()) {
+                // the module type itself is aliased by its name
+                if let Some(ty) = scope.types.remove(&old_alias) {
+                    scope.types.insert(Alias::Variable(new_name.clone()), ty);
+                }
+                for ty in scope.types.values_mut() {
+                    *ty = ty.rename_module(&old_name, &new_name);
+                }
+            }
+        }
+        if self.current_mod == *old {
+            self.current_mod = new.clone();
+        }
+        Ok(())
+    }
+
+    /// fold an environment built from another file into this one. The ids
+    /// of `other` are shifted past those handed out here, its modules are
+    /// added and its global aliases become visible. Nothing is changed if
+    /// a module other than a `core` one is defined in both or a global
+    /// alias has two types.
+    pub fn merge(&mut self, other: TypeEnv) -> Result<(), Diag> {
+        let (dims, vars) = (self.dim_counter, self.var_counter);
+        let TypeEnv {
+            dim_counter,
+            var_counter,
+            scopes_opened,
+            modules,
+            generics,
+            tsr_shapes,
+            imports,
+            exports,
+            dim_labels,
+            var_labels,
+            ..
+        } = other;
+        // the ids of `other` are shifted past ours
+        let dim_total = dims.checked_add(dim_counter)
+            .ok_or_else(|| Diag::OutOfTypeIds("dimension".to_owned()))?;
+        let var_total = vars.checked_add(var_counter)
+            .ok_or_else(|| Diag::OutOfTypeIds("type variable".to_owned()))?;
+
+        let opened = self.scopes_opened;
+        let renumber_scope = |scope: Scope| Scope {
+            types: scope.types.into_iter().map(|(a, ty)| (a, ty.renumber(dims, vars))).collect(),
+            opened: scope.opened + opened,
+        };
+        let mut modules = modules.into_iter()
+            .map(|(m, (stack, queue, inits))| {
+                let inits = inits.into_iter()
+                    .map(|(name, args)| (name, args.iter().map(|a| a.renumber(dims, vars)).collect()))
+                    .collect::<InitMap>();
+                let stack = stack.into_iter().map(&renumber_scope).collect::<ScopeStack>();
+                let queue = queue.into_iter().map(&renumber_scope).collect::<ScopeQueue>();
+                (m, (stack, queue, inits))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let tsr_shapes = tsr_shapes.into_iter()
+            .map(|(key, tsr)| (key, tsr.renumber(dims, vars)))
+            .collect::<BTreeMap<_, _>>();
+
+        // every env imports the prelude, and may import the same ops from
+        // `core`, those are the same module on both sides
+        {
+            let core = self.core.borrow();
+            let shared: Vec<_> = modules.keys()
+                .filter(|m| self.modules.contains_key(*m) && core.find_mod(m.as_str()).is_some())
+                .cloned()
+                .collect();
+            for m in shared {
+                modules.remove(&m);
+            }
+        }
+
+        // check everything before touching `self`
+        if let Some(m) = modules.keys().find(|m| **m != Global && self.modules.contains_key(*m)) {
+            return Err(Diag::DuplicateModule(m.as_str().to_owned()));
+        }
+        let (global_scopes, global_queue, global_inits) = modules.remove(&Global).unwrap();
+        let mut globals = vec![];
+        for scope in global_scopes {
+            for (alias, ty) in scope.types {
+                match self.resolve_type(&Global, &alias) {
+                    Some(ref orig) if *orig != ty => return Err(
+                        Diag::DuplicateVarInScope(alias.as_str().to_owned(), orig.clone(), ty)
+                    ),
+                    Some(_) => (),
+                    None => globals.push((alias, ty)),
+                }
+            }
+        }
+        for (key, tsr) in tsr_shapes.iter() {
+            if let Some(first) = self.first_tensor_shape(key) {
+                if conflicting_shapes(first, tsr) {
+                    return Err(Diag::ConflictingTensorShape(key.1.clone(), first.clone(), tsr.clone()));
+                }
+            }
+        }
+
+        self.dim_counter = dim_total;
+        self.var_counter = var_total;
+        self.scopes_opened += scopes_opened;
+        self.modules.extend(modules);
+        {
+            let global = self.modules.get_mut(&Global).unwrap();
+            global.0[0].types.extend(globals);
+            global.1.extend(global_queue);
+            for (name, args) in global_inits {
+                if !global.2.iter().any(|(n, _)| *n == name) {
+                    global.2.push((name, args));
+                }
+            }
+        }
+        self.generics.extend(generics.into_iter().map(|(name, scheme)|
+            (name, scheme.into_iter().map(|(param, id)| (param, id + dims)).collect())
+        ));
+        for (key, tsr) in tsr_shapes {
+            self.tsr_shapes.entry(key).or_insert(tsr);
+        }
+        for (name, paths) in imports {
+            let known = self.imports.entry(name).or_insert_with(Vec::new);
+            for path in paths {
+                if !known.contains(&path) {
+                    known.push(path);
+                }
+            }
+        }
+        for (path, assigns) in exports {
+            self.exports.entry(path).or_insert(assigns);
+        }
+        self.dim_labels.extend(dim_labels.into_iter().map(|(id, l)| (id + dims, l)));
+        self.var_labels.extend(var_labels.into_iter().map(|(id, l)| (id + vars, l)));
         Ok(())
     }
 
@@ -412,17 +1129,68 @@ impl TypeEnv {
         .collect())
     }
 
+    /// make the `pub` aliases of `program` importable as `use path_name::{..}`.
+    /// Its other aliases stay private to it.
+    pub fn export_file(&mut self, path_name: &str, program: &Term) {
+        let aliases = match program {
+            Term::Program(decls) => decls.iter()
+                .filter_map(|d| match d {
+                    Decl::Export(a) => Some(a.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        self.exports.insert(path_name.to_owned(), aliases);
+    }
+
+    /// import a module into global scope along with its associated functions.
+    /// A name that is already imported from another path keeps resolving to
+    /// the first import and an `AmbiguousImport` warning is returned.
+    pub fn import_name(&mut self, path_name: &str, name: &str, span: ByteSpan) -> Result<(), Diag> {
+        {
+            let paths = self.imports.entry(name.to_owned()).or_insert_with(Vec::new);
+            if !paths.is_empty() && !paths.iter().any(|p| p == path_name) {
+                paths.push(path_name.to_owned());
+                return Err(Diag::AmbiguousImport(name.to_owned(), paths.clone(), span));
+            }
+            paths.push(path_name.to_owned());
+        }
+        if let Some(aliases) = self.exports.get(path_name).cloned() {
+            return match aliases.iter().find(|a| a.ident() == name) {
+                Some(a) => self.import_node_assign(&Global, a),
+                None => Err(Diag::ImportError(name.to_owned(), span)),
+            };
+        }
+        let add_result = self.add_type(&Global, &Alias::Variable(name.to_owned()), Type::Module(name.to_owned(), None, span));
+        match self.import_module(path_name, name) {
+            Some(Ok(())) => add_result,
+            Some(Err(e)) => Err(e),
+            None => Err(Diag::ImportError(name.to_owned(), span)),
+        }
+    }
+
+    /// import every function core registers under the `prelude` path
     pub fn import_prelude(&mut self) -> Result<(), Diag> {
-        for fun in &vec!["view"] {
-            self.add_type(&Global,
-                &Alias::Variable(fun.to_string()),
-                module!(fun.to_string())
-            )?;
-            self.import_module("prelude", fun);
+        let funs = self.core.borrow().names("prelude");
+        for fun in funs {
+            self.import_prelude_fn(fun)?;
         }
         Ok(())
     }
 
+    fn import_prelude_fn(&mut self, fun: &str) -> Result<(), Diag> {
+        self.add_type(&Global,
+            &Alias::Variable(fun.to_string()),
+            module!(fun.to_string())
+        )?;
+        match self.import_module("prelude", fun) {
+            Some(result) => result,
+            // a prelude entry that core does not register
+            None => Err(Diag::ImportError(fun.to_owned(), CSpan::fresh_span())),
+        }
+    }
+
     pub fn resolve_unresolved(
         &mut self,
         ty: &Type,
@@ -448,10 +1216,30 @@ impl TypeEnv {
             match find_result {
                 Some(op) => {
                     let is_stateful = op.is_stateful();
+                    let variadic = op.variadic(fn_name);
+                    let args_span = arg_ty.span();
+                    let (arg_ty, args, names) = match variadic {
+                        Some(ref v) => v.pack(arg_ty, args, args_span)?,
+                        None => (arg_ty, args, vec![]),
+                    };
+                    // omitted args take their default, supplied ones win
+                    let args = with_defaults(args, op.defaults(fn_name));
+                    check_required(&args, op.required(fn_name), fn_name, args_span)?;
+                    op.validate(fn_name, &args)?;
+                    if let Some(expected) = op.layout(fn_name) {
+                        check_layout(&arg_ty, expected)?;
+                    }
+                    if let Some(w) = op.precision(fn_name).and_then(|p| check_precision(&arg_ty, p)) {
+                        self.diags.push(w);
+                    }
+                    let inits = inits.map(|inits| with_defaults(inits, op.defaults("new")));
                     op.resolve(self, fn_name, arg_ty, ret_ty, args, inits)
                         .transpose()
                         .map(|t|
-                            t.map(|i| (i, is_stateful))
+                            t.map(|i| match variadic {
+                                Some(ref v) => (v.unpack(i, &names), is_stateful),
+                                None => (i, is_stateful),
+                            })
                         )
                 }
                 None =>
@@ -462,3 +1250,693 @@ impl TypeEnv {
         }
     }
 }
+
+/// the id after `counter`, see `TypeEnv::fresh_dim`
+fn next_id(counter: TypeId, what: &str) -> TypeId {
+    match counter.checked_add(1) {
+        Some(id) => id,
+        None => panic!("{:?}", Diag::OutOfTypeIds(what.to_owned())),
+    }
+}
+
+/// restores the id counters of a `TypeEnv` on drop
+pub struct CounterScope<'a> {
+    tenv: &'a mut TypeEnv,
+    dim_counter: TypeId,
+    var_counter: TypeId,
+}
+
+impl<'a> Deref for CounterScope<'a> {
+    type Target = TypeEnv;
+    fn deref(&self) -> &TypeEnv {
+        self.tenv
+    }
+}
+
+impl<'a> DerefMut for CounterScope<'a> {
+    fn deref_mut(&mut self) -> &mut TypeEnv {
+        self.tenv
+    }
+}
+
+impl<'a> Drop for CounterScope<'a> {
+    fn drop(&mut self) {
+        self.tenv.dim_counter = self.dim_counter;
+        self.tenv.var_counter = self.var_counter;
+        // the ids are handed out again, so are their labels
+        self.tenv.dim_labels.split_off(&(self.dim_counter + 1));
+        self.tenv.var_labels.split_off(&(self.var_counter + 1));
+    }
+}
+
+/// see `TypeEnv::named`. A dim shows the alias it was declared with,
+/// its value once resolved and `?` otherwise.
+pub struct NamedType<'a>(&'a TypeEnv, &'a Type);
+
+impl<'a> NamedType<'a> {
+    fn join(&self, f: &mut Formatter, tys: &[Type]) -> Result<(), Error> {
+        for (i, ty) in tys.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", self.0.named(ty))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Display for NamedType<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        use self::Type::*;
+        let NamedType(tenv, ty) = *self;
+        match ty {
+            DIM(..) | VAR(..) => match tenv.label(ty) {
+                // other labels say where a variable came from, not what it is called
+                Some(name) if name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                    write!(f, "{}", name),
+                _ => write!(f, "?"),
+            },
+            ResolvedDim(n, _) => write!(f, "{}", n),
+            DerivedDim(op, a, b, _) => write!(f, "({} {} {})", tenv.named(a), op, tenv.named(b)),
+            TSR(dims, _, _) => {
+                write!(f, "[")?;
+                self.join(f, dims)?;
+                write!(f, "]")
+            }
+            Tuple(tys, _) => {
+                write!(f, "(")?;
+                self.join(f, tys)?;
+                write!(f, ")")
+            }
+            FnArgs(args, _) => self.join(f, args),
+            FnArg(Some(name), ty, _) => write!(f, "{}={}", name, tenv.named(ty)),
+            FnArg(None, ty, _) => write!(f, "{}", tenv.named(ty)),
+            FUN(_, _, p, r, _) => write!(f, "({}) -> {}", tenv.named(p), tenv.named(r)),
+            _ => write!(f, "{:?}", ty),
+        }
+    }
+}
+
+/// two bindings of a tensor alias disagree where both are resolved
+fn conflicting_shapes(first: &Type, tsr: &Type) -> bool {
+    use self::Type::*;
+    match (first, tsr) {
+        (TSR(ref d1, _, _), TSR(ref d2, _, _)) => {
+            d1.len() != d2.len() || d1.iter().zip(d2).any(|(a, b)| match (a, b) {
+                (ResolvedDim(n1, _), ResolvedDim(n2, _)) => n1 != n2,
+                (ResolvedDim(..), _) | (_, ResolvedDim(..)) => true,
+                _ => false,
+            })
+        }
+        _ => false,
+    }
+}
+
+/// dim tokens with each arithmetic expression replaced by the aliases it references
+fn dim_aliases(dims: &[String]) -> Vec<String> {
+    dims.iter()
+        .filter(|t| !Tags::is_tag(t))
+        .flat_map(|t| match DimExpr::parse(t) {
+            Some(ref e) if DimExpr::is_expr(t) => e.aliases(),
+            _ => vec![t.to_owned()],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{MethodName, PyTorch, Resolve};
+    use typing::typed_term::TyTerm;
+    use codespan::CodeMap;
+
+    fn tenv() -> TypeEnv {
+        TypeEnv::new(Rc::new(RefCell::new(Core::new())))
+    }
+
+    #[test]
+    fn should_resolve_every_stored_type() {
+        use compile::compile_str;
+
+        let model = compile_str("
+            use lin::Linear;
+            use nonlin::relu;
+            node Fixed<[4,2] -> [4,1]> {
+            }
+            weights Fixed<[4,2] -> [4,1]> {
+                fc1 = Linear::new(in=2, out=3);
+                fc2 = Linear::new(in=3, out=1);
+            }
+            graph Fixed<[4,2] -> [4,1]> {
+                def new() -> Self {
+                    self
+                }
+                def forward {
+                    x |> fc1 |> (def t => mul(x=t, y=2)) |> relu |> fc2
+                }
+            }
+        ").unwrap();
+        let mod_name = ModName::Named("Fixed".to_owned());
+        let stored = |tenv: &TypeEnv| -> Vec<(Alias, Type)> {
+            tenv.modules[&mod_name].0.iter()
+                .chain(tenv.modules[&mod_name].1.iter())
+                .flat_map(|s| s.types.iter().map(|(a, t)| (a.clone(), t.clone())))
+                .collect()
+        };
+        let mut tenv = model.tenv.borrow_mut();
+        let before = stored(&tenv);
+        // the lambda parameter is stored as a variable, the shapes are concrete
+        assert!(before.iter().any(|(a, t)| a.as_str() == "t" && !t.free_vars().is_empty()));
+
+        tenv.resolve_all(&model.sub);
+        let resolved = stored(&tenv);
+        assert_eq!(resolved.len(), before.len());
+        for ((alias, _), (_, ty)) in before.iter().zip(resolved.iter()) {
+            assert!(ty.free_vars().is_empty(), "{:?}: {:?}", alias, ty);
+        }
+        // idempotent
+        tenv.resolve_all(&model.sub);
+        assert_eq!(stored(&tenv), resolved);
+    }
+
+    #[test]
+    fn should_instantiate_generic_module_independently() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let module = Named("Block".to_owned());
+        let dims = vec!["?".to_owned(), "hidden".to_owned()];
+        tenv.upsert_module(&module);
+        tenv.import_top_level_ty_sig(&module, &TensorTy::Generic(dims.clone(), sp)).unwrap();
+        let x = tenv.create_tensor(&module, &dims, &sp);
+        let mod_ty = Type::Module("Block".to_owned(), Some(box fun!("Block", "forward", args!(arg!("x", x.clone())), x)), sp);
+        tenv.add_type(&Global, &Alias::Variable("Block".to_owned()), mod_ty).unwrap();
+        tenv.generalize_module(&module, &["hidden".to_owned()], &sp).unwrap();
+
+        let hidden = |ty: &Type| match ty {
+            Type::Module(_, Some(box ref f), _) => f.first_arg_ty().unwrap().as_vec().unwrap()[1].as_num(),
+            _ => None,
+        };
+        let b64 = tenv.instantiate_module("Block", &btreemap!{"hidden".to_owned() => 64}, &sp).unwrap();
+        let b128 = tenv.instantiate_module("Block", &btreemap!{"hidden".to_owned() => 128}, &sp).unwrap();
+        assert_eq!(hidden(&b64), Some(64));
+        assert_eq!(hidden(&b128), Some(128));
+        // the declaration itself stays generic
+        let decl = tenv.resolve_type(&Global, &Alias::Variable("Block".to_owned())).unwrap();
+        assert_eq!(hidden(&decl), None);
+    }
+
+    #[test]
+    fn should_list_module_inits_in_declaration_order() {
+        let mut tenv = tenv();
+        let module = Named("Mnist".to_owned());
+        tenv.upsert_module(&module);
+        let arg = |name: &str, i: i64| TyFnAppArg {
+            name: Some(name.to_owned()),
+            arg: box TyTerm::TyInteger(int!(), i, CSpan::fresh_span()),
+            span: CSpan::fresh_span(),
+        };
+        tenv.add_init(&module, "fc2", vec![arg("in", 50), arg("out", 10)]);
+        tenv.add_init(&module, "fc1", vec![arg("in", 320), arg("out", 50)]);
+
+        let inits = tenv.module_inits(&module);
+        let names: Vec<_> = inits.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["fc2", "fc1"]);
+        assert_eq!(inits[1].1[0].arg.as_num(), Some(320));
+        assert_eq!(inits[1].1[1].name, Some("out".to_owned()));
+    }
+
+    #[test]
+    fn should_rename_module_references() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let (block, model) = (Named("Block".to_owned()), Named("Model".to_owned()));
+        tenv.upsert_module(&block);
+        tenv.add_type(&Global, &Alias::Variable("Block".to_owned()), module!("Block")).unwrap();
+        tenv.add_type(&model, &Alias::Variable("blk".to_owned()),
+            Type::Module("Block".to_owned(), Some(box fun!("Block", "forward", args!(), unit!())), sp)).unwrap();
+
+        tenv.rename_module(&block, &Named("Residual".to_owned())).unwrap();
+        match tenv.resolve_type(&model, &Alias::Variable("blk".to_owned())) {
+            Some(Type::Module(ref n, Some(box Type::FUN(ref m, ..)), _)) => {
+                assert_eq!(n, "Residual");
+                assert_eq!(m, "Residual");
+            }
+            ty => panic!("{:?}", ty),
+        }
+        assert!(tenv.resolve_type(&Global, &Alias::Variable("Residual".to_owned())).is_some());
+        assert!(tenv.rename_module(&Named("Residual".to_owned()), &model).is_err());
+    }
+
+    #[test]
+    fn should_import_float_value_alias() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let assign = AliasAssign::Dimension {
+            ident: "lr".to_owned(),
+            rhs: Term::Float(0.1, sp),
+            span: sp,
+        };
+        tenv.import_node_assign(&Global, &assign).unwrap();
+        assert_eq!(tenv.resolve_type(&Global, &Alias::Variable("lr".to_owned())), Some(float!()));
+    }
+
+    #[test]
+    fn should_reject_non_finite_float_alias() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let assign = |num| AliasAssign::Dimension {
+            ident: "lr".to_owned(),
+            rhs: Term::Float(num, sp),
+            span: sp,
+        };
+        match tenv.import_node_assign(&Global, &assign(::std::f64::INFINITY)) {
+            Err(Diag::InvalidLiteral(ref lit, _)) => assert_eq!(lit, "inf"),
+            r => panic!("{:?}", r),
+        }
+        match tenv.import_node_assign(&Global, &assign(::std::f64::NAN)) {
+            Err(Diag::InvalidLiteral(ref lit, _)) => assert_eq!(lit, "NaN"),
+            r => panic!("{:?}", r),
+        }
+        assert!(!tenv.exists(&Global, &Alias::Variable("lr".to_owned())));
+        tenv.import_node_assign(&Global, &assign(3e-4)).unwrap();
+        assert_eq!(tenv.resolve_type(&Global, &Alias::Variable("lr".to_owned())), Some(float!()));
+    }
+
+    #[test]
+    fn should_reject_float_alias_as_dim() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let assign = AliasAssign::Dimension {
+            ident: "lr".to_owned(),
+            rhs: Term::Float(0.1, sp),
+            span: sp,
+        };
+        tenv.import_node_assign(&Global, &assign).unwrap();
+        let tsr = AliasAssign::Tensor {
+            ident: "weights".to_owned(),
+            params: vec![],
+            rhs: TensorTy::Generic(vec!["?".to_owned(), "lr".to_owned()], sp),
+            span: sp,
+        };
+        match tenv.import_node_assign(&Global, &tsr) {
+            Err(Diag::FloatDimension(ref name, _)) => assert_eq!(name, "lr"),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_instantiate_batch_parameterized_alias() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let batch = AliasAssign::Tensor {
+            ident: "Batch".to_owned(),
+            params: vec!["n".to_owned()],
+            rhs: TensorTy::Generic(vec!["n", "3", "224", "224"].into_iter().map(String::from).collect(), sp),
+            span: sp,
+        };
+        tenv.import_node_assign(&Global, &batch).unwrap();
+        {
+            let mut shape = |n: &str| tenv
+                .resolve_tensor(&Global, &TensorTy::Applied("Batch".to_owned(), vec![n.to_owned()], sp))
+                .unwrap()
+                .as_vec()
+                .unwrap()
+                .iter()
+                .map(|d| d.as_num())
+                .collect::<Vec<_>>();
+            assert_eq!(shape("1"), vec![Some(1), Some(3), Some(224), Some(224)]);
+            assert_eq!(shape("32"), vec![Some(32), Some(3), Some(224), Some(224)]);
+            // an unknown batch stays symbolic
+            assert_eq!(shape("?"), vec![None, Some(3), Some(224), Some(224)]);
+        }
+
+        match tenv.instantiate_tsr(&Global, "Batch", &["1".to_owned(), "2".to_owned()], &sp) {
+            Err(Diag::InvalidArgument(ref msg, _)) =>
+                assert_eq!(msg, "wrong number of dims for `Batch`: expected 1, got 2"),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_report_conflicting_tensor_shape() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let a = Named("A".to_owned());
+        tenv.upsert_module(&a);
+        let x = Alias::Variable("X".to_owned());
+        tenv.add_tsr_alias(&Global, &x, &["32".to_owned(), "10".to_owned()], &sp).unwrap();
+        match tenv.add_tsr_alias(&a, &x, &["batch".to_owned(), "10".to_owned()], &sp) {
+            Err(Diag::ConflictingTensorShape(ref name, ..)) => assert_eq!(name, "X"),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_allow_same_tensor_alias_in_two_modules() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let (a, b) = (Named("A".to_owned()), Named("B".to_owned()));
+        tenv.upsert_module(&a);
+        tenv.upsert_module(&b);
+        let out = Alias::Variable("Out".to_owned());
+        tenv.add_tsr_alias(&a, &out, &["32".to_owned(), "10".to_owned()], &sp).unwrap();
+        assert!(tenv.add_tsr_alias(&b, &out, &["batch".to_owned(), "1".to_owned()], &sp).is_ok());
+    }
+
+    #[test]
+    fn should_allow_consistent_tensor_reuse() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let (a, b) = (Named("A".to_owned()), Named("B".to_owned()));
+        tenv.upsert_module(&a);
+        tenv.upsert_module(&b);
+        let x = Alias::Variable("X".to_owned());
+        let dims = ["batch".to_owned(), "10".to_owned()];
+        tenv.add_tsr_alias(&a, &x, &dims, &sp).unwrap();
+        assert!(tenv.add_tsr_alias(&b, &x, &dims, &sp).is_ok());
+    }
+
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, Op)]
+    #[path = "prelude"]
+    #[forward = "?() -> unit"]
+    struct double;
+
+    impl Resolve for double {}
+
+    impl PyTorch for double {
+        fn pytorch_name(&self) -> &'static str {
+            "double"
+        }
+    }
+
+    #[test]
+    fn should_import_only_given_prelude() {
+        let tenv = TypeEnv::with_prelude(Core::new(), vec![PreludeEntry { name: "double", op: box double }]).unwrap();
+        assert!(tenv.resolve_type(&Global, &Alias::Variable("double".to_owned())).is_some());
+        assert!(tenv.resolve_type(&Named("double".to_owned()), &Alias::Function("forward".to_owned())).is_some());
+        assert!(tenv.resolve_type(&Global, &Alias::Variable("view".to_owned())).is_none());
+    }
+
+    #[test]
+    fn should_import_every_prelude_fn_core_registers() {
+        let core = Rc::new(RefCell::new(Core::new()));
+        core.borrow_mut().register("prelude", "double", box double);
+        let tenv = TypeEnv::new(core);
+        assert!(tenv.resolve_type(&Global, &Alias::Variable("double".to_owned())).is_some());
+        assert!(tenv.resolve_type(&Global, &Alias::Variable("view".to_owned())).is_some());
+    }
+
+    #[test]
+    fn should_warn_on_name_imported_from_two_paths() {
+        let core = Rc::new(RefCell::new(Core::new()));
+        core.borrow_mut().register("a", "double", box double);
+        core.borrow_mut().register("b", "double", box double);
+        let mut tenv = TypeEnv::new(core);
+        let sp = CSpan::fresh_span();
+        tenv.import_name("a", "double", sp).unwrap();
+        match tenv.import_name("b", "double", sp) {
+            Err(Diag::AmbiguousImport(ref name, ref paths, _)) => {
+                assert_eq!(name, "double");
+                assert_eq!(paths, &vec!["a".to_owned(), "b".to_owned()]);
+            }
+            e => panic!("{:?}", e),
+        }
+        assert!(tenv.resolve_type(&Global, &Alias::Variable("double".to_owned())).is_some());
+    }
+
+    #[test]
+    fn should_resolve_global_init_from_named_module() {
+        let mut tenv = tenv();
+        let module = Named("Block".to_owned());
+        tenv.upsert_module(&module);
+        let args = vec![TyFnAppArg {
+            name: Some("p".to_owned()),
+            arg: box TyTerm::TyFloat(float!(), 0.5, CSpan::fresh_span()),
+            span: CSpan::fresh_span(),
+        }];
+        tenv.add_init(&Global, "drop", args.clone());
+        assert_eq!(tenv.resolve_init(&module, "drop"), Some(args));
+        assert_eq!(tenv.resolve_init(&module, "missing"), None);
+    }
+
+    fn import_halved(tenv: &mut TypeEnv, n: i64) -> Result<(), Diag> {
+        let sp = CSpan::fresh_span();
+        let dim = AliasAssign::Dimension {
+            ident: "n".to_owned(),
+            rhs: Term::Integer(n, sp),
+            span: sp,
+        };
+        tenv.import_node_assign(&Global, &dim)?;
+        let tsr = AliasAssign::Tensor {
+            ident: "halved".to_owned(),
+            params: vec![],
+            rhs: TensorTy::Generic(vec!["n".to_owned(), "n/2".to_owned()], sp),
+            span: sp,
+        };
+        tenv.import_node_assign(&Global, &tsr)
+    }
+
+    #[test]
+    fn should_fold_dim_expression() {
+        let mut tenv = tenv();
+        import_halved(&mut tenv, 64).unwrap();
+        let halved = tenv.resolve_type(&Global, &Alias::Variable("halved".to_owned())).unwrap();
+        let dims: Vec<_> = halved.as_vec().unwrap().iter().map(|d| d.as_num()).collect();
+        assert_eq!(dims, vec![Some(64), Some(32)]);
+    }
+
+    #[test]
+    fn should_reject_inexact_dim_division() {
+        let mut tenv = tenv();
+        match import_halved(&mut tenv, 63) {
+            Err(Diag::InvalidDimExpr(ref expr, ..)) => assert_eq!(expr, "n/2"),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_reuse_ids_per_scope() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let outer = tenv.fresh_var(sp, None);
+        let compile = |tenv: &mut TypeEnv| -> Vec<Type> {
+            let mut scope = tenv.fresh_scope();
+            let mut ids = vec![];
+            for _ in 0..3 {
+                ids.push(scope.fresh_var(sp, None));
+            }
+            ids
+        };
+        let first = compile(&mut tenv);
+        let second = compile(&mut tenv);
+        // each program sees the same ids, none clash with ids from outside the scope
+        assert_eq!(first, second);
+        assert!(!first.contains(&outer));
+        assert_eq!(tenv.fresh_var(sp, None), first[0]);
+    }
+
+    #[test]
+    fn should_report_running_out_of_ids_when_merging() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        tenv.dim_counter = TypeId::max_value() - 1;
+        assert_eq!(tenv.fresh_dim(sp, None), Type::DIM(TypeId::max_value(), sp));
+
+        // merging shifts the ids of the other env, `self` is left as it was
+        let mut other = self::tenv();
+        other.fresh_dim(sp, None);
+        match tenv.merge(other) {
+            Err(Diag::OutOfTypeIds(ref what)) => assert_eq!(what, "dimension"),
+            e => panic!("{:?}", e),
+        }
+        assert_eq!(tenv.dim_counter, TypeId::max_value());
+    }
+
+    #[test]
+    #[should_panic(expected = "OutOfTypeIds(\"dimension\")")]
+    fn should_not_hand_out_an_id_twice() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        tenv.dim_counter = TypeId::max_value();
+        tenv.fresh_dim(sp, None);
+    }
+
+    #[test]
+    fn should_mention_label_in_ambiguity_diagnostic() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let h = tenv.fresh_dim(sp, Some("Conv2d.output.H"));
+        let plain = tenv.fresh_var(sp, None);
+        let code_map = CodeMap::new();
+        let msg = tenv.ambiguous(h.clone()).as_diagnostic(&code_map).message;
+        assert!(msg.contains("Conv2d.output.H"), "{}", msg);
+        assert_eq!(tenv.label(&plain), None);
+        {
+            let mut scope = tenv.fresh_scope();
+            let scoped = scope.fresh_dim(sp, Some("scoped"));
+            assert_eq!(scope.label(&scoped), Some("scoped"));
+        }
+        // ids handed out again do not keep the labels of the scope
+        let reused = tenv.fresh_dim(sp, None);
+        assert_eq!(tenv.label(&reused), None);
+        assert_eq!(tenv.label(&h), Some("Conv2d.output.H"));
+    }
+
+    #[test]
+    fn should_display_tensor_with_dimension_names() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let dims: Vec<_> = ["batch", "channels", "28", "28"].iter().map(|d| d.to_string()).collect();
+        let image = Alias::Variable("image".to_owned());
+        tenv.add_tsr_alias(&Global, &image, &dims, &sp).unwrap();
+        let ty = tenv.resolve_type(&Global, &image).unwrap();
+        assert_eq!(tenv.named(&ty).to_string(), "[batch, channels, 28, 28]");
+
+        let anon = Type::TSR(vec![tenv.fresh_dim(sp, None), tenv.fresh_dim(sp, Some("pad.output.H"))], Tags::default(), sp);
+        assert_eq!(tenv.named(&anon).to_string(), "[?, ?]");
+    }
+
+    #[test]
+    fn should_list_innermost_names_in_scope() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let module = Named("Net".to_owned());
+        let var = |name: &str| Alias::Variable(name.to_owned());
+        tenv.add_type(&Global, &var("batch"), Type::ResolvedDim(32, sp)).unwrap();
+        tenv.add_type(&Global, &var("hidden"), Type::ResolvedDim(8, sp)).unwrap();
+        tenv.upsert_module(&module);
+        tenv.add_type(&module, &var("hidden"), Type::ResolvedDim(16, sp)).unwrap();
+        tenv.add_type(&module, &Alias::Function("forward".to_owned()), unit!()).unwrap();
+        tenv.push_scope(&module).unwrap();
+        tenv.add_type(&module, &var("hidden"), Type::ResolvedDim(64, sp)).unwrap();
+        tenv.add_type(&module, &var("x"), Type::INT(sp)).unwrap();
+
+        let visible = tenv.types_in_scope(&module);
+        let get = |name: &str| visible.iter().filter(|(a, _)| *a == var(name)).map(|(_, t)| t.clone()).collect::<Vec<_>>();
+        assert_eq!(get("hidden"), vec![Type::ResolvedDim(64, sp)]);
+        assert_eq!(get("batch"), vec![Type::ResolvedDim(32, sp)]);
+        assert_eq!(get("x"), vec![Type::INT(sp)]);
+        assert!(visible.iter().any(|(a, _)| *a == Alias::Function("forward".to_owned())));
+        // prelude functions are imported globally
+        assert!(visible.iter().any(|(a, _)| a.as_str() == "view"));
+
+        let global = tenv.types_in_scope(&Global);
+        assert!(global.iter().all(|(a, _)| a.as_str() != "x"));
+        assert!(global.contains(&(var("hidden"), Type::ResolvedDim(8, sp))));
+    }
+
+    #[test]
+    fn should_list_function_and_variable_aliases_separately() {
+        let mut tenv = tenv();
+        let sp = CSpan::fresh_span();
+        let module = Named("Net".to_owned());
+        let var = |name: &str| Alias::Variable(name.to_owned());
+        let forward = fun!("Net", "forward", args!(arg!("x", Type::VAR(1, sp))), Type::VAR(1, sp));
+        tenv.add_type(&Global, &var("batch"), Type::ResolvedDim(32, sp)).unwrap();
+        tenv.upsert_module(&module);
+        tenv.add_type(&module, &var("hidden"), Type::ResolvedDim(16, sp)).unwrap();
+        tenv.add_type(&module, &Alias::Function("forward".to_owned()), forward.clone()).unwrap();
+        tenv.add_type(&module, &Alias::Function("fc1".to_owned()), module!("Linear")).unwrap();
+        tenv.push_scope(&module).unwrap();
+        tenv.add_type(&module, &var("hidden"), Type::ResolvedDim(64, sp)).unwrap();
+        tenv.add_type(&module, &var("x"), Type::INT(sp)).unwrap();
+
+        assert_eq!(tenv.function_aliases(&module), vec![
+            ("fc1".to_owned(), module!("Linear")),
+            ("forward".to_owned(), forward),
+        ]);
+        assert_eq!(tenv.variable_aliases(&module), vec![
+            ("hidden".to_owned(), Type::ResolvedDim(64, sp)),
+            ("x".to_owned(), Type::INT(sp)),
+        ]);
+        assert!(tenv.aliases_of_kind(&module, |a| a.as_str().starts_with("f")).len() == 2);
+        // the scopes of `Global` are only listed for `Global` itself
+        assert!(tenv.variable_aliases(&module).iter().all(|(name, _)| name != "batch"));
+        assert!(tenv.variable_aliases(&Global).contains(&("batch".to_owned(), Type::ResolvedDim(32, sp))));
+        assert!(tenv.function_aliases(&Global).iter().all(|(name, _)| name != "batch"));
+    }
+
+    #[test]
+    fn should_pop_scope_when_closure_fails() {
+        let tenv = RefCell::new(tenv());
+        let depth = |tenv: &RefCell<TypeEnv>| tenv.borrow().modules[&Global].0.len();
+        let before = depth(&tenv);
+        let ret: Result<(), Diag> = TypeEnv::with_module_scope(&tenv, &Global, || {
+            assert_eq!(depth(&tenv), before + 1);
+            tenv.borrow_mut().add_type(&Global, &Alias::Variable("a".to_owned()), int!())?;
+            tenv.borrow_mut().add_type(&Global, &Alias::Variable("a".to_owned()), int!())?;
+            Ok(())
+        }).unwrap();
+        assert!(ret.is_err());
+        assert_eq!(depth(&tenv), before);
+    }
+
+    #[test]
+    fn should_refuse_scopes_beyond_max_depth() {
+        let tenv = RefCell::new(tenv());
+        tenv.borrow_mut().set_max_scope_depth(3);
+        assert_eq!(tenv.borrow().current_scope_depth(&Global), 1);
+        tenv.borrow_mut().push_scope(&Global).unwrap();
+        tenv.borrow_mut().push_scope(&Global).unwrap();
+        assert_eq!(tenv.borrow().current_scope_depth(&Global), 3);
+        match TypeEnv::with_module_scope(&tenv, &Global, || panic!("ran past the limit")) {
+            Err(Diag::ScopeTooDeep(_, 3)) => (),
+            ret => panic!("{:?}", ret),
+        }
+        assert_eq!(tenv.borrow().current_scope_depth(&Global), 3);
+        assert_eq!(tenv.borrow().current_scope_depth(&Named("Net".to_owned())), 0);
+    }
+
+    #[test]
+    fn should_merge_envs_without_id_collision() {
+        let core = Rc::new(RefCell::new(Core::new()));
+        let sp = CSpan::fresh_span();
+        let declare = |tenv: &mut TypeEnv, name: &str| {
+            let module = Named(name.to_owned());
+            tenv.upsert_module(&module);
+            let hidden = tenv.fresh_dim(sp, Some("hidden"));
+            let x = Type::TSR(vec![hidden.clone()], Tags::default(), sp);
+            tenv.add_type(&module, &Alias::Variable("hidden".to_owned()), hidden).unwrap();
+            let forward = fun!(name, "forward", args!(arg!("x", x.clone())), x);
+            tenv.add_type(&Global, &Alias::Variable(name.to_owned()),
+                Type::Module(name.to_owned(), Some(box forward), sp)).unwrap();
+        };
+        let hidden = |tenv: &TypeEnv, m: &str|
+            tenv.resolve_type(&Named(m.to_owned()), &Alias::Variable("hidden".to_owned())).unwrap();
+
+        let (mut a, mut b) = (TypeEnv::new(core.clone()), TypeEnv::new(core.clone()));
+        declare(&mut a, "A");
+        declare(&mut b, "B");
+        // both files import the same op
+        a.import_module("lin", "Linear").unwrap().unwrap();
+        b.import_module("lin", "Linear").unwrap().unwrap();
+        assert_eq!(hidden(&a, "A"), hidden(&b, "B"));
+
+        a.merge(b).unwrap();
+        let (ha, hb) = (hidden(&a, "A"), hidden(&a, "B"));
+        assert_ne!(ha, hb);
+        assert_eq!(a.label(&hb), Some("hidden"));
+        // the module from the other file resolves and refers to the renumbered dim
+        let b_ty = a.resolve_type(&Global, &Alias::Variable("B".to_owned())).unwrap();
+        assert_eq!(b_ty.free_vars(), vec![hb.clone(), hb.clone()]);
+        let fresh = a.fresh_dim(sp, None);
+        assert!(fresh != ha && fresh != hb);
+
+        let mut c = TypeEnv::new(core.clone());
+        declare(&mut c, "A");
+        match a.merge(c) {
+            Err(Diag::DuplicateModule(ref name)) => assert_eq!(name, "A"),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_report_unknown_prelude_fn() {
+        let mut tenv = TypeEnv::without_prelude(Rc::new(RefCell::new(Core::new())));
+        match tenv.import_prelude_fn("vew") {
+            Err(Diag::ImportError(ref name, _)) => assert_eq!(name, "vew"),
+            e => panic!("{:?}", e),
+        }
+    }
+}
@@ -3,7 +3,7 @@
 use codespan::ByteSpan;
 use span::CSpan;
 use std::collections::BTreeMap;
-use typing::type_env::Alias;
+use typing::type_env::{Alias, TypeId};
 use typing::Type;
 use std::fmt::Write;
 
@@ -14,6 +14,8 @@ pub enum TyTerm {
     TyProgram(Vec<TyDecl>),
     TyInteger(Type, i64, ByteSpan),
     TyFloat(Type, f64, ByteSpan),
+    TyBool(Type, bool, ByteSpan),
+    TyStr(Type, String, ByteSpan),
     TyList(Vec<TyTerm>),
     TyIdent(Type, Alias, ByteSpan),
     TyFieldAccess(TyFieldAccess),
@@ -26,6 +28,13 @@ pub enum TyTerm {
     },
     TyExpr(Box<TyTerm>, Type, ByteSpan),
     TyStmt(Box<TyTerm>, ByteSpan),
+    /// asserted variable and the type it must have, a tensor for `@shape`.
+    /// For `@dtype` a scalar type, or the tensor with its element tag set
+    TyShapeAssert(Box<TyTerm>, Type, ByteSpan),
+    /// inline lambda applied to the piped input
+    TyLambda(Box<TyLambda>),
+    /// a block whose gradients are not tracked
+    TyNoGrad(Box<TyTerm>, ByteSpan),
 }
 
 impl TyTerm {
@@ -37,6 +46,23 @@ impl TyTerm {
         }
     }
 
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            TyTerm::TyBool(_, b, _) => Some(*b),
+            TyTerm::TyExpr(ref items, ..) => items.as_bool(),
+            _ => None,
+        }
+    }
+
+    /// contents of a string literal
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            TyTerm::TyStr(_, v, _) => Some(v.clone()),
+            TyTerm::TyExpr(ref items, ..) => items.as_text(),
+            _ => None,
+        }
+    }
+
     pub fn as_num(&self) -> Option<i64> {
         use self::TyTerm::*;
         match self {
@@ -55,6 +81,8 @@ impl TyTerm {
             TyProgram(_) => Unit(CSpan::fresh_span()),
             TyInteger(ref t, _, _) => t.clone(),
             TyFloat(ref t, _, _) => t.clone(),
+            TyBool(ref t, _, _) => t.clone(),
+            TyStr(ref t, _, _) => t.clone(),
             TyList(_) => Unit(CSpan::fresh_span()),
             TyIdent(ref t, _, _) => t.clone(),
             TyFieldAccess(ref f_a) => f_a.ty(),
@@ -63,6 +91,9 @@ impl TyTerm {
             TyExpr(_,ref ty, _) => ty.clone(),
             TyStmt(..) => Unit(CSpan::fresh_span()),
             TyTuple(ref t, ..) => t.clone(),
+            TyShapeAssert(..) => Unit(CSpan::fresh_span()),
+            TyLambda(ref lambda) => lambda.ret_ty.clone(),
+            TyNoGrad(ref body, _) => body.ty(),
         }
     }
     pub fn span(&self) -> ByteSpan {
@@ -72,12 +103,17 @@ impl TyTerm {
             TyProgram(_) => CSpan::fresh_span(),
             TyInteger(_, _, ref s) => *s,
             TyFloat(_, _, ref s) => *s,
+            TyBool(_, _, ref s) => *s,
+            TyStr(_, _, ref s) => *s,
             TyIdent(_, _, ref s) => *s,
             TyFieldAccess(ref f_a) => f_a.span(),
             TyFnApp(ref f_a) => f_a.span(),
             TyBlock {ref span, ..} => *span,
             TyExpr(_, _, ref span) => *span,
             TyStmt(_, ref span) => *span,
+            TyShapeAssert(_, _, ref span) => *span,
+            TyLambda(ref lambda) => lambda.span,
+            TyNoGrad(_, ref span) => *span,
             _ => panic!("{:?}", self),
         }
     }
@@ -90,6 +126,8 @@ impl TyTerm {
             TyExpr(ref items, ..) => write!(s, "{}", items.as_str()?).unwrap(),
             TyIdent(ref t, ..) => write!(s, "{}", t.as_string()).unwrap(),
             TyFloat(_, f, ..) => write!(s, "{}", f).unwrap(),
+            TyBool(_, b, ..) => write!(s, "{}", if *b { "True" } else { "False" }).unwrap(),
+            TyStr(_, v, ..) => write!(s, "{:?}", v).unwrap(),
             TyTuple(_, ref ts, _) => {
                 write!(s, "(").unwrap();
                 write!(s, "{}", ts
@@ -103,6 +141,52 @@ impl TyTerm {
         };
         Some(s)
     }
+
+    /// shift the ids of every type in an expression, see `Type::renumber`
+    pub fn renumber(&self, dims: TypeId, vars: TypeId) -> TyTerm {
+        use self::TyTerm::*;
+        let ty = |t: &Type| t.renumber(dims, vars);
+        let term = |t: &TyTerm| -> Box<TyTerm> { box t.renumber(dims, vars) };
+        match self {
+            TyNone => TyNone,
+            TyInteger(ref t, i, ref sp) => TyInteger(ty(t), *i, *sp),
+            TyFloat(ref t, f, ref sp) => TyFloat(ty(t), *f, *sp),
+            TyBool(ref t, b, ref sp) => TyBool(ty(t), *b, *sp),
+            TyStr(ref t, ref v, ref sp) => TyStr(ty(t), v.clone(), *sp),
+            TyList(ref ts) => TyList(ts.iter().map(|t| t.renumber(dims, vars)).collect()),
+            TyIdent(ref t, ref name, ref sp) => TyIdent(ty(t), name.clone(), *sp),
+            TyFieldAccess(ref f_a) => TyFieldAccess(self::TyFieldAccess {
+                ty: ty(&f_a.ty),
+                ..f_a.clone()
+            }),
+            TyFnApp(ref f_a) => TyFnApp(box self::TyFnApp {
+                arg_ty: ty(&f_a.arg_ty),
+                ret_ty: ty(&f_a.ret_ty),
+                args: f_a.args.iter().map(|a| a.renumber(dims, vars)).collect(),
+                ..(**f_a).clone()
+            }),
+            TyTuple(ref t, ref ts, ref sp) =>
+                TyTuple(ty(t), ts.iter().map(|t| t.renumber(dims, vars)).collect(), *sp),
+            TyBlock { ref stmts, ref ret, ref span } => TyBlock {
+                stmts: term(stmts),
+                ret: term(ret),
+                span: *span,
+            },
+            TyExpr(ref items, ref t, ref sp) => TyExpr(term(items), ty(t), *sp),
+            TyStmt(ref items, ref sp) => TyStmt(term(items), *sp),
+            TyShapeAssert(ref var, ref t, ref sp) => TyShapeAssert(term(var), ty(t), *sp),
+            TyLambda(ref lambda) => TyLambda(box self::TyLambda {
+                param: lambda.param.clone(),
+                arg_ty: ty(&lambda.arg_ty),
+                ret_ty: ty(&lambda.ret_ty),
+                arg: lambda.arg.renumber(dims, vars),
+                body: term(&lambda.body),
+                span: lambda.span,
+            }),
+            TyNoGrad(ref body, ref sp) => TyNoGrad(term(body), *sp),
+            TyProgram(_) => panic!("{:?}", self),
+        }
+    }
 }
 
 impl TyFieldAccess {
@@ -209,6 +293,30 @@ impl TyFnApp {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct TyLambda {
+    pub param: String,
+    pub arg_ty: Type,
+    pub ret_ty: Type,
+    /// the piped input, bound to `param`
+    pub arg: TyFnAppArg,
+    pub body: Box<TyTerm>,
+    pub span: ByteSpan,
+}
+
+impl TyLambda {
+    /// `FUN` from the parameter to the body
+    pub fn fn_ty(&self) -> Type {
+        Type::FUN(
+            String::new(),
+            "lambda".to_owned(),
+            box self.arg_ty.clone(),
+            box self.ret_ty.clone(),
+            self.span,
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct TyFnAppArg {
     pub name: Option<String>,
@@ -216,6 +324,16 @@ pub struct TyFnAppArg {
     pub span: ByteSpan,
 }
 
+impl TyFnAppArg {
+    pub fn renumber(&self, dims: TypeId, vars: TypeId) -> TyFnAppArg {
+        TyFnAppArg {
+            name: self.name.clone(),
+            arg: box self.arg.renumber(dims, vars),
+            span: self.span,
+        }
+    }
+}
+
 pub trait ArgsVecInto {
     fn to_ty(&self, span: &ByteSpan) -> Type;
     fn to_btreemap(&self) -> Option<BTreeMap<String, Box<TyTerm>>>;
@@ -1,5 +1,5 @@
 use codespan::ByteSpan;
-use std::fmt::{Debug, Error, Formatter};
+use std::fmt::{Debug, Display, Error, Formatter};
 /// Types for typed AST
 use std::hash::{Hash, Hasher};
 use typing::type_env::TypeId;
@@ -13,6 +13,7 @@ pub enum Type {
     INT(ByteSpan),
     FLOAT(ByteSpan),
     BOOL(ByteSpan),
+    STR(ByteSpan),
     UnresolvedModuleFun(&'static str, &'static str, &'static str, ByteSpan),
     // type variables that need to be resolved
     VAR(TypeId, ByteSpan),
@@ -24,8 +25,137 @@ pub enum Type {
     FnArgs(Vec<Type>, ByteSpan),
     FnArg(Option<String>, Box<Type>, ByteSpan),
     ResolvedDim(i64, ByteSpan),
+    /// a dim computed from other dims, e.g. a conv output `(h + 2) / 2 + 1`.
+    /// Build it with `Type::derive` so that it collapses once resolved.
+    DerivedDim(char, Box<Type>, Box<Type>, ByteSpan),
     FUN(String, String, Box<Type>, Box<Type>, ByteSpan),
-    TSR(Vec<Type>, ByteSpan),
+    /// dims and the tags declared after them
+    TSR(Vec<Type>, Tags, ByteSpan),
+}
+
+/// where the channel axis of an image batch sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Layout {
+    Nchw,
+    Nhwc,
+}
+
+impl Layout {
+    /// the `@nchw`/`@nhwc` tag closing a tensor signature
+    pub fn from_tag(tag: &str) -> Option<Layout> {
+        match tag {
+            "@nchw" => Some(Layout::Nchw),
+            "@nhwc" => Some(Layout::Nhwc),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Layout::Nchw => "NCHW",
+            Layout::Nhwc => "NHWC",
+        }
+    }
+
+    /// layout after `x.permute(order)`, `None` unless it swaps one known layout for the other
+    pub fn permuted(self, order: &[i64]) -> Option<Layout> {
+        match self {
+            Layout::Nhwc if *order == [0, 3, 1, 2] => Some(Layout::Nchw),
+            Layout::Nchw if *order == [0, 2, 3, 1] => Some(Layout::Nhwc),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// width of the floats a tensor holds, for mixed precision, `I64`
+/// for a tensor of indices such as class labels or `Bool` for a mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Precision {
+    F16,
+    BF16,
+    F32,
+    I64,
+    Bool,
+}
+
+impl Precision {
+    /// the `@f16`/`@bf16`/`@f32`/`@i64`/`@bool` tag closing a tensor signature
+    pub fn from_tag(tag: &str) -> Option<Precision> {
+        match tag {
+            "@f16" => Some(Precision::F16),
+            "@bf16" => Some(Precision::BF16),
+            "@f32" => Some(Precision::F32),
+            "@i64" => Some(Precision::I64),
+            "@bool" => Some(Precision::Bool),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Precision::F16 => "float16",
+            Precision::BF16 => "bfloat16",
+            Precision::F32 => "float32",
+            Precision::I64 => "int64",
+            Precision::Bool => "bool",
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        match self {
+            Precision::F16 | Precision::BF16 | Precision::F32 => true,
+            Precision::I64 | Precision::Bool => false,
+        }
+    }
+
+    /// the prelude op casting a tensor to this precision
+    pub fn cast_fn(self) -> &'static str {
+        match self {
+            Precision::F16 => "half",
+            Precision::BF16 => "bfloat16",
+            Precision::F32 => "float",
+            Precision::I64 => "long",
+            Precision::Bool => "bool",
+        }
+    }
+
+    /// precision of the result of arithmetic on `a` and `b`. The wider one
+    /// wins, the two half precisions only meet in float32. Undeclared
+    /// precision takes the other side's
+    pub fn promote(a: Option<Precision>, b: Option<Precision>) -> Option<Precision> {
+        match (a, b) {
+            (Some(a), Some(b)) if a == b => Some(a),
+            (Some(_), Some(_)) => Some(Precision::F32),
+            (Some(p), None) | (None, Some(p)) => Some(p),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Display for Precision {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// what a tensor signature declares besides its dims
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tags {
+    pub layout: Option<Layout>,
+    pub precision: Option<Precision>,
+}
+
+impl Tags {
+    /// a layout or precision tag, `@nchw` or `@f16`
+    pub fn is_tag(tag: &str) -> bool {
+        Layout::from_tag(tag).is_some() || Precision::from_tag(tag).is_some()
+    }
 }
 
 impl PartialEq for Type {
@@ -36,6 +166,7 @@ impl PartialEq for Type {
             (INT(_), INT(_)) => true,
             (FLOAT(_), FLOAT(_)) => true,
             (BOOL(_), BOOL(_)) => true,
+            (STR(_), STR(_)) => true,
             // // UnresolvedModuleFun(_,_,_) => false,
             (VAR(a, _), VAR(b, _)) => a == b,
             (DIM(b, _), DIM(a, _)) => a == b,
@@ -44,21 +175,14 @@ impl PartialEq for Type {
             (Tuple(ta, _), Tuple(tb, _)) => ta == tb,
             (FnArg(n1, t1, _), FnArg(n2, t2, _)) => (n1 == n2) && (t1 == t2),
             (ResolvedDim(a, _), ResolvedDim(b, _)) => a == b,
+            (DerivedDim(o1, a1, b1, _), DerivedDim(o2, a2, b2, _)) => (o1 == o2) && (a1 == a2) && (b1 == b2),
             (FUN(m1, n1, p1, r1, _), FUN(m2, n2, p2, r2, _)) =>
                 (p1 == p2) && (r1 == r2) && (m1 == m2) && (n1 == n2),
-            (TSR(ts1, _), TSR(ts2, _)) => ts1 == ts2,
+            (TSR(ts1, _, _), TSR(ts2, _, _)) => ts1 == ts2,
             (UnresolvedModuleFun(a1, b1, c1, _), UnresolvedModuleFun(a2, b2, c2, _)) =>
                 (a1 == a2) && (b1 == b2) && (c1 == c2),
-            (VAR(..), _) => false,
-            (_, VAR(..)) => false,
-            (ResolvedDim(..), DIM(..)) => false,
-            (DIM(..), ResolvedDim(..)) => false,
-            _ => {
-                println!("Undefined comparison:");
-                println!("(1) {:?}", self);
-                println!("(2) {:?}", other);
-                false
-            }
+            // types of different shape are never equal
+            _ => false,
         }
     }
 }
@@ -71,6 +195,7 @@ impl Hash for Type {
             INT(_) => 0.hash(state),
             FLOAT(_) => 1.hash(state),
             BOOL(_) => 2.hash(state),
+            STR(_) => 12.hash(state),
             // UnresolvedModuleFun(_,_,_) => false,
             VAR(a, _) => {
                 3.hash(state);
@@ -106,19 +231,25 @@ impl Hash for Type {
                 p.hash(state);
                 r.hash(state);
             }
-            TSR(ts, _) => {
+            TSR(ts, _, _) => {
                 10.hash(state);
                 ts.hash(state);
             }
+            DerivedDim(op, a, b, _) => {
+                13.hash(state);
+                op.hash(state);
+                a.hash(state);
+                b.hash(state);
+            }
             UnresolvedModuleFun(a, b, c, _) => {
                 11.hash(state);
                 a.hash(state);
                 b.hash(state);
                 c.hash(state);
             }
-            // MismatchedDim(_,_) => true,
-            _ => {
-                panic!("{:?}", self);
+            Tuple(ts, _) => {
+                14.hash(state);
+                ts.hash(state);
             }
         }
     }
@@ -134,6 +265,7 @@ impl Type {
             INT(s) => *s,
             FLOAT(s) => *s,
             BOOL(s) => *s,
+            STR(s) => *s,
             UnresolvedModuleFun(_, _, _, s) => *s,
             // type variables that need to be resolved
             VAR(_, s) => *s,
@@ -145,15 +277,50 @@ impl Type {
             FnArgs(_, s) => *s,
             FnArg(_, _, s) => *s,
             ResolvedDim(_, s) => *s,
+            DerivedDim(_, _, _, s) => *s,
             FUN(_, _, _, _, s) => *s,
-            TSR(_, s) => *s,
+            TSR(_, _, s) => *s,
+        }
+    }
+
+    /// layout declared for a tensor, if any
+    pub fn layout(&self) -> Option<Layout> {
+        self.tags().layout
+    }
+
+    /// precision of the floats in a tensor, if known
+    pub fn precision(&self) -> Option<Precision> {
+        self.tags().precision
+    }
+
+    pub fn tags(&self) -> Tags {
+        match self {
+            Type::TSR(_, tags, _) => *tags,
+            _ => Tags::default(),
+        }
+    }
+
+    /// the same tensor holding floats of precision `p`
+    pub fn with_precision(&self, p: Option<Precision>) -> Type {
+        match self {
+            Type::TSR(dims, tags, sp) => Type::TSR(dims.clone(), Tags { precision: p, ..*tags }, *sp),
+            _ => self.clone(),
         }
     }
 
     pub fn as_vec(&self) -> Option<Vec<Type>> {
         use self::Type::TSR;
         match self {
-            TSR(ts, _) => Some(ts.to_owned()),
+            TSR(ts, _, _) => Some(ts.to_owned()),
+            _ => None,
+        }
+    }
+
+    /// rebuild a tensor with `f` applied to each dim and its index,
+    /// `None` if this is not a tensor
+    pub fn map_dims(&self, f: impl Fn(usize, &Type) -> Type) -> Option<Type> {
+        match self {
+            Type::TSR(ts, tags, sp) => Some(Type::TSR(ts.iter().enumerate().map(|(i, t)| f(i, t)).collect(), *tags, *sp)),
             _ => None,
         }
     }
@@ -193,7 +360,7 @@ impl Type {
     pub fn first_arg_ty(&self) -> Option<Type> {
         match self {
             Type::FnArgs(vs, _) => {
-                if let Type::FnArg(_,box ref ty, _) = vs[0] {
+                if let Some(Type::FnArg(_,box ref ty, _)) = vs.first() {
                     Some(ty.clone())
                 } else { None }
             }
@@ -202,6 +369,23 @@ impl Type {
         }
     }
 
+    /// the same argument list with keyword arguments sorted by name, if
+    /// every argument is a keyword argument. Positional arguments are
+    /// matched in order so a list containing any is left as it is.
+    pub fn canonicalize(&self) -> Type {
+        match self {
+            Type::FnArgs(args, sp) => {
+                let is_named = |a: &Type| if let Type::FnArg(Some(_), _, _) = a { true } else { false };
+                let mut args = args.clone();
+                if args.iter().all(is_named) {
+                    args.sort_by_key(|a| if let Type::FnArg(ref name, _, _) = a { name.clone() } else { None });
+                }
+                Type::FnArgs(args, *sp)
+            }
+            _ => self.clone(),
+        }
+    }
+
     /// modifies the span parameter in type to the most relevant
     pub fn with_span(&self, sp: &ByteSpan) -> Type {
         use self::Type::*;
@@ -212,21 +396,120 @@ impl Type {
             INT(_) => INT(*sp),
             FLOAT(_) => FLOAT(*sp),
             BOOL(_) => BOOL(*sp),
+            STR(_) => STR(*sp),
             UnresolvedModuleFun(ref a, ref b, ref c, _) => UnresolvedModuleFun(a, b, c, *sp),
             FnArgs(ref args, _) => FnArgs(args.clone(), *sp),
             FnArg(ref name, ref ty, _) => FnArg(name.clone(), ty.clone(), *sp),
             ResolvedDim(ref d, _) => ResolvedDim(*d, *sp),
+            DerivedDim(ref op, ref a, ref b, _) => DerivedDim(*op, a.clone(), b.clone(), *sp),
             Module(ref s, ref ty, _) => Module(s.clone(), ty.clone(), *sp),
             FUN(ref m,ref n,ref p, ref r, _) => FUN(m.clone(),n.clone(),p.clone(), r.clone(), *sp),
-            TSR(ref dims, _) => TSR(dims.clone(), *sp),
+            TSR(ref dims, ref tags, _) => TSR(dims.clone(), *tags, *sp),
             Tuple(ref vs, _) => Tuple(vs.clone(), *sp),
         }
     }
 
-    pub fn as_mod_name(&self) -> ModName {
+    /// replace every occurrence of dimension variable `id` with `replacement`
+    pub fn replace_dim(&self, id: TypeId, replacement: &Type) -> Type {
+        use self::Type::*;
         match self {
-            Type::Module(s,..) => ModName::Named(s.to_owned()),
-            _ => unimplemented!(),
+            DIM(ref a, ref sp) if *a == id => replacement.with_span(sp),
+            DerivedDim(ref op, box ref a, box ref b, ref sp) =>
+                Type::derive(*op, a.replace_dim(id, replacement), b.replace_dim(id, replacement), *sp),
+            Tuple(ref vs, ref sp) => Tuple(vs.iter().map(|t| t.replace_dim(id, replacement)).collect(), *sp),
+            Module(ref n, Some(box ref ty), ref sp) => Module(n.clone(), Some(box ty.replace_dim(id, replacement)), *sp),
+            FnArgs(ref args, ref sp) => FnArgs(args.iter().map(|t| t.replace_dim(id, replacement)).collect(), *sp),
+            FnArg(ref name, box ref ty, ref sp) => FnArg(name.clone(), box ty.replace_dim(id, replacement), *sp),
+            FUN(ref m, ref n, box ref p, box ref r, ref sp) => FUN(
+                m.clone(),
+                n.clone(),
+                box p.replace_dim(id, replacement),
+                box r.replace_dim(id, replacement),
+                *sp,
+            ),
+            TSR(ref dims, ref tags, ref sp) =>
+                TSR(dims.iter().map(|t| t.replace_dim(id, replacement)).collect(), *tags, *sp),
+            _ => self.clone(),
+        }
+    }
+
+    /// replace type or dimension variable `tvar` with `replacement`,
+    /// without building a `Substitution`
+    pub fn substitute(&self, tvar: TypeId, replacement: &Type) -> Type {
+        use self::Type::*;
+        match self {
+            VAR(ref a, ref sp) | DIM(ref a, ref sp) if *a == tvar => replacement.with_span(sp),
+            DerivedDim(ref op, box ref a, box ref b, ref sp) =>
+                Type::derive(*op, a.substitute(tvar, replacement), b.substitute(tvar, replacement), *sp),
+            Tuple(ref vs, ref sp) => Tuple(vs.iter().map(|t| t.substitute(tvar, replacement)).collect(), *sp),
+            Module(ref n, Some(box ref ty), ref sp) => Module(n.clone(), Some(box ty.substitute(tvar, replacement)), *sp),
+            FnArgs(ref args, ref sp) => FnArgs(args.iter().map(|t| t.substitute(tvar, replacement)).collect(), *sp),
+            FnArg(ref name, box ref ty, ref sp) => FnArg(name.clone(), box ty.substitute(tvar, replacement), *sp),
+            FUN(ref m, ref n, box ref p, box ref r, ref sp) => FUN(
+                m.clone(),
+                n.clone(),
+                box p.substitute(tvar, replacement),
+                box r.substitute(tvar, replacement),
+                *sp,
+            ),
+            TSR(ref dims, ref tags, ref sp) =>
+                TSR(dims.iter().map(|t| t.substitute(tvar, replacement)).collect(), *tags, *sp),
+            _ => self.clone(),
+        }
+    }
+
+    /// rewrite references to module `old` into references to `new`
+    pub fn rename_module(&self, old: &str, new: &str) -> Type {
+        use self::Type::*;
+        let rename = |n: &String| if n == old { new.to_owned() } else { n.clone() };
+        match self {
+            Module(ref n, ref ty, ref sp) => Module(
+                rename(n),
+                ty.as_ref().map(|t| box t.rename_module(old, new)),
+                *sp,
+            ),
+            FUN(ref m, ref n, box ref p, box ref r, ref sp) => FUN(
+                rename(m),
+                n.clone(),
+                box p.rename_module(old, new),
+                box r.rename_module(old, new),
+                *sp,
+            ),
+            Tuple(ref vs, ref sp) => Tuple(vs.iter().map(|t| t.rename_module(old, new)).collect(), *sp),
+            FnArgs(ref args, ref sp) => FnArgs(args.iter().map(|t| t.rename_module(old, new)).collect(), *sp),
+            FnArg(ref name, box ref ty, ref sp) => FnArg(name.clone(), box ty.rename_module(old, new), *sp),
+            _ => self.clone(),
+        }
+    }
+
+    /// shift every dimension id by `dims` and every type variable id by `vars`
+    pub fn renumber(&self, dims: TypeId, vars: TypeId) -> Type {
+        use self::Type::*;
+        match self {
+            VAR(ref a, ref sp) => VAR(*a + vars, *sp),
+            DIM(ref a, ref sp) => DIM(*a + dims, *sp),
+            DerivedDim(ref op, box ref a, box ref b, ref sp) =>
+                DerivedDim(*op, box a.renumber(dims, vars), box b.renumber(dims, vars), *sp),
+            Tuple(ref vs, ref sp) => Tuple(vs.iter().map(|t| t.renumber(dims, vars)).collect(), *sp),
+            Module(ref n, Some(box ref ty), ref sp) => Module(n.clone(), Some(box ty.renumber(dims, vars)), *sp),
+            FnArgs(ref args, ref sp) => FnArgs(args.iter().map(|t| t.renumber(dims, vars)).collect(), *sp),
+            FnArg(ref name, box ref ty, ref sp) => FnArg(name.clone(), box ty.renumber(dims, vars), *sp),
+            FUN(ref m, ref n, box ref p, box ref r, ref sp) => FUN(
+                m.clone(),
+                n.clone(),
+                box p.renumber(dims, vars),
+                box r.renumber(dims, vars),
+                *sp,
+            ),
+            TSR(ref ds, ref tags, ref sp) => TSR(ds.iter().map(|t| t.renumber(dims, vars)).collect(), *tags, *sp),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn as_mod_name(&self) -> Option<ModName> {
+        match self {
+            Type::Module(s,..) => Some(ModName::Named(s.to_owned())),
+            _ => None,
         }
     }
 
@@ -234,8 +517,8 @@ impl Type {
         use self::Type::*;
         match self {
             Module(ref n, _, _) => n.to_owned(),
-            TSR(tys, _) => tys.iter().map(|t| t.as_string()).collect::<Vec<_>>().join(", "),
-            DIM(_, _) => "-1".to_owned(),
+            TSR(tys, _, _) => tys.iter().map(|t| t.as_string()).collect::<Vec<_>>().join(", "),
+            DIM(_, _) | DerivedDim(..) => "-1".to_owned(),
             ResolvedDim(i, _) => format!("{}", i),
             _ => panic!("{:?}", self),
         }
@@ -249,14 +532,92 @@ impl Type {
         }
     }
 
+    /// `a op b` for dims, folded into a `ResolvedDim` when both sides are.
+    /// Division rounds down like the output size of a conv or pool.
+    pub fn derive(op: char, a: Type, b: Type, span: ByteSpan) -> Type {
+        match (a.as_num(), b.as_num()) {
+            (Some(x), Some(y)) => match op {
+                '+' => Type::ResolvedDim(x + y, span),
+                '-' => Type::ResolvedDim(x - y, span),
+                '*' => Type::ResolvedDim(x * y, span),
+                '/' if y != 0 => Type::ResolvedDim(x / y, span),
+                _ => Type::DerivedDim(op, box a, box b, span),
+            },
+            (_, Some(0)) if op == '+' || op == '-' => a.with_span(&span),
+            (_, Some(1)) if op == '*' || op == '/' => a.with_span(&span),
+            _ => Type::DerivedDim(op, box a, box b, span),
+        }
+    }
+
+    /// the single unknown dim that makes this dim equal `target`, and its value.
+    /// `Ok(None)` if there is nothing to force or the formula cannot be
+    /// inverted, e.g. a division that rounds down. `Err` if no value fits.
+    pub fn solve_dim(&self, target: i64) -> Result<Option<(Type, i64)>, ()> {
+        use self::Type::*;
+        match self {
+            ResolvedDim(n, _) => if *n == target { Ok(None) } else { Err(()) },
+            VAR(..) | DIM(..) => Ok(Some((self.clone(), target))),
+            DerivedDim(ref op, box ref a, box ref b, _) => {
+                let exact_div = |n: i64, k: i64| if k != 0 && n % k == 0 { Ok(n / k) } else { Err(()) };
+                match (a.as_num(), b.as_num()) {
+                    (None, Some(k)) => {
+                        let t = match *op {
+                            '+' => target - k,
+                            '-' => target + k,
+                            '*' => exact_div(target, k)?,
+                            _ => return Ok(None),
+                        };
+                        a.solve_dim(t)
+                    }
+                    (Some(k), None) => {
+                        let t = match *op {
+                            '+' => target - k,
+                            '-' => k - target,
+                            '*' => exact_div(target, k)?,
+                            _ => return Ok(None),
+                        };
+                        b.solve_dim(t)
+                    }
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// number of dims, a rank-0 tensor and a scalar both have none
     pub fn as_rank(&self) -> usize {
         use self::Type::*;
         match self {
-            TSR(ref i, _) => i.len(),
+            TSR(ref i, _, _) => i.len(),
+            INT(_) | FLOAT(_) | BOOL(_) => 0,
             _ => unimplemented!(),
         }
     }
 
+    /// type and dimension variables that occur in this type
+    pub fn free_vars(&self) -> Vec<Type> {
+        use self::Type::*;
+        match self {
+            VAR(..) | DIM(..) => vec![self.clone()],
+            Module(_, Some(box ref ty), _) => ty.free_vars(),
+            FnArg(_, box ref ty, _) => ty.free_vars(),
+            FUN(_, _, box ref p, box ref r, _) => {
+                let mut vars = p.free_vars();
+                vars.extend(r.free_vars());
+                vars
+            }
+            FnArgs(ref ts, _) | Tuple(ref ts, _) | TSR(ref ts, _, _) =>
+                ts.iter().flat_map(|t| t.free_vars()).collect(),
+            DerivedDim(_, box ref a, box ref b, _) => {
+                let mut vars = a.free_vars();
+                vars.extend(b.free_vars());
+                vars
+            }
+            _ => vec![],
+        }
+    }
+
     pub fn is_resolved(&self) -> bool {
         use self::Type::*;
         match self {
@@ -264,19 +625,21 @@ impl Type {
             INT(..) => true,
             FLOAT(..) => true,
             BOOL(..) => true,
+            STR(..) => true,
             UnresolvedModuleFun(..) => false,
 
             VAR(..) => false,
             DIM(..) => false,
+            Tuple(ts, _) => ts.iter().all(|t| t.is_resolved()),
 
             Module(_, Some(i), _) => i.is_resolved(),
             Module(_, None, _) => false,
             FnArgs(ts, _) => ts.iter().map(|t| t.is_resolved()).all(|t| t),
             FnArg(_, t, _) => t.is_resolved(),
             ResolvedDim(_, _) => true,
+            DerivedDim(..) => false,
             FUN(_,_, p, r, _) => Type::is_resolved(p) && r.is_resolved(),
-            TSR(_ts, _) => true, //ts.iter().map(|t| t.is_resolved()).all(|t|t),
-            _ => unimplemented!(),
+            TSR(_ts, _, _) => true, //ts.iter().map(|t| t.is_resolved()).all(|t|t),
         }
     }
 }
@@ -289,6 +652,7 @@ impl Debug for Type {
             INT(_) => write!(f, "int"),
             FLOAT(_) => write!(f, "float"),
             BOOL(_) => write!(f, "bool"),
+            STR(_) => write!(f, "str"),
             UnresolvedModuleFun(ref a, ref b, ref c, _) => {
                 write!(f, "UNRESOLVED({}::{}::{})", a, b, c)
             }
@@ -298,40 +662,64 @@ impl Debug for Type {
             FnArgs(ref args, _) => write!(f, "FnArgs({:?})", args),
             FnArg(ref name, ref ty, _) => write!(f, "ARG({:?}={:?})", name, ty),
             ResolvedDim(ref d, _) => write!(f, "<{}>", d),
+            DerivedDim(ref op, ref a, ref b, _) => write!(f, "({:?} {} {:?})", a, op, b),
             Module(ref s, ref ty, _) => write!(f, "MODULE({}, {:?})", s, ty),
             FUN(ref module, ref name,ref p, ref r, _) => write!(f, "{}::{}({:?} -> {:?})", module,name,p, r),
-            TSR(ref dims, _) => {
+            TSR(ref dims, ref tags, _) => {
                 if !dims.is_empty() {
                     write!(f, "[")?;
                     for i in dims[0..dims.len() - 1].iter() {
                         write!(f, "{:?}, ", i)?;
                     }
-                    write!(f, "{:?}]", dims[dims.len() - 1])
+                    write!(f, "{:?}]", dims[dims.len() - 1])?;
                 } else {
-                    write!(f, "[]")
+                    write!(f, "[]")?;
+                }
+                match tags.layout {
+                    Some(Layout::Nchw) => write!(f, " @nchw")?,
+                    Some(Layout::Nhwc) => write!(f, " @nhwc")?,
+                    None => (),
+                }
+                match tags.precision {
+                    Some(Precision::F16) => write!(f, " @f16"),
+                    Some(Precision::BF16) => write!(f, " @bf16"),
+                    Some(Precision::F32) => write!(f, " @f32"),
+                    Some(Precision::I64) => write!(f, " @i64"),
+                    Some(Precision::Bool) => write!(f, " @bool"),
+                    None => Ok(()),
                 }
             }
         }
     }
 }
 
+/// `args!(a, b)`, or `args!(a, b; span)` to locate the arguments in source
 macro_rules! args {
-    ( $( $x:expr ),* ) => {
+    ( $( $x:expr ),* ; $sp:expr ) => {
         {
-            Type::FnArgs(vec![$($x),*], CSpan::fresh_span())
+            Type::FnArgs(vec![$($x),*], $sp)
         }
     };
+    ( $( $x:expr ),* ) => {
+        args!($($x),*; CSpan::fresh_span())
+    };
 }
 
 macro_rules! arg {
+    ($name:expr, $ty:expr, $sp:expr) => {
+        Type::FnArg(Some($name.to_owned()), box $ty, $sp)
+    };
     ($name:expr, $ty:expr) => {
-        Type::FnArg(Some($name.to_owned()), box $ty, CSpan::fresh_span())
+        arg!($name, $ty, CSpan::fresh_span())
     };
 }
 
 macro_rules! fun {
+    ($m:expr, $n: expr, $e1:expr, $e2:expr, $sp:expr) => {
+        Type::FUN($m.to_owned(),$n.to_owned(), box $e1, box $e2, $sp)
+    };
     ($m:expr, $n: expr, $e1:expr, $e2:expr) => {
-        Type::FUN($m.to_owned(),$n.to_owned(), box $e1, box $e2, CSpan::fresh_span())
+        fun!($m, $n, $e1, $e2, CSpan::fresh_span())
     };
 }
 
@@ -343,7 +731,7 @@ macro_rules! float {
 
 macro_rules! tsr {
     ($tsr:expr) => {
-        Type::TSR($tsr, CSpan::fresh_span())
+        Type::TSR($tsr, ::typing::types::Tags::default(), CSpan::fresh_span())
     };
 }
 
@@ -376,6 +764,8 @@ macro_rules! module {
 mod tests {
     use super::*;
     use codespan::{Span, ByteIndex};
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
     #[test]
     fn should_not_take_span_into_hash() {
         let h = hashset!(
@@ -387,4 +777,146 @@ mod tests {
         );
         assert_eq!(h.len(), 2);
     }
+
+    #[test]
+    fn should_hash_tuples_by_elements() {
+        let sp1 = Span::new(ByteIndex(1), ByteIndex(1));
+        let sp2 = Span::new(ByteIndex(2), ByteIndex(2));
+        let pair = |sp| Type::Tuple(vec![Type::INT(sp), Type::VAR(1, sp)], sp);
+        let h = hashset!(
+            pair(sp1),
+            pair(sp2),
+            Type::Tuple(vec![Type::VAR(1, sp1), Type::INT(sp1)], sp1),
+            Type::FnArg(Some("x".to_owned()), box pair(sp1), sp1),
+            Type::FnArg(Some("x".to_owned()), box pair(sp2), sp2),
+            Type::Tuple(vec![], sp1),
+            Type::FnArgs(vec![], sp1),
+        );
+        assert_eq!(h.len(), 5);
+    }
+
+    /// collects what is printed while it is installed with `io::set_print`
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_not_equal_types_of_different_shape() {
+        let sp = Span::new(ByteIndex(1), ByteIndex(1));
+        let tsr = Type::TSR(vec![Type::ResolvedDim(3, sp)], Tags::default(), sp);
+        let printed = Arc::new(Mutex::new(vec![]));
+        let prev = io::set_print(Some(box Sink(printed.clone())));
+        let eq = (
+            tsr == Type::INT(sp),
+            Type::FLOAT(sp) == Type::Tuple(vec![Type::FLOAT(sp)], sp),
+            Type::Module("Xor".to_owned(), None, sp) == Type::FnArgs(vec![], sp),
+        );
+        io::set_print(prev);
+        assert_eq!(eq, (false, false, false));
+        assert_eq!(String::from_utf8_lossy(&printed.lock().unwrap()), "");
+    }
+
+    #[test]
+    fn should_keep_hash_consistent_with_eq_across_variants() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        let hash = |ty: &Type| {
+            let mut h = DefaultHasher::new();
+            ty.hash(&mut h);
+            h.finish()
+        };
+        let mut tys = vec![];
+        for i in 0..3 {
+            let sp = Span::new(ByteIndex(i), ByteIndex(i + 1));
+            let dims = vec![Type::DIM(1, sp), Type::ResolvedDim(4, sp)];
+            tys.push(Type::Unit(sp));
+            tys.push(Type::INT(sp));
+            tys.push(Type::STR(sp));
+            tys.push(Type::VAR(1, sp));
+            tys.push(Type::DIM(1, sp));
+            tys.push(Type::ResolvedDim(1, sp));
+            tys.push(Type::TSR(dims.clone(), Tags::default(), sp));
+            tys.push(Type::Tuple(dims.clone(), sp));
+            tys.push(Type::FnArgs(dims.clone(), sp));
+            tys.push(Type::FnArg(Some("x".to_owned()), box Type::TSR(dims.clone(), Tags::default(), sp), sp));
+            tys.push(Type::FnArg(None, box Type::TSR(dims.clone(), Tags::default(), sp), sp));
+            tys.push(Type::DerivedDim('+', box Type::DIM(1, sp), box Type::ResolvedDim(1, sp), sp));
+            tys.push(Type::FUN("m".to_owned(), "f".to_owned(), box Type::FnArgs(dims.clone(), sp), box Type::TSR(dims, Tags::default(), sp), sp));
+            tys.push(Type::Module("m".to_owned(), None, sp));
+            tys.push(Type::UnresolvedModuleFun("a", "b", "c", sp));
+        }
+        for a in tys.iter() {
+            for b in tys.iter() {
+                assert_eq!(a == b, b == a, "{:?} {:?}", a, b);
+                if a == b {
+                    assert_eq!(hash(a), hash(b), "{:?} {:?}", a, b);
+                }
+            }
+        }
+        let set: HashSet<_> = tys.iter().cloned().collect();
+        assert_eq!(set.len(), tys.len() / 3);
+        assert!(tys.iter().all(|t| set.contains(t)));
+    }
+
+    #[test]
+    fn should_sort_keyword_args_by_name() {
+        let sp = Span::new(ByteIndex(1), ByteIndex(1));
+        let arg = |name: Option<&str>, ty: Type| Type::FnArg(name.map(|n| n.to_owned()), box ty, sp);
+        let ordered = Type::FnArgs(vec![arg(Some("kernel_size"), Type::INT(sp)), arg(Some("stride"), Type::FLOAT(sp))], sp);
+        let reversed = Type::FnArgs(vec![arg(Some("stride"), Type::FLOAT(sp)), arg(Some("kernel_size"), Type::INT(sp))], sp);
+        assert!(ordered != reversed);
+        assert_eq!(reversed.canonicalize(), ordered);
+        assert_eq!(ordered.canonicalize(), ordered);
+        // positional arguments keep their order
+        let mixed = Type::FnArgs(vec![arg(None, Type::FLOAT(sp)), arg(Some("b"), Type::INT(sp)), arg(Some("a"), Type::INT(sp))], sp);
+        assert_eq!(mixed.canonicalize(), mixed);
+    }
+
+    #[test]
+    fn should_resolve_tuple_by_elements() {
+        let sp = Span::new(ByteIndex(1), ByteIndex(1));
+        let resolved = Type::Tuple(vec![Type::INT(sp), Type::ResolvedDim(3, sp)], sp);
+        let unresolved = Type::Tuple(vec![Type::INT(sp), Type::VAR(1, sp)], sp);
+        assert!(resolved.is_resolved());
+        assert!(!unresolved.is_resolved());
+    }
+
+    #[test]
+    fn should_map_every_dim_of_tensor() {
+        let sp = Span::new(ByteIndex(1), ByteIndex(1));
+        let dim = |i| Type::ResolvedDim(i, sp);
+        let x = Type::TSR(vec![dim(1), dim(2), dim(3)], Tags::default(), sp);
+        let doubled = x.map_dims(|_, d| dim(d.as_num().unwrap() * 2)).unwrap();
+        assert_eq!(doubled, Type::TSR(vec![dim(2), dim(4), dim(6)], Tags::default(), sp));
+        assert_eq!(dim(1).map_dims(|_, d| d.clone()), None);
+    }
+
+    #[test]
+    fn should_substitute_dim_inside_nested_fun() {
+        let sp = Span::new(ByteIndex(1), ByteIndex(1));
+        let n = Type::DIM(1, sp);
+        let tsr = |d: Type| Type::TSR(vec![d, Type::ResolvedDim(10, sp)], Tags::default(), sp);
+        let inner = Type::FUN("m".to_owned(), "f".to_owned(), box tsr(n.clone()), box tsr(Type::derive('*', n.clone(), Type::ResolvedDim(2, sp), sp)), sp);
+        let outer = Type::FUN("m".to_owned(), "g".to_owned(), box Type::Tuple(vec![inner, n.clone()], sp), box tsr(n.clone()), sp);
+        let replaced = outer.substitute(1, &Type::ResolvedDim(4, sp));
+        let expected_inner = Type::FUN("m".to_owned(), "f".to_owned(), box tsr(Type::ResolvedDim(4, sp)), box tsr(Type::ResolvedDim(8, sp)), sp);
+        let expected = Type::FUN(
+            "m".to_owned(),
+            "g".to_owned(),
+            box Type::Tuple(vec![expected_inner, Type::ResolvedDim(4, sp)], sp),
+            box tsr(Type::ResolvedDim(4, sp)),
+            sp,
+        );
+        assert_eq!(replaced, expected);
+        assert!(replaced.is_resolved());
+        // other variables are left alone
+        assert_eq!(Type::VAR(2, sp).substitute(1, &Type::INT(sp)), Type::VAR(2, sp));
+    }
 }
@@ -1,17 +1,30 @@
 use typing::{Type, TypeEnv};
-use typing::type_env::TypeId;
 use span::CSpan;
 use errors::{Emitter, Diag };
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::process::exit;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use codespan::ByteSpan;
 
 use typing::constraint::{Constraints, Equals};
 
 pub struct Unifier {
     pub emitter: Rc<RefCell<Emitter>>,
     pub tenv: Rc<RefCell<TypeEnv>>,
+    /// where the resolved dim bound to a variable was first bound
+    origins: BTreeMap<Type, ByteSpan>,
+    /// variables the two sides of the equation being unified were
+    /// substituted for, if they were
+    sources: Sources,
+    /// equations on dims that cannot be evaluated until more is bound
+    deferred: Vec<Equals>,
+    /// called with each error as soon as it is recorded
+    on_error: Option<Box<FnMut(&Diag)>>,
+    /// report a conflicting dim with the shapes of both tensors
+    full_shapes: bool,
+    /// tensors whose dims at an axis are being unified, in full shapes mode
+    shape: Option<(Type, Type, usize)>,
 }
 
 impl Unifier {
@@ -20,21 +33,81 @@ impl Unifier {
         Unifier {
             emitter,
             tenv,
+            origins: BTreeMap::new(),
+            sources: (None, None),
+            deferred: vec![],
+            on_error: None,
+            full_shapes: false,
+            shape: None,
         }
     }
 
+    /// report dimension mismatches with the full shapes of the two
+    /// tensors and the axis they differ at, instead of the two dims
+    pub fn set_full_shapes(&mut self, full_shapes: bool) {
+        self.full_shapes = full_shapes;
+    }
+
+    /// stream errors to `f` while unifying, in addition to the emitter
+    pub fn on_error<F: FnMut(&Diag) + 'static>(&mut self, f: F) {
+        self.on_error = Some(Box::new(f));
+    }
+
+    fn report(&mut self, e: Diag) {
+        if let Some(ref mut f) = self.on_error {
+            f(&e);
+        }
+        self.emitter.borrow_mut().add(e);
+    }
+
     pub fn unify(&mut self, cs: Constraints) -> Substitution {
-        if cs.is_empty() {
-            Substitution::empty()
-        } else {
-            let emitter = cs.emitter.clone();
-            let tenv = cs.tenv.clone();
-            let mut it = cs.set.into_iter();
-            let mut subst = self.unify_one(it.next().unwrap());
-            let subst_tail = subst.apply(&Constraints {set: it.collect(), emitter, tenv});
-            let subst_tail: Substitution = self.unify(subst_tail);
-            subst.compose(subst_tail)
+        let outer = mem::replace(&mut self.deferred, vec![]);
+        let eqs = cs.set.into_iter().map(|eq| (eq, (None, None))).collect();
+        let mut subst = self.unify_tracked(eqs);
+
+        // retry the deferred equations as long as the bindings change them,
+        // the ones left are handed to the caller
+        loop {
+            let mut ready = BTreeSet::new();
+            for Equals(a, b) in mem::replace(&mut self.deferred, vec![]) {
+                let eq = Equals(subst.apply_ty(&a), subst.apply_ty(&b));
+                if eq == Equals(a, b) {
+                    self.deferred.push(eq);
+                } else {
+                    ready.insert(eq);
+                }
+            }
+            if ready.is_empty() {
+                break;
+            }
+            let s = self.unify(Constraints { set: ready, emitter: cs.emitter.clone(), tenv: cs.tenv.clone() });
+            subst = subst.compose(s);
+        }
+        self.deferred.extend(outer);
+        subst
+    }
+
+    /// unify the first equation and apply the result to the rest. A side
+    /// that becomes a resolved dim remembers the variable it replaced
+    fn unify_tracked(&mut self, mut eqs: BTreeMap<Equals, Sources>) -> Substitution {
+        let first = match eqs.keys().next() {
+            Some(eq) => eq.clone(),
+            None => return Substitution::empty(),
+        };
+        let sources = eqs.remove(&first).unwrap();
+        self.sources = sources.clone();
+        let mut subst = self.unify_one(first.clone());
+        self.sources = (None, None);
+        self.record_origins(&subst, &first, &sources);
+
+        let mut rest = BTreeMap::new();
+        for (Equals(a, b), (sa, sb)) in eqs {
+            let (a2, b2) = (subst.apply_ty(&a), subst.apply_ty(&b));
+            let sources = (source(&a, &a2, sa), source(&b, &b2, sb));
+            rest.entry(Equals(a2, b2)).or_insert(sources);
         }
+        let subst_tail = self.unify_tracked(rest);
+        subst.compose(subst_tail)
     }
 
     fn unify_one(&mut self, eq: Equals) -> Substitution {
@@ -47,34 +120,72 @@ impl Unifier {
             Equals(INT(_), INT(_)) => Substitution::empty(),
             Equals(FLOAT(_), FLOAT(_)) => Substitution::empty(),
             Equals(BOOL(_), BOOL(_)) => Substitution::empty(),
+            Equals(STR(_), STR(_)) => Substitution::empty(),
 
             Equals(INT(_), ResolvedDim(_, _)) => Substitution::empty(),
             Equals(ResolvedDim(_, _), INT(_)) => Substitution::empty(),
+            // an int arg that is a dim, e.g. `in=_`, stays free to be inferred
+            Equals(INT(_), DIM(_, _)) => Substitution::empty(),
+            Equals(DIM(_, _), INT(_)) => Substitution::empty(),
 
             Equals(a @ ResolvedDim(_, _), b @ ResolvedDim(_, _)) => {
                 if a.as_num() == b.as_num() {
                     Substitution::empty()
                 } else {
-                    self.emitter.borrow_mut().add(Diag::DimensionMismatch(a.clone(), b.clone()));
+                    let (sa, sb) = self.sources.clone();
+                    let (o1, o2) = (self.origin(&a, &sa), self.origin(&b, &sb));
+                    if o1 == a.span() && o2 == b.span() {
+                        let e = self.dim_mismatch(a.clone(), b.clone());
+                        self.report(e);
+                    } else {
+                        // at least one side was forced through a chain of dims
+                        self.report(Diag::ConflictingDimensions(a.clone(), o1, b.clone(), o2));
+                    }
                     Substitution::empty()
                 }
             }
 
-            Equals(VAR(tvar, _), ty) => self.unify_var(tvar, ty),
-            Equals(ty, VAR(tvar, _)) => self.unify_var(tvar, ty),
+            Equals(d @ DerivedDim(..), ResolvedDim(n, sp)) |
+            Equals(ResolvedDim(n, sp), d @ DerivedDim(..)) => self.unify_derived(d, n, sp),
 
-            Equals(DIM(tvar, _), ty) => self.unify_var(tvar, ty),
-            Equals(ty, DIM(tvar, _)) => self.unify_var(tvar, ty),
+            // neither formula can be evaluated yet, retried once the dims
+            // they depend on are bound
+            Equals(a @ DerivedDim(..), b @ DerivedDim(..)) => {
+                self.deferred.push(Equals(a, b));
+                Substitution::empty()
+            }
 
-            Equals(FnArgs(v1, _), FnArgs(v2, _)) => self.unify(
-                Constraints {
-                    set: v1.into_iter().zip(v2).map(|(i, j)| Equals(i, j)).collect(),
-                    emitter,
-                    tenv,
-                },
-            ),
+            Equals(var @ VAR(..), ty) => self.unify_var(var, ty),
+            Equals(ty, var @ VAR(..)) => self.unify_var(var, ty),
+
+            Equals(var @ DIM(..), ty) => self.unify_var(var, ty),
+            Equals(ty, var @ DIM(..)) => self.unify_var(var, ty),
+
+            Equals(a @ FnArgs(..), b @ FnArgs(..)) => {
+                // keyword arguments may be supplied in any order
+                let (pairs, unmatched) = match (a.canonicalize(), b.canonicalize()) {
+                    (FnArgs(v1, _), FnArgs(v2, _)) => match_args(v1, v2),
+                    _ => unreachable!("canonicalize keeps an argument list"),
+                };
+                for arg in unmatched {
+                    if let FnArg(name, _, sp) = arg {
+                        let msg = match name {
+                            Some(name) => format!("no matching argument for `{}`", name),
+                            None => "too many positional arguments".to_owned(),
+                        };
+                        self.report(Diag::InvalidArgument(msg, sp));
+                    }
+                }
+                self.unify(
+                    Constraints {
+                        set: pairs.into_iter().map(|(i, j)| Equals(i, j)).collect(),
+                        emitter,
+                        tenv,
+                    },
+                )
+            }
 
-            Equals(FnArg(Some(a), ty1, _), FnArg(Some(b), ty2, _)) => {
+            Equals(FnArg(Some(a), ty1, s1), FnArg(Some(b), ty2, s2)) => {
                 if a == b {
                     self.unify(
                         Constraints {
@@ -84,11 +195,13 @@ impl Unifier {
                         },
                         )
                 } else {
-                    panic!("supplied parameter is incorrect! {} != {}", a, b);
+                    // the supplied parameter is not the one expected
+                    self.report(Diag::IncompatibleTypes(FnArg(Some(a), ty1, s1), FnArg(Some(b), ty2, s2)));
+                    Substitution::empty()
                 }
             }
 
-            Equals(FUN(m1,n1,p1, r1, _), FUN(m2,n2,p2, r2, _)) => {
+            Equals(FUN(m1,n1,p1, r1, s1), FUN(m2,n2,p2, r2, s2)) => {
                 if n1 == n2 {
                     self.unify(
                         Constraints{
@@ -101,8 +214,8 @@ impl Unifier {
                         },
                     )
                 } else {
-                    println!("{} {} {} {}", m1, m2, n1, n2);
-                    panic!()
+                    self.report(Diag::IncompatibleTypes(FUN(m1, n1, p1, r1, s1), FUN(m2, n2, p2, r2, s2)));
+                    Substitution::empty()
                 }
             },
 
@@ -114,101 +227,214 @@ impl Unifier {
                 },
             ),
 
-            Equals(ts1 @ TSR(_, _), ts2 @ TSR(_, _)) => {
+            Equals(ts1 @ TSR(_, _, _), ts2 @ TSR(_, _, _)) => {
+                match (ts1.layout(), ts2.layout()) {
+                    (Some(l1), Some(l2)) if l1 != l2 =>
+                        self.report(Diag::LayoutMismatch(l1, l2, ts2.span())),
+                    _ => (),
+                }
                 if ts1.as_rank() == ts2.as_rank() {
-                    if let (TSR(dims1, s1), TSR(dims2, s2)) = (ts1.clone(), ts2.clone()) {
-                        let cons = Constraints {
-                            set: dims1
-                                .into_iter()
-                                .zip(dims2)
-                                .filter_map(|(i, j)| {
-                                    if let (Type::ResolvedDim(a,_), Type::ResolvedDim(b,_)) = (i.clone(),j.clone()) {
-                                        if a != b { self.emitter.borrow_mut().add(Diag::TypeError(ts1.clone(),ts2.clone())) }
-                                        None
+                    if let (TSR(dims1, _, s1), TSR(dims2, _, s2)) = (ts1.clone(), ts2.clone()) {
+                        let mut eqs = vec![];
+                        for (axis, (i, j)) in dims1.into_iter().zip(dims2).enumerate() {
+                            if let (Type::ResolvedDim(a,_), Type::ResolvedDim(b,_)) = (i.clone(),j.clone()) {
+                                if a != b {
+                                    let otherwise = if self.full_shapes {
+                                        Diag::ShapeMismatch(ts1.clone(), ts2.clone(), axis)
                                     } else {
-                                        Some(Equals(i.with_span(&s1), j.with_span(&s2)))
-                                    }
-                                })
-                                .collect(),
-                            emitter,
-                            tenv,
-                        };
-                        self.unify(cons)
+                                        Diag::TypeError(ts1.clone(), ts2.clone())
+                                    };
+                                    let e = self.tensor_mismatch(&ts1, &ts2, otherwise);
+                                    self.report(e);
+                                }
+                            } else {
+                                eqs.push((axis, Equals(i.with_span(&s1), j.with_span(&s2))));
+                            }
+                        }
+                        if self.full_shapes {
+                            // one axis at a time, so that a conflict knows where it is
+                            let mut sub = Substitution::empty();
+                            for (axis, Equals(a, b)) in eqs {
+                                let eq = Equals(sub.apply_ty(&a), sub.apply_ty(&b));
+                                self.shape = Some((ts1.clone(), ts2.clone(), axis));
+                                let s = self.unify_one(eq);
+                                self.shape = None;
+                                sub = sub.compose(s);
+                            }
+                            sub
+                        } else {
+                            self.unify(Constraints {
+                                set: eqs.into_iter().map(|(_, eq)| eq).collect(),
+                                emitter,
+                                tenv,
+                            })
+                        }
                     } else {
                         unimplemented!();
                     }
                 } else {
-                    self.emitter.borrow_mut().add(Diag::RankMismatch(ts1, ts2));
+                    let e = self.tensor_mismatch(&ts1, &ts2, Diag::RankMismatch(ts1.clone(), ts2.clone()));
+                    self.report(e);
                     Substitution::empty()
                 }
             }
 
-            Equals(Module(n1, Some(box ty1), _), Module(n2, Some(box ty2), _)) => self.unify(
-                Constraints {
-                    set: btreeset!{
-                        if n1 == n2 {
-                            Equals(ty1, ty2)
-                        } else {
-                            panic!();
-                        }
-                    },
-                    emitter,
-                    tenv,
-                },
-            ),
+            Equals(Module(n1, Some(box ty1), s1), Module(n2, Some(box ty2), s2)) => {
+                if n1 == n2 {
+                    self.unify(
+                        Constraints {
+                            set: btreeset!{ Equals(ty1, ty2) },
+                            emitter,
+                            tenv,
+                        },
+                    )
+                } else {
+                    self.report(Diag::IncompatibleTypes(Module(n1, Some(box ty1), s1), Module(n2, Some(box ty2), s2)));
+                    Substitution::empty()
+                }
+            }
 
             Equals(u @ UnresolvedModuleFun(_, _, _, _), ty) => {
                 Substitution::empty()
             }
 
             _ => {
+                // types can never be equal, report and keep unifying the rest
                 let Equals(a, b) = eq;
-                let mut em = self.emitter.borrow_mut();
-                em.add(Diag::TypeError(a, b));
-                em.print_errs();
-                exit(-1);
+                self.report(Diag::IncompatibleTypes(a, b));
+                Substitution::empty()
             }
         }
     }
 
-    fn unify_var(&mut self, tvar: TypeId, ty: Type) -> Substitution {
+    /// a variable bound to a resolved dim remembers where the dim was first
+    /// bound, which is the origin of the variable the dim replaced if any
+    fn record_origins(&mut self, subst: &Substitution, eq: &Equals, sources: &Sources) {
+        let Equals(ref a, ref b) = *eq;
+        for (var, ty) in subst.0.iter() {
+            if let Type::ResolvedDim(_, sp) = ty {
+                let origin = match (a, b) {
+                    (_, Type::ResolvedDim(_, sb)) if sb == sp => self.origin(b, &sources.1),
+                    (Type::ResolvedDim(_, sa), _) if sa == sp => self.origin(a, &sources.0),
+                    _ => *sp,
+                };
+                self.origins.entry(var.clone()).or_insert(origin);
+            }
+        }
+    }
+
+    /// a tensor that disagrees with the output declared by a module
+    /// signature is reported there, any other pair as `otherwise`
+    fn tensor_mismatch(&self, ts1: &Type, ts2: &Type, otherwise: Diag) -> Diag {
+        let tenv = self.tenv.borrow();
+        match (tenv.is_declared_output(&ts1.span()), tenv.is_declared_output(&ts2.span())) {
+            (true, false) => Diag::SignatureMismatch(ts1.clone(), ts2.clone()),
+            (false, true) => Diag::SignatureMismatch(ts2.clone(), ts1.clone()),
+            _ => otherwise,
+        }
+    }
+
+    /// in full shapes mode a conflicting dim is reported with the tensors
+    /// it belongs to
+    fn dim_mismatch(&self, a: Type, b: Type) -> Diag {
+        match self.shape {
+            Some((ref ts1, ref ts2, axis)) => Diag::ShapeMismatch(ts1.clone(), ts2.clone(), axis),
+            None => Diag::DimensionMismatch(a, b),
+        }
+    }
+
+    fn origin(&self, dim: &Type, source: &Option<Type>) -> ByteSpan {
+        source
+            .as_ref()
+            .and_then(|var| self.origins.get(var))
+            .cloned()
+            .unwrap_or_else(|| dim.span())
+    }
+
+    /// a derived dim must equal `n`, force its unknown input if the
+    /// formula can be inverted and leave it to later passes otherwise
+    fn unify_derived(&mut self, d: Type, n: i64, sp: ByteSpan) -> Substitution {
+        if let Type::DerivedDim('%', box ref a, box ref b, ref span) = d {
+            if let (Some(x), Some(k)) = (a.as_num(), b.as_num()) {
+                if k == 0 || x % k != n {
+                    self.report(Diag::NotDivisible(a.clone(), k, *span));
+                }
+                return Substitution::empty();
+            }
+        }
+        match d.solve_dim(n) {
+            Ok(Some((var, value))) => self.unify_one(Equals(var, Type::ResolvedDim(value, sp))),
+            Ok(None) => {
+                if !d.is_resolved() {
+                    self.deferred.push(Equals(d, Type::ResolvedDim(n, sp)));
+                }
+                Substitution::empty()
+            }
+            Err(()) => {
+                let e = self.dim_mismatch(d, Type::ResolvedDim(n, sp));
+                self.report(e);
+                Substitution::empty()
+            }
+        }
+    }
+
+    /// bind `var`, a `VAR` or a `DIM`, to `ty`. The binding is keyed by
+    /// the same kind of variable it was made for
+    fn unify_var(&mut self, var: Type, ty: Type) -> Substitution {
         use self::Type::*;
 
-        let span = CSpan::fresh_span();
+        let key = var.with_span(&CSpan::fresh_span());
         match ty.clone() {
-            VAR(tvar2, _) => {
-                if tvar == tvar2 {
+            VAR(..) | DIM(..) => {
+                if same_var(&var, &ty) {
                     Substitution::empty()
                 } else {
-                    Substitution(btreemap!{ VAR(tvar, span) => ty })
+                    // union the two classes, ty becomes the parent
+                    Substitution(btreemap!{ key => ty })
                 }
             }
-            DIM(tvar2, _) => {
-                if tvar == tvar2 {
-                    Substitution::empty()
-                } else {
-                    Substitution(btreemap!{ VAR(tvar, span) => ty })
-                }
+            DerivedDim(..) if occurs(&var, &ty) => {
+                // e.g. `h == h * 2`, possibly through other bound dims
+                self.report(Diag::CircularDim(var.with_span(&ty.span()), ty));
+                Substitution::empty()
             }
-            _ => if occurs(tvar, &ty) {
-                panic!("circular type")
+            _ => if occurs(&var, &ty) {
+                // binding it would make an infinite type
+                self.report(Diag::IncompatibleTypes(var, ty));
+                Substitution::empty()
             } else {
-                Substitution(btreemap!{ VAR(tvar, span) => ty })
+                Substitution(btreemap!{ key => ty })
             },
         }
     }
 }
 
-fn occurs(tvar: TypeId, ty: &Type) -> bool {
+/// `VAR(n)` and `DIM(n)` are different variables
+fn same_var(a: &Type, b: &Type) -> bool {
+    use self::Type::*;
+    match (a, b) {
+        (VAR(i, _), VAR(j, _)) | (DIM(i, _), DIM(j, _)) => i == j,
+        _ => false,
+    }
+}
+
+fn occurs(var: &Type, ty: &Type) -> bool {
     use self::Type::*;
     match ty {
-        FUN(_,_, ref p, ref r, _) => occurs(tvar, &p) | occurs(tvar, &r),
-        VAR(ref tvar2, _) => tvar == *tvar2,
+        FUN(_,_, ref p, ref r, _) => occurs(var, &p) | occurs(var, &r),
+        VAR(..) | DIM(..) => same_var(var, ty),
+        FnArgs(ref ts, _) | Tuple(ref ts, _) => ts.iter().any(|t| occurs(var, t)),
+        FnArg(_, ref t, _) | Module(_, Some(ref t), _) => occurs(var, t),
+        DerivedDim(..) => ty.free_vars().iter().any(|v| same_var(var, v)),
         _ => false,
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Substitution is kept as a union-find forest over type variables:
+/// a variable maps either to another variable (its parent) or to the type
+/// bound to its representative. Nothing is rewritten on `compose`, variables
+/// are resolved on demand by `apply_ty` and paths are compressed as we go.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Substitution(pub BTreeMap<Type, Type>);
 
 impl Substitution {
@@ -226,83 +452,803 @@ impl Substitution {
     }
 
     pub fn apply_ty(&mut self, ty: &Type) -> Type {
-        self.0.iter().fold(ty.clone(), |result, solution| {
-            let (ty, solution_type) = solution;
-            if let Type::VAR(ref tvar, ref span) = ty {
-                substitute_tvar(result, tvar, &solution_type.with_span(span))
-            } else {
-                panic!("Impossible!");
-            }
-        })
+        self.substitute(ty.clone())
     }
 
-    pub fn compose(&mut self, mut other: Substitution) -> Substitution {
-        let mut self_substituded: BTreeMap<Type, Type> = self.0
-            .clone()
-            .into_iter()
-            .map(|(k, s)| (k, other.apply_ty(&s)))
-            .collect();
-        self_substituded.extend(other.0);
-        Substitution(self_substituded)
+    /// find the type bound to the representative of `var`, compressing
+    /// the path so later lookups take a single step
+    pub fn resolve(&mut self, var: &Type) -> Option<Type> {
+        let key = var.with_span(&CSpan::fresh_span());
+        // unlink while resolving so that a cycle terminates
+        let bound = self.0.remove(&key)?;
+        let resolved = self.substitute(bound);
+        self.0.insert(key, resolved.clone());
+        Some(resolved)
+    }
+
+    pub fn compose(&mut self, other: Substitution) -> Substitution {
+        let mut composed = self.0.clone();
+        composed.extend(other.0);
+        Substitution(composed)
     }
 
     pub fn empty() -> Substitution {
         Substitution(BTreeMap::new())
     }
+
+    /// build a substitution from bindings solved elsewhere.
+    ///
+    /// Callers must uphold:
+    /// - every key is a `VAR` or a `DIM`. A binding for a `DIM` only
+    ///   applies to `DIM`s of that id, and likewise for a `VAR`.
+    /// - a value may mention other bound variables, chains are followed
+    ///   when applied, but a variable is never bound to a type containing itself.
+    pub fn from_bindings<I>(bindings: I) -> Result<Substitution, Diag>
+    where
+        I: IntoIterator<Item = (Type, Type)>,
+    {
+        use self::Type::*;
+        let mut map = BTreeMap::new();
+        for (k, v) in bindings {
+            match k {
+                VAR(..) | DIM(..) => (),
+                _ => return Err(Diag::InvalidArgument(
+                    format!("only type variables can be substituted, got {:?}", k),
+                    k.span(),
+                )),
+            }
+            if same_var(&k, &v) {
+                continue;
+            } else if occurs(&k, &v) {
+                return Err(Diag::InvalidArgument(
+                    format!("{:?} is bound to a type containing itself: {:?}", k, v),
+                    k.span(),
+                ));
+            }
+            map.insert(k.with_span(&CSpan::fresh_span()), v);
+        }
+        Ok(Substitution(map))
+    }
+
+    /// the bindings as inserted, chains between variables are not followed
+    pub fn bindings(&self) -> impl Iterator<Item = (&Type, &Type)> {
+        self.0.iter()
+    }
+
+    /// replace every bound type variable in ty
+    fn substitute(&mut self, ty: Type) -> Type {
+        use self::Type::*;
+        match ty {
+            UnresolvedModuleFun(_, _, _, _) => ty,
+            Unit(_) => ty,
+            INT(_) => ty,
+            BOOL(_) => ty,
+            STR(_) => ty,
+            FLOAT(_) => ty,
+            ResolvedDim(_, _) => ty,
+            DerivedDim(op, a, b, span) => {
+                let (a, b) = (self.substitute(*a), self.substitute(*b));
+                Type::derive(op, a, b, span)
+            }
+            VAR(_, span) | DIM(_, span) => match self.resolve(&ty) {
+                Some(replacement) => replacement.with_span(&span),
+                None => ty,
+            },
+            FnArgs(args, span) => FnArgs(
+                args.into_iter()
+                    .map(|ty| match ty {
+                        FnArg(name, a, s) => FnArg(name, box self.substitute(*a), s),
+                        _ => panic!(ty),
+                    })
+                    .collect(),
+                span,
+            ),
+            Tuple(tys, s) => Tuple(tys.into_iter().map(|t| self.substitute(t)).collect(), s),
+            FUN(module, name, p, r, s) => FUN(
+                module,
+                name,
+                box self.substitute(*p),
+                box self.substitute(*r),
+                s,
+            ),
+            TSR(_, _, _) => ty,
+
+            Module(n, Some(box ty), s) => Module(n, Some(box self.substitute(ty)), s),
+
+            Module(_, None, _) => ty,
+            FnArg(name, box ty, s) => FnArg(name, box self.substitute(ty), s),
+        }
+    }
 }
 
-/// replace tvar with replacement in ty
-fn substitute_tvar(ty: Type, tvar: &TypeId, replacement: &Type) -> Type {
-    use self::Type::*;
-    // println!("\nTVAR:::\n{:?}, \n'{:?}, \n{:?}\n", ty, tvar, replacement);
-    match ty {
-        UnresolvedModuleFun(_, _, _, _) => {
-            println!("{:?}, replacement: {:?}", ty, replacement);
-            ty
-        },
-        Unit(_) => ty,
-        INT(_) => ty,
-        BOOL(_) => ty,
-        FLOAT(_) => ty,
-        ResolvedDim(_, _) => ty,
-        VAR(tvar2, span) => {
-            if *tvar == tvar2 {
-                replacement.with_span(&span)
-            } else {
-                ty
+/// the variables the two sides of an equation were substituted for
+type Sources = (Option<Type>, Option<Type>);
+
+/// the variable `before` was, if it was substituted by a resolved dim
+fn source(before: &Type, after: &Type, source: Option<Type>) -> Option<Type> {
+    match (before, after) {
+        (Type::VAR(..), Type::ResolvedDim(..)) | (Type::DIM(..), Type::ResolvedDim(..)) =>
+            Some(before.with_span(&CSpan::fresh_span())),
+        (_, Type::ResolvedDim(..)) => source,
+        _ => None,
+    }
+}
+
+/// pairs up the argument types of two canonical `FnArgs`: keyword arguments by name,
+/// positional arguments in order, and a positional argument left over on
+/// one side with the next unpaired keyword argument of the other. The
+/// arguments that have no counterpart are returned as well.
+fn match_args(v1: Vec<Type>, v2: Vec<Type>) -> (Vec<(Type, Type)>, Vec<Type>) {
+    let is_named = |a: &Type| if let Type::FnArg(Some(_), _, _) = a { true } else { false };
+    let (named1, mut pos1): (Vec<Type>, Vec<Type>) = v1.into_iter().partition(&is_named);
+    let (mut named2, mut pos2): (Vec<Type>, Vec<Type>) = v2.into_iter().partition(&is_named);
+
+    let mut pairs = vec![];
+    let mut rest1 = vec![];
+    for a in named1 {
+        let found = named2.iter().position(|b| arg_name(b) == arg_name(&a));
+        match found {
+            Some(i) => pairs.push((a, named2.remove(i))),
+            None => rest1.push(a),
+        }
+    }
+
+    let n = pos1.len().min(pos2.len());
+    let (extra1, extra2) = (pos1.split_off(n), pos2.split_off(n));
+    pairs.extend(pos1.into_iter().zip(pos2));
+
+    let mut unmatched = vec![];
+    let mut rest2 = named2.into_iter();
+    for a in extra1 {
+        match rest2.next() {
+            Some(b) => pairs.push((a, b)),
+            None => unmatched.push(a),
+        }
+    }
+    unmatched.extend(rest2);
+    let mut rest1 = rest1.into_iter();
+    for b in extra2 {
+        match rest1.next() {
+            Some(a) => pairs.push((a, b)),
+            None => unmatched.push(b),
+        }
+    }
+    unmatched.extend(rest1);
+
+    let pairs = pairs.into_iter().map(|(a, b)| (arg_ty(a), arg_ty(b))).collect();
+    (pairs, unmatched)
+}
+
+fn arg_name(arg: &Type) -> Option<&String> {
+    match arg {
+        Type::FnArg(name, _, _) => name.as_ref(),
+        _ => None,
+    }
+}
+
+fn arg_ty(arg: Type) -> Type {
+    match arg {
+        Type::FnArg(_, box ty, _) => ty,
+        ty => ty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use typing::types::Tags;
+    use core::Core;
+    use codespan::CodeMap;
+    use test::Bencher;
+
+    /// reference implementation that rewrites every binding eagerly
+    fn apply_ty_eager(sub: &Substitution, ty: &Type) -> Type {
+        sub.0.iter().fold(ty.clone(), |result, (k, v)|
+            Substitution(btreemap!{ k.clone() => v.clone() }).apply_ty(&result)
+        )
+    }
+
+    fn compose_eager(a: &Substitution, b: &Substitution) -> Substitution {
+        let mut composed: BTreeMap<Type, Type> = a.0
+            .iter()
+            .map(|(k, s)| (k.clone(), apply_ty_eager(b, s)))
+            .collect();
+        composed.extend(b.0.clone());
+        Substitution(composed)
+    }
+
+    /// chain of substitutions as produced by successive unification passes
+    fn chain(n: usize) -> Vec<Substitution> {
+        let sp = CSpan::fresh_span();
+        (1..n)
+            .map(|i| {
+                let ty = if i % 3 == 0 {
+                    args!(arg!("x", Type::VAR(i + 1, sp)), arg!("y", Type::DIM(i + 1, sp)))
+                } else {
+                    Type::VAR(i + 1, sp)
+                };
+                Substitution(btreemap!{ Type::VAR(i, sp) => ty })
+            })
+            .chain(Some(Substitution(btreemap!{ Type::VAR(n, sp) => int!() })))
+            .collect()
+    }
+
+    fn unifier() -> Unifier {
+        let emitter = Rc::new(RefCell::new(Emitter::new(CodeMap::new(), false)));
+        let tenv = Rc::new(RefCell::new(TypeEnv::new(Rc::new(RefCell::new(Core::new())))));
+        Unifier::new(emitter, tenv)
+    }
+
+    #[test]
+    fn should_unify_keyword_args_in_any_order() {
+        let mut unifier = unifier();
+        let tvar = Type::VAR(1, CSpan::fresh_span());
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(
+                    args!(arg!("kernel_size", tvar.clone()), arg!("stride", int!())),
+                    args!(arg!("stride", int!()), arg!("kernel_size", int!()))
+                )
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        let mut sub = unifier.unify(cs);
+        assert_eq!(sub.apply_ty(&tvar), int!());
+    }
+
+    #[test]
+    fn should_match_keyword_args_by_name_when_arity_differs() {
+        let mut unifier = unifier();
+        let sp = CSpan::fresh_span();
+        let x = Type::VAR(1, sp);
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(
+                    args!(arg!("x", x.clone()), arg!("p", float!())),
+                    args!(arg!("p", float!()), arg!("x", tsr!(vec![Type::ResolvedDim(4, sp)])), arg!("inplace", Type::BOOL(sp)))
+                )
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        let mut sub = unifier.unify(cs);
+        assert_eq!(sub.apply_ty(&x), tsr!(vec![Type::ResolvedDim(4, sp)]));
+        let emitter = unifier.emitter.borrow();
+        assert_eq!(emitter.errs().len(), 1);
+        match emitter.errs()[0] {
+            Diag::InvalidArgument(ref msg, _) => assert!(msg.contains("`inplace`")),
+            ref e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_resolve_derived_dim_with_its_input() {
+        let sp = CSpan::fresh_span();
+        let dim = |n| Type::ResolvedDim(n, sp);
+        // output height of a 3x3 conv with stride 2
+        let h = Type::DIM(1, sp);
+        let out = Type::derive('+', Type::derive('/', Type::derive('+', h.clone(), dim(-3), sp), dim(2), sp), dim(1), sp);
+        assert!(!out.is_resolved());
+        assert_eq!(out.free_vars(), vec![h.clone()]);
+
+        let mut sub = Substitution::from_bindings(vec![(h, dim(11))]).unwrap();
+        assert_eq!(sub.apply_ty(&out).as_num(), Some(5));
+    }
+
+    #[test]
+    fn should_force_input_of_derived_dim() {
+        let sp = CSpan::fresh_span();
+        let mut unifier = unifier();
+        let h = Type::DIM(1, sp);
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(Type::derive('+', h.clone(), Type::ResolvedDim(4, sp), sp), Type::ResolvedDim(9, sp)),
+                Equals(Type::derive('*', Type::DIM(2, sp), Type::ResolvedDim(2, sp), sp), Type::ResolvedDim(7, sp))
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        let mut sub = unifier.unify(cs);
+        assert_eq!(sub.apply_ty(&h).as_num(), Some(5));
+        let emitter = unifier.emitter.borrow();
+        assert_eq!(emitter.errs().len(), 1);
+        match emitter.errs()[0] {
+            Diag::DimensionMismatch(..) => (),
+            ref e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_apply_external_substitution() {
+        let sp = CSpan::fresh_span();
+        let x = tsr!(vec![Type::DIM(1, sp), Type::ResolvedDim(3, sp)]);
+        let mut sub = Substitution::from_bindings(vec![
+            (Type::VAR(2, sp), Type::VAR(3, sp)),
+            (Type::VAR(3, sp), x.clone()),
+        ]).unwrap();
+        assert_eq!(sub.bindings().count(), 2);
+        assert_eq!(sub.apply_ty(&Type::VAR(2, sp)), x);
+        assert!(Substitution::from_bindings(vec![(int!(), x.clone())]).is_err());
+        assert!(Substitution::from_bindings(vec![(Type::VAR(4, sp), fun!("Linear", "forward", Type::VAR(4, sp), x.clone()))]).is_err());
+    }
+
+    #[test]
+    fn should_key_dim_binding_by_kind() {
+        let mut unifier = unifier();
+        let sp = CSpan::fresh_span();
+        let mut sub = unifier.unify_one(Equals(Type::DIM(7, sp), Type::ResolvedDim(32, sp)));
+        assert_eq!(sub.bindings().map(|(k, _)| k).collect::<Vec<_>>(), vec![&Type::DIM(7, sp)]);
+        assert_eq!(sub.apply_ty(&Type::DIM(7, sp)), Type::ResolvedDim(32, sp));
+        // a VAR with the same id is a different variable
+        assert_eq!(sub.apply_ty(&Type::VAR(7, sp)), Type::VAR(7, sp));
+    }
+
+    #[test]
+    fn should_iterate_collected_equations() {
+        let unifier = unifier();
+        let eq = Equals(Type::VAR(1, CSpan::fresh_span()), int!());
+        let cs = Constraints::from_equals(unifier.emitter.clone(), unifier.tenv.clone(), vec![eq.clone()]);
+        assert_eq!(cs.iter().collect::<Vec<_>>(), vec![&eq]);
+    }
+
+    #[test]
+    fn should_report_incompatible_types() {
+        let mut unifier = unifier();
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(
+                    tsr!(vec![Type::ResolvedDim(1, CSpan::fresh_span())]),
+                    fun!("Linear", "forward", args!(), unit!())
+                )
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        assert_eq!(unifier.unify(cs), Substitution::empty());
+        let emitter = unifier.emitter.borrow();
+        assert_eq!(emitter.errs().len(), 1);
+        match emitter.errs()[0] {
+            Diag::IncompatibleTypes(Type::TSR(..), Type::FUN(..)) => (),
+            ref e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_report_full_shapes_of_mismatching_dims() {
+        let sp = CSpan::fresh_span();
+        let shape = |dims: Vec<Type>| Type::TSR(dims, Tags::default(), sp);
+        let resolved = |dims: &[i64]| shape(dims.iter().map(|d| Type::ResolvedDim(*d, sp)).collect());
+        let h = Type::DIM(1, sp);
+        let mut unifier = unifier();
+        unifier.set_full_shapes(true);
+        let cs = Constraints::from_equals(unifier.emitter.clone(), unifier.tenv.clone(), vec![
+            Equals(resolved(&[32, 64, 7, 7]), resolved(&[32, 32, 7, 7])),
+            // the second use of `h` conflicts with the first
+            Equals(shape(vec![h.clone(), h.clone()]), resolved(&[3, 4])),
+        ]);
+        unifier.unify(cs);
+        let emitter = unifier.emitter.borrow();
+        let axes: Vec<_> = emitter.errs().iter()
+            .map(|e| match e {
+                Diag::ShapeMismatch(_, _, axis) => *axis,
+                e => panic!("{:?}", e),
+            })
+            .collect();
+        assert_eq!(axes, vec![1, 1]);
+        let messages: Vec<_> = emitter.errs().iter()
+            .map(|e| e.as_diagnostic(&CodeMap::new()).message)
+            .collect();
+        assert!(messages.contains(&"Shape mismatch: expected [32, 64, 7, 7], found [32, 32, 7, 7] (differ at axis 1)".to_owned()), "{:?}", messages);
+    }
+
+    #[test]
+    fn should_match_eager_substitution() {
+        let subs = chain(30);
+        let eager = subs.iter().fold(Substitution::empty(), |acc, s| compose_eager(&acc, s));
+        let mut lazy = subs.into_iter().fold(Substitution::empty(), |mut acc, s| acc.compose(s));
+        let sp = CSpan::fresh_span();
+        for i in 1..31 {
+            let ty = fun!("Linear", "forward", args!(arg!("x", Type::VAR(i, sp))), Type::DIM(i, sp));
+            assert_eq!(lazy.apply_ty(&ty), apply_ty_eager(&eager, &ty));
+        }
+    }
+
+    #[bench]
+    fn bench_apply_long_chain(b: &mut Bencher) {
+        let sp = CSpan::fresh_span();
+        b.iter(|| {
+            let mut sub = chain(300).into_iter().fold(Substitution::empty(), |mut acc, s| acc.compose(s));
+            (1..301).map(|i| sub.apply_ty(&Type::VAR(i, sp))).count()
+        });
+    }
+
+    #[test]
+    fn should_report_origins_of_transitive_dim_conflict() {
+        use codespan::{ByteIndex, Span};
+        let mut unifier = unifier();
+        let sp = |i: u32| Span::new(ByteIndex(i), ByteIndex(i + 1));
+        let (layer_x, layer_y) = (sp(10), sp(20));
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(Type::VAR(1, sp(1)), Type::ResolvedDim(10, layer_x)),
+                Equals(Type::VAR(1, sp(3)), Type::VAR(2, sp(5))),
+                Equals(Type::VAR(2, sp(7)), Type::ResolvedDim(20, layer_y))
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        unifier.unify(cs);
+        let emitter = unifier.emitter.borrow();
+        assert_eq!(emitter.errs().len(), 1);
+        match emitter.errs()[0] {
+            Diag::ConflictingDimensions(_, o1, _, o2) => {
+                let mut origins = vec![o1, o2];
+                origins.sort();
+                assert_eq!(origins, vec![layer_x, layer_y]);
             }
+            ref e => panic!("{:?}", e),
         }
-        DIM(tvar2, span) => {
-            if *tvar == tvar2 {
-                replacement.with_span(&span)
-            } else {
-                ty
+    }
+
+    #[test]
+    fn should_not_share_origins_between_fresh_spans() {
+        use codespan::{ByteIndex, Span};
+        let mut unifier = unifier();
+        let fresh = CSpan::fresh_span();
+        let layer = Span::new(ByteIndex(10), ByteIndex(11));
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(Type::VAR(1, fresh), Type::ResolvedDim(4, layer)),
+                Equals(Type::VAR(1, fresh), Type::VAR(2, fresh)),
+                Equals(Type::ResolvedDim(5, fresh), Type::ResolvedDim(6, fresh))
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        unifier.unify(cs);
+        let emitter = unifier.emitter.borrow();
+        assert_eq!(emitter.errs().len(), 1);
+        match emitter.errs()[0] {
+            Diag::DimensionMismatch(..) => (),
+            ref e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_tell_dims_from_vars_with_the_same_id() {
+        let sp = CSpan::fresh_span();
+        let (var, dim) = (Type::VAR(1, sp), Type::DIM(1, sp));
+        let sub = Substitution::from_bindings(vec![(var.clone(), args!(arg!("x", dim.clone())))]);
+        assert!(sub.is_ok());
+
+        let mut unifier = unifier();
+        let cs = Constraints {
+            set: btreeset!{ Equals(dim.clone(), var.clone()), Equals(var.clone(), int!()) },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        let mut sub = unifier.unify(cs);
+        assert_eq!(sub.apply_ty(&dim), int!());
+        assert!(unifier.emitter.borrow().errs().is_empty());
+    }
+
+    #[test]
+    fn should_resolve_dtype_from_assertion() {
+        use typing::type_env::{Alias, ModName};
+        use typing::typed_term::TyTerm;
+        let sp = CSpan::fresh_span();
+        let mut unifier = unifier();
+        let scale = Alias::Variable("scale".to_owned());
+        let tvar = unifier.tenv.borrow_mut().fresh_var(sp, None);
+        unifier.tenv.borrow_mut().add_type(&ModName::Global, &scale, tvar.clone()).unwrap();
+        let mut cs = Constraints::new(unifier.emitter.clone(), unifier.tenv.clone());
+        cs.collect(&TyTerm::TyShapeAssert(box TyTerm::TyIdent(tvar.clone(), scale, sp), Type::FLOAT(sp), sp));
+        let mut sub = unifier.unify(cs);
+        assert_eq!(sub.apply_ty(&tvar), Type::FLOAT(sp));
+        assert!(unifier.emitter.borrow().errs().is_empty());
+    }
+
+    #[test]
+    fn should_locate_errors_of_synthesized_constraints() {
+        use codespan::{ByteIndex, Span};
+        use typing::type_env::{Alias, ModName};
+        use typing::typed_term::{TyFnApp, TyTerm};
+        let sp = Span::new(ByteIndex(5), ByteIndex(9));
+        let mut unifier = unifier();
+        let ret_ty = {
+            let mut tenv = unifier.tenv.borrow_mut();
+            let x = Type::TSR(vec![Type::ResolvedDim(3, sp)], Tags::default(), sp);
+            let forward = fun!("Dense", "forward", args!(arg!("x", x.clone(), sp); sp), x, sp);
+            let module = ModName::Named("Dense".to_owned());
+            tenv.upsert_module(&module);
+            tenv.add_type(&module, &Alias::Function("forward".to_owned()), forward.clone()).unwrap();
+            tenv.add_type(&ModName::Global, &Alias::Variable("dense".to_owned()),
+                Type::Module("Dense".to_owned(), Some(box forward), sp)).unwrap();
+            tenv.fresh_var(sp, None)
+        };
+        // called with an int instead of a tensor
+        let call = TyFnApp {
+            mod_name: Some("Dense".to_owned()),
+            orig_name: Some("dense".to_owned()),
+            name: Alias::Function("forward".to_owned()),
+            arg_ty: Type::INT(sp),
+            ret_ty,
+            args: vec![],
+            span: sp,
+        };
+        let mut cs = Constraints::new(unifier.emitter.clone(), unifier.tenv.clone());
+        cs.collect(&TyTerm::TyFnApp(box call));
+        unifier.unify(cs);
+        let emitter = unifier.emitter.borrow();
+        assert!(!emitter.errs().is_empty());
+        for e in emitter.errs() {
+            let d = e.as_diagnostic(&CodeMap::new());
+            assert!(d.labels.iter().all(|l| !CSpan::is_fresh(&l.span)), "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn should_cascade_resolved_dim_into_derived_dims() {
+        let sp = CSpan::fresh_span();
+        let mut unifier = unifier();
+        let (h, h2, out) = (Type::DIM(1, sp), Type::DIM(2, sp), Type::DIM(3, sp));
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(out.clone(), Type::derive('+', h2.clone(), Type::ResolvedDim(1, sp), sp)),
+                Equals(h2.clone(), Type::derive('*', h.clone(), Type::ResolvedDim(2, sp), sp)),
+                Equals(h.clone(), Type::ResolvedDim(3, sp))
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        let mut sub = unifier.unify(cs);
+        assert_eq!(sub.apply_ty(&h2).as_num(), Some(6));
+        assert_eq!(sub.apply_ty(&out).as_num(), Some(7));
+        assert!(unifier.emitter.borrow().errs().is_empty());
+    }
+
+    #[test]
+    fn should_check_derived_dims_once_their_inputs_are_bound() {
+        let sp = CSpan::fresh_span();
+        let (h, w) = (Type::DIM(1, sp), Type::DIM(2, sp));
+        let unify = |w_value: i64| {
+            let mut unifier = unifier();
+            let cs = Constraints {
+                set: btreeset!{
+                    Equals(
+                        Type::derive('*', h.clone(), Type::ResolvedDim(2, sp), sp),
+                        Type::derive('*', w.clone(), Type::ResolvedDim(2, sp), sp)
+                    ),
+                    Equals(h.clone(), Type::ResolvedDim(3, sp)),
+                    Equals(w.clone(), Type::ResolvedDim(w_value, sp))
+                },
+                emitter: unifier.emitter.clone(),
+                tenv: unifier.tenv.clone(),
+            };
+            unifier.unify(cs);
+            let errs = unifier.emitter.borrow().errs().to_vec();
+            errs
+        };
+
+        let errs = unify(4);
+        assert_eq!(errs.len(), 1);
+        match errs[0] {
+            Diag::DimensionMismatch(..) => (),
+            ref e => panic!("{:?}", e),
+        }
+        assert!(unify(3).is_empty());
+    }
+
+    #[test]
+    fn should_report_circular_derived_dim() {
+        let sp = CSpan::fresh_span();
+        let mut unifier = unifier();
+        let (h, w) = (Type::DIM(1, sp), Type::DIM(2, sp));
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(w.clone(), Type::derive('*', h.clone(), Type::ResolvedDim(2, sp), sp)),
+                Equals(h.clone(), Type::derive('+', w.clone(), Type::ResolvedDim(1, sp), sp))
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        unifier.unify(cs);
+        let emitter = unifier.emitter.borrow();
+        assert_eq!(emitter.errs().len(), 1);
+        match emitter.errs()[0] {
+            Diag::CircularDim(..) => (),
+            ref e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_stream_each_recorded_error() {
+        use std::cell::Cell;
+        let sp = CSpan::fresh_span();
+        let dim = |n| Type::ResolvedDim(n, sp);
+        let mut unifier = unifier();
+        let seen = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&seen);
+        unifier.on_error(move |_| counter.set(counter.get() + 1));
+        let cs = Constraints {
+            set: btreeset!{
+                Equals(dim(1), dim(2)),
+                Equals(dim(3), dim(4)),
+                Equals(int!(), Type::BOOL(sp))
+            },
+            emitter: unifier.emitter.clone(),
+            tenv: unifier.tenv.clone(),
+        };
+        unifier.unify(cs);
+        assert_eq!(seen.get(), 3);
+        assert_eq!(seen.get(), unifier.emitter.borrow().errs().len());
+    }
+
+    #[test]
+    fn should_check_divisibility_once_resolved() {
+        let sp = CSpan::fresh_span();
+        let h = Type::DIM(1, sp);
+        let unify = |n: Option<i64>| {
+            let mut unifier = unifier();
+            let mut eqs = vec![Equals::divisible_by(h.clone(), 16, sp)];
+            if let Some(n) = n {
+                eqs.push(Equals(h.clone(), Type::ResolvedDim(n, sp)));
             }
+            let cs = Constraints::from_equals(unifier.emitter.clone(), unifier.tenv.clone(), eqs);
+            unifier.unify(cs);
+            let errs = unifier.emitter.borrow().errs().to_vec();
+            errs
+        };
+
+        // a patch size that does not divide the input
+        let errs = unify(Some(30));
+        assert_eq!(errs.len(), 1);
+        match errs[0] {
+            Diag::NotDivisible(ref dim, 16, _) => assert_eq!(dim.as_num(), Some(30)),
+            ref e => panic!("{:?}", e),
         }
-        FnArgs(args, span) => FnArgs(
-            args.into_iter()
-                .map(|ty| match ty {
-                    FnArg(name, a, s) => FnArg(name, box substitute_tvar(*a, tvar, replacement), s),
-                    _ => panic!(ty),
-                })
-                .collect(),
-            span,
-        ),
-        Tuple(tys, s) => Tuple(tys.into_iter().map(|t| substitute_tvar(t, tvar, replacement)).collect(), s),
-        FUN(module,name,p, r, s) => FUN(
-            module,
-            name,
-            box substitute_tvar(*p, tvar, &replacement),
-            box substitute_tvar(*r, tvar, &replacement),
-            s,
-        ),
-        TSR(_, _) => ty,
-
-        Module(n, Some(box ty), s) => {
-            Module(n, Some(box substitute_tvar(ty, tvar, replacement)), s)
-        }
-
-        Module(_, None, _) => ty,
-        FnArg(name, box ty, s) => FnArg(name, box substitute_tvar(ty, tvar, replacement), s),
-    }
-}
\ No newline at end of file
+        assert!(unify(Some(32)).is_empty());
+        // deferred while the dim is symbolic
+        assert!(unify(None).is_empty());
+    }
+
+    /// linear congruential generator, each fuzz case is reproduced by its seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+
+        fn pick<T: Clone>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len() as u64) as usize].clone()
+        }
+    }
+
+    /// `DIM` ids overlap `VAR` ids, a `TypeEnv` counts the two separately
+    fn gen_dim(rng: &mut Rng, depth: usize) -> Type {
+        let sp = CSpan::fresh_span();
+        match rng.below(if depth == 0 { 2 } else { 3 }) {
+            0 => Type::ResolvedDim(rng.below(9) as i64, sp),
+            1 => Type::DIM(1 + rng.below(4) as usize, sp),
+            _ => {
+                let op = rng.pick(&['+', '-', '*', '/', '%']);
+                let a = gen_dim(rng, depth - 1);
+                let b = gen_dim(rng, depth - 1);
+                Type::derive(op, a, b, sp)
+            }
+        }
+    }
+
+    fn gen_ty(rng: &mut Rng, depth: usize) -> Type {
+        let sp = CSpan::fresh_span();
+        match rng.below(if depth == 0 { 7 } else { 13 }) {
+            0 => Type::Unit(sp),
+            1 => Type::INT(sp),
+            2 => Type::FLOAT(sp),
+            3 => Type::BOOL(sp),
+            4 => Type::STR(sp),
+            5 => Type::VAR(1 + rng.below(4) as usize, sp),
+            6 => gen_dim(rng, 2),
+            7 => {
+                let rank = rng.below(4);
+                Type::TSR((0..rank).map(|_| gen_dim(rng, 1)).collect(), Tags::default(), sp)
+            }
+            8 => {
+                let n = rng.below(3) as usize;
+                let args = ["x", "y", "z"][..n].iter()
+                    .map(|name| Type::FnArg(Some(name.to_string()), box gen_ty(rng, depth - 1), sp))
+                    .collect();
+                Type::FnArgs(args, sp)
+            }
+            9 => {
+                let n = rng.below(3);
+                Type::Tuple((0..n).map(|_| gen_ty(rng, depth - 1)).collect(), sp)
+            }
+            10 => gen_fun(rng, depth - 1),
+            11 => {
+                let name = rng.pick(&["A", "B"]);
+                let ty = if rng.below(2) == 0 { Some(box gen_fun(rng, depth - 1)) } else { None };
+                Type::Module(name.to_owned(), ty, sp)
+            }
+            _ => Type::UnresolvedModuleFun("nonlin", "relu", "forward", sp),
+        }
+    }
+
+    fn gen_fun(rng: &mut Rng, depth: usize) -> Type {
+        let name = rng.pick(&["forward", "new"]);
+        let p = gen_ty(rng, depth);
+        let r = gen_ty(rng, depth);
+        fun!("m", name, p, r)
+    }
+
+    /// unify random equations, half of them built from the same
+    /// variables so that bindings interact
+    fn fuzz(seed: u64) {
+        let mut rng = Rng(seed);
+        let mut unifier = unifier();
+        unifier.set_full_shapes(seed % 2 == 0);
+        let n = 1 + rng.below(6);
+        let eqs: Vec<_> = (0..n).map(|_| Equals(gen_ty(&mut rng, 3), gen_ty(&mut rng, 3))).collect();
+        let cs = Constraints::from_equals(unifier.emitter.clone(), unifier.tenv.clone(), eqs.clone());
+        let mut sub = unifier.unify(cs);
+        // no variable is bound to a type containing itself
+        let bindings: Vec<_> = sub.bindings().map(|(k, v)| (k.clone(), v.clone())).collect();
+        if let Err(e) = Substitution::from_bindings(bindings) {
+            panic!("seed {}: {:?} from {:?}", seed, e, eqs);
+        }
+        for Equals(a, b) in eqs {
+            sub.apply_ty(&a);
+            sub.apply_ty(&b);
+        }
+    }
+
+    #[test]
+    fn should_unify_random_constraints_without_panicking() {
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+        for seed in 0..200 {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                fuzz(seed);
+                tx.send(()).unwrap();
+            });
+            // a panic drops the sender, a loop never sends
+            match rx.recv_timeout(Duration::from_secs(10)) {
+                Ok(()) => (),
+                Err(mpsc::RecvTimeoutError::Timeout) => panic!("seed {} did not terminate", seed),
+                Err(mpsc::RecvTimeoutError::Disconnected) => panic!("seed {} panicked", seed),
+            }
+        }
+    }
+
+    #[test]
+    fn should_report_each_kind_of_unsatisfiable_equation() {
+        let sp = CSpan::fresh_span();
+        let x = Type::VAR(1, sp);
+        let cases = vec![
+            Equals(x.clone(), Type::Tuple(vec![int!(), x.clone()], sp)),
+            Equals(x.clone(), args!(arg!("x", x.clone()))),
+            Equals(arg!("x", int!()), arg!("y", int!())),
+            Equals(fun!("m", "forward", args!(), unit!()), fun!("m", "new", args!(), unit!())),
+            Equals(
+                Type::Module("A".to_owned(), Some(box fun!("A", "forward", args!(), unit!())), sp),
+                Type::Module("B".to_owned(), Some(box fun!("B", "forward", args!(), unit!())), sp)
+            ),
+        ];
+        for eq in cases {
+            let mut unifier = unifier();
+            let cs = Constraints::from_equals(unifier.emitter.clone(), unifier.tenv.clone(), vec![eq.clone()]);
+            assert_eq!(unifier.unify(cs), Substitution::empty(), "{:?}", eq);
+            let emitter = unifier.emitter.borrow();
+            assert_eq!(emitter.errs().len(), 1, "{:?}", eq);
+            match emitter.errs()[0] {
+                Diag::IncompatibleTypes(..) => (),
+                ref e => panic!("{:?}: {:?}", eq, e),
+            }
+        }
+    }
+}
@@ -35,4 +35,101 @@ fn test_gan() {
         .and()
         .stdout().is(include_str!("output/gan.py"))
         .unwrap();
-}
\ No newline at end of file
+}
+#[test]
+fn test_strict_shapes() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/underconstrained.trs"])
+        .succeeds()
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/underconstrained.trs", "--strict-shapes"])
+        .fails()
+        .and()
+        .stderr().contains("is not resolved")
+        .unwrap();
+}
+
+#[test]
+fn test_shape_assert() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/shape_assert.trs"])
+        .succeeds()
+        .and()
+        .stdout().is(include_str!("output/xor.py"))
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/shape_assert_fail.trs"])
+        .fails()
+        .and()
+        .stderr().contains("Type mismatch")
+        .unwrap();
+}
+
+#[test]
+fn test_import_path() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/use_common.trs", "--import-path", "tests/input/lib"])
+        .succeeds()
+        .and()
+        .stdout().contains("self.fc1 = nn.Linear(in_features=2, out_features=3)")
+        .unwrap();
+}
+
+#[test]
+fn test_detach() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/detach.trs"])
+        .succeeds()
+        .and()
+        .stdout().contains("x = x.detach()")
+        .unwrap();
+}
+
+#[test]
+fn test_no_grad() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/no_grad.trs"])
+        .succeeds()
+        .and()
+        .stdout().contains("    def forward(self, x):
+        with torch.no_grad():
+            x = self.fc1(x)
+            x = F.sigmoid(x)
+            return self.fc2(x)")
+        .unwrap();
+}
+
+#[test]
+fn test_profile() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/mnist.trs", "--profile"])
+        .succeeds()
+        .and()
+        .stdout().is(include_str!("output/mnist.py"))
+        .and()
+        .stderr().contains("substitutions")
+        .unwrap();
+}
+
+#[test]
+fn test_check_weights() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/xor.trs", "--check-weights", "tests/input/xor_weights.json"])
+        .fails()
+        .and()
+        .stderr().contains("Parameter `fc2.weight` has shape [1, 3] but the checkpoint has [2, 3]")
+        .unwrap();
+}
+
+#[test]
+fn test_max_errors_takes_a_number() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["--in", "tests/input/xor.trs", "--max-errors", "few"])
+        .fails()
+        .and()
+        .stderr().contains("--max-errors takes a number")
+        .unwrap();
+}
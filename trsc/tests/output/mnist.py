@@ -11,7 +11,7 @@ class Mnist(nn.Module):
         super(Mnist, self).__init__()
         self.conv1 = nn.Conv2d(in_channels=1, out_channels=10, kernel_size=(5, 5))
         self.conv2 = nn.Conv2d(in_channels=10, out_channels=20, kernel_size=5)
-        self.dropout = Dropout2d(p=0.5)
+        self.dropout = nn.Dropout2d(p=0.5)
         self.fc1 = nn.Linear(in_features=320, out_features=50)
         self.fc2 = nn.Linear(in_features=50, out_features=10)
         nn.init.normal_(std=1)
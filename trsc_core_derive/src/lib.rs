@@ -40,6 +40,7 @@ fn impl_op(ast: &syn::DeriveInput) -> quote::Tokens {
     let path = get_path(&ast.attrs).unwrap_or_else(|| panic!("no path supplied"));
     let fn_decls = get_fn_decls(path, &fns);
     let ty_sigs = gen_ty_sigs(&fn_decls);
+    let variadic = gen_variadic(&fn_decls);
 
     quote! {
         impl Op for #name {
@@ -52,6 +53,7 @@ fn impl_op(ast: &syn::DeriveInput) -> quote::Tokens {
             fn is_stateful(&self) -> bool {
                 #stateful
             }
+            #variadic
         }
     }
 }
@@ -75,11 +77,42 @@ fn gen_ty_sigs(decls: &[FnDecl]) -> quote::Tokens {
     }
 }
 
+fn gen_variadic(decls: &[FnDecl]) -> quote::Tokens {
+    let variadic: Vec<&FnDecl> = decls.iter().filter(|i| i.variadic.is_some()).collect();
+    if variadic.is_empty() {
+        return quote! {};
+    }
+    let names: Vec<&String> = variadic.iter().map(|i| &i.name).collect();
+    let params: Vec<&String> = variadic.iter().map(|i| &i.variadic.as_ref().unwrap().0).collect();
+    let elems: Vec<&String> = variadic.iter().map(|i| &i.variadic.as_ref().unwrap().1).collect();
+    let fixed: Vec<quote::Tokens> = variadic.iter().map(|i| {
+        let fixed = &i.params;
+        quote! { &[#(#fixed),*] }
+    }).collect();
+    quote! {
+        fn variadic(&self, fn_name: &str) -> Option<Variadic> {
+            match fn_name {
+                #(
+                    #names => Some(Variadic {
+                        name: #params,
+                        fixed: #fixed,
+                        elem: #elems,
+                    }),
+                )*
+                _ => None,
+            }
+        }
+    }
+}
+
 fn gen_decl(fn_decl: &FnDecl) -> quote::Tokens {
     let name = &fn_decl.name;
     let path = &fn_decl.path;
 
     if fn_decl.resolved {
+        if fn_decl.variadic.is_some() {
+            panic!("variadic params need an unresolved signature");
+        }
         let params = &fn_decl.params;
         let tys = &fn_decl.tys;
         let ret = &fn_decl.ret;
@@ -42,6 +42,9 @@ pub struct FnDecl {
     pub resolved: bool,
     pub params: Vec<String>,
     pub tys: Vec<Type>,
+    /// trailing `*param: ty` collecting the remaining args, with the
+    /// keyword of their element type
+    pub variadic: Option<(String, String)>,
     pub ret: Type,
     pub name: String,
     pub path: String,
@@ -56,6 +59,7 @@ pub enum Token {
     ARROW,
     QMARK,
     COMMA,
+    STAR,
 }
 
 pub fn parse_decl(path: &str, name: &str, decl: &str) -> FnDecl {
@@ -81,6 +85,7 @@ fn parse(path: &str, name: &str, toks: &[Token]) -> FnDecl {
         resolved: true,
         params: vec![],
         tys: vec![],
+        variadic: None,
         ret: self::Type::Float,
     };
 
@@ -95,6 +100,25 @@ fn parse(path: &str, name: &str, toks: &[Token]) -> FnDecl {
     while let Some(tok) = it.peek().cloned() {
         if *tok == RPAREN {
             it.next();
+        } else if *tok == STAR {
+            it.next();
+            // variadic param name and element ty
+            let name = if let Some(WORD(ref name)) = it.next() {
+                name.clone()
+            } else {
+                panic!("No variadic param name specified");
+            };
+            eat!(it, SEMI, "semi");
+            if let Some(WORD(ref tyword)) = it.next() {
+                // only validated here, `Variadic` keeps the keyword
+                Type::from_str(tyword.as_str());
+                ret.variadic = Some((name, tyword.clone()));
+            } else {
+                panic!("No param type specified");
+            }
+            if it.peek() != Some(&&RPAREN) {
+                panic!("variadic param must be last");
+            }
         } else if let WORD(ref name) = *tok {
             // param name
             ret.params.push(name.clone());
@@ -145,6 +169,10 @@ fn lex(decl: &str) -> Vec<Token> {
                 toks.push(QMARK);
                 it.next();
             }
+            '*' => {
+                toks.push(STAR);
+                it.next();
+            }
             ' ' | '\n' => {
                 it.next();
             }